@@ -0,0 +1,26 @@
+use std::error::Error;
+use tmc::compliance::check_compliance;
+use tmc::list_instruments;
+
+// Check every attached instrument against the USBTMC spec and print a report.
+// This sends commands to the instruments, so don't run it on ones in use.
+fn main() -> Result<(), Box<dyn Error>> {
+    let context = rusb::Context::new()?;
+    let instruments = list_instruments(context)?;
+
+    if instruments.is_empty() {
+        println!("no instruments found");
+    }
+
+    for mut instrument in instruments {
+        println!(
+            "Checking instrument: {}",
+            instrument.read_resource_string()?
+        );
+
+        let mut handle = instrument.open()?;
+        println!("{}", check_compliance(&mut handle));
+    }
+
+    Ok(())
+}
@@ -1,4 +1,3 @@
-use rusb;
 use std::error::Error;
 use tmc::list_instruments;
 
@@ -12,7 +11,7 @@ fn main() -> Result<(), Box<dyn Error>> {
     let context = rusb::Context::new()?;
     let instruments = list_instruments(context)?;
 
-    if instruments.len() == 0 {
+    if instruments.is_empty() {
         println!("no instruments found");
         return Ok(());
     }
@@ -22,8 +21,8 @@ fn main() -> Result<(), Box<dyn Error>> {
     for mut instrument in instruments {
         println!("Found instrument: {}", instrument.read_resource_string()?);
 
-        let handle = instrument.open()?;
-        if let Some(id) = &handle.scpi_id {
+        let mut handle = instrument.open()?;
+        if let Some(id) = handle.scpi_id()?.map(str::to_owned) {
             if id.starts_with("Keysight Technologies,U2000A") {
                 println!("Found power sensor: {}", id);
                 power_sensor = Some(handle);
@@ -37,7 +36,7 @@ fn main() -> Result<(), Box<dyn Error>> {
     }
 
     if let Some(mut handle) = power_sensor {
-        handle.set_max_transfer_size(1024);
+        handle.set_max_transfer_size(1024)?;
         handle.set_term_char(Some(b'\n'))?;
 
         handle.write(&format!("SENS1:FREQ {}\n", FREQ_HZ as u64))?;
@@ -0,0 +1,262 @@
+//! Synchronised acquisition across several instruments: arm each of them, trigger
+//! them all as close together as possible, then collect their results.
+//!
+//! The time each trigger was sent is reported, so the skew between instruments
+//! can be analysed.  Triggers are sent back to back from the calling thread, or
+//! with [threaded](Acquisition::threaded) from one thread per instrument,
+//! released together by a barrier, so that one slow USB transfer doesn't delay
+//! the triggers after it.
+
+use crate::transport::Transport;
+use crate::{TMCHandle, TMCResult, TriggerStrategy};
+use std::sync::Barrier;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// How an instrument is triggered; the earlier name of [TriggerStrategy]
+pub type TriggerMethod = TriggerStrategy;
+
+/// One instrument taking part in an [Acquisition]
+#[derive(Debug)]
+pub struct Participant<'a, T: Transport> {
+    name: String,
+    handle: &'a mut TMCHandle<T>,
+    arm: Vec<String>,
+    trigger: Option<TriggerStrategy>,
+    fetch: Option<String>,
+}
+
+impl<'a, T: Transport> Participant<'a, T> {
+    /// An instrument which is triggered with its handle's [TriggerStrategy] and
+    /// has no arm commands or fetch query.  `name` identifies it in the report.
+    pub fn new(name: &str, handle: &'a mut TMCHandle<T>) -> Self {
+        Self {
+            name: name.to_owned(),
+            handle,
+            arm: Vec::new(),
+            trigger: None,
+            fetch: None,
+        }
+    }
+
+    /// Add a command to send when arming the instrument, such as `INIT`.
+    /// Commands are sent in the order they were added.
+    pub fn arm(mut self, command: &str) -> Self {
+        self.arm.push(command.to_owned());
+        self
+    }
+
+    /// Trigger the instrument with `strategy` rather than its handle's
+    pub fn trigger(mut self, strategy: TriggerStrategy) -> Self {
+        self.trigger = Some(strategy);
+        self
+    }
+
+    /// Query the instrument's result with `query`, such as `FETC?`, once every
+    /// instrument has been triggered
+    pub fn fetch(mut self, query: &str) -> Self {
+        self.fetch = Some(query.to_owned());
+        self
+    }
+
+    /// Send the arm commands, and settle how the instrument will be triggered
+    fn prepare(&mut self) -> TMCResult<TriggerStrategy> {
+        for command in self.arm.iter() {
+            self.handle.write(command)?;
+        }
+
+        let strategy = match &self.trigger {
+            Some(strategy) => strategy.clone(),
+            None => self.handle.get_trigger_strategy().clone(),
+        };
+        self.handle.resolve_trigger_strategy(&strategy)
+    }
+
+    fn fire(&mut self, strategy: &TriggerStrategy) -> Fired {
+        let started = Instant::now();
+        let triggered_at = SystemTime::now();
+        let result = self.handle.trigger_with(strategy);
+
+        Fired {
+            started,
+            triggered_at,
+            duration: started.elapsed(),
+            result,
+        }
+    }
+}
+
+/// When and how a trigger was sent
+struct Fired {
+    started: Instant,
+    triggered_at: SystemTime,
+    duration: Duration,
+    result: TMCResult<()>,
+}
+
+/// Arms, triggers and collects results from several instruments
+#[derive(Debug)]
+pub struct Acquisition<'a, T: Transport> {
+    participants: Vec<Participant<'a, T>>,
+    threaded: bool,
+}
+
+impl<T: Transport> Default for Acquisition<'_, T> {
+    fn default() -> Self {
+        Self {
+            participants: Vec::new(),
+            threaded: false,
+        }
+    }
+}
+
+impl<'a, T: Transport> Acquisition<'a, T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an instrument.  Instruments are armed, triggered and fetched in the
+    /// order they were added.
+    pub fn participant(mut self, participant: Participant<'a, T>) -> Self {
+        self.participants.push(participant);
+        self
+    }
+
+    /// Trigger each instrument from its own thread, with the threads released
+    /// together by a barrier, rather than one after another from the calling
+    /// thread
+    pub fn threaded(mut self, threaded: bool) -> Self {
+        self.threaded = threaded;
+        self
+    }
+
+    /// Arm every instrument, trigger them, then fetch their results.  Fails
+    /// without triggering any instrument if one can't be armed.  Errors
+    /// triggering or fetching are reported per instrument instead, so that one
+    /// failing instrument doesn't lose the results of the others.
+    pub fn run(&mut self) -> TMCResult<AcquisitionReport> {
+        let strategies = self
+            .participants
+            .iter_mut()
+            .map(Participant::prepare)
+            .collect::<TMCResult<Vec<_>>>()?;
+
+        let fired = if self.threaded {
+            self.fire_threaded(&strategies)
+        } else {
+            self.participants
+                .iter_mut()
+                .zip(strategies.iter())
+                .map(|(participant, strategy)| participant.fire(strategy))
+                .collect()
+        };
+
+        let first = fired.iter().map(|fired| fired.started).min();
+        let devices = self
+            .participants
+            .iter_mut()
+            .zip(fired)
+            .map(|(participant, fired)| {
+                let response = match (&fired.result, &participant.fetch) {
+                    (Ok(()), Some(query)) => Some(participant.handle.ask(query)),
+                    _ => None,
+                };
+
+                DeviceReport {
+                    name: participant.name.clone(),
+                    triggered_at: fired.triggered_at,
+                    trigger_offset: first
+                        .map(|first| fired.started.duration_since(first))
+                        .unwrap_or_default(),
+                    trigger_duration: fired.duration,
+                    trigger_result: fired.result,
+                    response,
+                }
+            })
+            .collect();
+
+        Ok(AcquisitionReport { devices })
+    }
+
+    fn fire_threaded(&mut self, strategies: &[TriggerStrategy]) -> Vec<Fired> {
+        let barrier = Barrier::new(self.participants.len());
+
+        thread::scope(|scope| {
+            let threads: Vec<_> = self
+                .participants
+                .iter_mut()
+                .zip(strategies.iter())
+                .map(|(participant, strategy)| {
+                    let barrier = &barrier;
+                    scope.spawn(move || {
+                        barrier.wait();
+                        participant.fire(strategy)
+                    })
+                })
+                .collect();
+
+            threads
+                .into_iter()
+                .map(|thread| {
+                    thread
+                        .join()
+                        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+                })
+                .collect()
+        })
+    }
+}
+
+/// The outcome of an acquisition for one instrument
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceReport {
+    /// The name the instrument was added with
+    pub name: String,
+
+    /// When sending the trigger began
+    pub triggered_at: SystemTime,
+
+    /// When sending the trigger began, relative to the first instrument's trigger
+    pub trigger_offset: Duration,
+
+    /// How long sending the trigger took
+    pub trigger_duration: Duration,
+
+    pub trigger_result: TMCResult<()>,
+
+    /// The response to the fetch query, if there is one and the instrument was
+    /// triggered
+    pub response: Option<TMCResult<String>>,
+}
+
+/// The outcome of an [Acquisition], with the instruments in the order they were
+/// added
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AcquisitionReport {
+    pub devices: Vec<DeviceReport>,
+}
+
+impl AcquisitionReport {
+    /// The spread between the earliest and the latest trigger of the instruments
+    /// which were triggered successfully
+    pub fn skew(&self) -> Duration {
+        self.devices
+            .iter()
+            .filter(|device| device.trigger_result.is_ok())
+            .map(|device| device.trigger_offset)
+            .fold(None, |range: Option<(Duration, Duration)>, offset| {
+                Some(range.map_or((offset, offset), |(low, high)| {
+                    (low.min(offset), high.max(offset))
+                }))
+            })
+            .map_or(Duration::ZERO, |(low, high)| high - low)
+    }
+
+    /// Whether every instrument was triggered and, where there is a fetch query,
+    /// answered it
+    pub fn is_complete(&self) -> bool {
+        self.devices
+            .iter()
+            .all(|device| device.trigger_result.is_ok() && !matches!(device.response, Some(Err(_))))
+    }
+}
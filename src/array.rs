@@ -0,0 +1,84 @@
+//! Numeric arrays sent as comma-separated ASCII text, which is how many
+//! instruments send traces and readings when they have no binary format.  The
+//! response is parsed as it arrives, one transfer at a time, so that parsing a
+//! large array overlaps reading it.  With the `fast-float` feature, numbers are
+//! parsed with `lexical-parse-float` rather than the standard library.
+
+use crate::transport::Transport;
+use crate::{TMCError, TMCHandle, TMCResult};
+
+/// Collects the numbers in a comma-separated response fed to it in pieces
+#[derive(Debug, Default)]
+struct ArrayParser {
+    values: Vec<f64>,
+    // the start of a field split across pieces
+    partial: Vec<u8>,
+}
+
+impl ArrayParser {
+    fn feed(&mut self, mut data: &[u8]) -> TMCResult<()> {
+        while let Some(comma) = data.iter().position(|&byte| byte == b',') {
+            if self.partial.is_empty() {
+                self.push(&data[..comma])?;
+            } else {
+                self.partial.extend_from_slice(&data[..comma]);
+                let field = std::mem::take(&mut self.partial);
+                self.push(&field)?;
+                self.partial = field;
+                self.partial.clear();
+            }
+            data = &data[comma + 1..];
+        }
+
+        self.partial.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn finish(mut self) -> TMCResult<Vec<f64>> {
+        let field = std::mem::take(&mut self.partial);
+        // an empty response is an empty array
+        if !self.values.is_empty() || !field.trim_ascii().is_empty() {
+            self.push(&field)?;
+        }
+        Ok(self.values)
+    }
+
+    fn push(&mut self, field: &[u8]) -> TMCResult<()> {
+        let field = field.trim_ascii();
+        let value = parse_f64(field).ok_or_else(|| {
+            TMCError::InvalidResponse(String::from_utf8_lossy(field).into_owned())
+        })?;
+        self.values.push(value);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "fast-float")]
+fn parse_f64(field: &[u8]) -> Option<f64> {
+    use lexical_parse_float::FromLexical;
+    f64::from_lexical(field).ok()
+}
+
+#[cfg(not(feature = "fast-float"))]
+fn parse_f64(field: &[u8]) -> Option<f64> {
+    std::str::from_utf8(field).ok()?.parse().ok()
+}
+
+impl<T: Transport> TMCHandle<T> {
+    /// Write a query and parse its response as comma-separated numbers, such as
+    /// `+1.234E-03,+5.678E-03`.  Whitespace around each number, including the
+    /// response's line ending, is ignored, and an empty response is an empty
+    /// array.  If a field isn't a number, the rest of the response is discarded
+    /// rather than read, and the field is returned in an
+    /// [InvalidResponse](TMCError::InvalidResponse) error.
+    pub fn query_f64_array(&mut self, command: &str) -> TMCResult<Vec<f64>> {
+        let mut parser = ArrayParser::default();
+
+        let error = self.ask_parse_with(command, |data, _| parser.feed(data).err())?;
+        if let Some(error) = error {
+            return Err(error);
+        }
+
+        parser.finish()
+    }
+}
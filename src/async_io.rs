@@ -0,0 +1,170 @@
+//! Tokio's async IO traits over an instrument's messages, so that codec-based
+//! pipelines (such as `FramedRead` with a lines codec) can consume instrument
+//! output directly.
+//!
+//! The handle's blocking operations run on Tokio's blocking thread pool, so an
+//! [AsyncStream] must be used from within a Tokio runtime.
+
+use crate::transport::Transport;
+use crate::{TMCHandle, TMCResult};
+use std::future::{self, Future};
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::task::{self, JoinHandle};
+
+/// A handle operation running on the blocking thread pool, which gives the handle
+/// back with its result
+type Operation<T, R> = JoinHandle<(Box<TMCHandle<T>>, TMCResult<R>)>;
+
+#[derive(Debug)]
+enum State<T: Transport> {
+    Idle(Box<TMCHandle<T>>),
+    Reading(Operation<T, Vec<u8>>),
+    Writing(Operation<T, ()>),
+
+    /// The handle was lost when an operation panicked
+    Lost,
+}
+
+/// An instrument as a byte stream, implementing [AsyncRead] and [AsyncWrite].
+///
+/// Reading returns the instrument's response messages one after another, each
+/// read as a whole with [read_raw](TMCHandle::read_raw) when the data before it
+/// has been consumed.  A read which times out is an error, as it is from the
+/// handle.  Each write sends its data as one command message.  Like writing to
+/// a `tokio::fs::File`, a write returns as soon as the message is queued, and an
+/// error sending it is returned by the next operation (or by a flush).
+#[derive(Debug)]
+pub struct AsyncStream<T: Transport> {
+    state: State<T>,
+    buffer: Vec<u8>,
+    position: usize,
+}
+
+impl<T: Transport + 'static> AsyncStream<T> {
+    pub fn new(handle: TMCHandle<T>) -> Self {
+        Self {
+            state: State::Idle(Box::new(handle)),
+            buffer: Vec::new(),
+            position: 0,
+        }
+    }
+
+    /// Wait for the read or write in progress and take back the handle.
+    /// Response data which has been read but not consumed is discarded, and an
+    /// error from the last write is not reported.
+    pub async fn into_inner(mut self) -> io::Result<TMCHandle<T>> {
+        let _ = future::poll_fn(|cx| self.poll_complete(cx)).await;
+        match self.state {
+            State::Idle(handle) => Ok(*handle),
+            _ => Err(lost()),
+        }
+    }
+
+    /// Wait for the operation in progress, if any, leaving the stream idle or
+    /// lost.  A read's data is kept in the buffer, and a write's error returned.
+    fn poll_complete(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let result = match &mut self.state {
+            State::Idle(_) => return Poll::Ready(Ok(())),
+            State::Lost => return Poll::Ready(Err(lost())),
+            State::Reading(task) => match Pin::new(task).poll(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Ok((handle, result))) => {
+                    self.state = State::Idle(handle);
+                    result.map(|data| {
+                        self.buffer = data;
+                        self.position = 0;
+                    })
+                }
+                Poll::Ready(Err(_)) => {
+                    self.state = State::Lost;
+                    return Poll::Ready(Err(lost()));
+                }
+            },
+            State::Writing(task) => match Pin::new(task).poll(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Ok((handle, result))) => {
+                    self.state = State::Idle(handle);
+                    result
+                }
+                Poll::Ready(Err(_)) => {
+                    self.state = State::Lost;
+                    return Poll::Ready(Err(lost()));
+                }
+            },
+        };
+        Poll::Ready(result.map_err(io::Error::from))
+    }
+
+    fn take_handle(&mut self) -> Box<TMCHandle<T>> {
+        match std::mem::replace(&mut self.state, State::Lost) {
+            State::Idle(handle) => handle,
+            _ => unreachable!("the stream is idle after poll_complete"),
+        }
+    }
+}
+
+impl<T: Transport + 'static> AsyncRead for AsyncStream<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        loop {
+            if this.position < this.buffer.len() {
+                let available = &this.buffer[this.position..];
+                let count = available.len().min(buf.remaining());
+                buf.put_slice(&available[..count]);
+                this.position += count;
+                return Poll::Ready(Ok(()));
+            }
+
+            ready!(this.poll_complete(cx))?;
+
+            // a write or an empty response leaves nothing to return, so read the
+            // next response
+            if this.position >= this.buffer.len() {
+                let mut handle = this.take_handle();
+                this.state = State::Reading(task::spawn_blocking(move || {
+                    let result = handle.read_raw(None);
+                    (handle, result)
+                }));
+            }
+        }
+    }
+}
+
+impl<T: Transport + 'static> AsyncWrite for AsyncStream<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_complete(cx))?;
+
+        let data = buf.to_vec();
+        let mut handle = this.take_handle();
+        this.state = State::Writing(task::spawn_blocking(move || {
+            let result = handle.write_raw(&data);
+            (handle, result)
+        }));
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_complete(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_complete(cx)
+    }
+}
+
+fn lost() -> io::Error {
+    io::Error::other("the instrument handle was lost when an operation panicked")
+}
@@ -0,0 +1,178 @@
+//! Session attributes in the style of VISA, for frameworks which get and set
+//! instrument configuration generically by attribute name.
+
+use crate::{InstrumentHandle, TMCError, TMCResult, Timeout};
+use core::time::Duration;
+use rusb::UsbContext;
+use std::collections::BTreeMap;
+use std::fmt;
+
+pub const ATTR_TMO_VALUE: &str = "VI_ATTR_TMO_VALUE";
+pub const ATTR_TERMCHAR: &str = "VI_ATTR_TERMCHAR";
+pub const ATTR_TERMCHAR_EN: &str = "VI_ATTR_TERMCHAR_EN";
+pub const ATTR_RSRC_NAME: &str = "VI_ATTR_RSRC_NAME";
+pub const ATTR_MANF_ID: &str = "VI_ATTR_MANF_ID";
+pub const ATTR_MODEL_CODE: &str = "VI_ATTR_MODEL_CODE";
+pub const ATTR_USB_SERIAL_NUM: &str = "VI_ATTR_USB_SERIAL_NUM";
+pub const ATTR_USB_INTFC_NUM: &str = "VI_ATTR_USB_INTFC_NUM";
+pub const ATTR_USB_PROTOCOL: &str = "VI_ATTR_USB_PROTOCOL";
+pub const ATTR_4882_COMPLIANT: &str = "VI_ATTR_4882_COMPLIANT";
+
+// These have no VISA equivalent
+pub const ATTR_MAX_TRANSFER_SIZE: &str = "TMC_ATTR_MAX_TRANSFER_SIZE";
+pub const ATTR_BCD_USBTMC: &str = "TMC_ATTR_BCD_USBTMC";
+pub const ATTR_PULSE: &str = "TMC_ATTR_PULSE";
+pub const ATTR_TALK_ONLY: &str = "TMC_ATTR_TALK_ONLY";
+pub const ATTR_LISTEN_ONLY: &str = "TMC_ATTR_LISTEN_ONLY";
+pub const ATTR_TERMCHAR_SUPPORTED: &str = "TMC_ATTR_TERMCHAR_SUPPORTED";
+pub const ATTR_SCPI: &str = "TMC_ATTR_SCPI";
+pub const ATTR_SCPI_ID: &str = "TMC_ATTR_SCPI_ID";
+
+/// Timeout attribute value for waiting forever, VISA's `VI_TMO_INFINITE`
+pub const TMO_INFINITE: u64 = 0xFFFF_FFFF;
+
+/// Timeout attribute value for not waiting, VISA's `VI_TMO_IMMEDIATE`
+pub const TMO_IMMEDIATE: u64 = 0;
+
+/// The timeout attribute value for a handle timeout.  Finite timeouts too long
+/// to give in VISA's 32-bit milliseconds are reported as infinite.
+fn timeout_attribute(timeout: Duration) -> u64 {
+    match timeout {
+        Duration::ZERO => TMO_IMMEDIATE,
+        timeout => timeout.as_millis().min(TMO_INFINITE as u128) as u64,
+    }
+}
+
+/// The handle timeout for a timeout attribute value, if it is in range
+fn attribute_timeout(millis: u64) -> Option<Timeout> {
+    match millis {
+        TMO_IMMEDIATE => Some(Timeout::Immediate),
+        TMO_INFINITE => Some(Timeout::Forever),
+        millis if millis < TMO_INFINITE => Some(Timeout::After(Duration::from_millis(millis))),
+        _ => None,
+    }
+}
+
+/// Value of a session attribute
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AttributeValue {
+    Bool(bool),
+    UInt(u64),
+    String(String),
+}
+
+impl fmt::Display for AttributeValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AttributeValue::Bool(value) => write!(f, "{}", value),
+            AttributeValue::UInt(value) => write!(f, "{}", value),
+            AttributeValue::String(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+impl<Ctx: UsbContext> InstrumentHandle<Ctx> {
+    /// Get all session attributes, keyed by their VISA-like names.  Attributes
+    /// which aren't known for this instrument (such as a serial number it doesn't
+    /// report) are left out.
+    pub fn attributes(&mut self) -> TMCResult<BTreeMap<&'static str, AttributeValue>> {
+        use AttributeValue::*;
+
+        let mut attrs = BTreeMap::new();
+
+        attrs.insert(ATTR_TMO_VALUE, UInt(timeout_attribute(self.get_timeout())));
+        attrs.insert(ATTR_TERMCHAR, UInt(self.term_char_value() as u64));
+        attrs.insert(ATTR_TERMCHAR_EN, Bool(self.get_term_char().is_some()));
+        attrs.insert(
+            ATTR_MAX_TRANSFER_SIZE,
+            UInt(self.get_max_transfer_size() as u64),
+        );
+
+        let instrument = self.instrument_mut();
+        attrs.insert(ATTR_RSRC_NAME, String(instrument.read_resource_string()?));
+        attrs.insert(
+            ATTR_MANF_ID,
+            UInt(instrument.device_desc.vendor_id() as u64),
+        );
+        attrs.insert(
+            ATTR_MODEL_CODE,
+            UInt(instrument.device_desc.product_id() as u64),
+        );
+        if let Some(serial_number) = instrument.read_serial_number()? {
+            attrs.insert(ATTR_USB_SERIAL_NUM, String(serial_number));
+        }
+        attrs.insert(
+            ATTR_USB_INTFC_NUM,
+            UInt(instrument.endpoints.interface_number as u64),
+        );
+        attrs.insert(
+            ATTR_USB_PROTOCOL,
+            UInt(instrument.endpoints.interface_protocol as u64),
+        );
+
+        let caps = self.usbtmc_capabilities()?.clone();
+        attrs.insert(ATTR_BCD_USBTMC, UInt(caps.bcd_usbtmc as u64));
+        attrs.insert(ATTR_PULSE, Bool(caps.pulse));
+        attrs.insert(ATTR_TALK_ONLY, Bool(caps.talk_only));
+        attrs.insert(ATTR_LISTEN_ONLY, Bool(caps.listen_only));
+        attrs.insert(ATTR_TERMCHAR_SUPPORTED, Bool(caps.term_char));
+
+        let usb488 = self.usb488_capabilities()?.cloned();
+        let usb488 = usb488.as_ref();
+        attrs.insert(
+            ATTR_4882_COMPLIANT,
+            Bool(usb488.is_some_and(|caps| caps.usb488_2)),
+        );
+        attrs.insert(ATTR_SCPI, Bool(usb488.is_some_and(|caps| caps.scpi)));
+
+        #[cfg(feature = "scpi")]
+        if let Some(scpi_id) = self.scpi_id()? {
+            attrs.insert(ATTR_SCPI_ID, String(scpi_id.to_owned()));
+        }
+
+        Ok(attrs)
+    }
+
+    /// Set a session attribute by its VISA-like name.  Only the timeout, term char
+    /// and maximum transfer size attributes can be set.  As in VISA, the term char
+    /// and whether it is matched are set independently, and the term char is
+    /// kept while matching is disabled.  The timeout is in milliseconds, with
+    /// [TMO_INFINITE] and [TMO_IMMEDIATE] meaning what they do in VISA.
+    pub fn set_attribute(&mut self, name: &str, value: AttributeValue) -> TMCResult<()> {
+        use AttributeValue::*;
+
+        match (name, value) {
+            (ATTR_TMO_VALUE, UInt(millis)) => match attribute_timeout(millis) {
+                Some(timeout) => {
+                    self.set_timeout(timeout);
+                    Ok(())
+                }
+                None => Err(TMCError::InvalidAttribute(name.to_owned())),
+            },
+            (ATTR_TERMCHAR, UInt(term_char)) if term_char <= u8::MAX as u64 => {
+                self.set_term_char_value(term_char as u8)
+            }
+            (ATTR_TERMCHAR_EN, Bool(enabled)) => self.set_term_char_enabled(enabled),
+            (ATTR_MAX_TRANSFER_SIZE, UInt(size)) if size > 0 && size <= u32::MAX as u64 => {
+                self.set_max_transfer_size(size as u32)
+            }
+            (name, _) => Err(TMCError::InvalidAttribute(name.to_owned())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timeout_attribute_round_trip() {
+        for millis in [TMO_IMMEDIATE, 1, 2000, TMO_INFINITE - 1, TMO_INFINITE] {
+            let timeout = attribute_timeout(millis).unwrap();
+            assert_eq!(timeout_attribute(timeout.as_duration()), millis);
+        }
+        assert_eq!(attribute_timeout(TMO_INFINITE), Some(Timeout::Forever));
+        assert_eq!(attribute_timeout(TMO_IMMEDIATE), Some(Timeout::Immediate));
+        assert_eq!(attribute_timeout(TMO_INFINITE + 1), None);
+    }
+}
@@ -0,0 +1,23 @@
+//! Present a simulated USBTMC instrument to a USB host through FunctionFS.
+//!
+//! Usage: `tmc-emulator <functionfs mountpoint> <script.yaml>`
+
+use std::error::Error;
+use tmc::gadget::Emulator;
+use tmc::sim::{Script, SimTransport};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = std::env::args().collect();
+    let (mountpoint, script) = match &args[..] {
+        [_, mountpoint, script] => (mountpoint, script),
+        _ => {
+            eprintln!("usage: tmc-emulator <functionfs mountpoint> <script.yaml>");
+            std::process::exit(2);
+        }
+    };
+
+    let script = Script::load(script)?;
+    let sim = SimTransport::new(&script)?;
+    Emulator::new(mountpoint, sim).run()?;
+    Ok(())
+}
@@ -0,0 +1,88 @@
+//! IEEE 488.2 arbitrary blocks, the usual way for instruments to send and take
+//! binary data: `#`, a digit giving the number of length digits, the length in
+//! bytes, then the data.  `#0` starts an indefinite-length block, which runs to
+//! the end of the message.
+
+use crate::transport::Transport;
+use crate::{TMCError, TMCHandle, TMCResult};
+use std::ops::Range;
+
+/// Parse the header of the block starting `message`, which may be incomplete:
+/// the offset of the block's data and, for a definite-length block, its length.
+/// `None` if not enough of the header has arrived to tell.
+pub(crate) fn block_header(message: &[u8]) -> TMCResult<Option<(usize, Option<usize>)>> {
+    let digits = match message {
+        [] | [b'#'] => return Ok(None),
+        [b'#', b'0', ..] => return Ok(Some((2, None))),
+        [b'#', digits, ..] if digits.is_ascii_digit() => (digits - b'0') as usize,
+        _ => return Err(TMCError::MalformedBlock),
+    };
+
+    let start = 2 + digits;
+    let length = match message.get(2..start) {
+        Some(length) => length,
+        None => return Ok(None),
+    };
+
+    std::str::from_utf8(length)
+        .ok()
+        .filter(|length| length.bytes().all(|b| b.is_ascii_digit()))
+        .and_then(|length| length.parse::<usize>().ok())
+        .map(|length| Some((start, Some(length))))
+        .ok_or(TMCError::MalformedBlock)
+}
+
+/// Where the data of the block starting `message` lies in it.  Anything after a
+/// definite-length block (typically a line ending) is ignored, as is a line
+/// ending closing an indefinite-length block.
+pub fn block_range(message: &[u8]) -> TMCResult<Range<usize>> {
+    match block_header(message)? {
+        Some((start, Some(length))) => start
+            .checked_add(length)
+            .filter(|&end| end <= message.len())
+            .map(|end| start..end)
+            .ok_or(TMCError::MalformedBlock),
+        Some((start, None)) => {
+            let end = match message {
+                [.., b'\r', b'\n'] => message.len() - 2,
+                [.., b'\n'] => message.len() - 1,
+                _ => message.len(),
+            };
+            Ok(start..end.max(start))
+        }
+        None => Err(TMCError::MalformedBlock),
+    }
+}
+
+/// The data of the block starting `message`
+pub fn parse_block(message: &[u8]) -> TMCResult<&[u8]> {
+    block_range(message).map(|range| &message[range])
+}
+
+/// The header of a definite-length block of `length` bytes, for sending a
+/// block's data separately
+pub fn encode_block_header(length: usize) -> Vec<u8> {
+    let length = length.to_string();
+    format!("#{}{}", length.len(), length).into_bytes()
+}
+
+/// Wrap `data` in a definite-length block
+pub fn encode_block(data: &[u8]) -> Vec<u8> {
+    let mut block = encode_block_header(data.len());
+    block.extend_from_slice(data);
+    block
+}
+
+impl<T: Transport> TMCHandle<T> {
+    /// Write a query and read its response as a single arbitrary block,
+    /// returning the block's data
+    pub fn ask_block(&mut self, command: &str) -> TMCResult<Vec<u8>> {
+        let data = self.encode_command(command)?;
+        let mut response = self.ask_raw(&data)?;
+
+        let range = block_range(&response)?;
+        response.truncate(range.end);
+        response.drain(..range.start);
+        Ok(response)
+    }
+}
@@ -0,0 +1,306 @@
+//! Bounded channels for streamed response data and polled samples, so that a
+//! slow consumer can't cause unbounded memory growth during a long capture.
+//!
+//! What happens when a channel is full is chosen by its [Backpressure] policy:
+//! the producer can wait for the consumer, make room by dropping the oldest
+//! item, or give up.  For [read_stream_into](crate::TMCHandle::read_stream_into)
+//! giving up aborts the bulk-in transfer, and for
+//! [start_bounded](crate::poller::Poller::start_bounded) it stops the poller.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// What a producer does when its channel is full
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Backpressure {
+    /// Wait until the consumer makes room, which holds up the USB reader (and so
+    /// the device) for as long as the consumer is behind
+    #[default]
+    Block,
+
+    /// Discard the oldest item in the channel to make room for the new one
+    DropOldest,
+
+    /// Refuse the new item, which stops the producer
+    Abort,
+}
+
+/// Why an item couldn't be sent, giving the item back
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SendError<T> {
+    /// The channel was full and its policy is [Backpressure::Abort]
+    Full(T),
+
+    /// The receiver has been dropped
+    Disconnected(T),
+}
+
+impl<T> SendError<T> {
+    pub fn into_inner(self) -> T {
+        match self {
+            SendError::Full(item) | SendError::Disconnected(item) => item,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct State<T> {
+    queue: VecDeque<T>,
+    senders: usize,
+    receiver: bool,
+    closed: bool,
+    dropped: u64,
+}
+
+#[derive(Debug)]
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    capacity: usize,
+    backpressure: Backpressure,
+    not_empty: Condvar,
+    not_full: Condvar,
+}
+
+impl<T> Shared<T> {
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        // the state is consistent between every statement, so it can still be
+        // used if a thread panicked while holding the lock
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Create a channel holding up to `capacity` items (at least one), with
+/// `backpressure` applied when it is full
+pub fn bounded<T>(
+    capacity: usize,
+    backpressure: Backpressure,
+) -> (BoundedSender<T>, BoundedReceiver<T>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            queue: VecDeque::new(),
+            senders: 1,
+            receiver: true,
+            closed: false,
+            dropped: 0,
+        }),
+        capacity: capacity.max(1),
+        backpressure,
+        not_empty: Condvar::new(),
+        not_full: Condvar::new(),
+    });
+
+    (
+        BoundedSender {
+            shared: shared.clone(),
+        },
+        BoundedReceiver { shared },
+    )
+}
+
+/// The sending half of a [bounded] channel, which may be cloned to have several
+/// producers
+#[derive(Debug)]
+pub struct BoundedSender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> BoundedSender<T> {
+    /// Send an item, applying the channel's [Backpressure] policy if it is full
+    pub fn send(&self, item: T) -> Result<(), SendError<T>> {
+        let shared = &self.shared;
+        let mut state = shared.lock();
+
+        loop {
+            if !state.receiver || state.closed {
+                return Err(SendError::Disconnected(item));
+            }
+            if state.queue.len() < shared.capacity {
+                break;
+            }
+
+            match shared.backpressure {
+                Backpressure::Block => {
+                    state = shared
+                        .not_full
+                        .wait(state)
+                        .unwrap_or_else(|poisoned| poisoned.into_inner());
+                }
+                Backpressure::DropOldest => {
+                    state.queue.pop_front();
+                    state.dropped += 1;
+                }
+                Backpressure::Abort => return Err(SendError::Full(item)),
+            }
+        }
+
+        state.queue.push_back(item);
+        shared.not_empty.notify_one();
+        Ok(())
+    }
+
+    pub fn backpressure(&self) -> Backpressure {
+        self.shared.backpressure
+    }
+
+    /// A function which closes the channel to its senders, waking any blocked
+    /// in [send](Self::send), for stopping a producer which may be waiting for
+    /// its consumer.  Unlike a clone of the sender, it doesn't keep the receiver
+    /// waiting for more items.
+    pub(crate) fn closer(&self) -> impl Fn() + Send + 'static
+    where
+        T: Send + 'static,
+    {
+        let shared = self.shared.clone();
+        move || {
+            shared.lock().closed = true;
+            shared.not_full.notify_all();
+        }
+    }
+}
+
+impl<T> Clone for BoundedSender<T> {
+    fn clone(&self) -> Self {
+        self.shared.lock().senders += 1;
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for BoundedSender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.senders -= 1;
+        if state.senders == 0 {
+            self.shared.not_empty.notify_all();
+        }
+    }
+}
+
+/// The receiving half of a [bounded] channel
+#[derive(Debug)]
+pub struct BoundedReceiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> BoundedReceiver<T> {
+    /// Wait for the next item, or `None` once the channel is empty and every
+    /// sender has been dropped
+    pub fn recv(&self) -> Option<T> {
+        self.recv_until(None)
+    }
+
+    /// Wait up to `timeout` for the next item.  `None` if none arrived in time,
+    /// or if the channel is empty and every sender has been dropped.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<T> {
+        // too far in the future to represent is as good as never
+        self.recv_until(Instant::now().checked_add(timeout))
+    }
+
+    /// The next item, if one is waiting
+    pub fn try_recv(&self) -> Option<T> {
+        self.take(&mut self.shared.lock())
+    }
+
+    /// Iterate over items as they arrive, until every sender has been dropped
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { receiver: self }
+    }
+
+    /// Items waiting in the channel
+    pub fn len(&self) -> usize {
+        self.shared.lock().queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.shared.capacity
+    }
+
+    /// Items discarded to make room under [Backpressure::DropOldest]
+    pub fn dropped(&self) -> u64 {
+        self.shared.lock().dropped
+    }
+
+    /// Whether every sender has been dropped, so nothing more will arrive once
+    /// the channel is empty
+    pub fn is_disconnected(&self) -> bool {
+        self.shared.lock().senders == 0
+    }
+
+    fn recv_until(&self, deadline: Option<Instant>) -> Option<T> {
+        let shared = &self.shared;
+        let mut state = shared.lock();
+
+        loop {
+            if let Some(item) = self.take(&mut state) {
+                return Some(item);
+            }
+            if state.senders == 0 {
+                return None;
+            }
+
+            state = match deadline {
+                None => shared
+                    .not_empty
+                    .wait(state)
+                    .unwrap_or_else(|poisoned| poisoned.into_inner()),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return None;
+                    }
+                    shared
+                        .not_empty
+                        .wait_timeout(state, deadline - now)
+                        .unwrap_or_else(|poisoned| poisoned.into_inner())
+                        .0
+                }
+            };
+        }
+    }
+
+    fn take(&self, state: &mut State<T>) -> Option<T> {
+        let item = state.queue.pop_front()?;
+        self.shared.not_full.notify_one();
+        Some(item)
+    }
+}
+
+impl<T> Drop for BoundedReceiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.receiver = false;
+        state.queue.clear();
+        self.shared.not_full.notify_all();
+    }
+}
+
+impl<'a, T> IntoIterator for &'a BoundedReceiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+/// Iterator over the items of a [BoundedReceiver] as they arrive
+#[derive(Debug)]
+pub struct Iter<'a, T> {
+    receiver: &'a BoundedReceiver<T>,
+}
+
+impl<T> Iterator for Iter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.receiver.recv()
+    }
+}
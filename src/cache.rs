@@ -0,0 +1,90 @@
+//! Caching of responses to queries whose answers don't change, such as `*IDN?`,
+//! for GUI code which polls static properties far more often than they could
+//! change.
+//!
+//! Only queries registered with the cache are answered from it, by
+//! [ask](crate::TMCHandle::ask).  Queries are matched ignoring case and
+//! surrounding whitespace, but short and long forms of a SCPI command are
+//! different queries to the cache.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Queries answered from a cache, with how long each response stays valid
+#[derive(Debug, Clone, Default)]
+pub struct ResponseCache {
+    ttls: HashMap<String, Option<Duration>>,
+    entries: HashMap<String, (Instant, String)>,
+    hits: u64,
+    misses: u64,
+}
+
+impl ResponseCache {
+    /// An empty cache, which caches nothing until queries are registered
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cache responses to `query` for `ttl`, or until the cache is invalidated
+    /// if `ttl` is `None`
+    pub fn idempotent(mut self, query: &str, ttl: Option<Duration>) -> Self {
+        self.ttls.insert(key(query), ttl);
+        self
+    }
+
+    /// Cache the usual identification queries (`*IDN?`, `*OPT?` and
+    /// `SYST:VERS?`) until the cache is invalidated
+    pub fn identification(self) -> Self {
+        self.idempotent("*IDN?", None)
+            .idempotent("*OPT?", None)
+            .idempotent("SYST:VERS?", None)
+    }
+
+    /// Forget every cached response.  The handle does this when the device is
+    /// cleared.
+    pub fn invalidate(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Queries answered from the cache
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    /// Registered queries which had to be sent to the instrument, because their
+    /// response wasn't cached or had expired
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    /// The cached response to `query`, if it is registered and still valid
+    pub(crate) fn lookup(&mut self, query: &str) -> Option<String> {
+        let key = key(query);
+        let ttl = *self.ttls.get(&key)?;
+
+        let response = self
+            .entries
+            .get(&key)
+            .filter(|(stored, _)| ttl.is_none_or(|ttl| stored.elapsed() < ttl))
+            .map(|(_, response)| response.clone());
+
+        match response {
+            Some(_) => self.hits += 1,
+            None => self.misses += 1,
+        }
+        response
+    }
+
+    /// Keep the response to `query`, if it is registered
+    pub(crate) fn store(&mut self, query: &str, response: &str) {
+        let key = key(query);
+        if self.ttls.contains_key(&key) {
+            self.entries
+                .insert(key, (Instant::now(), response.to_owned()));
+        }
+    }
+}
+
+fn key(query: &str) -> String {
+    query.trim().to_ascii_uppercase()
+}
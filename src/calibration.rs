@@ -0,0 +1,71 @@
+//! Estimating device latency, to choose a timeout suited to the instrument
+//! rather than relying on the default.
+
+use crate::transport::Transport;
+use crate::{TMCHandle, TMCResult};
+use core::time::Duration;
+use std::time::Instant;
+
+/// Shortest timeout suggested, however fast the device responds
+pub const MIN_SUGGESTED_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// Suggested timeouts allow this many times the slowest measured round trip
+const TIMEOUT_MARGIN: u32 = 5;
+
+/// Round trip times measured by [measure_roundtrip](TMCHandle::measure_roundtrip)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RoundTripEstimate {
+    pub min: Duration,
+    pub median: Duration,
+    pub max: Duration,
+    pub count: usize,
+}
+
+impl RoundTripEstimate {
+    /// A timeout with a generous margin over the slowest round trip.  Operations
+    /// which make the instrument do real work (such as a long measurement) need
+    /// that time added on top.
+    pub fn suggested_timeout(&self) -> Duration {
+        (self.max * TIMEOUT_MARGIN).max(MIN_SUGGESTED_TIMEOUT)
+    }
+}
+
+impl<T: Transport> TMCHandle<T> {
+    /// Time `count` (at least 1) round trips to the device: `*OPC?` queries if it
+    /// supports USB488.2, otherwise GET_CAPABILITIES requests.  The current timeout applies
+    /// to each round trip, so it should be long enough for a slow device.
+    pub fn measure_roundtrip(&mut self, count: usize) -> TMCResult<RoundTripEstimate> {
+        let count = count.max(1);
+
+        let usb488_2 = self
+            .usb488_capabilities()?
+            .is_some_and(|caps| caps.usb488_2);
+
+        let mut samples = Vec::with_capacity(count);
+        for _ in 0..count {
+            let start = Instant::now();
+            if usb488_2 {
+                self.ask("*OPC?")?;
+            } else {
+                self.get_capabilities()?;
+            }
+            samples.push(start.elapsed());
+        }
+
+        samples.sort();
+        Ok(RoundTripEstimate {
+            min: samples[0],
+            median: samples[count / 2],
+            max: samples[count - 1],
+            count,
+        })
+    }
+
+    /// Measure round trips as [measure_roundtrip](Self::measure_roundtrip) does,
+    /// and set the timeout to the suggested value
+    pub fn calibrate_timeout(&mut self, count: usize) -> TMCResult<RoundTripEstimate> {
+        let estimate = self.measure_roundtrip(count)?;
+        self.set_timeout(estimate.suggested_timeout());
+        Ok(estimate)
+    }
+}
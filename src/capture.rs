@@ -0,0 +1,281 @@
+//! Capturing the bulk transfers exchanged with an instrument, headers and all,
+//! to files which are rotated by size or age, for long-running monitoring where
+//! anomalies must be reconstructable long after the fact.
+//!
+//! Each file is named after its prefix and the UTC time it was started, such as
+//! `scope-20240301T120000Z.bin`.  Binary files start with [BINARY_MAGIC],
+//! followed by a record for each frame: the time in microseconds since the Unix
+//! epoch (8 bytes, little-endian), the direction (0 for sent, 1 for received),
+//! the frame's length (4 bytes, little-endian) and the frame itself.  They can be
+//! read back with [read_capture].  JSON Lines files have an object per frame,
+//! such as `{"timestamp_us":1709294400000000,"direction":"sent","frame":"0101fe00..."}`.
+
+use crate::clock::CivilTime;
+use crate::storage;
+use crate::transcript::Direction;
+use byteorder::{ByteOrder, LittleEndian};
+use core::time::Duration;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The first bytes of a binary capture file
+pub const BINARY_MAGIC: &[u8; 8] = b"TMCCAP\x00\x01";
+
+/// How captured frames are written
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum CaptureFormat {
+    /// Compact records, read back with [read_capture]
+    #[default]
+    Binary,
+
+    /// A JSON object per line, for general-purpose tools
+    JsonLines,
+}
+
+impl CaptureFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            CaptureFormat::Binary => "bin",
+            CaptureFormat::JsonLines => "jsonl",
+        }
+    }
+}
+
+/// A frame read back from a binary capture file
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CapturedFrame {
+    pub timestamp: SystemTime,
+    pub direction: Direction,
+
+    /// The whole transfer, including its header
+    pub frame: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct CaptureFile {
+    writer: BufWriter<File>,
+    path: PathBuf,
+    started: SystemTime,
+    written: u64,
+}
+
+/// Frames being captured by a handle, set up with
+/// [start_capture](crate::TMCHandle::start_capture)
+#[derive(Debug)]
+pub struct FrameCapture {
+    directory: PathBuf,
+    prefix: String,
+    format: CaptureFormat,
+    max_size: Option<u64>,
+    max_age: Option<Duration>,
+
+    file: Option<CaptureFile>,
+    error: Option<io::Error>,
+}
+
+impl FrameCapture {
+    /// Capture to files in `directory`, named starting with `prefix`.  The first
+    /// file (and the directory, if it doesn't exist) is created when the first
+    /// frame is captured.
+    pub fn new<P: AsRef<Path>>(directory: P, prefix: &str) -> Self {
+        Self {
+            directory: directory.as_ref().to_owned(),
+            prefix: prefix.to_owned(),
+            format: CaptureFormat::default(),
+            max_size: None,
+            max_age: None,
+            file: None,
+            error: None,
+        }
+    }
+
+    pub fn format(mut self, format: CaptureFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Start a new file once the current one holds at least `bytes`
+    pub fn rotate_at_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// Start a new file once the current one was started at least `interval` ago
+    pub fn rotate_every(mut self, interval: Duration) -> Self {
+        self.max_age = Some(interval);
+        self
+    }
+
+    /// The file being written, if one has been started
+    pub fn current_path(&self) -> Option<&Path> {
+        self.file.as_ref().map(|file| file.path.as_path())
+    }
+
+    /// The error which stopped the capture, if writing failed.  Nothing more is
+    /// captured after an error.
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    /// Write out anything buffered for the current file
+    pub fn flush(&mut self) -> io::Result<()> {
+        match &mut self.file {
+            Some(file) => file.writer.flush(),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn record(&mut self, timestamp: SystemTime, direction: Direction, frame: &[u8]) {
+        if self.error.is_some() {
+            return;
+        }
+
+        if let Err(error) = self.write_record(timestamp, direction, frame) {
+            self.error = Some(error);
+            self.file = None;
+        }
+    }
+
+    fn write_record(
+        &mut self,
+        timestamp: SystemTime,
+        direction: Direction,
+        frame: &[u8],
+    ) -> io::Result<()> {
+        let micros = timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+
+        let record = match self.format {
+            CaptureFormat::Binary => {
+                let mut record = vec![0u8; 13 + frame.len()];
+                LittleEndian::write_u64(&mut record[0..8], micros);
+                record[8] = match direction {
+                    Direction::Sent => 0,
+                    Direction::Received => 1,
+                };
+                LittleEndian::write_u32(&mut record[9..13], frame.len() as u32);
+                record[13..].copy_from_slice(frame);
+                record
+            }
+            CaptureFormat::JsonLines => {
+                let mut line = format!(
+                    "{{\"timestamp_us\":{},\"direction\":\"{}\",\"frame\":\"",
+                    micros,
+                    match direction {
+                        Direction::Sent => "sent",
+                        Direction::Received => "received",
+                    }
+                );
+                for byte in frame {
+                    let _ = write!(line, "{:02x}", byte);
+                }
+                line.push_str("\"}\n");
+                line.into_bytes()
+            }
+        };
+
+        let file = self.file_for(timestamp)?;
+        file.writer.write_all(&record)?;
+        file.written += record.len() as u64;
+        Ok(())
+    }
+
+    /// The file to write a record to, rotating if the current one is full or old
+    fn file_for(&mut self, now: SystemTime) -> io::Result<&mut CaptureFile> {
+        let rotate = match &self.file {
+            None => true,
+            Some(file) => {
+                self.max_size.is_some_and(|max| file.written >= max)
+                    || self.max_age.is_some_and(|max| {
+                        now.duration_since(file.started).unwrap_or_default() >= max
+                    })
+            }
+        };
+
+        let file = match self.file.take() {
+            Some(file) if !rotate => file,
+            old => {
+                if let Some(mut file) = old {
+                    file.writer.flush()?;
+                }
+                self.create_file(now)?
+            }
+        };
+
+        Ok(self.file.insert(file))
+    }
+
+    fn create_file(&self, now: SystemTime) -> io::Result<CaptureFile> {
+        let time = CivilTime::from_system_time(now);
+        let stem = format!(
+            "{}-{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+            self.prefix, time.year, time.month, time.day, time.hour, time.minute, time.second
+        );
+
+        // several files may be started within a second, which are numbered
+        let (file, path) = storage::create_unique(&self.directory, &stem, self.format.extension())?;
+        let mut writer = BufWriter::new(file);
+        let mut written = 0;
+        if self.format == CaptureFormat::Binary {
+            writer.write_all(BINARY_MAGIC)?;
+            written = BINARY_MAGIC.len() as u64;
+        }
+
+        Ok(CaptureFile {
+            writer,
+            path,
+            started: now,
+            written,
+        })
+    }
+}
+
+impl Drop for FrameCapture {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+/// Read the frames from a binary capture file
+pub fn read_capture<R: Read>(mut reader: R) -> io::Result<Vec<CapturedFrame>> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_owned());
+
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+
+    let mut rest = data
+        .strip_prefix(&BINARY_MAGIC[..])
+        .ok_or_else(|| invalid("not a binary capture file"))?;
+
+    let mut frames = Vec::new();
+    while !rest.is_empty() {
+        if rest.len() < 13 {
+            return Err(invalid("truncated capture record"));
+        }
+
+        let micros = LittleEndian::read_u64(&rest[0..8]);
+        let direction = match rest[8] {
+            0 => Direction::Sent,
+            1 => Direction::Received,
+            _ => return Err(invalid("invalid direction in capture record")),
+        };
+        let length = LittleEndian::read_u32(&rest[9..13]) as usize;
+        let frame = rest
+            .get(13..13 + length)
+            .ok_or_else(|| invalid("truncated capture record"))?;
+
+        frames.push(CapturedFrame {
+            timestamp: UNIX_EPOCH + Duration::from_micros(micros),
+            direction,
+            frame: frame.to_vec(),
+        });
+        rest = &rest[13 + length..];
+    }
+
+    Ok(frames)
+}
@@ -0,0 +1,97 @@
+//! Per-channel command scoping for multi-channel instruments.
+//!
+//! A [Channel] borrows a handle and prefixes each command sent through it with
+//! the channel's node, so that drivers can offer per-channel methods without
+//! formatting the channel into every command: through
+//! `handle.channel("CHAN{n}", 2)`, the command `SCAL 0.5` is sent as
+//! `:CHAN2:SCAL 0.5`.
+
+use crate::transport::Transport;
+use crate::{TMCError, TMCHandle, TMCResult};
+use std::str::FromStr;
+
+/// A channel of an instrument, which scopes the commands sent through it
+#[derive(Debug)]
+pub struct Channel<'a, T: Transport> {
+    handle: &'a mut TMCHandle<T>,
+    number: u32,
+    node: String,
+}
+
+impl<'a, T: Transport> Channel<'a, T> {
+    /// The channel's number
+    pub fn number(&self) -> u32 {
+        self.number
+    }
+
+    /// The node prefixed to commands, such as `CHAN2`
+    pub fn node(&self) -> &str {
+        &self.node
+    }
+
+    /// The handle the channel sends commands with, for commands outside its scope
+    pub fn handle(&mut self) -> &mut TMCHandle<T> {
+        self.handle
+    }
+
+    /// A channel within this one, such as a trace of a channel: its node is
+    /// appended to this channel's
+    pub fn subchannel(&mut self, template: &str, number: u32) -> Channel<'_, T> {
+        Channel {
+            handle: self.handle,
+            number,
+            node: format!(
+                "{}:{}",
+                self.node,
+                template.replace("{n}", &number.to_string())
+            ),
+        }
+    }
+
+    /// `command` scoped to the channel: the channel's node and `command` joined
+    /// with `:`, as an absolute header
+    pub fn scoped(&self, command: &str) -> String {
+        format!(":{}:{}", self.node, command.trim_start_matches(':'))
+    }
+
+    /// Write a command scoped to the channel
+    pub fn write(&mut self, command: &str) -> TMCResult<()> {
+        let command = self.scoped(command);
+        self.handle.write(&command)
+    }
+
+    /// Write a query scoped to the channel and read the response
+    pub fn ask(&mut self, query: &str) -> TMCResult<String> {
+        let query = self.scoped(query);
+        self.handle.ask(&query)
+    }
+
+    /// Write a query scoped to the channel and parse the response, without
+    /// surrounding whitespace
+    pub fn ask_as<R: FromStr>(&mut self, query: &str) -> TMCResult<R> {
+        let response = self.ask(query)?;
+        response
+            .trim()
+            .parse()
+            .map_err(|_| TMCError::InvalidResponse(response))
+    }
+
+    /// Write a query scoped to the channel and read its response as an arbitrary
+    /// block
+    pub fn ask_block(&mut self, query: &str) -> TMCResult<Vec<u8>> {
+        let query = self.scoped(query);
+        self.handle.ask_block(&query)
+    }
+}
+
+impl<T: Transport> TMCHandle<T> {
+    /// The channel numbered `number`, whose node is `template` with `{n}`
+    /// replaced by the number, such as `CHAN{n}` or `SOUR{n}`
+    pub fn channel(&mut self, template: &str, number: u32) -> Channel<'_, T> {
+        Channel {
+            handle: self,
+            number,
+            node: template.replace("{n}", &number.to_string()),
+        }
+    }
+}
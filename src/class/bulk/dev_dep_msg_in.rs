@@ -44,6 +44,26 @@ impl RequestDevDepMsgInHeader {
         buf[9] = self.term_char;
     }
 
+    pub fn unpack(data: &[u8]) -> Result<Self, ClassError> {
+        let bulk_out_header = BulkTransferHeader::unpack(data)?;
+
+        Ok(Self {
+            bulk_out_header,
+            transfer_size: LittleEndian::read_u32(&data[4..8]),
+            transfer_attributes: data[8],
+            term_char: data[9],
+        })
+    }
+
+    /// The term char the host asked the device to end the transfer on, if any
+    pub fn term_char(&self) -> Option<u8> {
+        if self.transfer_attributes & 0x02 != 0 {
+            Some(self.term_char)
+        } else {
+            None
+        }
+    }
+
     pub fn encode_message(b_tag: u8, transfer_size: u32, term_char: Option<u8>, buf: &mut Vec<u8>) {
         buf.resize(HEADER_SIZE, 0);
         RequestDevDepMsgInHeader::new(b_tag, transfer_size, term_char).pack(buf);
@@ -60,6 +80,39 @@ pub struct DevDepMsgInHeader {
 }
 
 impl DevDepMsgInHeader {
+    pub fn new(b_tag: u8, transfer_size: u32, eom: bool, term_char: bool) -> Self {
+        let bulk_in_header = BulkInHeader::new(MsgIdIn::DevDepMsgIn, b_tag);
+
+        let mut transfer_attributes = if eom { 1 } else { 0 };
+        if term_char {
+            transfer_attributes |= 2;
+        }
+
+        Self {
+            bulk_in_header,
+            transfer_size,
+            transfer_attributes,
+        }
+    }
+
+    pub fn pack(&self, buf: &mut [u8]) {
+        self.bulk_in_header.pack(buf);
+        LittleEndian::write_u32(&mut buf[4..8], self.transfer_size);
+        buf[8] = self.transfer_attributes;
+    }
+
+    /// Encode a response transfer, as sent by a device
+    pub fn encode_message(b_tag: u8, data: &[u8], eom: bool, term_char: bool, buf: &mut Vec<u8>) {
+        buf.clear();
+        buf.resize(HEADER_SIZE, 0u8);
+        DevDepMsgInHeader::new(b_tag, data.len() as u32, eom, term_char).pack(buf);
+
+        buf.extend_from_slice(data);
+
+        let len = buf.len();
+        buf.resize((len + 3) & !3, 0);
+    }
+
     pub fn unpack(data: &[u8]) -> Result<Self, ClassError> {
         let bulk_in_header = BulkTransferHeader::unpack(data)?;
 
@@ -75,8 +128,15 @@ impl DevDepMsgInHeader {
 
     pub fn decode_transfer(buf: &[u8]) -> Result<(Self, &[u8]), ClassError> {
         let header = Self::unpack(buf)?;
-        let data = &buf[HEADER_SIZE..HEADER_SIZE + header.transfer_size as usize];
-        Ok((header, data))
+        let end = HEADER_SIZE.saturating_add(header.transfer_size as usize);
+        if buf.len() < end {
+            return Err(ClassError::TruncatedBulkIn {
+                expected: end,
+                received: buf.len(),
+            });
+        }
+
+        Ok((header, &buf[HEADER_SIZE..end]))
     }
 
     pub fn is_eom(&self) -> bool {
@@ -28,6 +28,37 @@ impl DevDepMsgOutHeader {
         buf[8] = self.transfer_attributes;
     }
 
+    pub fn unpack(data: &[u8]) -> Result<Self, ClassError> {
+        let bulk_out_header = BulkTransferHeader::unpack(data)?;
+
+        let transfer_size = LittleEndian::read_u32(&data[4..8]);
+        let transfer_attributes = data[8];
+
+        Ok(Self {
+            bulk_out_header,
+            transfer_size,
+            transfer_attributes,
+        })
+    }
+
+    /// Split a bulk out transfer into its header and command data
+    pub fn decode_transfer(buf: &[u8]) -> Result<(Self, &[u8]), ClassError> {
+        let header = Self::unpack(buf)?;
+        let end = HEADER_SIZE.saturating_add(header.transfer_size as usize);
+        if buf.len() < end {
+            return Err(ClassError::TruncatedBulkOut {
+                expected: end,
+                received: buf.len(),
+            });
+        }
+
+        Ok((header, &buf[HEADER_SIZE..end]))
+    }
+
+    pub fn is_eom(&self) -> bool {
+        self.transfer_attributes & 0x01 != 0
+    }
+
     pub fn encode_message(b_tag: u8, data: &[u8], eom: bool, buf: &mut Vec<u8>) {
         // add the header
         buf.resize(HEADER_SIZE, 0u8);
@@ -3,6 +3,40 @@ use crate::class::*;
 /// All bulk transfer headers used in this class are the same size.
 pub const HEADER_SIZE: usize = 12;
 
+/// Ways in which the alignment padding after a bulk transfer's data can violate
+/// the spec (Section 3.3), which requires zero bytes up to a multiple of 4.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum PaddingViolation {
+    Missing,
+    NonZero,
+}
+
+/// Check the alignment padding after the data ending at `data_end` in a complete
+/// bulk transfer, including its header.
+pub fn check_padding(transfer: &[u8], data_end: usize) -> Option<PaddingViolation> {
+    let padded_end = (data_end + 3) & !3;
+
+    if transfer.len() < padded_end {
+        Some(PaddingViolation::Missing)
+    } else if transfer[data_end..padded_end].iter().any(|&b| b != 0) {
+        Some(PaddingViolation::NonZero)
+    } else {
+        None
+    }
+}
+
+/// How to treat bulk-in transfers whose alignment padding is missing or not zero
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "profiles", derive(serde::Serialize, serde::Deserialize))]
+pub enum PaddingPolicy {
+    /// Fail the read with [ClassError::InvalidPadding]
+    Strict,
+
+    /// Accept the transfer, counting the violation in the link diagnostics
+    #[default]
+    Lenient,
+}
+
 /// Common data in all bulk transfer headers (Sections 3.2 and 3.3), excluding
 /// the command-specific portion of the header.  Command-specific header types
 /// will embed this struct and add the additional fields.
@@ -36,7 +70,9 @@ impl<M: MsgId> BulkTransferHeader<M> {
 
     pub fn unpack(data: &[u8]) -> Result<Self, ClassError> {
         if data.len() < HEADER_SIZE {
-            return Err(ClassError::TruncatedHeader);
+            return Err(ClassError::TruncatedHeader {
+                received: data.len(),
+            });
         }
 
         let msg_id = M::try_from(data[0])?;
@@ -45,7 +81,9 @@ impl<M: MsgId> BulkTransferHeader<M> {
         let reserved = data[3];
 
         if b_tag_inverse != !b_tag {
-            Err(ClassError::TagCheckFailure)
+            let mut header = [0u8; HEADER_SIZE];
+            header.copy_from_slice(&data[..HEADER_SIZE]);
+            Err(ClassError::TagCheckFailure { header })
         } else {
             Ok(Self {
                 msg_id,
@@ -2,6 +2,8 @@ mod dev_dep_msg_in;
 mod dev_dep_msg_out;
 mod header;
 mod msgid;
+mod tag;
+mod trigger;
 mod vendor_specific_in;
 mod vendor_specific_out;
 
@@ -9,5 +11,7 @@ pub use dev_dep_msg_in::*;
 pub use dev_dep_msg_out::*;
 pub use header::*;
 pub use msgid::*;
+pub use tag::*;
+pub use trigger::*;
 pub use vendor_specific_in::*;
 pub use vendor_specific_out::*;
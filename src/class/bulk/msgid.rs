@@ -11,6 +11,9 @@ pub enum MsgIdOut {
     RequestDevDepMsgIn = 2,
     VendorSpecificOut = 3,
     RequestVendorSpecificIn = 4,
+
+    /// USB488 TRIGGER
+    Trigger = 128,
 }
 
 impl From<MsgIdOut> for u8 {
@@ -27,7 +30,8 @@ impl TryFrom<u8> for MsgIdOut {
             2 => Ok(Self::RequestDevDepMsgIn),
             3 => Ok(Self::VendorSpecificOut),
             4 => Ok(Self::RequestVendorSpecificIn),
-            _ => Err(ClassError::InvalidMsgId),
+            128 => Ok(Self::Trigger),
+            _ => Err(ClassError::InvalidMsgId(value)),
         }
     }
 }
@@ -53,7 +57,7 @@ impl TryFrom<u8> for MsgIdIn {
         match value {
             2 => Ok(Self::DevDepMsgIn),
             4 => Ok(Self::VendorSpecificIn),
-            _ => Err(ClassError::InvalidMsgId),
+            _ => Err(ClassError::InvalidMsgId(value)),
         }
     }
 }
@@ -0,0 +1,52 @@
+use std::fmt;
+
+/// Strategy for choosing the bTag of each successive transfer.
+///
+/// The class spec requires only that the bTag differs from the previous transfer's
+/// and is never 0, but some devices are pickier than that.
+pub trait TagPolicy: fmt::Debug + Send {
+    /// Choose the bTag following `previous`.  `previous` is 0 before the first
+    /// transfer of a connection.
+    fn next_tag(&self, previous: u8) -> u8;
+}
+
+/// The default policy: cycle through 2..=128, which keeps the bTag usable as the
+/// wValue of USB488 READ_STATUS_BYTE requests.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct DefaultTagPolicy;
+
+impl TagPolicy for DefaultTagPolicy {
+    fn next_tag(&self, previous: u8) -> u8 {
+        if !(2..=127).contains(&previous) {
+            2
+        } else {
+            previous + 1
+        }
+    }
+}
+
+/// Increase monotonically from 1 to 255, then wrap back to 1.  For devices which
+/// expect a connection's first bTag to be 1.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct SequentialTagPolicy;
+
+impl TagPolicy for SequentialTagPolicy {
+    fn next_tag(&self, previous: u8) -> u8 {
+        if previous == u8::MAX {
+            1
+        } else {
+            previous + 1
+        }
+    }
+}
+
+/// Always use the same bTag, which must not be 0.  This violates the spec, but
+/// some devices tolerate (or even require) reuse.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct FixedTagPolicy(pub u8);
+
+impl TagPolicy for FixedTagPolicy {
+    fn next_tag(&self, _previous: u8) -> u8 {
+        self.0
+    }
+}
@@ -0,0 +1,27 @@
+use crate::class::*;
+
+/// Header of the USB488 TRIGGER message (USB488 Section 3.2.1.1), which has the
+/// same effect as the IEEE 488.1 GET message.  The message is just the header,
+/// with no data.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TriggerHeader {
+    pub bulk_out_header: BulkOutHeader,
+}
+
+impl TriggerHeader {
+    pub fn new(b_tag: u8) -> Self {
+        Self {
+            bulk_out_header: BulkOutHeader::new(MsgIdOut::Trigger, b_tag),
+        }
+    }
+
+    pub fn pack(&self, buf: &mut [u8]) {
+        self.bulk_out_header.pack(buf);
+        buf[4..HEADER_SIZE].fill(0);
+    }
+
+    pub fn encode_message(b_tag: u8, buf: &mut Vec<u8>) {
+        buf.resize(HEADER_SIZE, 0u8);
+        TriggerHeader::new(b_tag).pack(buf);
+    }
+}
@@ -43,7 +43,14 @@ impl VendorSpecificInHeader {
 
     pub fn decode_transfer(buf: &[u8]) -> Result<(Self, &[u8]), ClassError> {
         let header = Self::unpack(buf)?;
-        let data = &buf[HEADER_SIZE..HEADER_SIZE + header.transfer_size as usize];
-        Ok((header, data))
+        let end = HEADER_SIZE.saturating_add(header.transfer_size as usize);
+        if buf.len() < end {
+            return Err(ClassError::TruncatedBulkIn {
+                expected: end,
+                received: buf.len(),
+            });
+        }
+
+        Ok((header, &buf[HEADER_SIZE..end]))
     }
 }
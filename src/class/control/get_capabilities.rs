@@ -1,6 +1,80 @@
 use crate::class::*;
 use byteorder::{ByteOrder, LittleEndian};
 use std::convert::TryFrom;
+use std::fmt;
+
+/// The size of a whole GET_CAPABILITIES response (Table 37), USB488 fields
+/// and reserved bytes included
+const CAPABILITIES_SIZE: usize = 0x18;
+
+/// How to treat GET_CAPABILITIES responses which are shorter than the
+/// capabilities they should hold, or which declare an invalid combination of
+/// USB488 capabilities
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "profiles", derive(serde::Serialize, serde::Deserialize))]
+pub enum CapabilitiesPolicy {
+    /// Fail with [ClassError::TruncatedControlResponse] or
+    /// [ClassError::InvalidCapabilities]
+    #[default]
+    Strict,
+
+    /// Take missing bytes as zero and accept invalid combinations, recording
+    /// each problem as a [CapabilitiesAnomaly]
+    Lenient,
+}
+
+/// A problem with a GET_CAPABILITIES response tolerated under
+/// [CapabilitiesPolicy::Lenient]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum CapabilitiesAnomaly {
+    /// The response ended before the capabilities the interface should have,
+    /// and the rest were taken as zero (not supported)
+    Truncated { received: usize },
+
+    /// A USB488 interface declared itself talk-only or listen-only
+    TalkOrListenOnly,
+
+    /// DT1 was declared without the TRIGGER message
+    DeviceTriggerWithoutTrigger,
+
+    /// RL1 was declared without the remote/local requests
+    RemoteLocalWithoutRequests,
+
+    /// SCPI was declared without USB488.2
+    ScpiWithoutUsb4882,
+}
+
+impl fmt::Display for CapabilitiesAnomaly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CapabilitiesAnomaly::Truncated { received } => write!(
+                f,
+                "capabilities response truncated to {} bytes, the rest taken as zero",
+                received
+            ),
+            CapabilitiesAnomaly::TalkOrListenOnly => {
+                f.write_str("USB488 interface declared talk-only or listen-only")
+            }
+            CapabilitiesAnomaly::DeviceTriggerWithoutTrigger => {
+                f.write_str("DT1 declared without the TRIGGER message")
+            }
+            CapabilitiesAnomaly::RemoteLocalWithoutRequests => {
+                f.write_str("RL1 declared without the remote/local requests")
+            }
+            CapabilitiesAnomaly::ScpiWithoutUsb4882 => {
+                f.write_str("SCPI declared without USB488.2")
+            }
+        }
+    }
+}
+
+/// `buf` padded with zeros to a whole response
+fn zero_filled(buf: &[u8]) -> [u8; CAPABILITIES_SIZE] {
+    let mut filled = [0u8; CAPABILITIES_SIZE];
+    let n = buf.len().min(CAPABILITIES_SIZE);
+    filled[..n].copy_from_slice(&buf[..n]);
+    filled
+}
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct USBTMCCapabilities {
@@ -31,19 +105,38 @@ impl USBTMCCapabilities {
 
     /// parse a "GET_CAPABILTIES" response.  The status field is checked and must be SUCCESS.
     pub fn parse(buf: &[u8]) -> Result<Self, ClassError> {
+        Self::parse_with(buf, CapabilitiesPolicy::Strict, &mut Vec::new())
+    }
+
+    /// Parse a GET_CAPABILITIES response under `policy`, adding any anomalies it
+    /// tolerates to `anomalies`.  Even a lenient parse needs the status byte.
+    pub fn parse_with(
+        buf: &[u8],
+        policy: CapabilitiesPolicy,
+        anomalies: &mut Vec<CapabilitiesAnomaly>,
+    ) -> Result<Self, ClassError> {
+        if buf.is_empty() || (buf.len() < 12 && policy == CapabilitiesPolicy::Strict) {
+            return Err(ClassError::TruncatedControlResponse {
+                expected: 12,
+                received: buf.len(),
+            });
+        }
+        Status::try_from(buf[0])?.check()?;
+
         if buf.len() < 12 {
-            Err(ClassError::TruncatedControlResponse)
-        } else {
-            Status::try_from(buf[0])?.check()?;
-
-            Ok(Self {
-                bcd_usbtmc: LittleEndian::read_u16(&buf[2..4]),
-                pulse: buf[4] & 0x04 != 0,
-                talk_only: buf[4] & 0x02 != 0,
-                listen_only: buf[4] & 0x01 != 0,
-                term_char: buf[5] & 0x01 != 0,
-            })
+            anomalies.push(CapabilitiesAnomaly::Truncated {
+                received: buf.len(),
+            });
         }
+
+        let buf = zero_filled(buf);
+        Ok(Self {
+            bcd_usbtmc: LittleEndian::read_u16(&buf[2..4]),
+            pulse: buf[4] & 0x04 != 0,
+            talk_only: buf[4] & 0x02 != 0,
+            listen_only: buf[4] & 0x01 != 0,
+            term_char: buf[5] & 0x01 != 0,
+        })
     }
 }
 
@@ -70,9 +163,48 @@ impl USB488Capabilities {
         usbtmc_capabilities: &USBTMCCapabilities,
         buf: &[u8],
     ) -> Result<Option<Self>, ClassError> {
+        Self::parse_with(
+            usbtmc_capabilities,
+            buf,
+            CapabilitiesPolicy::Strict,
+            &mut Vec::new(),
+        )
+    }
+
+    /// Parse the USB488 part of a GET_CAPABILITIES response under `policy`,
+    /// adding any anomalies it tolerates to `anomalies`
+    pub fn parse_with(
+        usbtmc_capabilities: &USBTMCCapabilities,
+        buf: &[u8],
+        policy: CapabilitiesPolicy,
+        anomalies: &mut Vec<CapabilitiesAnomaly>,
+    ) -> Result<Option<Self>, ClassError> {
+        let lenient = policy == CapabilitiesPolicy::Lenient;
         if buf.len() < 16 {
-            return Ok(None);
+            if !lenient {
+                return Ok(None);
+            }
+            let truncated = CapabilitiesAnomaly::Truncated {
+                received: buf.len(),
+            };
+            if !anomalies.contains(&truncated) {
+                anomalies.push(truncated);
+            }
         }
+        let buf = zero_filled(buf);
+
+        // reject (or under a lenient policy, record) a combination of features
+        // which the USB488 spec defines as invalid
+        let mut check = |valid: bool, anomaly: CapabilitiesAnomaly| {
+            if valid {
+                Ok(())
+            } else if lenient {
+                anomalies.push(anomaly);
+                Ok(())
+            } else {
+                Err(ClassError::InvalidCapabilities)
+            }
+        };
 
         let usb488_capabilities = USB488Capabilities {
             bcd_usb488: LittleEndian::read_u16(&buf[12..14]),
@@ -89,26 +221,27 @@ impl USB488Capabilities {
             return Ok(None);
         }
 
-        // reject several combinations of features that are defined as invalid in USB488 spec
-        if usbtmc_capabilities.talk_only || usbtmc_capabilities.listen_only {
-            return Err(ClassError::InvalidCapabilities);
-        }
-
-        if usb488_capabilities.dt && !usb488_capabilities.trigger {
-            return Err(ClassError::InvalidCapabilities);
-        }
-
-        if usb488_capabilities.rl && !usb488_capabilities.remote_local {
-            return Err(ClassError::InvalidCapabilities);
-        }
+        check(
+            !usbtmc_capabilities.talk_only && !usbtmc_capabilities.listen_only,
+            CapabilitiesAnomaly::TalkOrListenOnly,
+        )?;
+        check(
+            !usb488_capabilities.dt || usb488_capabilities.trigger,
+            CapabilitiesAnomaly::DeviceTriggerWithoutTrigger,
+        )?;
+        check(
+            !usb488_capabilities.rl || usb488_capabilities.remote_local,
+            CapabilitiesAnomaly::RemoteLocalWithoutRequests,
+        )?;
 
         //if usb488_capabilities.usb488_2 && !usb488_capabilities.sr {
         // return Err(ClassError::InvalidCapabilities);
         //}
 
-        if usb488_capabilities.scpi && !usb488_capabilities.usb488_2 {
-            return Err(ClassError::InvalidCapabilities);
-        }
+        check(
+            !usb488_capabilities.scpi || usb488_capabilities.usb488_2,
+            CapabilitiesAnomaly::ScpiWithoutUsb4882,
+        )?;
 
         // looks good, return the capabilities list
         Ok(Some(usb488_capabilities))
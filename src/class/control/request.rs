@@ -28,7 +28,10 @@ impl ControlRequest {
     /// Attempt to read the first byte of the provided buffer as a USB TMC status code
     pub fn read_response_status(buf: &[u8]) -> Result<Status, ClassError> {
         if buf.is_empty() {
-            Err(ClassError::TruncatedControlResponse)
+            Err(ClassError::TruncatedControlResponse {
+                expected: 1,
+                received: 0,
+            })
         } else {
             Status::try_from(buf[0])
         }
@@ -37,9 +40,33 @@ impl ControlRequest {
     /// Check the first byte of a buffer (if it's long enough) and ensure it indicates a "success" status.
     pub fn check_response_status(buf: &[u8]) -> Result<(), ClassError> {
         if buf.is_empty() {
-            Err(ClassError::TruncatedControlResponse)
+            Err(ClassError::TruncatedControlResponse {
+                expected: 1,
+                received: 0,
+            })
         } else {
             Status::try_from(buf[0])?.check()
         }
     }
 }
+
+/// Scope of a custom control request directed at the TMC interface.  Standard
+/// requests are handled by the USB stack and are not sent this way.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum RequestScope {
+    /// A class-specific request, such as one defined by a USBTMC subclass
+    /// specification that this crate doesn't implement
+    Class,
+
+    /// A vendor-specific request
+    Vendor,
+}
+
+impl From<RequestScope> for rusb::RequestType {
+    fn from(value: RequestScope) -> Self {
+        match value {
+            RequestScope::Class => rusb::RequestType::Class,
+            RequestScope::Vendor => rusb::RequestType::Vendor,
+        }
+    }
+}
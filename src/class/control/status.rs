@@ -19,7 +19,7 @@ impl TryFrom<u8> for Status {
     fn try_from(value: u8) -> Result<Self, Self::Error> {
         use Status::*;
         match value {
-            0x00 => Err(ClassError::IllegalStatus),
+            0x00 => Err(ClassError::IllegalStatus(value)),
             0x01 => Ok(Success),
             0x02 => Ok(Pending),
             0x80 => Ok(Failed),
@@ -38,6 +38,20 @@ impl TryFrom<u8> for Status {
 }
 
 impl Status {
+    /// The status code as sent by the device
+    pub fn code(self) -> u8 {
+        use Status::*;
+        match self {
+            Success => 0x01,
+            Pending => 0x02,
+            Failed => 0x80,
+            TransferNotInProgress => 0x81,
+            SplitNotInProgress => 0x82,
+            SplitInProgress => 0x83,
+            UnknownWarning(code) | UnknownFailure(code) => code,
+        }
+    }
+
     pub fn check(self) -> Result<(), ClassError> {
         if self == Status::Success {
             Ok(())
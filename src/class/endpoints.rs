@@ -1,3 +1,5 @@
+use crate::class::HEADER_SIZE;
+
 /// Information about a USB device's TMC interface, needed to find the right
 /// endpoints and such for communication to the instrument.
 #[derive(Debug)]
@@ -11,22 +13,63 @@ pub struct TMCInterface {
     pub interface_protocol: u8,
 
     /// The endpoint address (not number) of the USB TMC bulk out endpoint
-    /// (mandatory per section 2) for sending messages to the instrument
-    pub bulk_out_address: u8,
+    /// for sending messages to the instrument.  Section 2 makes it mandatory,
+    /// but some gadgets only receive.
+    pub bulk_out_address: Option<u8>,
 
     /// The endpoint address (not number) of the USB TMC bulk in endpoint
-    /// (mandatory per section 2) for receiving messages from the instrument
-    pub bulk_in_address: u8,
+    /// for receiving messages from the instrument.  Section 2 makes it
+    /// mandatory, but some gadgets only send.
+    pub bulk_in_address: Option<u8>,
 
     /// The endpoint address (not number) of the USB TMC interrupt in endpoint
     /// (optional per section 2) for receiving asynchronous notifications from
     /// the instrument
     pub interrupt_in_address: Option<u8>,
 
-    // TODO: these might not actually be needed.  The TMC spec does reference
-    // them but I don't think it says anything that isn't implicit in the way
-    // USB just works already.
+    /// Maximum packet sizes of the endpoints.  Transfers which fill a whole
+    /// number of packets are only ended by a zero-length packet, so these
+    /// affect which transfer sizes work well.
     pub control_in_max_packet_size: u16,
     pub bulk_out_max_packet_size: u16,
     pub bulk_in_max_packet_size: u16,
 }
+
+impl TMCInterface {
+    /// Whether messages can be sent to the instrument
+    pub fn can_write(&self) -> bool {
+        self.bulk_out_address.is_some()
+    }
+
+    /// Whether messages can be read from the instrument
+    pub fn can_read(&self) -> bool {
+        self.bulk_in_address.is_some()
+    }
+
+    /// Maximum packet size of the bulk-in endpoint, or 512 (the high speed
+    /// size) if the descriptor doesn't give one
+    pub fn bulk_in_max_packet(&self) -> usize {
+        match self.bulk_in_max_packet_size {
+            0 => 512,
+            size => size as usize,
+        }
+    }
+
+    /// Maximum packet size of the bulk-out endpoint, or 512 (the high speed
+    /// size) if the descriptor doesn't give one
+    pub fn bulk_out_max_packet(&self) -> usize {
+        match self.bulk_out_max_packet_size {
+            0 => 512,
+            size => size as usize,
+        }
+    }
+
+    /// Whether a bulk transfer carrying `size` bytes of message data, with its
+    /// header and alignment padding, fills a whole number of packets in either
+    /// direction
+    pub fn fills_whole_packets(&self, size: u32) -> bool {
+        let transfer = (HEADER_SIZE + size as usize + 3) & !3;
+        transfer.is_multiple_of(self.bulk_in_max_packet())
+            || transfer.is_multiple_of(self.bulk_out_max_packet())
+    }
+}
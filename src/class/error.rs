@@ -4,31 +4,52 @@ use thiserror::Error;
 
 #[derive(Error, Debug, Clone, PartialEq, Eq, Hash)]
 pub enum ClassError {
-    #[error("illegal status")]
-    IllegalStatus,
+    #[error("illegal status {0:#04x}")]
+    IllegalStatus(u8),
 
     #[error("invalid capabilities")]
     InvalidCapabilities,
 
-    #[error("invalid message ID")]
-    InvalidMsgId,
+    #[error("invalid message ID {0}")]
+    InvalidMsgId(u8),
+
+    #[error("invalid alignment padding")]
+    InvalidPadding,
 
     #[error("invalid terminal character")]
     InvalidTermChar,
 
-    #[error("tag check failure")]
-    TagCheckFailure,
+    #[error("invalid transfer size {0}")]
+    InvalidTransferSize(u32),
+
+    #[error("device kept repeating the transfer with header {header:02x?}")]
+    RepeatedTransfer { header: [u8; HEADER_SIZE] },
+
+    #[error("response too large")]
+    ResponseTooLarge,
+
+    #[error("tag check failure in header {header:02x?}")]
+    TagCheckFailure { header: [u8; HEADER_SIZE] },
+
+    #[error("transfer too large: requested {requested} bytes, device announced {announced}")]
+    TransferTooLarge { requested: u32, announced: u32 },
+
+    #[error("truncated bulk-in: expected {expected} bytes, received {received}")]
+    TruncatedBulkIn { expected: usize, received: usize },
 
-    #[error("truncated bulk-out")]
-    TruncatedBulkOut,
+    #[error("truncated bulk-out: expected {expected} bytes, sent {received}")]
+    TruncatedBulkOut { expected: usize, received: usize },
 
-    #[error("truncated control response")]
-    TruncatedControlResponse,
+    #[error("truncated control response: expected {expected} bytes, received {received}")]
+    TruncatedControlResponse { expected: usize, received: usize },
 
-    #[error("truncated header")]
-    TruncatedHeader,
+    #[error(
+        "truncated header: expected {} bytes, received {received}",
+        HEADER_SIZE
+    )]
+    TruncatedHeader { received: usize },
 
-    #[error("unexpected status \"{0:?}\"")]
+    #[error("unexpected status \"{0:?}\" ({code:#04x})", code = .0.code())]
     UnexpectedStatus(Status),
 
     #[error("unsupported feature")]
@@ -0,0 +1,167 @@
+//! Setting the instrument's clock from the host's, so that timestamps recorded
+//! by the instrument can be correlated with host logs, and timestamps taken on
+//! both of the host's clocks for correlating transfers with host-side events.
+
+#[cfg(feature = "scpi")]
+use crate::class::ClassError;
+#[cfg(feature = "scpi")]
+use crate::transport::Transport;
+#[cfg(feature = "scpi")]
+use crate::{TMCHandle, TMCResult};
+use core::time::Duration;
+#[cfg(feature = "scpi")]
+use std::thread::sleep;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Round trips timed to estimate the delay before the instrument acts on a
+/// message
+#[cfg(feature = "scpi")]
+const ROUND_TRIPS: usize = 3;
+
+/// A date and time of day in UTC, to the second
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CivilTime {
+    pub year: i64,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl CivilTime {
+    /// The UTC date and time of `timestamp`, truncated to the second
+    pub fn from_system_time(timestamp: SystemTime) -> Self {
+        let secs = match timestamp.duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_secs() as i64,
+            Err(before) => -(before.duration().as_secs() as i64),
+        };
+        let (days, secs) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+
+        // days since the epoch to a proleptic Gregorian date, counting years from
+        // March so that leap days come at the end
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let day_of_era = z.rem_euclid(146097);
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_from_march = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+        let month = if month_from_march < 10 {
+            month_from_march + 3
+        } else {
+            month_from_march - 9
+        };
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        Self {
+            year,
+            month: month as u8,
+            day: day as u8,
+            hour: (secs / 3600) as u8,
+            minute: (secs / 60 % 60) as u8,
+            second: (secs % 60) as u8,
+        }
+    }
+}
+
+/// A moment as seen by both of the host's clocks, read back to back.  The
+/// monotonic time is for measuring intervals to sub-millisecond precision
+/// against the application's own [Instant]s, and the wall-clock time for
+/// placing the moment in logs kept elsewhere.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Timestamp {
+    pub monotonic: Instant,
+    pub wall: SystemTime,
+}
+
+impl Timestamp {
+    pub fn now() -> Self {
+        Self {
+            monotonic: Instant::now(),
+            wall: SystemTime::now(),
+        }
+    }
+
+    /// Time elapsed on the monotonic clock since this moment
+    pub fn elapsed(&self) -> Duration {
+        self.monotonic.elapsed()
+    }
+
+    /// Time on the monotonic clock from `earlier` to this moment, or zero if
+    /// `earlier` is later
+    pub fn since(&self, earlier: &Timestamp) -> Duration {
+        self.monotonic.saturating_duration_since(earlier.monotonic)
+    }
+
+    /// How much further the wall clock has run than the monotonic clock since
+    /// this moment.  On Linux and macOS the monotonic clock stops while the host
+    /// is suspended, so this is about how long it slept; it also includes any
+    /// step in the wall clock, such as a correction from NTP.
+    pub fn suspended(&self) -> Duration {
+        SystemTime::now()
+            .duration_since(self.wall)
+            .unwrap_or_default()
+            .saturating_sub(self.elapsed())
+    }
+}
+
+/// The outcome of [sync_clock](TMCHandle::sync_clock)
+#[cfg(feature = "scpi")]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ClockSync {
+    /// The host time the instrument's clock was set to
+    pub set_to: SystemTime,
+
+    /// Median round trip to the instrument, half of which was allowed for the
+    /// message setting the clock to arrive
+    pub round_trip: Duration,
+}
+
+#[cfg(feature = "scpi")]
+impl<T: Transport> TMCHandle<T> {
+    /// Set the instrument's date and time to the host's, in UTC, with SCPI
+    /// `SYST:DATE` and `SYST:TIME`.  The message is sent half a round trip
+    /// before a whole second, so that the instrument's clock is set close to the
+    /// moment it names.
+    pub fn sync_clock(&mut self) -> TMCResult<ClockSync> {
+        if !self.usb488_capabilities()?.is_some_and(|caps| caps.scpi) {
+            return Err(ClassError::UnsupportedFeature.into());
+        }
+
+        self.sync_clock_with(|handle, time| {
+            let time = CivilTime::from_system_time(time);
+            handle.write(&format!(
+                "SYST:DATE {},{},{};:SYST:TIME {},{},{}",
+                time.year, time.month, time.day, time.hour, time.minute, time.second
+            ))
+        })
+    }
+
+    /// Set the instrument's clock with `set`, for devices which don't take the
+    /// SCPI commands used by [sync_clock](Self::sync_clock).  `set` is called
+    /// half a round trip before the whole second it is given, and should send
+    /// a single message setting the clock to that time.
+    pub fn sync_clock_with<F>(&mut self, mut set: F) -> TMCResult<ClockSync>
+    where
+        F: FnMut(&mut Self, SystemTime) -> TMCResult<()>,
+    {
+        let round_trip = self.measure_roundtrip(ROUND_TRIPS)?.median;
+        let one_way = round_trip / 2;
+
+        // the next whole second which can still be reached, allowing for the
+        // message's journey
+        let arrival = SystemTime::now() + one_way;
+        let since_epoch = arrival.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let set_to = UNIX_EPOCH + Duration::from_secs(since_epoch.as_secs() + 1);
+
+        let send_at = set_to - one_way;
+        if let Ok(wait) = send_at.duration_since(SystemTime::now()) {
+            sleep(wait);
+        }
+        set(self, set_to)?;
+
+        Ok(ClockSync { set_to, round_trip })
+    }
+}
@@ -0,0 +1,493 @@
+//! Restrictions on which commands a handle will send, so that tools handed to
+//! operators can guarantee that destructive commands (such as `*RST` or
+//! `SYST:SEC:IMM`) never reach the instrument from them.
+//!
+//! A message is checked one message unit at a time: it is split on `;` and line
+//! endings outside of quoted strings, and the data of arbitrary blocks is left
+//! out.  Each unit is checked with its header resolved as the instrument would
+//! resolve it, relative to the previous header in the message unless it starts
+//! with `:`.  A rejected message is not sent at all.
+//!
+//! With the `command-policy` feature, an [Interlock] can also hold back commands
+//! which enable outputs until the operator or an external interlock allows them.
+
+use crate::block::block_header;
+use std::fmt;
+
+#[cfg(feature = "command-policy")]
+use regex::Regex;
+#[cfg(feature = "command-policy")]
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "command-policy")]
+use std::sync::Arc;
+
+/// Decides whether a command may be sent
+pub trait CommandPolicy: fmt::Debug + Send {
+    /// Check one command (a single message unit, trimmed of whitespace), giving
+    /// the reason if it must not be sent
+    fn check(&self, command: &str) -> Result<(), String>;
+}
+
+/// Splits a message, given whole or in pieces, into its message units for
+/// checking against a policy.  Each unit is returned as soon as its text is
+/// complete: at its separator, or where an arbitrary block starts, as the
+/// block's data (and anything after it) is left out.  Relative headers are
+/// resolved against the path SCPI would use, so that `SYST:BEEP;SEC:IMM` is
+/// checked as `SYST:BEEP` and `SYST:SEC:IMM`.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct CommandScan {
+    quote: Option<u8>,
+
+    // bytes of a definite-length block still to skip
+    block: usize,
+
+    // an indefinite-length block runs to the end of the message
+    binary: bool,
+
+    // the start of a block header which has only partly arrived
+    block_header: Option<Vec<u8>>,
+
+    // the text of the current unit, and whether it has been returned
+    text: Vec<u8>,
+    text_done: bool,
+
+    // bytes fed since the current unit started, while its text is incomplete
+    unchecked: usize,
+
+    path: HeaderPath,
+}
+
+impl CommandScan {
+    /// Feed the next piece of the message, returning the units whose text it
+    /// completes
+    pub(crate) fn feed(&mut self, data: &[u8]) -> Vec<String> {
+        let mut units = Vec::new();
+        let mut i = 0;
+        while i < data.len() {
+            if self.binary {
+                break;
+            }
+            if self.block > 0 {
+                let skipped = self.block.min(data.len() - i);
+                self.block -= skipped;
+                i += skipped;
+                continue;
+            }
+
+            let byte = data[i];
+            if !self.text_done {
+                self.unchecked += 1;
+            }
+
+            if let Some(mut header) = self.block_header.take() {
+                header.push(byte);
+                match block_header(&header) {
+                    Ok(None) => self.block_header = Some(header),
+                    Ok(Some((offset, Some(length)))) => {
+                        self.block = (offset + length).saturating_sub(header.len());
+                        self.start_block(&mut units);
+                    }
+                    Ok(Some((_, None))) => {
+                        self.binary = true;
+                        self.start_block(&mut units);
+                    }
+                    // a non-decimal number, such as #H1F, and not a block
+                    Err(_) => {
+                        header.pop();
+                        self.text.extend_from_slice(&header);
+                        self.unchecked -= 1;
+                        continue;
+                    }
+                }
+                i += 1;
+                continue;
+            }
+
+            match self.quote {
+                Some(open) if byte == open => self.quote = None,
+                Some(_) => {}
+                None => match byte {
+                    b'"' | b'\'' => self.quote = Some(byte),
+                    b'#' => {
+                        self.block_header = Some(vec![byte]);
+                        i += 1;
+                        continue;
+                    }
+                    b';' | b'\n' => {
+                        self.end_unit(&mut units);
+                        if byte == b'\n' {
+                            self.path = HeaderPath::default();
+                        }
+                        i += 1;
+                        continue;
+                    }
+                    _ => {}
+                },
+            }
+            if !self.text_done {
+                self.text.push(byte);
+            }
+            i += 1;
+        }
+        units
+    }
+
+    /// End the message, returning its last unit if its text wasn't complete
+    /// yet, and start again for the next one
+    pub(crate) fn finish(&mut self) -> Vec<String> {
+        let mut units = Vec::new();
+        self.end_unit(&mut units);
+        *self = Self::default();
+        units
+    }
+
+    /// How many of the bytes fed belong to a unit whose text isn't complete,
+    /// and so hasn't been checked
+    pub(crate) fn unchecked(&self) -> usize {
+        self.unchecked
+    }
+
+    fn start_block(&mut self, units: &mut Vec<String>) {
+        if !self.text_done {
+            self.emit(units);
+            self.text_done = true;
+        }
+    }
+
+    fn end_unit(&mut self, units: &mut Vec<String>) {
+        if !self.text_done {
+            self.emit(units);
+        }
+        self.text.clear();
+        self.text_done = false;
+        self.block_header = None;
+        self.unchecked = 0;
+    }
+
+    fn emit(&mut self, units: &mut Vec<String>) {
+        let text = String::from_utf8_lossy(&self.text).trim().to_owned();
+        if !text.is_empty() {
+            units.push(self.path.resolve(text));
+        }
+        self.unchecked = 0;
+    }
+}
+
+/// The header path SCPI resolves a relative header against: the nodes before
+/// the leaf of the previous header in the message
+#[derive(Debug, Default, Clone)]
+struct HeaderPath(Vec<String>);
+
+impl HeaderPath {
+    /// Resolve a unit to its absolute header, moving the path on to it.  Common
+    /// commands (`*RST`) leave the path alone, and a leading `:` returns to the
+    /// root.
+    fn resolve(&mut self, unit: String) -> String {
+        if unit.starts_with('*') {
+            return unit;
+        }
+
+        let resolved = if unit.starts_with(':') || self.0.is_empty() {
+            unit
+        } else {
+            format!("{}:{}", self.0.join(":"), unit)
+        };
+
+        let header = resolved
+            .split(char::is_whitespace)
+            .next()
+            .unwrap_or_default();
+        let mut nodes: Vec<String> = header
+            .trim_start_matches(':')
+            .split(':')
+            .map(str::to_owned)
+            .collect();
+        nodes.pop();
+        self.0 = nodes;
+        resolved
+    }
+}
+
+/// The message units of a whole message, as found by [CommandScan]
+pub(crate) fn commands(message: &[u8]) -> Vec<String> {
+    let mut scan = CommandScan::default();
+    let mut units = scan.feed(message);
+    units.extend(scan.finish());
+    units
+}
+
+/// Finds whether a message, given whole or in pieces, is a query: whether it
+/// has a `?` outside quoted strings and the data of arbitrary blocks, so that
+/// block data which happens to hold the byte (a waveform or file being
+/// uploaded, say) isn't taken for one
+#[derive(Debug, Default, Clone)]
+pub(crate) struct QueryScan {
+    quote: Option<u8>,
+
+    // bytes of a definite-length block, from its `#`, still to skip
+    block: usize,
+
+    // an indefinite-length block, or one whose header was cut off, runs to
+    // the end of the message
+    binary: bool,
+
+    query: bool,
+}
+
+impl QueryScan {
+    pub(crate) fn feed(&mut self, data: &[u8]) {
+        let mut i = 0;
+        while i < data.len() && !self.binary && !self.query {
+            if self.block > 0 {
+                let skipped = self.block.min(data.len() - i);
+                self.block -= skipped;
+                i += skipped;
+                continue;
+            }
+
+            let byte = data[i];
+            match self.quote {
+                Some(open) if byte == open => self.quote = None,
+                Some(_) => {}
+                None => match byte {
+                    b'"' | b'\'' => self.quote = Some(byte),
+                    b'?' => self.query = true,
+                    b'#' => match block_header(&data[i..]) {
+                        Ok(Some((offset, Some(length)))) => {
+                            self.block = offset.saturating_add(length);
+                            continue;
+                        }
+                        Ok(Some((_, None))) | Ok(None) => self.binary = true,
+                        // a non-decimal number, such as #H1F
+                        Err(_) => {}
+                    },
+                    _ => {}
+                },
+            }
+            i += 1;
+        }
+    }
+
+    pub(crate) fn is_query(&self) -> bool {
+        self.query
+    }
+}
+
+/// Whether a whole message is a query, as found by [QueryScan]
+pub(crate) fn is_query(message: &[u8]) -> bool {
+    let mut scan = QueryScan::default();
+    scan.feed(message);
+    scan.is_query()
+}
+
+/// A policy made of regular expressions: a command is refused if it matches any
+/// denied pattern, or if there are allowed patterns and it matches none of them.
+/// SCPI commands are case-insensitive and have short and long forms, so patterns
+/// should allow for these, for example `(?i)^:?SYST(em)?:SEC(urity)?:IMM`.
+#[cfg(feature = "command-policy")]
+#[derive(Debug, Clone, Default)]
+pub struct PatternPolicy {
+    allow: Vec<Regex>,
+    deny: Vec<Regex>,
+}
+
+#[cfg(feature = "command-policy")]
+impl PatternPolicy {
+    /// A policy which allows everything until patterns are added
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow commands matching `pattern`.  Once any pattern is allowed, commands
+    /// which match none of them are refused.
+    pub fn allow(mut self, pattern: Regex) -> Self {
+        self.allow.push(pattern);
+        self
+    }
+
+    /// Refuse commands matching `pattern`, even if they are also allowed
+    pub fn deny(mut self, pattern: Regex) -> Self {
+        self.deny.push(pattern);
+        self
+    }
+}
+
+#[cfg(feature = "command-policy")]
+impl CommandPolicy for PatternPolicy {
+    fn check(&self, command: &str) -> Result<(), String> {
+        if let Some(pattern) = self.deny.iter().find(|pattern| pattern.is_match(command)) {
+            return Err(format!("matches denied pattern {}", pattern));
+        }
+
+        if !self.allow.is_empty() && !self.allow.iter().any(|pattern| pattern.is_match(command)) {
+            return Err("matches no allowed pattern".to_owned());
+        }
+
+        Ok(())
+    }
+}
+
+/// The state of an external interlock, such as a test fixture's lid switch,
+/// shared between whatever monitors it and an [Interlock] policy.  Clones refer
+/// to the same state.
+#[cfg(feature = "command-policy")]
+#[derive(Debug, Clone, Default)]
+pub struct InterlockState(Arc<AtomicBool>);
+
+#[cfg(feature = "command-policy")]
+impl InterlockState {
+    /// An interlock which starts out `closed` (safe) or open
+    pub fn new(closed: bool) -> Self {
+        Self(Arc::new(AtomicBool::new(closed)))
+    }
+
+    pub fn set_closed(&self, closed: bool) {
+        self.0.store(closed, Ordering::SeqCst);
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(feature = "command-policy")]
+enum Guard {
+    Confirm(Box<dyn Fn(&str) -> bool + Send + Sync>),
+    State(InterlockState),
+}
+
+#[cfg(feature = "command-policy")]
+impl fmt::Debug for Guard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Guard::Confirm(_) => f.write_str("Confirm"),
+            Guard::State(state) => f.debug_tuple("State").field(state).finish(),
+        }
+    }
+}
+
+/// A policy holding back commands which enable outputs (or do anything else
+/// dangerous) until they are confirmed: each registered pattern has a callback
+/// asked about every command matching it, or an [InterlockState] which must be
+/// closed when one is sent.  Commands matching no pattern are allowed.  Patterns
+/// should allow for the forms of SCPI commands as for [PatternPolicy], for
+/// example `(?i)^:?OUTP(ut)?(:STAT(e)?)?\s+(ON|1)$`.
+#[cfg(feature = "command-policy")]
+#[derive(Debug, Default)]
+pub struct Interlock {
+    guards: Vec<(Regex, Guard)>,
+}
+
+#[cfg(feature = "command-policy")]
+impl Interlock {
+    /// An interlock which allows everything until patterns are registered
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only send commands matching `pattern` if `confirm` returns true for them,
+    /// such as after asking the operator
+    pub fn confirm<F>(mut self, pattern: Regex, confirm: F) -> Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.guards
+            .push((pattern, Guard::Confirm(Box::new(confirm))));
+        self
+    }
+
+    /// Only send commands matching `pattern` while `state` is closed
+    pub fn require(mut self, pattern: Regex, state: InterlockState) -> Self {
+        self.guards.push((pattern, Guard::State(state)));
+        self
+    }
+}
+
+#[cfg(feature = "command-policy")]
+impl CommandPolicy for Interlock {
+    fn check(&self, command: &str) -> Result<(), String> {
+        for (pattern, guard) in self.guards.iter() {
+            if !pattern.is_match(command) {
+                continue;
+            }
+
+            match guard {
+                Guard::Confirm(confirm) if !confirm(command) => {
+                    return Err(format!("not confirmed for interlocked pattern {}", pattern));
+                }
+                Guard::State(state) if !state.is_closed() => {
+                    return Err(format!("interlock for pattern {} is open", pattern));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queries_outside_strings_and_blocks() {
+        assert!(is_query(b"*IDN?"));
+        assert!(is_query(b":VOLT 1;:MEAS:VOLT?\n"));
+        assert!(!is_query(b":DISP:TEXT \"why?\""));
+        assert!(!is_query(b":DISP:TEXT 'why?'"));
+        assert!(!is_query(b":MMEM:DATA \"f.bin\",#14a?cd\n"));
+        assert!(is_query(b":MMEM:DATA \"f.bin\",#14a?cd;:SYST:ERR?\n"));
+        assert!(!is_query(b":MMEM:DATA \"f.bin\",#0a?cd\n"));
+    }
+
+    #[test]
+    fn headers_resolved_against_path() {
+        assert_eq!(
+            commands(b"SYST:BEEP;SEC:IMM"),
+            ["SYST:BEEP", "SYST:SEC:IMM"]
+        );
+        assert_eq!(commands(b":SYST:BEEP;:SEC:IMM"), [":SYST:BEEP", ":SEC:IMM"]);
+        assert_eq!(
+            commands(b"SOUR:VOLT 1;*WAI;CURR 2\nCURR 3"),
+            ["SOUR:VOLT 1", "*WAI", "SOUR:CURR 2", "CURR 3"]
+        );
+        assert_eq!(
+            commands(b":MMEM:DATA \"a;b\",#14;:;:;RST\n;RST"),
+            [":MMEM:DATA \"a;b\",", "MMEM:RST", "RST"]
+        );
+    }
+
+    #[test]
+    fn units_complete_as_pieces_arrive() {
+        let mut scan = CommandScan::default();
+        assert!(scan
+            .feed(b"*IDN?;SYST:BE")
+            .starts_with(&["*IDN?".to_owned()]));
+        assert_eq!(scan.unchecked(), 7);
+        assert_eq!(scan.feed(b"EP;SEC:"), ["SYST:BEEP"]);
+        assert_eq!(scan.feed(b"IMM"), Vec::<String>::new());
+        assert_eq!(scan.unchecked(), 7);
+        assert_eq!(scan.finish(), ["SYST:SEC:IMM"]);
+
+        // a unit is complete where its block starts, even if the header is split
+        assert_eq!(scan.feed(b":MMEM:DATA #"), Vec::<String>::new());
+        assert_eq!(scan.feed(b"2"), Vec::<String>::new());
+        assert_eq!(scan.feed(b"10;;;;"), [":MMEM:DATA"]);
+        assert_eq!(scan.unchecked(), 0);
+        assert_eq!(scan.feed(b";;;;;;;*RST"), Vec::<String>::new());
+        assert_eq!(scan.finish(), ["*RST"]);
+    }
+
+    #[test]
+    fn block_split_across_pieces() {
+        let mut scan = QueryScan::default();
+        scan.feed(b":MMEM:DATA \"f.bin\",#210");
+        scan.feed(b"????");
+        scan.feed(b"??????\n");
+        assert!(!scan.is_query());
+
+        scan.feed(b";*OPC?");
+        assert!(scan.is_query());
+    }
+}
@@ -0,0 +1,239 @@
+//! The instrument's own catalog of its SCPI command headers, from
+//! `SYST:HELP:HEAD?`, as a tree for auto-completion in interactive tools.
+//!
+//! Instruments list one header per line, such as
+//! `[:SENSe]:VOLTage[:DC]:RANGe[:UPPer]`, with optional nodes in brackets and
+//! `/nquery/` or `/qonly/` after headers which have no query form or only a query
+//! form.  Not every SCPI instrument implements the query.
+
+use crate::block::block_range;
+use crate::transport::Transport;
+use crate::{TMCError, TMCHandle, TMCResult};
+
+/// Which forms of a command the instrument accepts
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum CommandForm {
+    SetAndQuery,
+
+    /// Marked `/nquery/`
+    SetOnly,
+
+    /// Marked `/qonly/`
+    QueryOnly,
+}
+
+/// A node of the command tree
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct CommandNode {
+    /// The keyword in its long form, with the short form in upper case and any
+    /// numeric suffix as the instrument gives it, such as `CALCulate[1|2]`
+    pub name: String,
+
+    /// Whether the node may be left out of a command, being shown in brackets
+    pub optional: bool,
+
+    /// Set if the header ending at this node is itself a command
+    pub form: Option<CommandForm>,
+    pub children: Vec<CommandNode>,
+}
+
+impl CommandNode {
+    fn keyword(&self) -> &str {
+        self.name.split('[').next().unwrap_or_default()
+    }
+
+    /// The keyword's long form, without any numeric suffix
+    pub fn long_form(&self) -> String {
+        self.keyword().to_ascii_uppercase()
+    }
+
+    /// The keyword's short form: the upper case letters of its long form, and any
+    /// leading `*` of a common command
+    pub fn short_form(&self) -> String {
+        self.keyword()
+            .chars()
+            .filter(|c| !c.is_ascii_lowercase())
+            .collect()
+    }
+
+    /// Whether `keyword` names this node, in its short or long form and with or
+    /// without a numeric suffix, ignoring case
+    pub fn matches(&self, keyword: &str) -> bool {
+        let keyword = keyword
+            .trim_end_matches(|c: char| c.is_ascii_digit())
+            .to_ascii_uppercase();
+        keyword == self.short_form() || keyword == self.long_form()
+    }
+
+    fn child_mut(&mut self, name: &str, optional: bool) -> &mut CommandNode {
+        let position = self
+            .children
+            .iter()
+            .position(|child| child.name.eq_ignore_ascii_case(name));
+        let index = position.unwrap_or_else(|| {
+            self.children.push(CommandNode {
+                name: name.to_owned(),
+                optional,
+                ..Default::default()
+            });
+            self.children.len() - 1
+        });
+        &mut self.children[index]
+    }
+
+    /// The children reachable by `keyword`, looking through optional children
+    /// which it doesn't name
+    fn find(&self, keyword: &str) -> Vec<&CommandNode> {
+        let mut found = Vec::new();
+        for child in self.children.iter() {
+            if child.matches(keyword) {
+                found.push(child);
+            } else if child.optional {
+                found.extend(child.find(keyword));
+            }
+        }
+        found
+    }
+
+    /// The children which could follow, including those of optional children
+    fn completions(&self) -> Vec<&CommandNode> {
+        let mut completions = Vec::new();
+        for child in self.children.iter() {
+            completions.push(child);
+            if child.optional {
+                completions.extend(child.completions());
+            }
+        }
+        completions
+    }
+}
+
+/// The instrument's command headers as a tree.  Common commands such as `*CLS`
+/// are children of the root along with the top level SCPI nodes.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct CommandTree {
+    pub root: CommandNode,
+}
+
+impl CommandTree {
+    /// Parse a header catalog, one header per line
+    pub fn parse(catalog: &str) -> Self {
+        let mut tree = Self::default();
+
+        for line in catalog.lines() {
+            let line = line.trim().trim_matches('"');
+            let (header, form) = if let Some(header) = line.strip_suffix("/nquery/") {
+                (header, CommandForm::SetOnly)
+            } else if let Some(header) = line.strip_suffix("/qonly/") {
+                (header, CommandForm::QueryOnly)
+            } else {
+                (line, CommandForm::SetAndQuery)
+            };
+
+            let header = header.trim().trim_end_matches('?');
+            if header.is_empty() {
+                continue;
+            }
+
+            let mut node = &mut tree.root;
+            for segment in header_segments(header) {
+                let (name, optional) = match segment
+                    .strip_prefix('[')
+                    .and_then(|inner| inner.strip_suffix(']'))
+                {
+                    Some(inner) => (inner.trim_start_matches(':'), true),
+                    None => (segment, false),
+                };
+                node = node.child_mut(name, optional);
+            }
+            node.form = Some(form);
+        }
+
+        tree
+    }
+
+    /// The keywords which could complete `partial`, a command header typed so
+    /// far such as `:SENS:VOLT:R`, in their long forms with the short form in
+    /// upper case.  A header ending in `:` lists every node which could follow
+    /// it.
+    pub fn complete(&self, partial: &str) -> Vec<String> {
+        let partial = partial.trim_start_matches(':');
+        let (path, last) = match partial.rsplit_once(':') {
+            Some((path, last)) => (Some(path), last),
+            None => (None, partial),
+        };
+
+        let mut nodes = vec![&self.root];
+        for keyword in path.into_iter().flat_map(|path| path.split(':')) {
+            nodes = nodes.iter().flat_map(|node| node.find(keyword)).collect();
+        }
+
+        let last = last.to_ascii_uppercase();
+        let mut completions: Vec<String> = nodes
+            .iter()
+            .flat_map(|node| node.completions())
+            .map(|node| node.keyword().to_owned())
+            .filter(|keyword| keyword.to_ascii_uppercase().starts_with(&last))
+            .collect();
+        completions.sort();
+        completions.dedup();
+        completions
+    }
+}
+
+/// Split a header on the colons between its nodes, keeping bracketed optional
+/// nodes (which contain their own colon) whole
+fn header_segments(header: &str) -> Vec<&str> {
+    let mut segments = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (i, c) in header.char_indices() {
+        match c {
+            '[' => {
+                // an optional node can follow a keyword without a colon, as in
+                // VOLTage[:DC], but a numeric suffix belongs to the keyword
+                if depth == 0 && i > start && header[i..].starts_with("[:") {
+                    segments.push(&header[start..i]);
+                    start = i;
+                }
+                depth += 1;
+            }
+            ']' => {
+                depth -= 1;
+                if depth == 0 && header[start..].starts_with("[:") {
+                    segments.push(&header[start..=i]);
+                    start = i + 1;
+                }
+            }
+            ':' if depth == 0 => {
+                segments.push(&header[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    segments.push(&header[start..]);
+
+    segments.retain(|segment| !segment.is_empty());
+    segments
+}
+
+impl<T: Transport> TMCHandle<T> {
+    /// Read the instrument's catalog of command headers with `SYST:HELP:HEAD?`,
+    /// which instruments send either as an arbitrary block or as plain text
+    pub fn command_tree(&mut self) -> TMCResult<CommandTree> {
+        let response = self.ask_raw(b"SYST:HELP:HEAD?")?;
+        let catalog = match response.first() {
+            Some(b'#') => &response[block_range(&response)?],
+            _ => &response[..],
+        };
+
+        let catalog = String::from_utf8(catalog.to_vec())?;
+        let tree = CommandTree::parse(&catalog);
+        if tree.root.children.is_empty() {
+            return Err(TMCError::InvalidResponse(catalog));
+        }
+        Ok(tree)
+    }
+}
@@ -0,0 +1,197 @@
+//! The IEEE 488.2 common commands, with typed arguments and results.
+//!
+//! Each command needs an instrument which supports USB488.2 or SCPI, and fails
+//! with [UnsupportedFeature](crate::ClassError::UnsupportedFeature) otherwise.
+
+use crate::class::ClassError;
+use crate::support::Feature;
+use crate::transport::Transport;
+use crate::{TMCError, TMCHandle, TMCResult};
+
+/// The IEEE 488.2 status byte, as returned by `*STB?`
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct StatusByte(pub u8);
+
+impl StatusByte {
+    /// The error/event queue is not empty (SCPI)
+    pub const ERROR_QUEUE: u8 = 0x04;
+    pub const QUESTIONABLE: u8 = 0x08;
+    pub const MESSAGE_AVAILABLE: u8 = 0x10;
+    pub const EVENT_STATUS: u8 = 0x20;
+    pub const MASTER_SUMMARY: u8 = 0x40;
+    pub const OPERATION: u8 = 0x80;
+
+    pub fn error_queue(self) -> bool {
+        self.0 & Self::ERROR_QUEUE != 0
+    }
+
+    pub fn message_available(self) -> bool {
+        self.0 & Self::MESSAGE_AVAILABLE != 0
+    }
+
+    /// An enabled bit of the standard event status register is set
+    pub fn event_status(self) -> bool {
+        self.0 & Self::EVENT_STATUS != 0
+    }
+
+    /// The instrument is requesting service
+    pub fn master_summary(self) -> bool {
+        self.0 & Self::MASTER_SUMMARY != 0
+    }
+}
+
+/// The IEEE 488.2 standard event status register, as returned by `*ESR?`, or a
+/// mask of its bits as set with `*ESE`
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct StandardEvents(pub u8);
+
+impl StandardEvents {
+    pub const OPERATION_COMPLETE: u8 = 0x01;
+    pub const REQUEST_CONTROL: u8 = 0x02;
+    pub const QUERY_ERROR: u8 = 0x04;
+    pub const DEVICE_ERROR: u8 = 0x08;
+    pub const EXECUTION_ERROR: u8 = 0x10;
+    pub const COMMAND_ERROR: u8 = 0x20;
+    pub const USER_REQUEST: u8 = 0x40;
+    pub const POWER_ON: u8 = 0x80;
+
+    /// Any of the error bits
+    pub const ERRORS: u8 =
+        Self::QUERY_ERROR | Self::DEVICE_ERROR | Self::EXECUTION_ERROR | Self::COMMAND_ERROR;
+
+    pub fn operation_complete(self) -> bool {
+        self.0 & Self::OPERATION_COMPLETE != 0
+    }
+
+    pub fn has_errors(self) -> bool {
+        self.0 & Self::ERRORS != 0
+    }
+
+    pub fn power_on(self) -> bool {
+        self.0 & Self::POWER_ON != 0
+    }
+}
+
+/// An instrument's response to `*IDN?`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Identity {
+    pub manufacturer: String,
+    pub model: String,
+
+    /// Serial number, or `0` if the instrument doesn't report one
+    pub serial_number: String,
+    pub firmware: String,
+}
+
+impl Identity {
+    /// Split an `*IDN?` response into its four fields
+    pub fn parse(response: &str) -> Option<Self> {
+        let mut fields = response.trim().splitn(4, ',').map(str::trim);
+
+        Some(Self {
+            manufacturer: fields.next()?.to_owned(),
+            model: fields.next()?.to_owned(),
+            serial_number: fields.next()?.to_owned(),
+            firmware: fields.next()?.to_owned(),
+        })
+    }
+}
+
+impl<T: Transport> TMCHandle<T> {
+    fn require_common_commands(&mut self) -> TMCResult<()> {
+        if self.supports(Feature::Usb488_2)?.is_supported()
+            || self.supports(Feature::Scpi)?.is_supported()
+        {
+            Ok(())
+        } else {
+            Err(ClassError::UnsupportedFeature.into())
+        }
+    }
+
+    fn common_command(&mut self, command: &str) -> TMCResult<()> {
+        self.require_common_commands()?;
+        self.write(command)
+    }
+
+    fn common_query<R: std::str::FromStr>(&mut self, query: &str) -> TMCResult<R> {
+        self.require_common_commands()?;
+        let response = self.ask(query)?;
+        response
+            .trim()
+            .parse()
+            .map_err(|_| TMCError::InvalidResponse(response))
+    }
+
+    /// Clear status: empty the error queue and clear the event registers
+    pub fn cls(&mut self) -> TMCResult<()> {
+        self.common_command("*CLS")
+    }
+
+    /// Set the standard event status enable mask
+    pub fn ese(&mut self, mask: StandardEvents) -> TMCResult<()> {
+        self.common_command(&format!("*ESE {}", mask.0))
+    }
+
+    /// Read the standard event status enable mask
+    pub fn ese_query(&mut self) -> TMCResult<StandardEvents> {
+        self.common_query("*ESE?").map(StandardEvents)
+    }
+
+    /// Read and clear the standard event status register
+    pub fn esr(&mut self) -> TMCResult<StandardEvents> {
+        self.common_query("*ESR?").map(StandardEvents)
+    }
+
+    /// Read the instrument's identity
+    pub fn idn(&mut self) -> TMCResult<Identity> {
+        self.require_common_commands()?;
+        let response = self.ask("*IDN?")?;
+        Identity::parse(&response).ok_or(TMCError::InvalidResponse(response))
+    }
+
+    /// Set the operation complete bit of the event status register once pending
+    /// operations finish
+    pub fn opc(&mut self) -> TMCResult<()> {
+        self.common_command("*OPC")
+    }
+
+    /// Wait, up to the bulk timeout, for pending operations to finish
+    pub fn opc_query(&mut self) -> TMCResult<()> {
+        match self.common_query::<u8>("*OPC?")? {
+            1 => Ok(()),
+            other => Err(TMCError::InvalidResponse(other.to_string())),
+        }
+    }
+
+    /// Reset the instrument to its default settings
+    pub fn rst(&mut self) -> TMCResult<()> {
+        self.common_command("*RST")
+    }
+
+    /// Set the service request enable mask
+    pub fn sre(&mut self, mask: StatusByte) -> TMCResult<()> {
+        self.common_command(&format!("*SRE {}", mask.0))
+    }
+
+    /// Read the service request enable mask
+    pub fn sre_query(&mut self) -> TMCResult<StatusByte> {
+        self.common_query("*SRE?").map(StatusByte)
+    }
+
+    /// Read the status byte with `*STB?`
+    pub fn stb_query(&mut self) -> TMCResult<StatusByte> {
+        self.common_query("*STB?").map(StatusByte)
+    }
+
+    /// Run the instrument's self test, returning its result code: zero if it
+    /// passed
+    pub fn tst(&mut self) -> TMCResult<i32> {
+        self.common_query("*TST?")
+    }
+
+    /// Make the instrument finish pending operations before carrying out further
+    /// commands
+    pub fn wai(&mut self) -> TMCResult<()> {
+        self.common_command("*WAI")
+    }
+}
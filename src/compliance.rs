@@ -0,0 +1,244 @@
+//! Checks of a connected device's behaviour against the USBTMC class spec, for
+//! triaging whether a problem lies with the device or with this crate.
+//!
+//! The checks send commands to the device (including SCPI `*IDN?` queries when it
+//! claims SCPI support), so they should not be run while it is in use.
+//!
+//! The crate has no command-line tool of its own for this; the `compliance`
+//! example (`cargo run --example compliance`) checks every attached instrument
+//! and prints the reports.
+
+use crate::class::DevDepMsgInHeader;
+use crate::transport::Transport;
+use crate::{TMCHandle, TMCResult};
+use std::fmt;
+
+/// Outcome of one compliance check
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Outcome {
+    Pass,
+    Fail(String),
+
+    /// The check does not apply to this device, or depends on a feature it lacks
+    Skipped(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub outcome: Outcome,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ComplianceReport {
+    pub results: Vec<CheckResult>,
+}
+
+impl ComplianceReport {
+    /// Whether no check failed
+    pub fn passed(&self) -> bool {
+        self.results
+            .iter()
+            .all(|result| !matches!(result.outcome, Outcome::Fail(_)))
+    }
+
+    fn record(&mut self, name: &'static str, outcome: Outcome) {
+        self.results.push(CheckResult { name, outcome });
+    }
+
+    fn record_result(&mut self, name: &'static str, result: TMCResult<Outcome>) {
+        let outcome = result.unwrap_or_else(|err| Outcome::Fail(err.to_string()));
+        self.record(name, outcome);
+    }
+}
+
+impl fmt::Display for ComplianceReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for result in self.results.iter() {
+            match &result.outcome {
+                Outcome::Pass => writeln!(f, "PASS  {}", result.name)?,
+                Outcome::Fail(reason) => writeln!(f, "FAIL  {}: {}", result.name, reason)?,
+                Outcome::Skipped(reason) => writeln!(f, "SKIP  {}: {}", result.name, reason)?,
+            }
+        }
+
+        let verdict = if self.passed() { "PASSED" } else { "FAILED" };
+        write!(f, "{}", verdict)
+    }
+}
+
+/// Run every compliance check against the device, restoring the handle's
+/// settings afterwards.
+pub fn check_compliance<T: Transport>(handle: &mut TMCHandle<T>) -> ComplianceReport {
+    let max_transfer_size = handle.get_max_transfer_size();
+    let term_char = handle.get_term_char();
+
+    let mut report = ComplianceReport::default();
+    report.record_result("capabilities", check_capabilities(handle));
+    report.record_result("clear", handle.clear().map(|_| Outcome::Pass));
+    report.record_result(
+        "idle abort bulk-out",
+        handle.abort_bulk_out().map(|_| Outcome::Pass),
+    );
+    report.record_result(
+        "idle abort bulk-in",
+        handle.abort_bulk_in().map(|_| Outcome::Pass),
+    );
+    report.record_result("abort pending query", check_abort_pending(handle));
+    report.record_result("end of message", check_eom(handle));
+    report.record_result("term char", check_term_char(handle));
+    report.record_result("clear after queries", handle.clear().map(|_| Outcome::Pass));
+
+    let _ = handle.set_max_transfer_size(max_transfer_size);
+    let _ = handle.set_term_char(term_char);
+
+    report
+}
+
+fn check_capabilities<T: Transport>(handle: &mut TMCHandle<T>) -> TMCResult<Outcome> {
+    let caps = handle.usbtmc_capabilities()?;
+
+    if !caps.is_valid() {
+        return Ok(Outcome::Fail(format!(
+            "invalid bcdUSBTMC {:#06x}",
+            caps.bcd_usbtmc
+        )));
+    }
+
+    if caps.talk_only && caps.listen_only {
+        return Ok(Outcome::Fail("both talk-only and listen-only".to_owned()));
+    }
+
+    if handle.interface().interface_protocol == 1 && handle.usb488_capabilities()?.is_none() {
+        return Ok(Outcome::Fail(
+            "USB488 interface without USB488 capabilities".to_owned(),
+        ));
+    }
+
+    Ok(Outcome::Pass)
+}
+
+fn has_scpi<T: Transport>(handle: &mut TMCHandle<T>) -> TMCResult<bool> {
+    Ok(cfg!(feature = "scpi") && handle.usb488_capabilities()?.is_some_and(|caps| caps.scpi))
+}
+
+/// Query the device's identity with transfers much smaller than the response, so
+/// the device has to split it and only mark the last transfer as end of message.
+fn check_eom<T: Transport>(handle: &mut TMCHandle<T>) -> TMCResult<Outcome> {
+    if !has_scpi(handle)? {
+        return Ok(Outcome::Skipped(
+            "needs SCPI to query the device".to_owned(),
+        ));
+    }
+
+    let max_transfer_size = handle.get_max_transfer_size();
+    let whole = handle.ask("*IDN?")?;
+    handle.set_max_transfer_size(4)?;
+    let split = handle.ask("*IDN?");
+    handle.set_max_transfer_size(max_transfer_size)?;
+    let split = split?;
+
+    if whole.is_empty() {
+        Ok(Outcome::Fail("empty response".to_owned()))
+    } else if whole != split {
+        Ok(Outcome::Fail(format!(
+            "response read in small transfers differs: {:?} vs {:?}",
+            whole, split
+        )))
+    } else {
+        Ok(Outcome::Pass)
+    }
+}
+
+/// Read only the first transfer of a query's response, then abort the bulk-in
+/// transfer requested after it.  The device must have had that transfer in
+/// progress and discard the rest of the response, so that the same query
+/// afterwards is answered in full.
+fn check_abort_pending<T: Transport>(handle: &mut TMCHandle<T>) -> TMCResult<Outcome> {
+    if !has_scpi(handle)? {
+        return Ok(Outcome::Skipped(
+            "needs SCPI to query the device".to_owned(),
+        ));
+    }
+
+    let whole = handle.ask("*IDN?")?;
+    handle.write("*IDN?")?;
+    let first = handle.read_raw_transfer(4)?;
+    if DevDepMsgInHeader::unpack(&first)?.is_eom() {
+        return Ok(Outcome::Skipped("response fits in one transfer".to_owned()));
+    }
+
+    if !handle.discard_message(4)? {
+        return Ok(Outcome::Fail(
+            "device had no transfer in progress to abort".to_owned(),
+        ));
+    }
+
+    let again = handle.ask("*IDN?")?;
+    if whole != again {
+        Ok(Outcome::Fail(format!(
+            "response after abort differs: {:?} vs {:?}",
+            whole, again
+        )))
+    } else {
+        Ok(Outcome::Pass)
+    }
+}
+
+/// Read a response with the term char set to a character in the middle of it.
+/// The device must end the first transfer just after that character, and the
+/// message as a whole must be unchanged.
+fn check_term_char<T: Transport>(handle: &mut TMCHandle<T>) -> TMCResult<Outcome> {
+    if !handle.usbtmc_capabilities()?.term_char {
+        return Ok(Outcome::Skipped(
+            "device does not support term char".to_owned(),
+        ));
+    }
+
+    if !has_scpi(handle)? {
+        return Ok(Outcome::Skipped(
+            "needs SCPI to query the device".to_owned(),
+        ));
+    }
+
+    handle.set_term_char(None)?;
+    let whole = handle.ask("*IDN?")?;
+    let expected = match whole.find(',') {
+        Some(end) => whole.as_bytes()[..=end].to_vec(),
+        None => {
+            return Ok(Outcome::Skipped(
+                "identity contains no ',' to use as the term char".to_owned(),
+            ))
+        }
+    };
+
+    handle.set_term_char(Some(b','))?;
+    let result = first_transfer(handle).and_then(|first| Ok((first, handle.ask("*IDN?")?)));
+    handle.set_term_char(None)?;
+    let (first, with_term_char) = result?;
+
+    if first != expected {
+        Ok(Outcome::Fail(format!(
+            "first transfer with term char doesn't end at it: {:?}",
+            String::from_utf8_lossy(&first)
+        )))
+    } else if whole != with_term_char {
+        Ok(Outcome::Fail(format!(
+            "response with term char differs: {:?} vs {:?}",
+            whole, with_term_char
+        )))
+    } else {
+        Ok(Outcome::Pass)
+    }
+}
+
+/// Send `*IDN?` and return the data of the first transfer of the response,
+/// discarding the rest
+fn first_transfer<T: Transport>(handle: &mut TMCHandle<T>) -> TMCResult<Vec<u8>> {
+    handle.write("*IDN?")?;
+    let mut frames = handle.read_raw_frames(None);
+    match frames.next() {
+        Some(frame) => Ok(frame?.payload().to_vec()),
+        None => Ok(Vec::new()),
+    }
+}
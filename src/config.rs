@@ -0,0 +1,142 @@
+//! Crate-wide defaults for new handles, so that a large application can set its
+//! policy once rather than repeating it wherever an instrument is opened.
+//!
+//! [OpenOptions::new] takes a copy of the installed [UsbtmcConfig], and the
+//! handle opened with those options starts out with its settings.  Handles
+//! already open keep the settings they were opened with.
+
+#[cfg(feature = "profiles")]
+use crate::profile::ProfileRegistry;
+use crate::{StallRecovery, Timeout};
+use core::time::Duration;
+use std::sync::{PoisonError, RwLock};
+
+static INSTALLED: RwLock<Option<UsbtmcConfig>> = RwLock::new(None);
+
+/// Default settings for new handles, built up with chained calls starting from
+/// [UsbtmcConfig::new] and made the crate-wide default with
+/// [install](Self::install)
+#[derive(Debug, Clone, PartialEq)]
+pub struct UsbtmcConfig {
+    pub(crate) bulk_timeout: Timeout,
+    pub(crate) control_timeout: Timeout,
+    pub(crate) interrupt_timeout: Timeout,
+    pub(crate) claim_retry: Duration,
+    pub(crate) stall_recovery: StallRecovery,
+    pub(crate) max_transfer_size: u32,
+    #[cfg(feature = "tracing")]
+    pub(crate) log_level: tracing::level_filters::LevelFilter,
+    #[cfg(feature = "profiles")]
+    pub(crate) profiles: Option<ProfileRegistry>,
+}
+
+impl UsbtmcConfig {
+    /// The settings handles have when no configuration is installed
+    pub fn new() -> Self {
+        Self {
+            bulk_timeout: Timeout::After(Duration::from_secs(1)),
+            control_timeout: Timeout::After(Duration::from_secs(1)),
+            interrupt_timeout: Timeout::After(Duration::from_secs(1)),
+            claim_retry: Duration::ZERO,
+            stall_recovery: StallRecovery::default(),
+            max_transfer_size: 1024 * 1024,
+            #[cfg(feature = "tracing")]
+            log_level: tracing::level_filters::LevelFilter::TRACE,
+            #[cfg(feature = "profiles")]
+            profiles: None,
+        }
+    }
+
+    /// The installed configuration, or the defaults if none has been installed
+    pub fn current() -> Self {
+        INSTALLED
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+            .unwrap_or_default()
+    }
+
+    /// Make this the configuration for handles opened from now on, replacing any
+    /// installed before
+    pub fn install(self) {
+        *INSTALLED.write().unwrap_or_else(PoisonError::into_inner) = Some(self);
+    }
+
+    /// Go back to the default settings for handles opened from now on
+    pub fn uninstall() {
+        *INSTALLED.write().unwrap_or_else(PoisonError::into_inner) = None;
+    }
+
+    /// Use `timeout` for bulk, control and interrupt transfers, as with
+    /// [set_timeout](crate::TMCHandle::set_timeout)
+    pub fn timeout<D: Into<Timeout>>(self, timeout: D) -> Self {
+        let timeout = timeout.into();
+        self.bulk_timeout(timeout)
+            .control_timeout(timeout)
+            .interrupt_timeout(timeout)
+    }
+
+    pub fn bulk_timeout<D: Into<Timeout>>(mut self, timeout: D) -> Self {
+        self.bulk_timeout = timeout.into();
+        self
+    }
+
+    pub fn control_timeout<D: Into<Timeout>>(mut self, timeout: D) -> Self {
+        self.control_timeout = timeout.into();
+        self
+    }
+
+    pub fn interrupt_timeout<D: Into<Timeout>>(mut self, timeout: D) -> Self {
+        self.interrupt_timeout = timeout.into();
+        self
+    }
+
+    /// Keep trying to claim the TMC interface for up to `window` while it is in
+    /// use, as with [OpenOptions::claim_retry](crate::OpenOptions::claim_retry)
+    pub fn claim_retry(mut self, window: Duration) -> Self {
+        self.claim_retry = window;
+        self
+    }
+
+    /// Whether a transfer the device stalls is retried after clearing the halt
+    pub fn stall_recovery(mut self, stall_recovery: StallRecovery) -> Self {
+        self.stall_recovery = stall_recovery;
+        self
+    }
+
+    /// The largest amount of message data sent or requested in one bulk
+    /// transfer.  Zero is ignored, keeping the previous size.  A size which
+    /// [set_max_transfer_size](crate::TMCHandle::set_max_transfer_size) would
+    /// reject for an instrument, such as one filling whole packets, fails
+    /// opening it.
+    pub fn max_transfer_size(mut self, max_transfer_size: u32) -> Self {
+        if max_transfer_size != 0 {
+            self.max_transfer_size = max_transfer_size;
+        }
+        self
+    }
+
+    /// The most verbose level handles log at, below any limit set by the
+    /// subscriber.  [LevelFilter::DEBUG](tracing::level_filters::LevelFilter::DEBUG),
+    /// for example, leaves out the trace of every bulk transfer.
+    #[cfg(feature = "tracing")]
+    pub fn log_level(mut self, log_level: tracing::level_filters::LevelFilter) -> Self {
+        self.log_level = log_level;
+        self
+    }
+
+    /// Apply the registry's profile for each instrument's model, as with
+    /// [OpenOptions::profiles](crate::OpenOptions::profiles), so that
+    /// instrument quirks are handled wherever an instrument is opened
+    #[cfg(feature = "profiles")]
+    pub fn profiles(mut self, profiles: ProfileRegistry) -> Self {
+        self.profiles = Some(profiles);
+        self
+    }
+}
+
+impl Default for UsbtmcConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,417 @@
+//! Regression testing against recorded device exchanges.  A corpus is a
+//! directory of cases, each a YAML file describing the messages an application
+//! exchanged with a real instrument, alongside a binary [capture](crate::capture)
+//! of the bulk transfers made at the time.  Replaying a case runs its steps
+//! through a handle whose device answers with the captured transfers, so that
+//! changes to how messages are framed and decoded are caught, including the
+//! quirks of the instrument that was recorded.
+//!
+//! A case looks like:
+//!
+//! ```yaml
+//! device: Rigol DS1054Z, firmware 00.04.04
+//! capture: ds1054z-idn.bin
+//! max_transfer_size: 64
+//! steps:
+//!   - write: ":RUN"
+//!   - query: "*IDN?"
+//!     response: "RIGOL TECHNOLOGIES,DS1054Z,DS1ZA000000000,00.04.04.SP4\n"
+//!   - query: ":WAV:DATA?"
+//!     error: true
+//! ```
+//!
+//! The capture path is relative to the case file.  A query's response is only
+//! checked if one is given, and `error: true` expects the query to fail, for
+//! recording how a malformed response is rejected.
+//!
+//! Transfers are matched in order.  bTags are ignored when comparing what is
+//! sent, and rewritten in what is received (unless the device got them wrong),
+//! so that a case doesn't depend on the tag sequence of the handle that
+//! recorded it.  The handle starts from the default settings rather than any
+//! installed [UsbtmcConfig](crate::UsbtmcConfig).  Control requests aren't
+//! captured, so they are answered with success and the device appears to have
+//! no optional capabilities.
+
+use crate::capture::{read_capture, CapturedFrame};
+use crate::class::{TMCInterface, HEADER_SIZE};
+use crate::transcript::Direction;
+use crate::transport::Transport;
+use crate::{ConnectClear, OpenOptions, TMCHandle, TMCResult, UsbtmcConfig};
+use core::time::Duration;
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Why a case couldn't be loaded
+#[derive(Error, Debug)]
+pub enum CorpusError {
+    /// A case, its capture or the corpus directory could not be read
+    #[error("reading {path:?} failed: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    /// A case is not valid YAML, or doesn't have the expected structure
+    #[error("parsing {path:?} failed: {source}")]
+    Yaml {
+        path: PathBuf,
+        source: serde_yaml::Error,
+    },
+}
+
+/// One step of a [Case]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum Step {
+    Write {
+        write: String,
+    },
+    Query {
+        query: String,
+        #[serde(default)]
+        response: Option<String>,
+        #[serde(default)]
+        error: bool,
+    },
+}
+
+/// A recorded exchange with an instrument, as described in the module
+/// documentation
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Case {
+    /// What was recorded, for the report
+    #[serde(default)]
+    pub device: String,
+
+    /// The binary capture of the exchange, relative to the case file
+    pub capture: PathBuf,
+
+    /// The handle's maximum transfer size when the capture was made, if not the
+    /// default
+    #[serde(default)]
+    pub max_transfer_size: Option<u32>,
+
+    pub steps: Vec<Step>,
+}
+
+impl Case {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<(Self, Vec<CapturedFrame>), CorpusError> {
+        let path = path.as_ref();
+        let io_error = |path: &Path| {
+            let path = path.to_owned();
+            move |source| CorpusError::Io { path, source }
+        };
+
+        let yaml = fs::read_to_string(path).map_err(io_error(path))?;
+        let case: Case = serde_yaml::from_str(&yaml).map_err(|source| CorpusError::Yaml {
+            path: path.to_owned(),
+            source,
+        })?;
+
+        let capture = path
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .join(&case.capture);
+        let file = File::open(&capture).map_err(io_error(&capture))?;
+        let frames = read_capture(BufReader::new(file)).map_err(io_error(&capture))?;
+
+        Ok((case, frames))
+    }
+
+    /// Run the steps through a handle replaying `frames`, reporting every step
+    /// which didn't go as recorded
+    pub fn replay(&self, frames: Vec<CapturedFrame>) -> TMCResult<CaseReport> {
+        let mut report = CaseReport {
+            device: self.device.clone(),
+            ..CaseReport::default()
+        };
+
+        let options =
+            OpenOptions::with_config(UsbtmcConfig::new()).connect_clear(ConnectClear::Never);
+        let mut handle = TMCHandle::with_transport(CaptureTransport::new(frames), options)?;
+        if let Some(max_transfer_size) = self.max_transfer_size {
+            handle.set_max_transfer_size(max_transfer_size)?;
+        }
+
+        for (index, step) in self.steps.iter().enumerate() {
+            let failure = match step {
+                Step::Write { write } => handle.write(write).err().map(|error| error.to_string()),
+                Step::Query {
+                    query,
+                    response,
+                    error,
+                } => match (handle.ask(query), response) {
+                    (Ok(actual), _) if *error => {
+                        Some(format!("expected an error, received {:?}", actual))
+                    }
+                    (Ok(actual), Some(expected)) if actual != *expected => {
+                        Some(format!("expected {:?}, received {:?}", expected, actual))
+                    }
+                    (Ok(_), _) => None,
+                    (Err(_), _) if *error => None,
+                    (Err(error), _) => Some(error.to_string()),
+                },
+            };
+
+            if let Some(message) = failure {
+                report.failures.push(StepFailure { index, message });
+            }
+        }
+
+        report.unused_frames = handle.transport().frames.len();
+        report.divergence = handle.transport().divergence.clone();
+        Ok(report)
+    }
+}
+
+/// A step of a case which didn't go as recorded
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct StepFailure {
+    /// Index of the step in the case
+    pub index: usize,
+    pub message: String,
+}
+
+/// Outcome of replaying a [Case]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct CaseReport {
+    pub device: String,
+    pub failures: Vec<StepFailure>,
+
+    /// Captured transfers left over once every step had run, which means the
+    /// handle exchanged fewer transfers than the recorded one did
+    pub unused_frames: usize,
+
+    /// Where the handle first sent something other than the next captured
+    /// transfer, after which steps fail with a USB pipe error
+    pub divergence: Option<String>,
+}
+
+impl CaseReport {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty() && self.unused_frames == 0 && self.divergence.is_none()
+    }
+}
+
+/// Outcome of [run_corpus], with a report for each case file
+#[derive(Debug, Default)]
+pub struct CorpusReport {
+    pub cases: Vec<(PathBuf, TMCResult<CaseReport>)>,
+}
+
+impl CorpusReport {
+    pub fn passed(&self) -> bool {
+        self.cases
+            .iter()
+            .all(|(_, report)| report.as_ref().is_ok_and(CaseReport::passed))
+    }
+}
+
+/// Replay every case (every `.yaml` or `.yml` file) in `directory`, in order of
+/// file name
+pub fn run_corpus<P: AsRef<Path>>(directory: P) -> Result<CorpusReport, CorpusError> {
+    let directory = directory.as_ref();
+    let io_error = |source| CorpusError::Io {
+        path: directory.to_owned(),
+        source,
+    };
+
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(directory).map_err(io_error)? {
+        let path = entry.map_err(io_error)?.path();
+        if matches!(
+            path.extension().and_then(|extension| extension.to_str()),
+            Some("yaml" | "yml")
+        ) {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    let mut report = CorpusReport::default();
+    for path in paths {
+        let (case, frames) = Case::load(&path)?;
+        report.cases.push((path, case.replay(frames)));
+    }
+    Ok(report)
+}
+
+/// Transport whose device answers with captured bulk transfers
+#[derive(Debug)]
+struct CaptureTransport {
+    interface: TMCInterface,
+    frames: VecDeque<CapturedFrame>,
+
+    // the part of the current bulk-in transfer not read yet
+    transfer: VecDeque<u8>,
+
+    // the bTags of the last bulk-out transfer as captured and as sent now
+    tags: (u8, u8),
+    divergence: Option<String>,
+}
+
+impl CaptureTransport {
+    fn new(frames: Vec<CapturedFrame>) -> Self {
+        Self {
+            interface: TMCInterface {
+                interface_number: 0,
+                interface_protocol: 0,
+                bulk_out_address: Some(0x01),
+                bulk_in_address: Some(0x82),
+                interrupt_in_address: None,
+                control_in_max_packet_size: 64,
+                bulk_out_max_packet_size: 512,
+                bulk_in_max_packet_size: 512,
+            },
+            frames: frames.into(),
+            transfer: VecDeque::new(),
+            tags: (0, 0),
+            divergence: None,
+        }
+    }
+}
+
+/// Whether two bulk-out transfers are the same apart from their bTags
+fn same_transfer(sent: &[u8], captured: &[u8]) -> bool {
+    let untagged = |frame: &[u8]| {
+        let mut frame = frame.to_vec();
+        if frame.len() >= 3 {
+            frame[1] = 0;
+            frame[2] = 0;
+        }
+        frame
+    };
+    untagged(sent) == untagged(captured)
+}
+
+impl Transport for CaptureTransport {
+    fn interface(&self) -> &TMCInterface {
+        &self.interface
+    }
+
+    fn backend(&self) -> &'static str {
+        "capture"
+    }
+
+    fn claim_interface(&mut self) -> rusb::Result<()> {
+        Ok(())
+    }
+
+    fn release_interface(&mut self) -> rusb::Result<()> {
+        Ok(())
+    }
+
+    fn read_control(
+        &mut self,
+        _request_type: u8,
+        _request: u8,
+        _value: u16,
+        _index: u16,
+        buf: &mut [u8],
+        _timeout: Duration,
+    ) -> rusb::Result<usize> {
+        // success, with no capabilities or outstanding transfers
+        buf.fill(0);
+        if let Some(status) = buf.first_mut() {
+            *status = 0x01;
+        }
+        Ok(buf.len())
+    }
+
+    fn write_control(
+        &mut self,
+        _request_type: u8,
+        _request: u8,
+        _value: u16,
+        _index: u16,
+        buf: &[u8],
+        _timeout: Duration,
+    ) -> rusb::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn read_bulk(
+        &mut self,
+        endpoint: u8,
+        buf: &mut [u8],
+        _timeout: Duration,
+    ) -> rusb::Result<usize> {
+        if Some(endpoint) != self.interface.bulk_in_address {
+            return Err(rusb::Error::InvalidParam);
+        }
+
+        if self.transfer.is_empty() {
+            match self.frames.front() {
+                Some(frame) if frame.direction == Direction::Received => {
+                    let mut frame = frame.frame.clone();
+                    self.frames.pop_front();
+
+                    // a device which got the tag wrong should still get it wrong
+                    let (captured, sent) = self.tags;
+                    if frame.len() >= HEADER_SIZE && frame[1] == captured && frame[2] == !captured {
+                        frame[1] = sent;
+                        frame[2] = !sent;
+                    }
+                    self.transfer.extend(frame);
+                }
+                _ => return Err(rusb::Error::Timeout),
+            }
+        }
+
+        let n = self.transfer.len().min(buf.len());
+        for (dst, src) in buf.iter_mut().zip(self.transfer.drain(..n)) {
+            *dst = src;
+        }
+        Ok(n)
+    }
+
+    fn write_bulk(&mut self, endpoint: u8, buf: &[u8], _timeout: Duration) -> rusb::Result<usize> {
+        if Some(endpoint) != self.interface.bulk_out_address {
+            return Err(rusb::Error::InvalidParam);
+        }
+
+        match self.frames.front() {
+            Some(frame)
+                if frame.direction == Direction::Sent && same_transfer(buf, &frame.frame) =>
+            {
+                if let (Some(&captured), Some(&sent)) = (frame.frame.get(1), buf.get(1)) {
+                    self.tags = (captured, sent);
+                }
+                self.frames.pop_front();
+                self.transfer.clear();
+                Ok(buf.len())
+            }
+            // the handle has gone a different way from the recording
+            next => {
+                if self.divergence.is_none() {
+                    let captured = match next {
+                        Some(frame) if frame.direction == Direction::Sent => {
+                            format!("{:02x?} was captured", frame.frame)
+                        }
+                        Some(_) => "a response was captured".to_owned(),
+                        None => "the capture has ended".to_owned(),
+                    };
+                    self.divergence = Some(format!("sent {:02x?} where {}", buf, captured));
+                }
+                Err(rusb::Error::Pipe)
+            }
+        }
+    }
+
+    fn read_interrupt(
+        &mut self,
+        _endpoint: u8,
+        _buf: &mut [u8],
+        _timeout: Duration,
+    ) -> rusb::Result<usize> {
+        Err(rusb::Error::Timeout)
+    }
+
+    fn clear_halt(&mut self, _endpoint: u8) -> rusb::Result<()> {
+        Ok(())
+    }
+}
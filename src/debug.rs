@@ -0,0 +1,128 @@
+//! Formatting of bulk transfer frames for protocol debugging.
+//!
+//! With the `tracing` feature, every bulk frame a handle sends or receives is
+//! logged at trace level with the `tmc::frames` target, formatted by
+//! [describe_frame].  Everything a handle logs is in its `tmc` span, with
+//! `model`, `serial`, `bus` and `address` fields identifying the instrument, so
+//! logs from several instruments can be filtered per device.
+
+use crate::class::{MsgIdIn, MsgIdOut, HEADER_SIZE};
+use crate::transcript::Direction;
+use byteorder::{ByteOrder, LittleEndian};
+use std::convert::TryFrom;
+use std::fmt::Write;
+
+/// Bytes shown on each line of a hexdump
+const BYTES_PER_LINE: usize = 16;
+
+/// Format data as lines of offset, hex bytes and printable ASCII
+pub fn hexdump(data: &[u8]) -> String {
+    let mut out = String::new();
+
+    for (line, chunk) in data.chunks(BYTES_PER_LINE).enumerate() {
+        let _ = write!(out, "{:08x} ", line * BYTES_PER_LINE);
+
+        for i in 0..BYTES_PER_LINE {
+            if i == BYTES_PER_LINE / 2 {
+                out.push(' ');
+            }
+            match chunk.get(i) {
+                Some(b) => {
+                    let _ = write!(out, " {:02x}", b);
+                }
+                None => out.push_str("   "),
+            }
+        }
+
+        out.push_str("  |");
+        out.extend(chunk.iter().map(|&b| {
+            if b.is_ascii_graphic() || b == b' ' {
+                char::from(b)
+            } else {
+                '.'
+            }
+        }));
+        out.push_str("|\n");
+    }
+
+    out
+}
+
+/// Describe a bulk transfer frame: its header decoded field by field, then a
+/// hexdump of the whole frame.  Frames which are too short or have an unknown
+/// message ID are described as far as possible.
+pub fn describe_frame(direction: Direction, frame: &[u8]) -> String {
+    let mut out = String::new();
+
+    let endpoint = match direction {
+        Direction::Sent => "bulk-out",
+        Direction::Received => "bulk-in",
+    };
+    let _ = writeln!(out, "{} frame, {} bytes", endpoint, frame.len());
+
+    if frame.len() < HEADER_SIZE {
+        out.push_str("  (no complete header)\n");
+    } else {
+        describe_header(&mut out, direction, frame);
+    }
+
+    out.push_str(&hexdump(frame));
+    out
+}
+
+fn describe_header(out: &mut String, direction: Direction, frame: &[u8]) {
+    let msg_id = match direction {
+        Direction::Sent => MsgIdOut::try_from(frame[0]).map(|id| format!("{:?}", id)),
+        Direction::Received => MsgIdIn::try_from(frame[0]).map(|id| format!("{:?}", id)),
+    }
+    .unwrap_or_else(|_| "unknown".to_owned());
+
+    let b_tag = frame[1];
+    let b_tag_inverse = frame[2];
+    let transfer_size = LittleEndian::read_u32(&frame[4..8]);
+    let attributes = frame[8];
+
+    let _ = writeln!(out, "  MsgID        {} ({})", frame[0], msg_id);
+    let _ = writeln!(
+        out,
+        "  bTag         {} (inverse {:#04x}{})",
+        b_tag,
+        b_tag_inverse,
+        if b_tag_inverse == !b_tag {
+            ""
+        } else {
+            ", mismatch"
+        }
+    );
+    if frame[3] != 0 {
+        let _ = writeln!(out, "  reserved     {:#04x}", frame[3]);
+    }
+    let _ = writeln!(out, "  TransferSize {}", transfer_size);
+
+    match direction {
+        Direction::Sent => match MsgIdOut::try_from(frame[0]) {
+            Ok(MsgIdOut::DevDepMsgOut) => {
+                let _ = writeln!(out, "  EOM          {}", attributes & 0x01 != 0);
+            }
+            Ok(MsgIdOut::RequestDevDepMsgIn) => {
+                let _ = writeln!(
+                    out,
+                    "  TermCharEnabled {}, TermChar {:#04x}",
+                    attributes & 0x02 != 0,
+                    frame[9]
+                );
+            }
+            _ => {}
+        },
+        Direction::Received => {
+            if let Ok(MsgIdIn::DevDepMsgIn) = MsgIdIn::try_from(frame[0]) {
+                let _ = writeln!(
+                    out,
+                    "  EOM          {}, TermChar {}",
+                    attributes & 0x01 != 0,
+                    attributes & 0x02 != 0
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,27 @@
+//! Counters of protocol anomalies that were tolerated rather than treated as
+//! errors, so that misbehaving devices can be identified.
+
+/// Anomalies seen on an instrument's link since it was opened (or since the
+/// counters were last reset).
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct LinkDiagnostics {
+    /// Bulk-in transfers which ended without their alignment padding
+    pub missing_padding: u64,
+
+    /// Bulk-in transfers whose alignment padding contained nonzero bytes
+    pub nonzero_padding: u64,
+
+    /// Bulk-in transfers dropped because they repeated the previous transfer of
+    /// the message, as some firmware does instead of sending the next one
+    pub duplicate_transfers: u64,
+
+    /// Bulk transfers retried after the device stalled the endpoint
+    pub stalls_cleared: u64,
+
+    /// Times the bTag sequence wrapped around to a lower value
+    pub b_tag_wraps: u64,
+
+    /// Bulk-in transfers the device was still sending when the next one was
+    /// requested, after reading them was cut short, and which were aborted
+    pub pending_requests_aborted: u64,
+}
@@ -1,4 +1,7 @@
 pub use crate::class::ClassError;
+use crate::transport::Operation;
+use crate::Encoding;
+use core::time::Duration;
 use std::{io::ErrorKind, string::FromUtf8Error};
 
 use thiserror::Error;
@@ -12,6 +15,20 @@ pub enum TMCError {
         source: rusb::Error,
     },
 
+    /// An error occurred while looking for an instrument, before connecting to it
+    #[error("Discovery Error: {source}")]
+    Discovery {
+        #[from]
+        source: DiscoveryError,
+    },
+
+    /// An error occurred while connecting to an instrument which was found
+    #[error("Connect Error: {source}")]
+    Connect {
+        #[from]
+        source: ConnectError,
+    },
+
     /// An error occurred in the handling of a USB TMC class operation
     #[error("USBTMC Error: {source}")]
     Class {
@@ -25,12 +42,274 @@ pub enum TMCError {
         #[from]
         source: FromUtf8Error,
     },
+
+    /// The application requested a string response, but the data from the device
+    /// was not valid in the handle's encoding
+    #[error("invalid {encoding:?} data at byte {position}")]
+    Undecodable { encoding: Encoding, position: usize },
+
+    /// A command contains a character which can't be sent in the handle's encoding
+    #[error("{character:?} at byte {position} can't be sent as {encoding:?}")]
+    Unencodable {
+        encoding: Encoding,
+        character: char,
+        position: usize,
+    },
+
+    /// The application tried to set an attribute which doesn't exist or is read-only,
+    /// or used a value of the wrong type or range
+    #[error("invalid attribute or value: {0}")]
+    InvalidAttribute(String),
+
+    /// A combined query returned a different number of responses than there were
+    /// queries
+    #[error("expected {expected} responses, received {received}")]
+    ResponseCountMismatch { expected: usize, received: usize },
+
+    /// With write verification enabled, the instrument's error queue reported an
+    /// error after a write
+    #[error("instrument error {code}: {message}")]
+    InstrumentError { code: i32, message: String },
+
+    /// With write verification enabled, the instrument's status byte indicated an
+    /// error after a write
+    #[error("instrument status byte {status_byte:#04x} indicates an error")]
+    StatusByteError { status_byte: u8 },
+
+    /// A read timed out after part of the response message had arrived, so the
+    /// device stopped partway through rather than not answering at all.  The
+    /// rest of the message may still come; aborting the bulk-in transfer
+    /// discards it, whereas a device which sent nothing may need clearing.
+    #[error("response stopped after {received} bytes")]
+    ResponseInterrupted { received: usize },
+
+    /// The instrument's response to a query was not in the expected form
+    #[error("unexpected response: {0:?}")]
+    InvalidResponse(String),
+
+    /// A response which should have started with an IEEE 488.2 arbitrary block
+    /// didn't, or was shorter than the block's length
+    #[error("malformed arbitrary block in response")]
+    MalformedBlock,
+
+    /// The handle's command policy refused to send a message containing `command`
+    #[error("command {command:?} rejected: {reason}")]
+    CommandRejected { command: String, reason: String },
+
+    /// [write_synchronized](crate::TMCHandle::write_synchronized) was given a
+    /// query, whose response it would have no way to return
+    #[error("{command:?} is a query and can't be synchronized")]
+    QueryNotSynchronized { command: String },
+
+    /// A message started with `write_partial_raw` must be finished with
+    /// `flush_message` before anything else is written
+    #[error("a partly written message has not been flushed")]
+    MessageInProgress,
+
+    /// An earlier error poisoned the handle, and it must be recovered before it
+    /// is used again
+    #[error("handle poisoned by an earlier error: {0}")]
+    Poisoned(Box<TMCError>),
+
+    /// The handle was opened in [shared mode](crate::OpenOptions::shared), where
+    /// only the status of the instrument can be monitored
+    #[error("{operation} is not possible in shared mode")]
+    SharedMode { operation: String },
+
+    /// The interface has no bulk endpoint in the direction needed, because the
+    /// device only sends or only receives messages
+    #[error("the interface has no {operation:?} endpoint")]
+    MissingEndpoint { operation: Operation },
+
+    /// A response being [spooled](crate::TMCHandle::read_raw_spooled) couldn't
+    /// be written to its temporary file, and the rest of it was discarded
+    #[error("spilling the response to disk failed: {message}")]
+    Spill {
+        kind: std::io::ErrorKind,
+        message: String,
+    },
+
+    /// Every attempt at a [robust_ask](crate::TMCHandle::robust_ask) timed out
+    /// (or failed otherwise, after timing out the first time), with the
+    /// failure of each attempt in order
+    #[error("{command:?} failed on every attempt: {}", join_failures(.failures))]
+    AttemptsFailed {
+        command: String,
+        failures: Vec<TMCError>,
+    },
+
+    /// With the watchdog enabled, a transfer stayed blocked for longer than its
+    /// timeout plus the watchdog's grace period
+    #[error("{operation:?} transfer still blocked after {elapsed:?}")]
+    WatchdogTriggered {
+        operation: Operation,
+        elapsed: Duration,
+    },
 }
 
 pub type TMCResult<T> = Result<T, TMCError>;
 
+fn join_failures(failures: &[TMCError]) -> String {
+    failures
+        .iter()
+        .enumerate()
+        .map(|(attempt, failure)| format!("attempt {}: {}", attempt + 1, failure))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Why an instrument couldn't be found, or couldn't be described well enough to
+/// tell whether it was the one wanted
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum DiscoveryError {
+    /// The attached devices couldn't be listed
+    #[error("listing USB devices failed: {source}")]
+    Enumeration { source: rusb::Error },
+
+    /// A device's descriptors or strings couldn't be read
+    #[error("reading descriptors failed: {source}")]
+    Descriptor { source: rusb::Error },
+
+    /// This process isn't allowed to open a device to read its strings, such as
+    /// its serial number
+    #[error("permission denied reading device strings")]
+    PermissionDenied,
+
+    /// A resource string could not be parsed
+    #[error("invalid resource string: {0}")]
+    InvalidResource(String),
+
+    /// No attached instrument matches the requested IDs or resource string
+    #[error("instrument not found: {0}")]
+    NotFound(String),
+}
+
+impl DiscoveryError {
+    /// Classify a USB error from reading a device's descriptors or strings
+    pub(crate) fn from_descriptor(source: rusb::Error) -> Self {
+        match source {
+            rusb::Error::Access => DiscoveryError::PermissionDenied,
+            source => DiscoveryError::Descriptor { source },
+        }
+    }
+}
+
+/// Why an instrument which was found couldn't be connected to
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ConnectError {
+    /// This process isn't allowed to open the device or claim its TMC interface
+    #[error("permission denied opening the device")]
+    PermissionDenied,
+
+    /// The TMC interface is claimed by a kernel driver or another process, and
+    /// didn't become free within the claim retry window.  `holder` describes what
+    /// is holding it, if the platform can tell.
+    #[error(
+        "interface {interface} is in use{}",
+        holder.as_ref().map(|holder| format!(" by {}", holder)).unwrap_or_default()
+    )]
+    InterfaceBusy {
+        interface: u8,
+        holder: Option<String>,
+    },
+
+    /// Opening the device, detaching kernel drivers, selecting its configuration
+    /// or claiming its interface failed
+    #[error("opening the device failed: {source}")]
+    Open { source: rusb::Error },
+
+    /// The device was opened, but didn't respond properly to the clear (or the
+    /// check for pending data) done while connecting
+    #[error("connection handshake failed: {0}")]
+    Handshake(Box<TMCError>),
+}
+
+impl ConnectError {
+    /// Classify a USB error from opening the device or claiming its interface
+    pub(crate) fn from_open(source: rusb::Error) -> Self {
+        match source {
+            rusb::Error::Access => ConnectError::PermissionDenied,
+            source => ConnectError::Open { source },
+        }
+    }
+}
+
+impl TMCError {
+    /// Whether this error means the device has gone away (unplugged, powered off,
+    /// or lost to a bus reset), so that the handle will need to be reopened.  An
+    /// I/O error on its own may be transient, so a handle only reports one as a
+    /// disconnect if the device then fails to answer too.
+    pub fn is_disconnect(&self) -> bool {
+        matches!(
+            self,
+            TMCError::Rusb {
+                source: rusb::Error::NoDevice,
+            } | TMCError::Connect {
+                source: ConnectError::Open {
+                    source: rusb::Error::NoDevice,
+                },
+            }
+        )
+    }
+
+    /// Whether this error means a transfer was malformed or cut short, so that
+    /// the host and device may no longer agree on where a message starts
+    pub fn is_protocol_error(&self) -> bool {
+        match self {
+            TMCError::Class { source } => !matches!(
+                source,
+                ClassError::UnsupportedFeature
+                    | ClassError::InvalidTermChar
+                    | ClassError::InvalidTransferSize(_)
+            ),
+            _ => false,
+        }
+    }
+
+    /// Whether this error means a transfer didn't complete in time: a USB
+    /// timeout, a response which stopped partway, or the watchdog giving up on
+    /// a blocked transfer
+    pub fn is_timeout(&self) -> bool {
+        matches!(
+            self,
+            TMCError::Rusb {
+                source: rusb::Error::Timeout,
+            } | TMCError::ResponseInterrupted { .. }
+                | TMCError::WatchdogTriggered { .. }
+        )
+    }
+
+    /// Whether this error is a failed USB transfer, such as a timeout or stall
+    pub fn is_transfer_error(&self) -> bool {
+        matches!(
+            self,
+            TMCError::Rusb { .. }
+                | TMCError::ResponseInterrupted { .. }
+                | TMCError::WatchdogTriggered { .. }
+        )
+    }
+}
+
+/// Timeouts become [TimedOut](std::io::ErrorKind::TimedOut), disconnects
+/// [NotConnected](std::io::ErrorKind::NotConnected), including one which
+/// poisoned the handle, and missing endpoints
+/// [Unsupported](std::io::ErrorKind::Unsupported), and spilling failures keep the
+/// kind of the error writing the file, so that code handling [std::io::Error] by kind treats
+/// them correctly.  The original error is kept as the inner error.
 impl From<TMCError> for std::io::Error {
     fn from(value: TMCError) -> Self {
-        std::io::Error::new(ErrorKind::Other, value.to_string())
+        let kind = match &value {
+            // retrying won't help until the handle is recovered, whatever
+            // poisoned it, unless the device has gone away altogether
+            TMCError::Poisoned(cause) if cause.is_disconnect() => ErrorKind::NotConnected,
+            TMCError::Poisoned(_) => ErrorKind::Other,
+            error if error.is_disconnect() => ErrorKind::NotConnected,
+            error if error.is_timeout() => ErrorKind::TimedOut,
+            TMCError::MissingEndpoint { .. } => ErrorKind::Unsupported,
+            TMCError::Spill { kind, .. } => *kind,
+            _ => ErrorKind::Other,
+        };
+
+        std::io::Error::new(kind, value)
     }
 }
@@ -0,0 +1,196 @@
+//! Fetching an instrument's error queue or event log as structured records, for
+//! attaching to test reports.
+//!
+//! Besides the SCPI error queue, many instruments keep a log of past events which
+//! is read with a vendor query, either one record at a time (as with
+//! `SYST:EVEN:NEXT?`) or all at once.  Records are expected in the error queue
+//! form `<code>,"<message>"`, but lines without a code are kept too.  A date and
+//! time found in a record's message, in the forms `2024-06-21 12:36:05`,
+//! `2024/06/21 12:36:05` or `2024-06-21T12:36:05`, is taken as its timestamp.
+
+use crate::block::block_range;
+use crate::clock::CivilTime;
+use crate::handle::parse_error_entry;
+use crate::mass_memory::split_outside_quotes;
+use crate::transport::Transport;
+use crate::{TMCHandle, TMCResult};
+
+/// Records read one query at a time stop after this many, in case the log never
+/// reports that it is empty
+const MAX_SEQUENTIAL_RECORDS: usize = 1024;
+
+/// Where to read records from
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum LogSource {
+    /// The SCPI error queue, emptied one entry at a time with `SYST:ERR?`
+    ErrorQueue,
+
+    /// The SCPI error queue, emptied in one response with `SYST:ERR:ALL?`
+    ErrorQueueAll,
+
+    /// A query answered with one record each time, until it returns an empty
+    /// response or a record with code 0
+    Sequential(String),
+
+    /// A query answered with every record, one per line or as comma-separated
+    /// `<code>,"<message>"` pairs, optionally in an arbitrary block
+    All(String),
+}
+
+/// One entry of an error queue or event log
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LogRecord {
+    /// The error or event number, if the record has one
+    pub code: Option<i32>,
+    pub message: String,
+
+    /// When the event happened, by the instrument's clock (usually its local
+    /// time), if the record says
+    pub timestamp: Option<CivilTime>,
+
+    /// The record as the instrument sent it
+    pub raw: String,
+}
+
+impl LogRecord {
+    /// Parse a record, which is usually of the form `<code>,"<message>"`
+    pub fn parse(raw: &str) -> Self {
+        let raw = raw.trim();
+        let (code, message) = match parse_error_entry(raw) {
+            Some((code, message)) => (Some(code), message),
+            None => (None, raw.trim_matches('"').to_owned()),
+        };
+
+        Self {
+            code,
+            timestamp: find_timestamp(&message),
+            message,
+            raw: raw.to_owned(),
+        }
+    }
+
+    /// Whether this is the "no error" entry which marks an empty queue
+    pub fn is_empty_marker(&self) -> bool {
+        self.code == Some(0)
+    }
+}
+
+/// Parse a response holding several records: one per line, or as
+/// comma-separated `<code>,"<message>"` pairs on a single line.  "No error"
+/// entries are left out.
+pub fn parse_records(response: &str) -> Vec<LogRecord> {
+    let lines: Vec<&str> = response
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect();
+
+    let raw_records: Vec<String> = match lines.as_slice() {
+        [line] => {
+            let fields = split_outside_quotes(line);
+            if fields.len() > 2 && fields.len().is_multiple_of(2) {
+                fields
+                    .chunks(2)
+                    .map(|pair| format!("{},{}", pair[0].trim(), pair[1].trim()))
+                    .collect()
+            } else {
+                vec![line.to_string()]
+            }
+        }
+        lines => lines.iter().map(|line| line.to_string()).collect(),
+    };
+
+    raw_records
+        .iter()
+        .map(|raw| LogRecord::parse(raw))
+        .filter(|record| !record.is_empty_marker())
+        .collect()
+}
+
+/// Find a date and time in `text`
+fn find_timestamp(text: &str) -> Option<CivilTime> {
+    let bytes = text.as_bytes();
+    (0..bytes.len()).find_map(|start| parse_timestamp(&bytes[start..]))
+}
+
+/// Parse `YYYY-MM-DD HH:MM:SS` (with `/` or `-` between the date's fields and a
+/// space or `T` before the time) at the start of `text`
+fn parse_timestamp(text: &[u8]) -> Option<CivilTime> {
+    let text = text.get(..19)?;
+    let number = |range: std::ops::Range<usize>| -> Option<u32> {
+        let digits = &text[range];
+        if !digits.iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+        std::str::from_utf8(digits).ok()?.parse().ok()
+    };
+
+    let date_separator = text[4];
+    if !matches!(date_separator, b'-' | b'/')
+        || text[7] != date_separator
+        || !matches!(text[10], b' ' | b'T')
+        || text[13] != b':'
+        || text[16] != b':'
+    {
+        return None;
+    }
+
+    let timestamp = CivilTime {
+        year: number(0..4)? as i64,
+        month: number(5..7)? as u8,
+        day: number(8..10)? as u8,
+        hour: number(11..13)? as u8,
+        minute: number(14..16)? as u8,
+        second: number(17..19)? as u8,
+    };
+
+    let valid = (1..=12).contains(&timestamp.month)
+        && (1..=31).contains(&timestamp.day)
+        && timestamp.hour < 24
+        && timestamp.minute < 60
+        && timestamp.second <= 60;
+    Some(timestamp).filter(|_| valid)
+}
+
+impl<T: Transport> TMCHandle<T> {
+    /// Read the records from an error queue or event log.  Reading the error
+    /// queue empties it, as may reading a vendor log.
+    pub fn read_event_log(&mut self, source: &LogSource) -> TMCResult<Vec<LogRecord>> {
+        match source {
+            LogSource::ErrorQueue => self.read_sequential_records("SYST:ERR?"),
+            LogSource::ErrorQueueAll => self.read_all_records("SYST:ERR:ALL?"),
+            LogSource::Sequential(query) => self.read_sequential_records(query),
+            LogSource::All(query) => self.read_all_records(query),
+        }
+    }
+
+    fn read_sequential_records(&mut self, query: &str) -> TMCResult<Vec<LogRecord>> {
+        let mut records = Vec::new();
+
+        for _ in 0..MAX_SEQUENTIAL_RECORDS {
+            let response = self.ask(query)?;
+            if response.trim().is_empty() {
+                break;
+            }
+
+            let record = LogRecord::parse(&response);
+            if record.is_empty_marker() {
+                break;
+            }
+            records.push(record);
+        }
+
+        Ok(records)
+    }
+
+    fn read_all_records(&mut self, query: &str) -> TMCResult<Vec<LogRecord>> {
+        let data = self.encode_command(query)?;
+        let response = self.ask_raw(&data)?;
+        let response = match response.first() {
+            Some(b'#') => &response[block_range(&response)?],
+            _ => &response[..],
+        };
+
+        Ok(parse_records(&String::from_utf8_lossy(response)))
+    }
+}
@@ -0,0 +1,180 @@
+//! A thread handling libusb events, which hotplug notifications (and libusb's
+//! asynchronous transfers) need to make progress.  The synchronous transfers
+//! a handle makes handle their own events, so this is only needed for those.
+//!
+//! [EventLoop::start] runs a loop for a context until it is stopped or dropped.
+//! [EventLoop::global] shares one loop for libusb's global context, started
+//! when first needed and stopped when the last user drops it.
+
+use crate::{Instrument, InstrumentKey, TMCError, TMCResult};
+use rusb::{Device, GlobalContext, Hotplug, HotplugBuilder, Registration, UsbContext};
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How long the event thread waits for events before checking whether it has
+/// been stopped, in case the interruption sent when stopping it is missed
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+static GLOBAL: Mutex<Weak<EventLoop<GlobalContext>>> = Mutex::new(Weak::new());
+
+/// A thread handling events for a libusb context.  Dropping it stops the thread
+/// and waits for it to finish.
+#[derive(Debug)]
+pub struct EventLoop<Ctx: UsbContext + 'static> {
+    context: Ctx,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<rusb::Result<()>>>,
+}
+
+impl<Ctx: UsbContext + 'static> EventLoop<Ctx> {
+    /// Start handling events for `context` on a new thread, failing if the
+    /// thread can't be spawned
+    pub fn start(context: Ctx) -> io::Result<Self> {
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let thread = {
+            let context = context.clone();
+            let stop = stop.clone();
+            thread::Builder::new()
+                .name("tmc-usb-events".to_owned())
+                .spawn(move || {
+                    while !stop.load(Ordering::Acquire) {
+                        match context.handle_events(Some(POLL_INTERVAL)) {
+                            Ok(()) | Err(rusb::Error::Interrupted) => {}
+                            Err(error) => return Err(error),
+                        }
+                    }
+                    Ok(())
+                })?
+        };
+
+        Ok(Self {
+            context,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn context(&self) -> &Ctx {
+        &self.context
+    }
+
+    /// Whether the thread is still handling events.  It only stops by itself if
+    /// handling events fails, and [stop](Self::stop) reports why.
+    pub fn is_running(&self) -> bool {
+        self.thread
+            .as_ref()
+            .is_some_and(|thread| !thread.is_finished())
+    }
+
+    /// Stop the thread, waiting for it to finish, and report the error it
+    /// stopped with if it stopped by itself
+    pub fn stop(mut self) -> TMCResult<()> {
+        self.stop_thread()
+    }
+
+    /// Report instruments as they are attached and detached, starting with those
+    /// already attached, until the returned registration is dropped.
+    ///
+    /// `callback` is called from the event thread, so it should return quickly,
+    /// and must not make transfers to the instruments it is told about (open
+    /// them from another thread instead).  Fails with
+    /// [NotSupported](rusb::Error::NotSupported) on platforms without hotplug
+    /// notifications, where [scan_changes](crate::scan_changes) can be polled
+    /// instead.
+    pub fn watch<F>(&self, callback: F) -> TMCResult<Registration<Ctx>>
+    where
+        F: FnMut(HotplugEvent<Ctx>) + Send + 'static,
+    {
+        if !rusb::has_hotplug() {
+            return Err(rusb::Error::NotSupported.into());
+        }
+
+        let registration = HotplugBuilder::new()
+            .enumerate(true)
+            .register(&self.context, Box::new(Watcher { callback }))?;
+        Ok(registration)
+    }
+
+    fn stop_thread(&mut self) -> TMCResult<()> {
+        match self.join() {
+            Some(Ok(result)) => result.map_err(TMCError::from),
+            Some(Err(panic)) => std::panic::resume_unwind(panic),
+            None => Ok(()),
+        }
+    }
+
+    /// Tell the thread to stop and wait for it, unless it has already been
+    /// joined
+    fn join(&mut self) -> Option<thread::Result<rusb::Result<()>>> {
+        let thread = self.thread.take()?;
+
+        self.stop.store(true, Ordering::Release);
+        self.context.interrupt_handle_events();
+        Some(thread.join())
+    }
+}
+
+impl EventLoop<GlobalContext> {
+    /// The event loop for libusb's global context, shared by everything using it
+    /// through this function.  It is started by the first call and stopped
+    /// once every clone of what it returns has been dropped; a later call
+    /// starts it again.  Fails if the thread can't be spawned.
+    pub fn global() -> io::Result<Arc<Self>> {
+        let mut global = GLOBAL.lock().unwrap_or_else(PoisonError::into_inner);
+
+        match global.upgrade() {
+            Some(event_loop) => Ok(event_loop),
+            None => {
+                let event_loop = Arc::new(Self::start(GlobalContext::default())?);
+                *global = Arc::downgrade(&event_loop);
+                Ok(event_loop)
+            }
+        }
+    }
+}
+
+impl<Ctx: UsbContext + 'static> Drop for EventLoop<Ctx> {
+    fn drop(&mut self) {
+        // a panic on the thread can't be passed on while dropping
+        let _ = self.join();
+    }
+}
+
+/// An instrument being attached or detached, as reported by
+/// [EventLoop::watch]
+#[derive(Debug)]
+pub enum HotplugEvent<Ctx: UsbContext> {
+    Arrived(Instrument<Ctx>),
+    Left(InstrumentKey),
+}
+
+/// Passes hotplug notifications for USBTMC devices to a callback
+struct Watcher<F> {
+    callback: F,
+}
+
+impl<Ctx, F> Hotplug<Ctx> for Watcher<F>
+where
+    Ctx: UsbContext,
+    F: FnMut(HotplugEvent<Ctx>) + Send,
+{
+    fn device_arrived(&mut self, device: Device<Ctx>) {
+        // devices whose descriptors can't be read aren't instruments as far as
+        // anyone else can tell either
+        if let Ok(Some(instrument)) = Instrument::new(device) {
+            (self.callback)(HotplugEvent::Arrived(instrument));
+        }
+    }
+
+    fn device_left(&mut self, device: Device<Ctx>) {
+        // the descriptors are cached, so can still be read once the device has
+        // gone; only USBTMC devices are reported
+        if let Ok(Some(instrument)) = Instrument::new(device) {
+            (self.callback)(HotplugEvent::Left(instrument.key()));
+        }
+    }
+}
@@ -0,0 +1,113 @@
+//! Apache Arrow output.  Samples are collected into record batches of a fixed
+//! number of rows, each of which is handed to a callback as soon as it is full
+//! (for example to write it to an IPC stream or Parquet file).
+//!
+//! The batches have the columns `timestamp` (microseconds, UTC), `query`, and
+//! nullable `value` and `error` columns, exactly one of which is set per row.
+
+use crate::export::{since_epoch, SampleSink};
+use crate::poller::Sample;
+use arrow_array::{ArrayRef, RecordBatch, StringArray, TimestampMicrosecondArray};
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef, TimeUnit};
+use std::mem;
+use std::sync::Arc;
+
+pub struct ArrowSink<F>
+where
+    F: FnMut(RecordBatch) -> Result<(), ArrowError>,
+{
+    schema: SchemaRef,
+    batch_size: usize,
+    on_batch: F,
+
+    timestamps: Vec<i64>,
+    queries: Vec<String>,
+    values: Vec<Option<String>>,
+    errors: Vec<Option<String>>,
+}
+
+impl<F> ArrowSink<F>
+where
+    F: FnMut(RecordBatch) -> Result<(), ArrowError>,
+{
+    /// Create a sink which calls `on_batch` with every `batch_size` samples
+    pub fn new(batch_size: usize, on_batch: F) -> Self {
+        Self {
+            schema: Arc::new(Self::schema()),
+            batch_size: batch_size.max(1),
+            on_batch,
+
+            timestamps: Vec::with_capacity(batch_size),
+            queries: Vec::with_capacity(batch_size),
+            values: Vec::with_capacity(batch_size),
+            errors: Vec::with_capacity(batch_size),
+        }
+    }
+
+    /// The schema of the record batches produced
+    pub fn schema() -> Schema {
+        Schema::new(vec![
+            Field::new(
+                "timestamp",
+                DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+                false,
+            ),
+            Field::new("query", DataType::Utf8, false),
+            Field::new("value", DataType::Utf8, true),
+            Field::new("error", DataType::Utf8, true),
+        ])
+    }
+
+    fn emit_batch(&mut self) -> Result<(), ArrowError> {
+        if self.timestamps.is_empty() {
+            return Ok(());
+        }
+
+        let timestamps =
+            TimestampMicrosecondArray::from(mem::take(&mut self.timestamps)).with_timezone("UTC");
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(timestamps),
+            Arc::new(StringArray::from(mem::take(&mut self.queries))),
+            Arc::new(StringArray::from(mem::take(&mut self.values))),
+            Arc::new(StringArray::from(mem::take(&mut self.errors))),
+        ];
+
+        let batch = RecordBatch::try_new(self.schema.clone(), columns)?;
+        (self.on_batch)(batch)
+    }
+}
+
+impl<F> SampleSink for ArrowSink<F>
+where
+    F: FnMut(RecordBatch) -> Result<(), ArrowError>,
+{
+    type Error = ArrowError;
+
+    fn write_sample(&mut self, sample: &Sample) -> Result<(), Self::Error> {
+        self.timestamps
+            .push(since_epoch(sample.timestamp).as_micros() as i64);
+        self.queries.push(sample.query.clone());
+
+        match &sample.result {
+            Ok(value) => {
+                self.values.push(Some(value.trim().to_owned()));
+                self.errors.push(None);
+            }
+            Err(err) => {
+                self.values.push(None);
+                self.errors.push(Some(err.to_string()));
+            }
+        }
+
+        if self.timestamps.len() >= self.batch_size {
+            self.emit_batch()?;
+        }
+
+        Ok(())
+    }
+
+    /// Emit the samples collected so far as a (short) batch
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.emit_batch()
+    }
+}
@@ -0,0 +1,59 @@
+//! CSV output, with one row per sample: `timestamp,query,value,error`.  The
+//! timestamp is in seconds since the Unix epoch, and exactly one of `value` and
+//! `error` is filled in.
+
+use crate::export::{since_epoch, SampleSink};
+use crate::poller::Sample;
+use std::io;
+
+pub struct CsvSink<W: io::Write> {
+    writer: csv::Writer<W>,
+    header_written: bool,
+}
+
+impl<W: io::Write> CsvSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: csv::Writer::from_writer(writer),
+            header_written: false,
+        }
+    }
+
+    /// Flush and get back the underlying writer
+    pub fn into_inner(self) -> io::Result<W> {
+        self.writer
+            .into_inner()
+            .map_err(|err| io::Error::other(err.to_string()))
+    }
+}
+
+impl<W: io::Write> SampleSink for CsvSink<W> {
+    type Error = csv::Error;
+
+    fn write_sample(&mut self, sample: &Sample) -> Result<(), Self::Error> {
+        if !self.header_written {
+            self.writer
+                .write_record(["timestamp", "query", "value", "error"])?;
+            self.header_written = true;
+        }
+
+        let since_epoch = since_epoch(sample.timestamp);
+        let timestamp = format!(
+            "{}.{:06}",
+            since_epoch.as_secs(),
+            since_epoch.subsec_micros()
+        );
+
+        let (value, error) = match &sample.result {
+            Ok(value) => (value.trim().to_owned(), String::new()),
+            Err(err) => (String::new(), err.to_string()),
+        };
+
+        self.writer
+            .write_record([timestamp.as_str(), &sample.query, &value, &error])
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(self.writer.flush()?)
+    }
+}
@@ -0,0 +1,42 @@
+//! Sinks which write [Sample]s from a [Poller](crate::poller::Poller)
+//! incrementally, so long logging sessions don't need to keep every sample in
+//! memory.  Each output format is enabled by the cargo feature of the same name.
+
+use crate::poller::Sample;
+#[cfg(any(feature = "arrow", feature = "csv"))]
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "arrow")]
+pub mod arrow;
+#[cfg(feature = "csv")]
+pub mod csv;
+
+/// Destination for polled samples
+pub trait SampleSink {
+    type Error;
+
+    fn write_sample(&mut self, sample: &Sample) -> Result<(), Self::Error>;
+
+    /// Write out anything buffered so far
+    fn flush(&mut self) -> Result<(), Self::Error>;
+}
+
+/// Write every sample received until the poller stops, then flush the sink.
+/// `samples` is usually a `&Receiver<Sample>` or `&BoundedReceiver<Sample>`.
+pub fn write_samples<I, S>(samples: I, sink: &mut S) -> Result<(), S::Error>
+where
+    I: IntoIterator<Item = Sample>,
+    S: SampleSink,
+{
+    for sample in samples {
+        sink.write_sample(&sample)?;
+    }
+
+    sink.flush()
+}
+
+/// Time since the Unix epoch, or zero for (unlikely) timestamps before it
+#[cfg(any(feature = "arrow", feature = "csv"))]
+fn since_epoch(timestamp: SystemTime) -> Duration {
+    timestamp.duration_since(UNIX_EPOCH).unwrap_or_default()
+}
@@ -0,0 +1,112 @@
+//! Reading a response one bulk-in transfer at a time, exactly as the device
+//! sends it, for seeing how a device splits its messages into transfers.
+//!
+//! Unlike the other reads, nothing is checked or tolerated beyond what is needed
+//! to find the end of each transfer: repeated transfers, missing or nonzero
+//! padding and oversized responses are all passed on as they arrive.
+
+use crate::class::{DevDepMsgInHeader, HEADER_SIZE};
+use crate::transport::Transport;
+use crate::{TMCHandle, TMCResult};
+
+/// One bulk-in transfer of a response
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RawFrame {
+    pub header: DevDepMsgInHeader,
+
+    /// The whole transfer: header, payload and any alignment padding
+    pub transfer: Vec<u8>,
+}
+
+impl RawFrame {
+    /// The message data carried by the transfer
+    pub fn payload(&self) -> &[u8] {
+        &self.transfer[HEADER_SIZE..self.payload_end()]
+    }
+
+    /// Whatever followed the payload, which should be up to 3 zero bytes
+    /// aligning the transfer to 4 bytes
+    pub fn padding(&self) -> &[u8] {
+        &self.transfer[self.payload_end()..]
+    }
+
+    pub fn is_eom(&self) -> bool {
+        self.header.is_eom()
+    }
+
+    fn payload_end(&self) -> usize {
+        HEADER_SIZE
+            .saturating_add(self.header.transfer_size as usize)
+            .min(self.transfer.len())
+    }
+}
+
+/// The transfers of one response message, from
+/// [read_raw_frames](TMCHandle::read_raw_frames).  Each call to `next` requests
+/// and reads one transfer, and iteration ends after the transfer marking the end
+/// of the message, or after an error.  Dropping it partway through the message
+/// aborts the bulk-in transfer, discarding the rest.
+#[derive(Debug)]
+pub struct RawFrames<'a, T: Transport> {
+    handle: &'a mut TMCHandle<T>,
+    transfer_size: u32,
+    in_message: bool,
+    done: bool,
+}
+
+impl<T: Transport> Iterator for RawFrames<'_, T> {
+    type Item = TMCResult<RawFrame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let frame = self
+            .handle
+            .read_raw_transfer(self.transfer_size)
+            .and_then(|transfer| {
+                let header = DevDepMsgInHeader::unpack(&transfer)?;
+                Ok(RawFrame { header, transfer })
+            });
+
+        match &frame {
+            Ok(frame) => {
+                self.in_message = !frame.is_eom();
+                self.done = frame.is_eom();
+            }
+            Err(_) => {
+                self.in_message = false;
+                self.done = true;
+            }
+        }
+
+        Some(frame)
+    }
+}
+
+impl<T: Transport> Drop for RawFrames<'_, T> {
+    fn drop(&mut self) {
+        if self.in_message {
+            let _ = self.handle.discard_message(self.transfer_size);
+        }
+    }
+}
+
+impl<T: Transport> TMCHandle<T> {
+    /// Read a response message transfer by transfer, asking for up to
+    /// `transfer_size` bytes (or the maximum transfer size) in each
+    pub fn read_raw_frames(&mut self, transfer_size: Option<u32>) -> RawFrames<'_, T> {
+        let transfer_size = match transfer_size {
+            Some(size) if size < self.get_max_transfer_size() => size,
+            _ => self.get_max_transfer_size(),
+        };
+
+        RawFrames {
+            handle: self,
+            transfer_size,
+            in_message: false,
+            done: false,
+        }
+    }
+}
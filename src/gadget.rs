@@ -0,0 +1,366 @@
+//! An emulated USBTMC instrument presented to a USB host through the Linux USB
+//! gadget framework, for end-to-end tests of applications (and of this crate)
+//! through a real USB stack.
+//!
+//! The emulator serves a FunctionFS instance, which must already be mounted and
+//! bound to a USB device controller as part of a gadget set up with configfs.
+//! Requests from the host are passed to a [SimTransport], whose responses are
+//! sent back: the simulation behaves just as it does in process, but the host
+//! sees a real device.  The `tmc-emulator` binary runs an emulator for a
+//! simulation script.
+
+use crate::class::TMCInterface;
+use crate::sim::SimTransport;
+use crate::transport::Transport;
+use byteorder::{ByteOrder, LittleEndian};
+use core::time::Duration;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, sleep, JoinHandle};
+
+const DESCRIPTORS_MAGIC_V2: u32 = 3;
+const STRINGS_MAGIC: u32 = 2;
+
+const HAS_FS_DESC: u32 = 1;
+const HAS_HS_DESC: u32 = 2;
+// endpoint files are named after, and setup requests carry, the addresses in
+// the descriptors rather than the ones the controller assigns
+const VIRTUAL_ADDR: u32 = 16;
+
+const EVENT_SIZE: usize = 12;
+const EVENT_ENABLE: u8 = 2;
+const EVENT_SETUP: u8 = 4;
+
+const FULL_SPEED_PACKET: u16 = 64;
+const HIGH_SPEED_PACKET: u16 = 512;
+
+/// Read size for the bulk-out endpoint.  Reads end at a short packet or when
+/// the buffer is full, so reading one full speed packet at a time never waits
+/// on the next transfer to complete this one.
+const BULK_OUT_READ: usize = FULL_SPEED_PACKET as usize;
+
+/// Most data taken from the simulation per write to the bulk-in endpoint; a
+/// whole number of packets, so that a longer transfer continues seamlessly
+const BULK_IN_CHUNK: usize = 64 * 1024;
+
+/// Pause between polls of the simulation for data to send the host
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Pause before retrying an endpoint which failed, such as while the host has
+/// the function disabled
+const RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The USB interface and endpoint descriptors for `interface`, at full and high
+/// speed, in the FunctionFS format
+pub fn descriptors(interface: &TMCInterface) -> Vec<u8> {
+    const BULK: u8 = 0x02;
+    const INTERRUPT: u8 = 0x03;
+
+    let endpoints: Vec<_> = vec![
+        (interface.bulk_out_address, BULK),
+        (interface.bulk_in_address, BULK),
+        (interface.interrupt_in_address, INTERRUPT),
+    ]
+    .into_iter()
+    .filter_map(|(address, attributes)| Some((address?, attributes)))
+    .collect();
+
+    let speed_descriptors = |packet_size: u16, interval: u8| {
+        let mut descriptors = vec![
+            9,
+            0x04, // interface
+            interface.interface_number,
+            0,
+            endpoints.len() as u8,
+            0xfe, // application specific
+            0x03, // test and measurement
+            interface.interface_protocol,
+            1,
+        ];
+        for &(address, attributes) in endpoints.iter() {
+            let mut endpoint = [7, 0x05, address, attributes, 0, 0, 0];
+            if attributes == INTERRUPT {
+                // notifications are two bytes
+                LittleEndian::write_u16(&mut endpoint[4..6], 2);
+                endpoint[6] = interval;
+            } else {
+                LittleEndian::write_u16(&mut endpoint[4..6], packet_size);
+            }
+            descriptors.extend_from_slice(&endpoint);
+        }
+        descriptors
+    };
+    let full_speed = speed_descriptors(FULL_SPEED_PACKET, 1);
+    let high_speed = speed_descriptors(HIGH_SPEED_PACKET, 4);
+    let count = 1 + endpoints.len() as u32;
+
+    let mut header = [0u8; 20];
+    let length = header.len() + full_speed.len() + high_speed.len();
+    LittleEndian::write_u32(&mut header[0..4], DESCRIPTORS_MAGIC_V2);
+    LittleEndian::write_u32(&mut header[4..8], length as u32);
+    LittleEndian::write_u32(&mut header[8..12], HAS_FS_DESC | HAS_HS_DESC | VIRTUAL_ADDR);
+    LittleEndian::write_u32(&mut header[12..16], count);
+    LittleEndian::write_u32(&mut header[16..20], count);
+
+    let mut out = header.to_vec();
+    out.extend(full_speed);
+    out.extend(high_speed);
+    out
+}
+
+/// The string table naming the interface `name`, in the FunctionFS format
+pub fn strings(name: &str) -> Vec<u8> {
+    let mut out = vec![0u8; 16];
+    LittleEndian::write_u32(&mut out[0..4], STRINGS_MAGIC);
+    LittleEndian::write_u32(&mut out[8..12], 1);
+    LittleEndian::write_u32(&mut out[12..16], 1);
+    out.extend([0x09, 0x04]); // US English
+    out.extend(name.bytes().filter(|&b| b != 0));
+    out.push(0);
+
+    let length = out.len() as u32;
+    LittleEndian::write_u32(&mut out[4..8], length);
+    out
+}
+
+/// Serves a simulated instrument through a FunctionFS instance
+#[derive(Debug)]
+pub struct Emulator {
+    mountpoint: PathBuf,
+    name: String,
+    sim: Arc<Mutex<SimTransport>>,
+}
+
+impl Emulator {
+    /// Serve `sim` through the FunctionFS instance mounted at `mountpoint`
+    pub fn new<P: AsRef<Path>>(mountpoint: P, sim: SimTransport) -> Self {
+        Self {
+            mountpoint: mountpoint.as_ref().to_owned(),
+            name: "USBTMC emulator".to_owned(),
+            sim: Arc::new(Mutex::new(sim)),
+        }
+    }
+
+    /// Name the interface, as the host sees it
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = name.to_owned();
+        self
+    }
+
+    /// Describe the interface to FunctionFS and serve the host until the control
+    /// endpoint fails (such as when the instance is unmounted) or the thread
+    /// serving one of the other endpoints does.  An endpoint thread's failure
+    /// is returned once the next event arrives on the control endpoint.
+    pub fn run(self) -> io::Result<()> {
+        let mut ep0 = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(self.mountpoint.join("ep0"))?;
+
+        let (bulk_out, bulk_in, interrupt_in, descriptors) = {
+            let mut sim = self.sim.lock().unwrap();
+            let _ = sim.claim_interface();
+            let interface = sim.interface();
+            (
+                interface.bulk_out_address,
+                interface.bulk_in_address,
+                interface.interrupt_in_address,
+                descriptors(interface),
+            )
+        };
+        ep0.write_all(&descriptors)?;
+        ep0.write_all(&strings(&self.name))?;
+
+        let mut endpoints = Vec::new();
+        let mut events = [0u8; 4 * EVENT_SIZE];
+        loop {
+            let n = ep0.read(&mut events)?;
+            for event in events[..n].chunks_exact(EVENT_SIZE) {
+                match event[8] {
+                    EVENT_ENABLE if endpoints.is_empty() => {
+                        if let Some(address) = bulk_out {
+                            endpoints.push(self.spawn_bulk_out(address)?);
+                        }
+                        if let Some(address) = bulk_in {
+                            endpoints.push(self.spawn_bulk_in(address)?);
+                        }
+                        if let Some(address) = interrupt_in {
+                            endpoints.push(self.spawn_interrupt_in(address)?);
+                        }
+                    }
+                    EVENT_SETUP => self.setup(&mut ep0, &event[..8])?,
+                    _ => {}
+                }
+            }
+
+            // the endpoint threads only return if they fail
+            if let Some(i) = endpoints.iter().position(JoinHandle::is_finished) {
+                return match endpoints.swap_remove(i).join() {
+                    Ok(result) => result,
+                    Err(_) => Err(io::Error::other("an endpoint thread panicked")),
+                };
+            }
+        }
+    }
+
+    fn endpoint_path(&self, address: u8) -> PathBuf {
+        self.mountpoint.join(format!("ep{:02x}", address))
+    }
+
+    /// Answer a control request.  USBTMC and USB488 requests all take data from
+    /// the device, so requests sending data to it are stalled, as are any the
+    /// simulation rejects.
+    fn setup(&self, ep0: &mut File, request: &[u8]) -> io::Result<()> {
+        let request_type = request[0];
+        let value = LittleEndian::read_u16(&request[2..4]);
+        let index = LittleEndian::read_u16(&request[4..6]);
+        let length = LittleEndian::read_u16(&request[6..8]) as usize;
+
+        if request_type & 0x80 == 0 {
+            // writing to the control endpoint in an OUT request stalls it
+            let _ = ep0.write(&[]);
+            return Ok(());
+        }
+
+        let mut response = vec![0u8; length];
+        let result = self.sim.lock().unwrap().read_control(
+            request_type,
+            request[1],
+            value,
+            index,
+            &mut response,
+            Duration::ZERO,
+        );
+        match result {
+            Ok(n) => ep0.write_all(&response[..n]),
+            Err(_) => {
+                // reading from the control endpoint in an IN request stalls it
+                let _ = ep0.read(&mut []);
+                Ok(())
+            }
+        }
+    }
+
+    /// Pass transfers from the host to the simulation, split out of the packets
+    /// read by their headers
+    fn spawn_bulk_out(&self, address: u8) -> io::Result<JoinHandle<io::Result<()>>> {
+        let path = self.endpoint_path(address);
+        let sim = self.sim.clone();
+
+        spawn("bulk-out", move || {
+            let mut endpoint = File::open(&path)?;
+            let mut pending = Vec::new();
+            let mut packet = [0u8; BULK_OUT_READ];
+            loop {
+                let n = match endpoint.read(&mut packet) {
+                    Ok(n) => n,
+                    Err(_) => {
+                        sleep(RETRY_INTERVAL);
+                        continue;
+                    }
+                };
+                pending.extend_from_slice(&packet[..n]);
+
+                while let Some(length) = transfer_length(&pending) {
+                    let transfer: Vec<u8> = pending.drain(..length).collect();
+                    // the simulation stalls what it doesn't understand, which
+                    // the host will find out about when it reads the response
+                    let _ = sim
+                        .lock()
+                        .unwrap()
+                        .write_bulk(address, &transfer, Duration::ZERO);
+                }
+            }
+        })
+    }
+
+    /// Send the host the transfers the simulation has ready
+    fn spawn_bulk_in(&self, address: u8) -> io::Result<JoinHandle<io::Result<()>>> {
+        let path = self.endpoint_path(address);
+        let sim = self.sim.clone();
+
+        spawn("bulk-in", move || {
+            let mut endpoint = OpenOptions::new().write(true).open(&path)?;
+            let mut buf = vec![0u8; BULK_IN_CHUNK];
+            loop {
+                let result = sim
+                    .lock()
+                    .unwrap()
+                    .read_bulk(address, &mut buf, Duration::ZERO);
+                match result {
+                    Ok(n) => {
+                        if endpoint.write_all(&buf[..n]).is_err() {
+                            sleep(RETRY_INTERVAL);
+                        }
+                    }
+                    Err(_) => sleep(POLL_INTERVAL),
+                }
+            }
+        })
+    }
+
+    /// Send the host the notifications the simulation raises
+    fn spawn_interrupt_in(&self, address: u8) -> io::Result<JoinHandle<io::Result<()>>> {
+        let path = self.endpoint_path(address);
+        let sim = self.sim.clone();
+
+        spawn("interrupt-in", move || {
+            let mut endpoint = OpenOptions::new().write(true).open(&path)?;
+            let mut notification = [0u8; 2];
+            loop {
+                let result =
+                    sim.lock()
+                        .unwrap()
+                        .read_interrupt(address, &mut notification, Duration::ZERO);
+                match result {
+                    Ok(n) => {
+                        if endpoint.write_all(&notification[..n]).is_err() {
+                            sleep(RETRY_INTERVAL);
+                        }
+                    }
+                    Err(_) => sleep(POLL_INTERVAL),
+                }
+            }
+        })
+    }
+}
+
+/// The length of the bulk-out transfer at the start of `data`, if it has all
+/// arrived.  Unrecognised transfers are taken to run to the end of the data.
+fn transfer_length(data: &[u8]) -> Option<usize> {
+    if data.len() < 12 {
+        return None;
+    }
+
+    let length = match data[0] {
+        // DEV_DEP_MSG_OUT and VENDOR_SPECIFIC_OUT carry data, padded to 4 bytes
+        1 | 126 => 12 + ((LittleEndian::read_u32(&data[4..8]) as usize + 3) & !3),
+        // REQUEST_DEV_DEP_MSG_IN, REQUEST_VENDOR_SPECIFIC_IN and TRIGGER
+        2 | 127 | 128 => 12,
+        _ => data.len(),
+    };
+    (data.len() >= length).then_some(length)
+}
+
+/// Run `f` on a named thread, returning its result from the thread
+fn spawn<F>(name: &str, f: F) -> io::Result<JoinHandle<io::Result<()>>>
+where
+    F: FnOnce() -> io::Result<()> + Send + 'static,
+{
+    thread::Builder::new()
+        .name(format!("tmc-emulator {}", name))
+        .spawn(move || {
+            let result = f();
+            #[cfg(feature = "tracing")]
+            if let Err(error) = &result {
+                tracing::error!(
+                    "{} endpoint failed: {}",
+                    thread::current().name().unwrap_or(""),
+                    error
+                );
+            }
+            result
+        })
+}
@@ -0,0 +1,132 @@
+//! Shortcuts using libusb's global context, for small programs which don't need
+//! to manage contexts and instruments themselves.
+
+use crate::{find_instrument_with_vid_pid, list_instruments};
+use crate::{DiscoveryError, Instrument, InstrumentHandle, TMCResult};
+use rusb::GlobalContext;
+
+/// List the instruments attached to this host
+pub fn list() -> TMCResult<Vec<Instrument<GlobalContext>>> {
+    list_instruments(GlobalContext::default())
+}
+
+/// Open the first attached instrument with the given vendor and product IDs
+pub fn open(vendor_id: u16, product_id: u16) -> TMCResult<InstrumentHandle<GlobalContext>> {
+    match find_instrument_with_vid_pid(GlobalContext::default(), vendor_id, product_id)? {
+        Some(instrument) => instrument.open(),
+        None => {
+            Err(DiscoveryError::NotFound(format!("{:04x}:{:04x}", vendor_id, product_id)).into())
+        }
+    }
+}
+
+/// Open the attached instrument with the given resource string, either as
+/// produced by [Instrument::read_resource_string] or in the VISA form
+/// `USB[board]::<vendor id>::<product id>[::<serial number>[::<interface>]][::INSTR]`,
+/// with IDs in decimal or `0x`-prefixed hex.
+pub fn open_resource(resource: &str) -> TMCResult<InstrumentHandle<GlobalContext>> {
+    if !is_usb_resource(resource) {
+        return Err(DiscoveryError::InvalidResource(resource.to_owned()).into());
+    }
+
+    // resource strings from read_resource_string may have IDs in hex without a
+    // prefix, which can't be parsed unambiguously, so also compare them whole
+    let ids = parse_resource(resource);
+
+    // an instrument whose strings can't be read (because another user has it
+    // open, say) isn't taken to be the one wanted, but the first such failure
+    // is reported if no other instrument matches
+    let mut failure = None;
+    let mut note_failure = |error| {
+        failure.get_or_insert(error);
+        false
+    };
+
+    for mut instrument in list()? {
+        // only instruments with the right IDs are opened to read their strings
+        let vendor_id = instrument.device_desc.vendor_id();
+        let product_id = instrument.device_desc.product_id();
+        if !resource_ids_match(resource, vendor_id, product_id) {
+            continue;
+        }
+
+        let matches = match &ids {
+            Some((resource_vendor_id, resource_product_id, serial_number))
+                if *resource_vendor_id == vendor_id && *resource_product_id == product_id =>
+            {
+                match (serial_number, instrument.read_serial_number()) {
+                    (None, _) => true,
+                    (Some(_), Ok(found)) => found == *serial_number,
+                    (Some(_), Err(error)) => note_failure(error),
+                }
+            }
+            _ => false,
+        };
+
+        let matches = matches
+            || match instrument.read_resource_string() {
+                Ok(found) => found.eq_ignore_ascii_case(resource),
+                Err(error) => note_failure(error),
+            };
+        if matches {
+            return instrument.open();
+        }
+    }
+
+    Err(failure.unwrap_or_else(|| DiscoveryError::NotFound(resource.to_owned()).into()))
+}
+
+/// Whether the resource string's interface type is USB
+fn is_usb_resource(resource: &str) -> bool {
+    let interface = resource.split("::").next().unwrap_or_default();
+
+    match (interface.get(..3), interface.get(3..)) {
+        (Some(prefix), Some(board)) => {
+            prefix.eq_ignore_ascii_case("USB") && board.bytes().all(|b| b.is_ascii_digit())
+        }
+        _ => false,
+    }
+}
+
+/// Split a VISA-style USB resource string into vendor ID, product ID and serial
+/// number
+fn parse_resource(resource: &str) -> Option<(u16, u16, Option<String>)> {
+    let mut parts: Vec<&str> = resource.split("::").collect();
+    if parts
+        .last()
+        .is_some_and(|part| part.eq_ignore_ascii_case("INSTR"))
+    {
+        parts.pop();
+    }
+
+    if parts.len() < 3 || parts.len() > 5 {
+        return None;
+    }
+
+    let vendor_id = parse_id(parts[1])?;
+    let product_id = parse_id(parts[2])?;
+    let serial_number = parts.get(3).map(|serial| serial.to_string());
+
+    Some((vendor_id, product_id, serial_number))
+}
+
+/// Whether the IDs in a resource string could be `vendor_id` and `product_id`,
+/// including in hex without a prefix, as [Instrument::read_resource_string]
+/// writes them
+fn resource_ids_match(resource: &str, vendor_id: u16, product_id: u16) -> bool {
+    let id_matches = |part: Option<&str>, id: u16| {
+        part.is_some_and(|part| {
+            parse_id(part) == Some(id) || u16::from_str_radix(part, 16) == Ok(id)
+        })
+    };
+
+    let mut parts = resource.split("::").skip(1);
+    id_matches(parts.next(), vendor_id) && id_matches(parts.next(), product_id)
+}
+
+fn parse_id(id: &str) -> Option<u16> {
+    match id.strip_prefix("0x").or_else(|| id.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => id.parse().ok(),
+    }
+}
@@ -1,135 +1,591 @@
+use crate::bounded::BoundedSender;
+#[cfg(feature = "scpi")]
+use crate::cache::ResponseCache;
 use crate::class::*;
-use crate::{Instrument, TMCResult};
+use crate::clock::Timestamp;
+use crate::command_policy::{commands, is_query, CommandPolicy, CommandScan, QueryScan};
+#[cfg(feature = "scpi")]
+use crate::common::StatusByte;
+use crate::diagnostics::LinkDiagnostics;
+use crate::interrupts::{SubscriptionId, VendorSubscriptions, MAX_INTERRUPT_PACKET};
+use crate::middleware::Middleware;
+use crate::observer::SessionObserver;
+use crate::spill::{ResponseSpill, SpooledResponse, Spooler};
+use crate::support::{Feature, SupportLevel};
+use crate::transcript::{Direction, Transcript, TranscriptEntry, TransferEntry};
+use crate::transport::{Operation, Transport, UsbTransport};
+use crate::watchdog::Watchdog;
+use crate::Encoding;
+use crate::{
+    ConnectClear, ConnectError, Instrument, OpenOptions, TMCError, TMCResult, TextDecoding,
+    Timeout, TransactionCleanup,
+};
 use core::time::Duration;
 use rusb::DeviceHandle;
 use rusb::UsbContext;
+use std::borrow::Cow;
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::ops::{ControlFlow, RangeInclusive};
 use std::str;
+use std::sync::Arc;
 use std::thread::sleep;
+use std::time::Instant;
+#[cfg(feature = "capture")]
+use std::time::SystemTime;
+
+#[cfg(feature = "capture")]
+use crate::capture::FrameCapture;
+#[cfg(feature = "metrics")]
+use crate::telemetry::HandleMetrics;
+#[cfg(feature = "timing")]
+use crate::timing::{OperationClass, TimingReport};
+
+/// Largest single bulk-in read; bigger transfers are read in several pieces.  This
+/// is a multiple of every valid bulk max packet size.
+pub const MAX_BULK_IN_READ: usize = 64 * 1024;
+
+/// Message available bit of the IEEE 488.2 status byte
+const STB_MAV: u8 = 0x10;
+
+/// First byte of a USB488 interrupt-in notification requesting service
+#[cfg(feature = "scpi")]
+const SRQ_NOTIFICATION: u8 = 0x81;
+
+/// Consecutive repeated bulk-in transfers dropped before a read gives up
+const MAX_DUPLICATE_TRANSFERS: u32 = 4;
+
+/// Timeout for reads made to find out whether a device has anything to send, by
+/// [read_all_pending](TMCHandle::read_all_pending) and while connecting
+const PENDING_READ_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// How much further the wall clock must have run than the monotonic clock since
+/// the last operation for the host to be taken to have slept, rather than the
+/// wall clock to have been adjusted slightly
+const SUSPEND_THRESHOLD: Duration = Duration::from_secs(2);
+
+/// The longest a control request made while dropping a handle may wait, even if
+/// the handle's control timeout is longer
+const DROP_CONTROL_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Timeout for the response to a query sent to probe for a feature
+const PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Standard GET_DESCRIPTOR request, and the type and size of the device
+/// descriptor it is made for to check that a device is still there
+const GET_DESCRIPTOR: u8 = 0x06;
+const DEVICE_DESCRIPTOR_TYPE: u16 = 0x01;
+const DEVICE_DESCRIPTOR_SIZE: usize = 18;
+
+/// What a handle does on drop about a query whose response hasn't been read, so
+/// that the next client doesn't read it instead of its own response
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "profiles", derive(serde::Serialize, serde::Deserialize))]
+pub enum DropCleanup {
+    /// Leave the response in the instrument's output queue
+    #[default]
+    Nothing,
+
+    /// Read and discard the response if it arrives within a short wait, and
+    /// otherwise abort the bulk-in transfer, so that dropping the handle isn't
+    /// held up for long whatever its timeout
+    Discard,
+
+    /// Clear the device, discarding the response and any partial command
+    Clear,
+}
+
+/// How [trigger](TMCHandle::trigger) triggers the device
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "profiles", derive(serde::Serialize, serde::Deserialize))]
+pub enum TriggerStrategy {
+    /// The USB488 TRIGGER message if the device supports it, otherwise the
+    /// `*TRG` common command
+    #[default]
+    Auto,
+
+    /// The USB488 TRIGGER message
+    Usb488,
+
+    /// A command, such as `*TRG`, `INIT` or a vendor's `TRIG:IMM`
+    Command(String),
+}
 
 #[derive(Debug)]
-pub struct InstrumentHandle<Ctx: UsbContext> {
-    usb: DeviceHandle<Ctx>,
+pub struct TMCHandle<T: Transport> {
+    transport: T,
 
     b_tag: u8,
+    last_bulk_tag: u8,
+    tag_policy: Box<dyn TagPolicy>,
+    command_policy: Option<Box<dyn CommandPolicy>>,
+    middleware: Vec<Box<dyn Middleware>>,
+    // the last text message sent, before middleware rewrote it
+    last_message: String,
     max_transfer_size: u32,
-    term_char: Option<u8>,
-    timeout: Duration,
-
-    pub instrument: Instrument<Ctx>,
-    pub usbtmc_capabilities: USBTMCCapabilities,
-    pub usb488_capabilities: Option<USB488Capabilities>,
-    pub scpi_id: Option<String>,
-
-    // When connecting, we may need to reconfigure some stuff.  Remember the
-    // previous state here and restore it on drop().
-    restore_config: Option<u8>,
-    reattach_kernel_driver: Vec<u8>,
+    max_reads: Option<u32>,
+    max_response_size: Option<usize>,
+    response_spill: Option<ResponseSpill>,
+    // the term char is kept while term char matching is disabled, so that
+    // enabling it again brings back the same character
+    term_char: u8,
+    term_char_enabled: bool,
+    bulk_timeout: Timeout,
+    control_timeout: Timeout,
+    interrupt_timeout: Timeout,
+    padding_policy: PaddingPolicy,
+    encoding: Encoding,
+    text_decoding: TextDecoding,
+    diagnostics: LinkDiagnostics,
+
+    // Optional idle time after which the interface may be released by
+    // release_if_idle(), called by the application or by a
+    // TaskScope::release_when_idle() task, so other software can use the
+    // instrument.  The interface is claimed again automatically on the next
+    // operation.
+    idle_release: Option<Duration>,
+    last_activity: Timestamp,
+    response_pending: bool,
+    // a bulk-in transfer has been requested and not read in full
+    bulk_in_outstanding: bool,
+    // padding of the last bulk-in transfer which hadn't arrived when its data
+    // had, and may start the next read
+    stray_padding: usize,
+    resolve_pending_requests: bool,
+    partial_message: Option<PartialMessage>,
+    poison_policy: PoisonPolicy,
+    poisoned: Option<TMCError>,
+    stall_recovery: StallRecovery,
+    resume_recovery: ResumeRecovery,
+    reset_tag_on_clear: bool,
+    drop_cleanup: DropCleanup,
+    trigger_strategy: TriggerStrategy,
+    interface_claimed: bool,
+    claim_retry: Duration,
+    shared: bool,
+    verify_writes: bool,
+    transaction_cleanup: TransactionCleanup,
+    robust_attempts: u32,
+    transcript: Option<Transcript>,
+    #[cfg(feature = "capture")]
+    capture: Option<FrameCapture>,
+    observers: Vec<Arc<dyn SessionObserver>>,
+    watchdog: Option<Watchdog>,
+    vendor_interrupts: VendorSubscriptions,
+
+    // read from the device when first needed, rather than while connecting
+    probed_support: HashMap<Feature, bool>,
+    capabilities: OnceCell<(USBTMCCapabilities, Option<USB488Capabilities>)>,
+    capabilities_policy: CapabilitiesPolicy,
+    capability_anomalies: Vec<CapabilitiesAnomaly>,
+    #[cfg(feature = "scpi")]
+    scpi_id: OnceCell<Option<String>>,
+    #[cfg(feature = "scpi")]
+    response_cache: Option<ResponseCache>,
+
+    #[cfg(feature = "timing")]
+    timing: TimingReport,
+    #[cfg(feature = "metrics")]
+    metrics: HandleMetrics,
+
+    // labels everything the handle logs with the instrument's identity
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+    #[cfg(feature = "tracing")]
+    log_level: tracing::level_filters::LevelFilter,
 }
 
-impl<Ctx: UsbContext> Drop for InstrumentHandle<Ctx> {
-    fn drop(&mut self) {
-        // TODO: is there something more useful we can do if these fail?
-        let endpoints = &self.instrument.endpoints;
+/// Which failed message exchanges leave a handle poisoned, refusing further
+/// messages until [recover](TMCHandle::recover) has put the device back in a
+/// known state
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "profiles", derive(serde::Serialize, serde::Deserialize))]
+pub enum PoisonPolicy {
+    /// Carry on after any error
+    #[default]
+    Never,
+
+    /// Poison the handle when a transfer is malformed or truncated
+    ProtocolErrors,
+
+    /// Also poison the handle when a transfer fails with a USB error such as a
+    /// timeout or stall
+    TransferErrors,
+}
+
+impl PoisonPolicy {
+    pub fn poisons(self, error: &TMCError) -> bool {
+        match self {
+            PoisonPolicy::Never => false,
+            PoisonPolicy::ProtocolErrors => error.is_protocol_error(),
+            PoisonPolicy::TransferErrors => error.is_protocol_error() || error.is_transfer_error(),
+        }
+    }
+}
+
+/// What to do when a bulk transfer fails because the device stalled the endpoint
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "profiles", derive(serde::Serialize, serde::Deserialize))]
+pub enum StallRecovery {
+    /// Fail the transfer, leaving the endpoint halted until the device is cleared
+    Fail,
+
+    /// Clear the halt and retry the transfer once, counting the stall in the
+    /// link diagnostics
+    #[default]
+    ClearAndRetry,
+}
+
+/// What to do when the host has slept since the handle was last used, as a
+/// laptop does when its lid is closed.  The device may have been suspended or
+/// lost power meanwhile, and operations fail with
+/// [disconnect](TMCError::is_disconnect) errors until the interface is claimed
+/// again.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "profiles", derive(serde::Serialize, serde::Deserialize))]
+pub enum ResumeRecovery {
+    /// Carry on as before, leaving recovery to the application
+    Ignore,
+
+    /// [Revalidate](TMCHandle::revalidate) the session before the next
+    /// operation, and after an operation which failed with a disconnect error
+    /// across a sleep
+    #[default]
+    Revalidate,
+}
+
+/// A command message being built up by
+/// [write_partial_raw](TMCHandle::write_partial_raw)
+#[derive(Debug)]
+struct PartialMessage {
+    started: Timestamp,
+
+    /// Data not yet sent, always including the end of the message
+    held: Vec<u8>,
 
-        let _ = self.usb.release_interface(endpoints.interface_number);
+    /// Data already sent, kept only while a transcript is being recorded
+    sent: Vec<u8>,
+    query: QueryScan,
+
+    /// The units checked against the command policy so far
+    commands: CommandScan,
+}
 
-        if let Some(old_config) = self.restore_config {
-            let _ = self.usb.set_active_configuration(old_config);
+/// Check commands against a policy, failing with the first it rejects
+fn check_commands(policy: &dyn CommandPolicy, commands: Vec<String>) -> TMCResult<()> {
+    for command in commands {
+        if let Err(reason) = policy.check(&command) {
+            return Err(TMCError::CommandRejected { command, reason });
         }
+    }
+    Ok(())
+}
+
+/// Parse a SCPI error queue entry of the form `<code>,"<message>"`
+#[cfg(feature = "scpi")]
+pub(crate) fn parse_error_entry(response: &str) -> Option<(i32, String)> {
+    let (code, message) = response.trim().split_once(',')?;
+    let code = code.trim().parse().ok()?;
+    Some((code, message.trim().trim_matches('"').to_owned()))
+}
+
+/// The span for a handle's logs, with fields identifying the device
+#[cfg(feature = "tracing")]
+fn device_span(label: &crate::transport::DeviceLabel) -> tracing::Span {
+    tracing::info_span!(
+        "tmc",
+        model = label.model.as_deref(),
+        serial = label.serial_number.as_deref(),
+        bus = label.bus_number,
+        address = label.address,
+    )
+}
+
+#[cfg(feature = "tracing")]
+fn trace_frame(direction: Direction, frame: &[u8]) {
+    tracing::trace!(
+        target: "tmc::frames",
+        "{}",
+        crate::debug::describe_frame(direction, frame)
+    );
+}
+
+/// Handle for an instrument attached to this host, communicating through libusb
+pub type InstrumentHandle<Ctx> = TMCHandle<UsbTransport<Ctx>>;
+
+impl<T: Transport> Drop for TMCHandle<T> {
+    fn drop(&mut self) {
+        if self.interface_claimed {
+            if self.response_pending {
+                // the handle's own timeouts may be long, or forever
+                self.bulk_timeout = PENDING_READ_TIMEOUT.into();
+                if self.control_timeout.as_duration() > DROP_CONTROL_TIMEOUT {
+                    self.control_timeout = DROP_CONTROL_TIMEOUT.into();
+                }
 
-        for &interface in self.reattach_kernel_driver.iter() {
-            let _ = self.usb.attach_kernel_driver(interface);
+                let _ = match self.drop_cleanup {
+                    DropCleanup::Nothing => Ok(()),
+                    DropCleanup::Discard => self
+                        .read_message(None)
+                        .map(drop)
+                        .or_else(|_| self.abort_bulk_in_transfer()),
+                    DropCleanup::Clear => self.clear_device(),
+                };
+            }
+
+            let _ = self.transport.release_interface();
         }
     }
 }
 
 impl<Ctx: UsbContext> InstrumentHandle<Ctx> {
-    pub(crate) fn connect(instrument: Instrument<Ctx>) -> TMCResult<Self> {
-        let usb = instrument.device.open()?;
+    pub(crate) fn connect(instrument: Instrument<Ctx>, options: OpenOptions) -> TMCResult<Self> {
+        let transport = if options.shared {
+            UsbTransport::open_shared(instrument)?
+        } else {
+            UsbTransport::open(instrument)?
+        };
+        Self::with_transport(transport, options)
+    }
 
-        let mut handle = Self {
-            instrument,
-            usb,
+    pub fn instrument(&self) -> &Instrument<Ctx> {
+        self.transport.instrument()
+    }
 
-            b_tag: 0,
-            max_transfer_size: 1024 * 1024,
-            timeout: Duration::from_secs(1),
-            term_char: None,
+    pub fn instrument_mut(&mut self) -> &mut Instrument<Ctx> {
+        self.transport.instrument_mut()
+    }
+
+    /// Run a closure with temporary access to the underlying USB device handle, for
+    /// things like vendor-specific control requests that aren't supported directly.
+    ///
+    /// Only a shared reference is given out, so the closure cannot release the TMC
+    /// interface, switch configurations or reset the device, any of which would leave
+    /// this handle in an inconsistent state.  The closure should also not perform bulk
+    /// transfers on the TMC interface's endpoints, since this handle tracks message
+    /// state (such as the bTag sequence) which those transfers would not update.
+    pub fn with_usb_handle<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&DeviceHandle<Ctx>) -> R,
+    {
+        // the closure can't report errors in our terms, so if the interface can't
+        // be re-claimed let it see (and fail on) the unclaimed handle
+        let _ = self.ensure_claimed();
+        f(self.transport.device_handle())
+    }
+}
 
-            restore_config: None,
-            reattach_kernel_driver: Vec::new(),
+impl<T: Transport> TMCHandle<T> {
+    /// Connect to an instrument through the given transport: claim the TMC
+    /// interface and clear the device, as chosen by
+    /// [connect_clear](OpenOptions::connect_clear).  Its capabilities and
+    /// identity are read when first needed.
+    pub fn with_transport(transport: T, options: OpenOptions) -> TMCResult<Self> {
+        #[cfg(any(feature = "tracing", feature = "metrics"))]
+        let label = transport.device_label();
+        #[cfg(feature = "tracing")]
+        let span = device_span(&label);
+
+        let config = &options.config;
+        let mut handle = Self {
+            transport,
 
-            usbtmc_capabilities: USBTMCCapabilities::new(),
-            usb488_capabilities: None,
-            scpi_id: None,
+            b_tag: 0,
+            last_bulk_tag: 0,
+            tag_policy: options.tag_policy,
+            command_policy: options.command_policy,
+            middleware: options.middleware,
+            last_message: String::new(),
+            max_transfer_size: config.max_transfer_size,
+            max_reads: None,
+            max_response_size: None,
+            response_spill: None,
+            bulk_timeout: config.bulk_timeout,
+            control_timeout: config.control_timeout,
+            interrupt_timeout: config.interrupt_timeout,
+            term_char: b'\n',
+            term_char_enabled: false,
+            padding_policy: PaddingPolicy::default(),
+            encoding: Encoding::default(),
+            text_decoding: TextDecoding::default(),
+            diagnostics: LinkDiagnostics::default(),
+
+            idle_release: None,
+            last_activity: Timestamp::now(),
+            response_pending: false,
+            bulk_in_outstanding: false,
+            stray_padding: 0,
+            resolve_pending_requests: true,
+            partial_message: None,
+            poison_policy: PoisonPolicy::default(),
+            poisoned: None,
+            stall_recovery: config.stall_recovery,
+            resume_recovery: ResumeRecovery::default(),
+            reset_tag_on_clear: false,
+            drop_cleanup: DropCleanup::default(),
+            trigger_strategy: TriggerStrategy::default(),
+            interface_claimed: false,
+            claim_retry: options.claim_retry,
+            shared: options.shared,
+            verify_writes: false,
+            transaction_cleanup: TransactionCleanup::default(),
+            robust_attempts: 3,
+            transcript: None,
+            #[cfg(feature = "capture")]
+            capture: None,
+            observers: options.observers,
+            watchdog: None,
+            vendor_interrupts: VendorSubscriptions::default(),
+
+            probed_support: HashMap::new(),
+            capabilities: OnceCell::new(),
+            capabilities_policy: CapabilitiesPolicy::default(),
+            capability_anomalies: Vec::new(),
+            #[cfg(feature = "scpi")]
+            scpi_id: OnceCell::new(),
+            #[cfg(feature = "scpi")]
+            response_cache: None,
+
+            #[cfg(feature = "timing")]
+            timing: TimingReport::default(),
+            #[cfg(feature = "metrics")]
+            metrics: HandleMetrics::new(&label),
+
+            #[cfg(feature = "tracing")]
+            span,
+            #[cfg(feature = "tracing")]
+            log_level: config.log_level,
         };
-        let usb = &mut handle.usb;
-        let endpoints = &handle.instrument.endpoints;
 
-        let old_config = usb.active_configuration()?;
+        // the configured size can't be checked against the packet sizes until the
+        // interface is known, which it now is
+        handle.set_max_transfer_size(config.max_transfer_size)?;
 
-        if rusb::supports_detach_kernel_driver() {
-            for config in 0..handle
-                .instrument
-                .device
-                .device_descriptor()?
-                .num_configurations()
-            {
-                for interface in 0..handle
-                    .instrument
-                    .device
-                    .config_descriptor(config)?
-                    .num_interfaces()
-                {
-                    if usb.kernel_driver_active(interface)? {
-                        handle.reattach_kernel_driver.push(interface);
-                        usb.detach_kernel_driver(interface)?;
-                    }
-                }
-            }
+        handle.ensure_claimed()?;
+
+        if !handle.shared {
+            handle
+                .connect_clear(options.connect_clear)
+                .map_err(|error| ConnectError::Handshake(Box::new(error)))?;
         }
 
-        if old_config != 0 {
-            match handle.instrument.device.config_descriptor(old_config) {
-                Err(rusb::Error::NotFound) => {}
-                Err(rusb_error) => return Err(rusb_error.into()),
-                Ok(_old_config_desc) => {}
-            };
+        #[cfg(feature = "profiles")]
+        if let Some(profiles) = &options.profiles {
+            profiles.apply(&mut handle)?;
         }
 
-        let new_config = handle.instrument.config_desc.number();
-        if old_config != new_config {
-            handle.restore_config = Some(old_config);
-            usb.set_active_configuration(new_config)?;
+        for observer in handle.observers.iter() {
+            observer.connected();
         }
 
-        usb.claim_interface(endpoints.interface_number)?;
+        Ok(handle)
+    }
+
+    /// Clear the device on connecting, if the policy calls for it
+    fn connect_clear(&mut self, connect_clear: ConnectClear) -> TMCResult<()> {
+        let clear = match connect_clear {
+            ConnectClear::Always => true,
+            ConnectClear::Never => false,
+            ConnectClear::IfPendingData => self.pending_data_suspected()?,
+        };
+        if clear {
+            self.clear()?;
+        }
 
-        //TODO should this clear be here?
-        handle.clear()?;
-        handle.get_capabilities()?;
+        Ok(())
+    }
 
-        if let Some(caps) = &handle.usb488_capabilities {
-            if caps.scpi {
-                if let Ok(id_str) = handle.ask("*IDN?") {
-                    handle.scpi_id = Some(id_str.trim().to_owned());
-                }
+    /// Whether the device seems to have output left over from an earlier session
+    fn pending_data_suspected(&mut self) -> TMCResult<bool> {
+        if let Some(ep) = self.interface().bulk_in_address {
+            let mut buf = vec![0u8; self.bulk_in_packet_size()];
+            match self.transport.read_bulk(ep, &mut buf, PENDING_READ_TIMEOUT) {
+                Ok(n) if n > 0 => return Ok(true),
+                Ok(_) | Err(rusb::Error::Timeout) => {}
+                Err(error) => return Err(error.into()),
             }
         }
 
-        Ok(handle)
+        Ok(self.usb488_capabilities()?.is_some() && self.read_status_byte()? & STB_MAV != 0)
+    }
+
+    /// Information about the TMC interface and its endpoints
+    pub fn interface(&self) -> &TMCInterface {
+        self.transport.interface()
+    }
+
+    /// The address of the bulk endpoint used for `operation`, failing with
+    /// [MissingEndpoint](TMCError::MissingEndpoint) if the interface has none
+    fn bulk_endpoint(&self, operation: Operation) -> TMCResult<u8> {
+        let address = match operation {
+            Operation::BulkIn => self.interface().bulk_in_address,
+            _ => self.interface().bulk_out_address,
+        };
+        address.ok_or(TMCError::MissingEndpoint { operation })
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
     }
 
     pub fn get_max_transfer_size(&self) -> u32 {
         self.max_transfer_size
     }
 
-    pub fn set_max_transfer_size(&mut self, max_transfer_size: u32) {
+    /// Set the largest amount of message data sent or requested in one bulk
+    /// transfer.  Zero is rejected, and so are sizes for which transfers fill a
+    /// whole number of packets (see [TMCInterface::fills_whole_packets]), as
+    /// those rely on the device handling zero-length packets, which some don't.
+    pub fn set_max_transfer_size(&mut self, max_transfer_size: u32) -> TMCResult<()> {
+        if max_transfer_size == 0 || self.interface().fills_whole_packets(max_transfer_size) {
+            return Err(ClassError::InvalidTransferSize(max_transfer_size).into());
+        }
+
         self.max_transfer_size = max_transfer_size;
+        Ok(())
+    }
+
+    /// Get the maximum number of bulk-in transfers performed by a single call to
+    /// [read_raw](Self::read_raw), if limited.
+    pub fn get_max_reads(&self) -> Option<u32> {
+        self.max_reads
+    }
+
+    /// Limit the number of bulk-in transfers performed by a single call to
+    /// [read_raw](Self::read_raw).  If the device has not ended the message by
+    /// then, the read fails with [ClassError::ResponseTooLarge].
+    pub fn set_max_reads(&mut self, max_reads: Option<u32>) {
+        self.max_reads = max_reads;
+    }
+
+    /// Get the maximum number of bytes returned by a single call to
+    /// [read_raw](Self::read_raw), if limited.
+    pub fn get_max_response_size(&self) -> Option<usize> {
+        self.max_response_size
+    }
+
+    /// Limit the number of bytes accumulated by a single call to
+    /// [read_raw](Self::read_raw).  Larger responses fail with
+    /// [ClassError::ResponseTooLarge] instead of growing without bound, and no
+    /// transfer asks the device for more than one byte over the limit.
+    pub fn set_max_response_size(&mut self, max_response_size: Option<usize>) {
+        self.max_response_size = max_response_size;
+    }
+
+    pub fn get_response_spill(&self) -> Option<&ResponseSpill> {
+        self.response_spill.as_ref()
+    }
+
+    /// Choose how much of a response [read_raw_spooled](Self::read_raw_spooled)
+    /// holds in memory before spilling it to a temporary file, or `None` to
+    /// hold it all in memory.  The [maximum response size](Self::set_max_response_size)
+    /// still applies to the whole response.
+    pub fn set_response_spill(&mut self, response_spill: Option<ResponseSpill>) {
+        self.response_spill = response_spill;
     }
 
     pub fn get_term_char(&self) -> Option<u8> {
-        self.term_char
+        self.term_char_enabled.then_some(self.term_char)
     }
 
     pub fn set_term_char(&mut self, term_char: Option<u8>) -> TMCResult<()> {
@@ -137,285 +593,2785 @@ impl<Ctx: UsbContext> InstrumentHandle<Ctx> {
             return Err(ClassError::InvalidTermChar.into());
         }
 
-        if term_char.is_some() && !self.usbtmc_capabilities.term_char {
+        if term_char.is_some() && !self.supports(Feature::TermChar)?.is_supported() {
             return Err(ClassError::UnsupportedFeature.into());
         }
 
-        self.term_char = term_char;
+        if let Some(term_char) = term_char {
+            self.term_char = term_char;
+        }
+        self.term_char_enabled = term_char.is_some();
         Ok(())
     }
 
-    pub fn get_timeout(&self) -> Duration {
-        self.timeout
+    /// The term char matched while term char matching is enabled, which is kept
+    /// while it is disabled
+    pub(crate) fn term_char_value(&self) -> u8 {
+        self.term_char
     }
 
-    pub fn set_timeout(&mut self, timeout: Duration) {
-        self.timeout = timeout;
+    /// Choose the term char without enabling or disabling term char matching, so
+    /// that no support for it is needed until it is enabled
+    pub(crate) fn set_term_char_value(&mut self, term_char: u8) -> TMCResult<()> {
+        if term_char == 0 {
+            return Err(ClassError::InvalidTermChar.into());
+        }
+        self.term_char = term_char;
+        Ok(())
     }
 
-    fn read_control(
-        &mut self,
-        request: ControlRequest,
-        read_size: usize,
-        out: &mut Vec<u8>,
-    ) -> TMCResult<()> {
-        let request_type = rusb::request_type(
-            rusb::Direction::In,
-            rusb::RequestType::Class,
-            rusb::Recipient::Interface,
-        );
+    /// Enable or disable term char matching with the term char last chosen
+    pub(crate) fn set_term_char_enabled(&mut self, enabled: bool) -> TMCResult<()> {
+        self.set_term_char(enabled.then_some(self.term_char))
+    }
 
-        out.resize(read_size, 0);
-        self.incr_b_tag();
-        let size = match request {
-            ControlRequest::Tmc488ReadStatusByte => self.usb.read_control(
-                request_type,
-                request as u8,
-                self.b_tag as u16,
-                self.instrument.endpoints.interface_number as u16,
-                out,
-                self.timeout,
-            )?,
-            _ => self.usb.read_control(
-                request_type,
-                request as u8,
-                self.b_tag as u16,
-                self.instrument.endpoints.interface_number as u16,
-                out,
-                self.timeout,
-            )?,
-        };
-        // self.usb.read_control(
-        //   request_type,
-        //   request as u8,
-        //   0x0000,
-        //   self.instrument.endpoints.interface_number as u16,
-        //   out,
-        //   self.timeout,
-        // )?;
-        out.truncate(size);
+    pub fn get_verify_writes(&self) -> bool {
+        self.verify_writes
+    }
+
+    /// Check for an instrument error after each write (but not after the command
+    /// part of a query), with `SYST:ERR?` if the instrument supports SCPI (and the
+    /// `scpi` feature is enabled) or
+    /// otherwise by reading the status byte.  Requires a USB488 instrument.
+    pub fn set_verify_writes(&mut self, verify_writes: bool) -> TMCResult<()> {
+        if verify_writes && self.usb488_capabilities()?.is_none() {
+            return Err(ClassError::UnsupportedFeature.into());
+        }
 
+        self.verify_writes = verify_writes;
         Ok(())
     }
 
-    // TODO: these messages are defined in the class spec, are they useful?
-    //
-    // I think it might be useful to use abort_bulk_in when connecting to
-    // make sure we don't read stale data if the last connection was interrupted.
-    //
-    // pub fn abort_bulk_out(...)
-    // pub fn abort_bulk_in(...)
+    pub fn get_transaction_cleanup(&self) -> TransactionCleanup {
+        self.transaction_cleanup
+    }
 
-    // Send USBTMC "clear" command
-    pub fn clear(&mut self) -> TMCResult<()> {
-        let mut out = Vec::with_capacity(2);
-        self.read_control(ControlRequest::InitiateClear, 1, &mut out)?;
+    /// Set the recovery steps taken when a [transaction](Self::transaction) fails
+    pub fn set_transaction_cleanup(&mut self, cleanup: TransactionCleanup) {
+        self.transaction_cleanup = cleanup;
+    }
 
-        ControlRequest::check_response_status(&out)?;
+    pub fn get_robust_attempts(&self) -> u32 {
+        self.robust_attempts
+    }
 
-        // device accepted `clear` command, wait while status is "pending"
-        loop {
-            self.read_control(ControlRequest::CheckClearStatus, 2, &mut out)?;
+    /// Set how many times [robust_ask](Self::robust_ask) tries a query before
+    /// giving up (at least once)
+    pub fn set_robust_attempts(&mut self, attempts: u32) {
+        self.robust_attempts = attempts.max(1);
+    }
 
-            match ControlRequest::read_response_status(&out)? {
-                Status::Success => break,
-                Status::Pending => {}
-                status => status.check()?,
-            };
+    pub fn get_poison_policy(&self) -> PoisonPolicy {
+        self.poison_policy
+    }
 
-            sleep(Duration::from_millis(100));
-        }
+    /// Choose which failures during a message exchange poison the handle, so
+    /// that the session can't silently carry on out of step with the device
+    pub fn set_poison_policy(&mut self, poison_policy: PoisonPolicy) {
+        self.poison_policy = poison_policy;
+    }
 
-        self.usb
-            .clear_halt(self.instrument.endpoints.bulk_out_address)?;
-        Ok(())
+    pub fn get_stall_recovery(&self) -> StallRecovery {
+        self.stall_recovery
     }
 
-    fn get_capabilities(&mut self) -> TMCResult<()> {
-        // 64 bytes is the largest possible control transfer, so use that to avoid
-        // overflow if the device sends a lot back.
-        let mut out = vec![0u8; 64];
-        self.read_control(ControlRequest::GetCapabilities, 64, &mut out)?;
+    /// Choose whether a stalled bulk endpoint is cleared and the transfer retried
+    pub fn set_stall_recovery(&mut self, stall_recovery: StallRecovery) {
+        self.stall_recovery = stall_recovery;
+    }
 
-        self.usbtmc_capabilities = USBTMCCapabilities::parse(&out)?;
+    pub fn get_resume_recovery(&self) -> ResumeRecovery {
+        self.resume_recovery
+    }
 
-        if self.instrument.endpoints.interface_protocol == 1 {
-            self.usb488_capabilities = USB488Capabilities::parse(&self.usbtmc_capabilities, &out)?;
-        }
+    /// Choose whether the session is revalidated automatically when the host
+    /// wakes from sleep.  Waking is noticed from the wall clock running ahead of
+    /// the monotonic clock, which stops during sleep on Linux and macOS but not
+    /// on Windows, where [revalidate](Self::revalidate) must be called instead.
+    pub fn set_resume_recovery(&mut self, resume_recovery: ResumeRecovery) {
+        self.resume_recovery = resume_recovery;
+    }
 
-        Ok(())
+    /// Check that the session survived the host sleeping: claim the TMC
+    /// interface afresh and read the device's capabilities again, telling the
+    /// observers the session has [resumed](SessionObserver::resumed) if that
+    /// works.  Fails with a disconnect error if the device went away while the
+    /// host slept, in which case it must be reopened.
+    pub fn revalidate(&mut self) -> TMCResult<()> {
+        let result = self.revalidate_impl();
+        self.observe(result)
     }
 
-    pub fn pulse(&mut self) -> TMCResult<()> {
-        if !self.usbtmc_capabilities.pulse {
-            return Err(ClassError::UnsupportedFeature.into());
+    fn revalidate_impl(&mut self) -> TMCResult<()> {
+        self.last_activity = Timestamp::now();
+
+        if self.interface_claimed {
+            // the claim may not have survived, so releasing it can fail too
+            let _ = self.transport.release_interface();
+            self.interface_claimed = false;
+        }
+        match self.claim_interface() {
+            Err(_) if self.shared => {}
+            result => result?,
         }
 
-        let mut out = Vec::with_capacity(1);
-        self.read_control(ControlRequest::IndicatorPulse, 1, &mut out)?;
-        ControlRequest::check_response_status(&out)?;
+        self.forget_device_state();
+        self.probed_support.clear();
+        self.capabilities()?;
+
+        #[cfg(feature = "tracing")]
+        if self.log_level >= tracing::Level::INFO {
+            let _entered = self.span.enter();
+            tracing::info!("session revalidated after the host slept");
+        }
+        for observer in self.observers.iter() {
+            observer.resumed();
+        }
         Ok(())
     }
 
-    fn incr_b_tag(&mut self) {
-        // bTag must be different on each successive bulk-out transfer and not 0
-        self.b_tag = if self.b_tag > 127 || self.b_tag < 2 {
-            2
-        } else {
-            self.b_tag + 1
-        };
+    /// Whether revalidation is on and the host seems to have slept since the
+    /// last operation started
+    fn host_slept(&self) -> bool {
+        self.resume_recovery == ResumeRecovery::Revalidate
+            && self.last_activity.suspended() >= SUSPEND_THRESHOLD
     }
 
-    /// Write a command message to the instrument
-    pub fn write_raw(&mut self, data: &[u8]) -> TMCResult<()> {
-        let ep = self.instrument.endpoints.bulk_out_address;
+    pub fn get_reset_tag_on_clear(&self) -> bool {
+        self.reset_tag_on_clear
+    }
 
-        let mut buf = Vec::with_capacity(HEADER_SIZE + data.len() + 3);
-        let mut end_offset: usize = 0;
+    /// Choose whether the bTag sequence starts again after a device clear, as
+    /// though the connection were new, for firmware which expects that
+    pub fn set_reset_tag_on_clear(&mut self, reset_tag_on_clear: bool) {
+        self.reset_tag_on_clear = reset_tag_on_clear;
+    }
 
-        for block in data.chunks(self.max_transfer_size as usize) {
-            end_offset += block.len();
-            let eom = end_offset >= data.len();
+    /// The bTag of the most recent transfer or class control request, or 0 if
+    /// there hasn't been one yet (or since the sequence was reset)
+    pub fn current_b_tag(&self) -> u8 {
+        self.b_tag
+    }
 
-            self.incr_b_tag();
-            DevDepMsgOutHeader::encode_message(self.b_tag, data, eom, &mut buf);
+    /// The error which poisoned the handle, if it is poisoned
+    pub fn poisoned(&self) -> Option<&TMCError> {
+        self.poisoned.as_ref()
+    }
 
-            let n_written = self.usb.write_bulk(ep, &buf, self.timeout)?;
-            if n_written < block.len() {
-                return Err(ClassError::TruncatedBulkOut.into());
-            }
+    /// Put the device back in a known state after an error, by aborting any bulk
+    /// transfers and clearing it, and lift any poisoning
+    pub fn recover(&mut self) -> TMCResult<()> {
+        let _ = self.abort_bulk_out_transfer();
+        let _ = self.abort_bulk_in_transfer();
+
+        let result = self.clear_device();
+        if result.is_ok() {
+            self.poisoned = None;
         }
+        self.observe(result)
+    }
 
-        Ok(())
+    pub fn get_resolve_pending_requests(&self) -> bool {
+        self.resolve_pending_requests
     }
 
-    /// Read status byte from instrument
-    pub fn read_stb(&mut self, _timeout: Option<Duration>) -> TMCResult<bool> {
-        let mut status_buf: Vec<u8> = Vec::with_capacity(3);
-        self.read_control(ControlRequest::Tmc488ReadStatusByte, 3, &mut status_buf)?;
+    /// Choose whether a bulk-in transfer which was requested but not read in
+    /// full (because the read timed out or failed, say) is aborted before the
+    /// next message is sent or transfer requested.  The device is asked to abort it with
+    /// INITIATE_ABORT_BULK_IN, which also tells whether it still had the
+    /// transfer in progress; if so, whatever remains of the interrupted
+    /// response is discarded, rather than being read as the response to the
+    /// next request with the wrong bTag.  On by default.
+    pub fn set_resolve_pending_requests(&mut self, resolve_pending_requests: bool) {
+        self.resolve_pending_requests = resolve_pending_requests;
+    }
 
-        if ControlRequest::check_response_status(&status_buf).is_ok() {
-            let buf = &mut [0u8, 2];
-            let _interrupt = self.usb.read_interrupt(
-                self.instrument.endpoints.interrupt_in_address.unwrap_or(0),
-                buf,
-                Duration::from_micros(1),
-            )?;
+    pub fn get_drop_cleanup(&self) -> DropCleanup {
+        self.drop_cleanup
+    }
 
-            if *buf.last().unwrap_or(&0) & 16 != 0 {
-                return Ok(true);
-            }
-        }
-        Ok(false)
+    /// Choose what to do on drop if a query (a message containing `?`) has been
+    /// sent and its response not read.  Nothing is sent when no response is
+    /// pending.
+    pub fn set_drop_cleanup(&mut self, drop_cleanup: DropCleanup) {
+        self.drop_cleanup = drop_cleanup;
     }
 
-    /// Read response data from the instrument
-    pub fn read_raw(
-        &mut self,
-        transfer_size: Option<u32>,
-        //timeout: Option<Duration>,
-    ) -> TMCResult<Vec<u8>> {
-        let transfer_size = match transfer_size {
-            Some(size) if size < self.max_transfer_size => size,
-            _ => self.max_transfer_size,
-        };
+    pub fn get_trigger_strategy(&self) -> &TriggerStrategy {
+        &self.trigger_strategy
+    }
 
-        let mut read_data = Vec::with_capacity(HEADER_SIZE + transfer_size as usize + 3);
-        let mut buf = Vec::new();
+    /// Choose how [trigger](Self::trigger) triggers the device, such as with a
+    /// command for instruments which ignore both the USB488 TRIGGER message and
+    /// `*TRG`
+    pub fn set_trigger_strategy(&mut self, trigger_strategy: TriggerStrategy) {
+        self.trigger_strategy = trigger_strategy;
+    }
 
-        /* let time = std::time::Instant::now();
-        let end_time = time + timeout.unwrap_or(Duration::from_millis(1000));
+    /// Answer the queries registered with `cache` from it when
+    /// [ask](Self::ask)ed, rather than sending them every time, replacing any
+    /// cache already in use
+    #[cfg(feature = "scpi")]
+    pub fn set_response_cache(&mut self, cache: Option<ResponseCache>) {
+        self.response_cache = cache;
+    }
 
-        let mut message_available = false;
-        //TODO For compatibility reasons, before doing this while loop we
-        //      need to check whether the instrument claims to implement IEEE 488
-        //      (All Keithley Instruments implement IEEE 488, so we don't need to change it right now.)
-        while std::time::Instant::now() < end_time && !message_available {
-            let mut status_buf: Vec<u8> = Vec::with_capacity(3);
-            self.read_control(ControlRequest::Tmc488ReadStatusByte, 3, &mut status_buf)?;
+    /// The response cache in use, if any
+    #[cfg(feature = "scpi")]
+    pub fn response_cache(&self) -> Option<&ResponseCache> {
+        self.response_cache.as_ref()
+    }
 
-            if ControlRequest::check_response_status(&status_buf).is_ok() {
-                let buf = &mut [0u8, 2];
-                let _interrupt = self.usb.read_interrupt(
-                    self.instrument.endpoints.interrupt_in_address.unwrap_or(0),
-                    buf,
-                    Duration::from_millis(10),
-                )?;
+    #[cfg(feature = "scpi")]
+    pub fn response_cache_mut(&mut self) -> Option<&mut ResponseCache> {
+        self.response_cache.as_mut()
+    }
 
-                if *buf.last().unwrap_or(&0) & 16 != 0 {
-                    message_available = true;
-                }
-            }
-            sleep(Duration::from_millis(100));
-        }
+    pub fn get_command_policy(&self) -> Option<&dyn CommandPolicy> {
+        self.command_policy.as_deref()
+    }
 
-        if !message_available {
-            return Ok(Vec::new());
-        } */
+    /// Check every message against a policy before sending it, refusing those
+    /// with a command the policy rejects.  This includes the messages the handle
+    /// sends itself, such as `SYST:ERR?` when verifying writes.
+    pub fn set_command_policy(&mut self, command_policy: Option<Box<dyn CommandPolicy>>) {
+        self.command_policy = command_policy;
+    }
 
-        loop {
-            // Send OUT command header to request device send data
-            self.incr_b_tag();
-            RequestDevDepMsgInHeader::encode_message(
-                self.b_tag,
-                transfer_size,
-                self.term_char,
-                &mut buf,
-            );
-            self.usb.write_bulk(
-                self.instrument.endpoints.bulk_out_address,
-                &buf,
-                self.timeout,
-            )?;
+    /// Pass text messages and their responses through `middleware`, after any
+    /// added before it (see [middleware](crate::middleware))
+    pub fn add_middleware<M: Middleware + 'static>(&mut self, middleware: M) {
+        self.middleware.push(Box::new(middleware));
+    }
 
-            // Read the requested data from the device. Extra space in output buffer is
-            // for the bulk-in header and 3 potential alignment-padding bytes.
-            buf.resize(HEADER_SIZE + transfer_size as usize + 3, 0);
-            let n_read = self.usb.read_bulk(
-                self.instrument.endpoints.bulk_in_address,
-                &mut buf,
-                self.timeout,
-            )?;
-            buf.truncate(n_read);
+    /// Remove all middleware, so that text is sent and returned unchanged
+    pub fn clear_middleware(&mut self) {
+        self.middleware.clear();
+    }
 
-            let (header, data) = DevDepMsgInHeader::decode_transfer(&buf)?;
-            read_data.extend_from_slice(data);
+    /// Record every message sent and received from now on in the given
+    /// transcript, replacing any transcript already being recorded
+    pub fn start_transcript(&mut self, transcript: Transcript) {
+        self.transcript = Some(transcript);
+    }
 
-            if header.is_eom() {
-                break;
-            }
-        }
+    /// The transcript being recorded, if any
+    pub fn transcript(&self) -> Option<&Transcript> {
+        self.transcript.as_ref()
+    }
 
-        Ok(read_data)
+    pub fn transcript_mut(&mut self) -> Option<&mut Transcript> {
+        self.transcript.as_mut()
     }
 
-    /// Read UTF-8 response data from the instrument
-    pub fn read(&mut self, transfer_size: Option<u32>) -> TMCResult<String> {
-        //let read_data = self.read_raw(transfer_size, None)?;
-        let read_data = self.read_raw(transfer_size)?;
-        Ok(String::from_utf8(read_data)?)
+    /// Stop recording, returning the transcript
+    pub fn stop_transcript(&mut self) -> Option<Transcript> {
+        self.transcript.take()
     }
 
-    /// Write a UTF-8 command message to the instrument
-    pub fn write(&mut self, message: &str) -> TMCResult<()> {
-        self.write_raw(message.as_bytes())
+    /// Start capturing the bulk transfers exchanged with the instrument,
+    /// replacing any capture already running
+    #[cfg(feature = "capture")]
+    pub fn start_capture(&mut self, capture: FrameCapture) {
+        self.capture = Some(capture);
     }
 
-    /// Write a UTF-8 command message to the instrument and read a UTF-8 response
-    pub fn ask(&mut self, data: &str) -> TMCResult<String> {
-        let response_data = self.ask_raw(data.as_bytes())?;
-        let response_str = String::from_utf8(response_data)?;
-        Ok(response_str)
+    #[cfg(feature = "capture")]
+    pub fn capture(&self) -> Option<&FrameCapture> {
+        self.capture.as_ref()
     }
 
-    /// Write a command message to the instrument and read a response
-    pub fn ask_raw(&mut self, data: &[u8]) -> TMCResult<Vec<u8>> {
-        self.write_raw(data)?;
-        //self.read_raw(None, None)
-        self.read_raw(None)
+    #[cfg(feature = "capture")]
+    pub fn capture_mut(&mut self) -> Option<&mut FrameCapture> {
+        self.capture.as_mut()
     }
 
-    // TODO: support for vendor-specific bulk transfers
-    // TODO: support for interrupt in endpoint
-    // TODO: more complete support for USB488 features
+    /// Stop capturing transfers, returning the capture
+    #[cfg(feature = "capture")]
+    pub fn stop_capture(&mut self) -> Option<FrameCapture> {
+        self.capture.take()
+    }
+
+    /// The span the handle logs in, whose fields identify the instrument by
+    /// model, serial number and bus location where the transport can tell.
+    /// Enter it to label an application's own logs about the instrument the
+    /// same way.
+    #[cfg(feature = "tracing")]
+    pub fn span(&self) -> &tracing::Span {
+        &self.span
+    }
+
+    /// Get the most verbose level the handle logs at
+    #[cfg(feature = "tracing")]
+    pub fn get_log_level(&self) -> tracing::level_filters::LevelFilter {
+        self.log_level
+    }
+
+    /// Set the most verbose level the handle logs at, below any limit set by
+    /// the subscriber
+    #[cfg(feature = "tracing")]
+    pub fn set_log_level(&mut self, log_level: tracing::level_filters::LevelFilter) {
+        self.log_level = log_level;
+    }
+
+    /// Pass a bulk transfer to the frame trace and capture, if enabled
+    #[cfg_attr(
+        not(any(feature = "tracing", feature = "capture", feature = "metrics")),
+        allow(unused_variables)
+    )]
+    fn record_frame(&mut self, direction: Direction, frame: &[u8]) {
+        #[cfg(feature = "metrics")]
+        self.metrics.frame(direction, frame);
+
+        #[cfg(feature = "tracing")]
+        if self.log_level >= tracing::Level::TRACE {
+            self.span.in_scope(|| trace_frame(direction, frame));
+        }
+
+        #[cfg(feature = "capture")]
+        if let Some(capture) = &mut self.capture {
+            capture.record(SystemTime::now(), direction, frame);
+        }
+    }
+
+    fn record_transcript(&mut self, started: Timestamp, direction: Direction, message: &[u8]) {
+        #[cfg(feature = "metrics")]
+        self.metrics.latency(direction, started.elapsed());
+
+        if let Some(transcript) = &mut self.transcript {
+            transcript.record(TranscriptEntry {
+                timestamp: started.wall,
+                monotonic: Some(started.monotonic),
+                duration: started.elapsed(),
+                direction,
+                message: String::from_utf8_lossy(message).into_owned(),
+            });
+        }
+    }
+
+    /// Time a bulk transfer, if the transcript is timing transfers
+    fn record_transfer(&mut self, started: Timestamp, direction: Direction, length: usize) {
+        if let Some(transcript) = &mut self.transcript {
+            transcript.record_transfer(TransferEntry {
+                started,
+                duration: started.elapsed(),
+                direction,
+                length,
+            });
+        }
+    }
+
+    /// Register an observer to be told about events on this session
+    pub fn add_observer(&mut self, observer: Arc<dyn SessionObserver>) {
+        self.observers.push(observer);
+    }
+
+    /// Tell the observers about a failed operation.  If it failed because the
+    /// host slept during it, the session is revalidated, and the observers are
+    /// told it has resumed instead of that the device is disconnected.  An I/O
+    /// error is reported as the device having gone only if it then fails to
+    /// answer a request for its device descriptor as well.
+    fn observe<R>(&mut self, mut result: TMCResult<R>) -> TMCResult<R> {
+        if let Err(
+            error @ TMCError::Rusb {
+                source: rusb::Error::Io,
+            },
+        ) = &mut result
+        {
+            if self.device_gone() {
+                *error = rusb::Error::NoDevice.into();
+            }
+        }
+
+        if let Err(error) = &result {
+            #[cfg(feature = "metrics")]
+            self.metrics.error(error);
+
+            for observer in self.observers.iter() {
+                observer.error(error);
+            }
+            if error.is_disconnect() && !(self.host_slept() && self.revalidate_impl().is_ok()) {
+                for observer in self.observers.iter() {
+                    observer.disconnected();
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Whether the device has gone away, judged by whether it still answers a
+    /// standard GET_DESCRIPTOR request for its device descriptor.  Stalling the
+    /// request, as a device may, still shows it's there.
+    fn device_gone(&mut self) -> bool {
+        let request_type = rusb::request_type(
+            rusb::Direction::In,
+            rusb::RequestType::Standard,
+            rusb::Recipient::Device,
+        );
+        let mut buf = [0u8; DEVICE_DESCRIPTOR_SIZE];
+        let result = self.transport.read_control(
+            request_type,
+            GET_DESCRIPTOR,
+            DEVICE_DESCRIPTOR_TYPE << 8,
+            0,
+            &mut buf,
+            self.control_timeout.to_transfer(),
+        );
+        matches!(result, Err(rusb::Error::NoDevice | rusb::Error::Io))
+    }
+
+    pub(crate) fn notify_reconnected(&self) {
+        for observer in self.observers.iter() {
+            observer.reconnected();
+        }
+    }
+
+    /// Get the bulk transfer timeout, as described for
+    /// [Timeout::as_duration]
+    pub fn get_timeout(&self) -> Duration {
+        self.bulk_timeout.as_duration()
+    }
+
+    /// Set the timeout for all transfers: bulk, control and interrupt.  A
+    /// [Duration] can be given, where [Duration::ZERO] means
+    /// [Timeout::Immediate] and [Duration::MAX] means [Timeout::Forever].
+    pub fn set_timeout<D: Into<Timeout>>(&mut self, timeout: D) {
+        let timeout = timeout.into();
+        self.bulk_timeout = timeout;
+        self.control_timeout = timeout;
+        self.interrupt_timeout = timeout;
+    }
+
+    pub fn get_bulk_timeout(&self) -> Duration {
+        self.bulk_timeout.as_duration()
+    }
+
+    /// Set the timeout for each bulk transfer of a message, which may need to be
+    /// long for slow measurements
+    pub fn set_bulk_timeout<D: Into<Timeout>>(&mut self, timeout: D) {
+        self.bulk_timeout = timeout.into();
+    }
+
+    pub fn get_control_timeout(&self) -> Duration {
+        self.control_timeout.as_duration()
+    }
+
+    /// Set the timeout for control requests, which devices should answer quickly.
+    /// It also bounds how long a clear or abort the device reports as pending is
+    /// waited for before failing with a timeout.
+    pub fn set_control_timeout<D: Into<Timeout>>(&mut self, timeout: D) {
+        self.control_timeout = timeout.into();
+    }
+
+    pub fn get_interrupt_timeout(&self) -> Duration {
+        self.interrupt_timeout.as_duration()
+    }
+
+    /// Set the timeout for reading the status byte from the interrupt-in endpoint
+    pub fn set_interrupt_timeout<D: Into<Timeout>>(&mut self, timeout: D) {
+        self.interrupt_timeout = timeout.into();
+    }
+
+    /// Get how long past their timeout bulk transfers may block before the
+    /// watchdog triggers, if it is enabled
+    pub fn get_watchdog(&self) -> Option<Duration> {
+        self.watchdog.as_ref().map(Watchdog::grace)
+    }
+
+    /// Enable or disable a background thread which watches for bulk transfers
+    /// blocking for more than `grace` past their timeout.  When one does, the
+    /// observers registered so far are told straight away, and the transfer fails
+    /// with [WatchdogTriggered](TMCError::WatchdogTriggered) when it returns.
+    pub fn set_watchdog(&mut self, grace: Option<Duration>) {
+        self.watchdog = grace.map(|grace| Watchdog::spawn(grace, self.observers.clone()));
+    }
+
+    pub fn get_capabilities_policy(&self) -> CapabilitiesPolicy {
+        self.capabilities_policy
+    }
+
+    /// Choose whether a GET_CAPABILITIES response which is short or declares an
+    /// invalid combination of capabilities is rejected, or accepted with the
+    /// problems recorded in [capability_anomalies](Self::capability_anomalies).
+    /// Capabilities already read are forgotten, and read again on next use.
+    pub fn set_capabilities_policy(&mut self, capabilities_policy: CapabilitiesPolicy) {
+        if capabilities_policy != self.capabilities_policy {
+            self.capabilities_policy = capabilities_policy;
+            // capabilities parsed under the old policy may have been rejected
+            // or accepted differently under the new one
+            self.capabilities.take();
+            self.capability_anomalies.clear();
+        }
+    }
+
+    /// Problems with the device's GET_CAPABILITIES response which were
+    /// tolerated under [CapabilitiesPolicy::Lenient], once the capabilities
+    /// have been read
+    pub fn capability_anomalies(&self) -> &[CapabilitiesAnomaly] {
+        &self.capability_anomalies
+    }
+
+    pub fn get_padding_policy(&self) -> PaddingPolicy {
+        self.padding_policy
+    }
+
+    /// Choose whether bulk-in transfers with missing or nonzero alignment padding
+    /// are rejected, or accepted and counted in the link diagnostics.
+    pub fn set_padding_policy(&mut self, padding_policy: PaddingPolicy) {
+        self.padding_policy = padding_policy;
+    }
+
+    pub fn get_encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Set the character set used by the text API ([write](Self::write),
+    /// [read](Self::read), [ask](Self::ask) and friends) in both directions
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.encoding = encoding;
+    }
+
+    pub fn get_text_decoding(&self) -> TextDecoding {
+        self.text_decoding
+    }
+
+    /// Choose how [read](Self::read) and [ask](Self::ask) handle responses which
+    /// aren't valid in the handle's [Encoding]
+    pub fn set_text_decoding(&mut self, text_decoding: TextDecoding) {
+        self.text_decoding = text_decoding;
+    }
+
+    /// Get the counts of protocol anomalies tolerated on this link
+    pub fn diagnostics(&self) -> &LinkDiagnostics {
+        &self.diagnostics
+    }
+
+    pub fn reset_diagnostics(&mut self) {
+        self.diagnostics = LinkDiagnostics::default();
+    }
+
+    /// Get the idle time after which [release_if_idle](Self::release_if_idle) releases
+    /// the TMC interface, if enabled.
+    pub fn get_idle_release(&self) -> Option<Duration> {
+        self.idle_release
+    }
+
+    /// Allow the TMC interface to be released after it has been idle for the given
+    /// time, so that other software (such as vendor tools) can use the instrument
+    /// while this handle is kept open.  The release itself happens when
+    /// [release_if_idle](Self::release_if_idle) is called, either from an
+    /// application's main loop or, for applications which block elsewhere, by a
+    /// background task started with
+    /// [release_when_idle](crate::tasks::TaskScope::release_when_idle).  The
+    /// interface is claimed again automatically the next time the handle is used.
+    pub fn set_idle_release(&mut self, idle_release: Option<Duration>) {
+        self.idle_release = idle_release;
+    }
+
+    /// Release the TMC interface if idle release is enabled and the handle has not
+    /// been used for at least the configured time.  Returns whether the interface
+    /// was released by this call.
+    pub fn release_if_idle(&mut self) -> TMCResult<bool> {
+        match self.idle_release {
+            Some(idle) if self.interface_claimed && self.last_activity.elapsed() >= idle => {
+                self.transport.release_interface()?;
+                self.interface_claimed = false;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Whether the TMC interface is currently claimed by this handle
+    pub fn is_interface_claimed(&self) -> bool {
+        self.interface_claimed
+    }
+
+    /// Called at the start of every operation: re-claim the interface if it was
+    /// released while idle, and note the activity.  In shared mode the
+    /// interface is only claimed if it is free, and operations carry on
+    /// without it otherwise.
+    fn ensure_claimed(&mut self) -> TMCResult<()> {
+        if self.host_slept() {
+            return self.revalidate_impl();
+        }
+
+        if !self.interface_claimed {
+            match self.claim_interface() {
+                Err(_) if self.shared => {}
+                result => result?,
+            }
+        }
+
+        self.last_activity = Timestamp::now();
+        Ok(())
+    }
+
+    /// Claim the TMC interface, retrying for up to the claim retry window while
+    /// something else holds it.
+    fn claim_interface(&mut self) -> TMCResult<()> {
+        // too far in the future to represent is as good as never
+        let deadline = Instant::now().checked_add(self.claim_retry);
+
+        loop {
+            match self.transport.claim_interface() {
+                Ok(()) => break,
+                Err(rusb::Error::Busy) => {
+                    let remaining = deadline.map_or(self.claim_retry, |deadline| {
+                        deadline.saturating_duration_since(Instant::now())
+                    });
+                    if remaining.is_zero() {
+                        return Err(ConnectError::InterfaceBusy {
+                            interface: self.interface().interface_number,
+                            holder: self.transport.interface_holder(),
+                        }
+                        .into());
+                    }
+
+                    sleep(remaining.min(Duration::from_millis(100)));
+                }
+                Err(rusb_error) => return Err(ConnectError::from_open(rusb_error).into()),
+            }
+        }
+
+        self.interface_claimed = true;
+        Ok(())
+    }
+
+    /// Whether the handle was opened in [shared mode](OpenOptions::shared)
+    pub fn is_shared(&self) -> bool {
+        self.shared
+    }
+
+    /// Refuse an operation which would disturb other software using the
+    /// instrument, if the handle is in shared mode
+    fn check_exclusive(&self, operation: &str) -> TMCResult<()> {
+        if self.shared {
+            Err(TMCError::SharedMode {
+                operation: operation.to_owned(),
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn get_claim_retry(&self) -> Duration {
+        self.claim_retry
+    }
+
+    /// Set how long to keep trying to claim the TMC interface while it is in use by
+    /// another driver or process, when it needs to be claimed again after
+    /// [release_if_idle](Self::release_if_idle).
+    pub fn set_claim_retry(&mut self, window: Duration) {
+        self.claim_retry = window;
+    }
+
+    /// Get the latency histograms recorded so far for each class of transaction
+    #[cfg(feature = "timing")]
+    pub fn timing_report(&self) -> TimingReport {
+        self.timing.clone()
+    }
+
+    /// Discard all latency data recorded so far
+    #[cfg(feature = "timing")]
+    pub fn reset_timing(&mut self) {
+        self.timing.reset();
+    }
+
+    /// Send a custom control request to the TMC interface and read the device's
+    /// response into `data`, returning the number of bytes received.
+    pub fn control_in(
+        &mut self,
+        scope: RequestScope,
+        request: u8,
+        value: u16,
+        data: &mut [u8],
+    ) -> TMCResult<usize> {
+        self.ensure_claimed()?;
+
+        let request_type = rusb::request_type(
+            rusb::Direction::In,
+            scope.into(),
+            rusb::Recipient::Interface,
+        );
+
+        let index = self.interface().interface_number as u16;
+        Ok(self.transport.read_control(
+            request_type,
+            request,
+            value,
+            index,
+            data,
+            self.control_timeout.to_transfer(),
+        )?)
+    }
+
+    /// Send a custom control request with `data` as its payload to the TMC
+    /// interface, returning the number of bytes sent.
+    pub fn control_out(
+        &mut self,
+        scope: RequestScope,
+        request: u8,
+        value: u16,
+        data: &[u8],
+    ) -> TMCResult<usize> {
+        self.check_exclusive("a control-out request")?;
+        self.ensure_claimed()?;
+
+        let request_type = rusb::request_type(
+            rusb::Direction::Out,
+            scope.into(),
+            rusb::Recipient::Interface,
+        );
+
+        let index = self.interface().interface_number as u16;
+        Ok(self.transport.write_control(
+            request_type,
+            request,
+            value,
+            index,
+            data,
+            self.control_timeout.to_transfer(),
+        )?)
+    }
+
+    fn read_control(
+        &mut self,
+        request: ControlRequest,
+        read_size: usize,
+        out: &mut Vec<u8>,
+    ) -> TMCResult<()> {
+        if !matches!(
+            request,
+            ControlRequest::GetCapabilities | ControlRequest::Tmc488ReadStatusByte
+        ) {
+            self.check_exclusive(&format!("the {:?} request", request))?;
+        }
+        self.ensure_claimed()?;
+
+        let request_type = rusb::request_type(
+            rusb::Direction::In,
+            rusb::RequestType::Class,
+            rusb::Recipient::Interface,
+        );
+
+        out.resize(read_size, 0);
+        self.incr_b_tag();
+        let value = match request {
+            ControlRequest::Tmc488ReadStatusByte => status_b_tag(self.b_tag),
+            _ => self.b_tag,
+        };
+        let index = self.interface().interface_number as u16;
+        let size = self.transport.read_control(
+            request_type,
+            request as u8,
+            value as u16,
+            index,
+            out,
+            self.control_timeout.to_transfer(),
+        )?;
+        // self.transport.read_control(
+        //   request_type,
+        //   request as u8,
+        //   0x0000,
+        //   self.interface().interface_number as u16,
+        //   out,
+        //   self.control_timeout,
+        // )?;
+        out.truncate(size);
+
+        Ok(())
+    }
+
+    /// Send a class request addressed to one of the TMC interface's endpoints, as
+    /// used by the abort requests.
+    fn read_endpoint_control(
+        &mut self,
+        request: ControlRequest,
+        value: u16,
+        endpoint: u8,
+        read_size: usize,
+        out: &mut Vec<u8>,
+    ) -> TMCResult<()> {
+        self.check_exclusive(&format!("the {:?} request", request))?;
+        self.ensure_claimed()?;
+
+        let request_type = rusb::request_type(
+            rusb::Direction::In,
+            rusb::RequestType::Class,
+            rusb::Recipient::Endpoint,
+        );
+
+        out.resize(read_size, 0);
+        let size = self.transport.read_control(
+            request_type,
+            request as u8,
+            value,
+            endpoint as u16,
+            out,
+            self.control_timeout.to_transfer(),
+        )?;
+        out.truncate(size);
+
+        Ok(())
+    }
+
+    /// Send USBTMC "abort bulk out" command for the most recent bulk transfer.  It
+    /// is not an error if the device has no transfer in progress.
+    pub fn abort_bulk_out(&mut self) -> TMCResult<()> {
+        let result = self.abort_bulk_out_transfer();
+        self.observe(result)
+    }
+
+    fn abort_bulk_out_transfer(&mut self) -> TMCResult<()> {
+        let ep = self.bulk_endpoint(Operation::BulkOut)?;
+        let mut out = Vec::with_capacity(8);
+        self.read_endpoint_control(
+            ControlRequest::InitiateAbortBulkOut,
+            self.last_bulk_tag as u16,
+            ep,
+            2,
+            &mut out,
+        )?;
+        self.partial_message = None;
+
+        match ControlRequest::read_response_status(&out)? {
+            Status::Success => {}
+            // nothing in progress, or a transfer other than the one we asked about
+            Status::Failed | Status::TransferNotInProgress => return Ok(()),
+            status => status.check()?,
+        };
+
+        // device accepted the abort, wait while status is "pending"
+        let started = Instant::now();
+        loop {
+            self.read_endpoint_control(
+                ControlRequest::CheckAbortBulkOutStatus,
+                0,
+                ep,
+                8,
+                &mut out,
+            )?;
+
+            match ControlRequest::read_response_status(&out)? {
+                Status::Success => break,
+                Status::Pending => {}
+                status => status.check()?,
+            };
+
+            self.check_pending(started)?;
+            sleep(Duration::from_millis(100));
+        }
+
+        self.transport.clear_halt(ep)?;
+        Ok(())
+    }
+
+    /// Send USBTMC "abort bulk in" command for the most recent bulk transfer,
+    /// discarding any response data the device has already queued.  It is not an
+    /// error if the device has no transfer in progress.
+    pub fn abort_bulk_in(&mut self) -> TMCResult<()> {
+        let result = self.abort_bulk_in_transfer();
+        self.observe(result)
+    }
+
+    fn abort_bulk_in_transfer(&mut self) -> TMCResult<()> {
+        self.try_abort_bulk_in().map(drop)
+    }
+
+    /// Abort the most recent bulk-in transfer, returning whether the device
+    /// still had it in progress
+    fn try_abort_bulk_in(&mut self) -> TMCResult<bool> {
+        let ep = self.bulk_endpoint(Operation::BulkIn)?;
+        let mut out = Vec::with_capacity(8);
+        self.read_endpoint_control(
+            ControlRequest::InitiateAbortBulkIn,
+            self.last_bulk_tag as u16,
+            ep,
+            2,
+            &mut out,
+        )?;
+        self.response_pending = false;
+        self.bulk_in_outstanding = false;
+        self.stray_padding = 0;
+
+        match ControlRequest::read_response_status(&out)? {
+            Status::Success => {}
+            // nothing in progress, or a transfer other than the one we asked about
+            Status::Failed | Status::TransferNotInProgress => return Ok(false),
+            status => status.check()?,
+        };
+
+        // device accepted the abort; read whatever it had queued, then wait while
+        // status is "pending", reading again whenever it reports more queued data
+        let started = Instant::now();
+        loop {
+            self.drain_bulk_in()?;
+            self.read_endpoint_control(ControlRequest::CheckAbortBulkInStatus, 0, ep, 8, &mut out)?;
+
+            let queued = match ControlRequest::read_response_status(&out)? {
+                Status::Success => break,
+                Status::Pending => out.len() > 1 && out[1] & 0x01 != 0,
+                status => {
+                    status.check()?;
+                    false
+                }
+            };
+
+            self.check_pending(started)?;
+            if !queued {
+                sleep(Duration::from_millis(100));
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Fail with a timeout once a request the device keeps reporting as pending
+    /// has been waited on for longer than the control timeout, so that a device
+    /// stuck answering STATUS_PENDING can't hang the handle
+    fn check_pending(&self, started: Instant) -> TMCResult<()> {
+        if started.elapsed() >= self.control_timeout.as_duration() {
+            return Err(rusb::Error::Timeout.into());
+        }
+        Ok(())
+    }
+
+    /// Perform a transfer with the handle's timeout, under the watchdog if it is
+    /// enabled
+    fn watched<R, F>(&mut self, operation: Operation, mut f: F) -> TMCResult<R>
+    where
+        F: FnMut(&mut T, Duration) -> rusb::Result<R>,
+    {
+        match operation {
+            Operation::BulkOut => self.check_exclusive("sending a message")?,
+            Operation::BulkIn => self.check_exclusive("reading a message")?,
+            _ => {}
+        }
+        let result = self.watched_once(operation, &mut f);
+
+        let stalled = matches!(
+            result,
+            Err(TMCError::Rusb {
+                source: rusb::Error::Pipe
+            })
+        );
+        if !stalled || self.stall_recovery == StallRecovery::Fail {
+            return result;
+        }
+
+        let ep = self.bulk_endpoint(operation)?;
+        self.transport.clear_halt(ep)?;
+        self.diagnostics.stalls_cleared += 1;
+
+        self.watched_once(operation, &mut f)
+    }
+
+    fn watched_once<R, F>(&mut self, operation: Operation, f: &mut F) -> TMCResult<R>
+    where
+        F: FnMut(&mut T, Duration) -> rusb::Result<R>,
+    {
+        if let Some(watchdog) = &self.watchdog {
+            watchdog.watch(operation, self.bulk_timeout.as_duration());
+        }
+
+        let result = f(&mut self.transport, self.bulk_timeout.to_transfer());
+
+        if let Some(elapsed) = self.watchdog.as_ref().and_then(Watchdog::finish) {
+            return Err(TMCError::WatchdogTriggered { operation, elapsed });
+        }
+
+        Ok(result?)
+    }
+
+    /// Read one complete bulk-in transfer (header, data and any alignment padding)
+    /// of up to `transfer_size` bytes of data into `buf`.  A transfer may arrive in
+    /// several pieces, either because the device splits it or because it is larger
+    /// than a single read of at most [MAX_BULK_IN_READ] bytes, so keep reading until
+    /// all the data declared in the header has arrived.  A header declaring more
+    /// data than was requested fails the read rather than being trusted.
+    fn read_bulk_in_transfer(&mut self, transfer_size: u32, buf: &mut Vec<u8>) -> TMCResult<()> {
+        let ep = self.bulk_endpoint(Operation::BulkIn)?;
+        let packet_size = self.bulk_in_packet_size();
+        let started = Timestamp::now();
+
+        // until the header has arrived, all we know is how much was requested
+        let mut expected = HEADER_SIZE + transfer_size as usize;
+        buf.clear();
+
+        loop {
+            if buf.len() >= HEADER_SIZE {
+                let header = DevDepMsgInHeader::unpack(buf)?;
+                if header.transfer_size > transfer_size {
+                    return Err(ClassError::TransferTooLarge {
+                        requested: transfer_size,
+                        announced: header.transfer_size,
+                    }
+                    .into());
+                }
+                expected = HEADER_SIZE + header.transfer_size as usize;
+
+                if buf.len() >= expected {
+                    self.stray_padding =
+                        (expected.saturating_add(3) & !3).saturating_sub(buf.len());
+                    self.bulk_in_outstanding = false;
+                    self.record_transfer(started, Direction::Received, buf.len());
+                    return Ok(());
+                }
+            }
+
+            // ask for the rest, including padding, in whole packets so that the
+            // device can't overflow the buffer
+            let received = buf.len();
+            let remaining = (expected.saturating_add(3) & !3)
+                .saturating_sub(received)
+                .max(1);
+            let request = (remaining.div_ceil(packet_size) * packet_size).min(MAX_BULK_IN_READ);
+
+            buf.resize(received + request, 0);
+            let result = self.watched(Operation::BulkIn, |transport, timeout| {
+                transport.read_bulk(ep, &mut buf[received..], timeout)
+            });
+            let n_read = *result.as_ref().unwrap_or(&0);
+            buf.truncate(received + n_read);
+            result?;
+
+            // padding split from the end of the previous transfer, which is
+            // zero, unlike the MsgID every transfer starts with
+            if received == 0 && self.stray_padding > 0 {
+                let stray = buf
+                    .iter()
+                    .take(self.stray_padding)
+                    .take_while(|&&byte| byte == 0)
+                    .count();
+                buf.drain(..stray);
+                self.stray_padding = 0;
+                if buf.is_empty() && n_read > 0 {
+                    continue;
+                }
+            }
+
+            if n_read == 0 {
+                return Err(if received < HEADER_SIZE {
+                    ClassError::TruncatedHeader { received }
+                } else {
+                    ClassError::TruncatedBulkIn { expected, received }
+                }
+                .into());
+            }
+        }
+    }
+
+    /// Apply the padding policy to a complete bulk-in transfer
+    fn check_padding(&mut self, transfer: &[u8], data_end: usize) -> TMCResult<()> {
+        match check_padding(transfer, data_end) {
+            None => {}
+            Some(_) if self.padding_policy == PaddingPolicy::Strict => {
+                return Err(ClassError::InvalidPadding.into());
+            }
+            Some(PaddingViolation::Missing) => self.diagnostics.missing_padding += 1,
+            Some(PaddingViolation::NonZero) => self.diagnostics.nonzero_padding += 1,
+        }
+
+        Ok(())
+    }
+
+    fn bulk_in_packet_size(&self) -> usize {
+        self.interface().bulk_in_max_packet()
+    }
+
+    /// Read and discard bulk-in data until the device sends a short packet (or
+    /// nothing at all).
+    fn drain_bulk_in(&mut self) -> TMCResult<()> {
+        self.check_exclusive("reading a message")?;
+        let ep = self.bulk_endpoint(Operation::BulkIn)?;
+        let packet_size = self.bulk_in_packet_size();
+
+        let mut buf = vec![0u8; packet_size];
+        loop {
+            match self
+                .transport
+                .read_bulk(ep, &mut buf, self.bulk_timeout.to_transfer())
+            {
+                Ok(n) if n == packet_size => {}
+                Ok(_) | Err(rusb::Error::Timeout) => return Ok(()),
+                Err(rusb_error) => return Err(rusb_error.into()),
+            }
+        }
+    }
+
+    // Send USBTMC "clear" command
+    pub fn clear(&mut self) -> TMCResult<()> {
+        let result = self.clear_device();
+        if result.is_ok() {
+            self.forget_device_state();
+            #[cfg(feature = "scpi")]
+            if let Some(cache) = &mut self.response_cache {
+                cache.invalidate();
+            }
+            for observer in self.observers.iter() {
+                observer.cleared();
+            }
+        }
+        self.observe(result)
+    }
+
+    fn clear_device(&mut self) -> TMCResult<()> {
+        let mut out = Vec::with_capacity(2);
+        self.read_control(ControlRequest::InitiateClear, 1, &mut out)?;
+
+        ControlRequest::check_response_status(&out)?;
+        self.response_pending = false;
+        self.bulk_in_outstanding = false;
+        self.stray_padding = 0;
+        self.partial_message = None;
+
+        // device accepted `clear` command, wait while status is "pending"
+        let started = Instant::now();
+        loop {
+            self.read_control(ControlRequest::CheckClearStatus, 2, &mut out)?;
+
+            match ControlRequest::read_response_status(&out)? {
+                Status::Success => break,
+                Status::Pending => {}
+                status => status.check()?,
+            };
+
+            self.check_pending(started)?;
+            sleep(Duration::from_millis(100));
+        }
+
+        if let Some(ep) = self.interface().bulk_out_address {
+            self.transport.clear_halt(ep)?;
+        }
+
+        if self.reset_tag_on_clear {
+            self.b_tag = 0;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn get_capabilities(
+        &mut self,
+    ) -> TMCResult<(USBTMCCapabilities, Option<USB488Capabilities>)> {
+        // 64 bytes is the largest possible control transfer, so use that to avoid
+        // overflow if the device sends a lot back.
+        let mut out = vec![0u8; 64];
+        self.read_control(ControlRequest::GetCapabilities, 64, &mut out)?;
+
+        let policy = self.capabilities_policy;
+        let mut anomalies = Vec::new();
+        let usbtmc_capabilities = USBTMCCapabilities::parse_with(&out, policy, &mut anomalies)?;
+
+        let usb488_capabilities = if self.interface().interface_protocol == 1 {
+            USB488Capabilities::parse_with(&usbtmc_capabilities, &out, policy, &mut anomalies)?
+        } else {
+            None
+        };
+
+        #[cfg(feature = "tracing")]
+        if self.log_level >= tracing::Level::WARN {
+            let _entered = self.span.enter();
+            for anomaly in anomalies.iter() {
+                tracing::warn!(%anomaly, "tolerated invalid capabilities");
+            }
+        }
+        self.capability_anomalies = anomalies;
+
+        Ok((usbtmc_capabilities, usb488_capabilities))
+    }
+
+    fn capabilities(&mut self) -> TMCResult<&(USBTMCCapabilities, Option<USB488Capabilities>)> {
+        if self.capabilities.get().is_none() {
+            let capabilities = self.get_capabilities()?;
+            let _ = self.capabilities.set(capabilities);
+        }
+        Ok(self.capabilities.get().unwrap())
+    }
+
+    /// Forget the capabilities and identity read from the device.  They are kept
+    /// for the rest of the session once read, so that feature checks don't cost
+    /// a control request each, and are only read again after a clear or after
+    /// revalidating, which are how a session is brought back from a device in an
+    /// unknown state.
+    fn forget_device_state(&mut self) {
+        self.capabilities.take();
+        self.capability_anomalies.clear();
+        #[cfg(feature = "scpi")]
+        self.scpi_id.take();
+    }
+
+    /// The device's USBTMC capabilities, read from it on first use
+    pub fn usbtmc_capabilities(&mut self) -> TMCResult<&USBTMCCapabilities> {
+        Ok(&self.capabilities()?.0)
+    }
+
+    /// The device's USB488 capabilities, if it is a USB488 device, read from it on
+    /// first use
+    pub fn usb488_capabilities(&mut self) -> TMCResult<Option<&USB488Capabilities>> {
+        Ok(self.capabilities()?.1.as_ref())
+    }
+
+    /// How the device supports `feature`: as declared by its capabilities, unless
+    /// support has been recorded by [probe_support](Self::probe_support) or
+    /// [set_probed_support](Self::set_probed_support)
+    pub fn supports(&mut self, feature: Feature) -> TMCResult<SupportLevel> {
+        // Only service requests can be used without disturbing other software
+        if self.shared && feature != Feature::Srq {
+            return Ok(SupportLevel::Unsupported);
+        }
+        let declared = self.declares(feature)?;
+        Ok(match (self.probed_support.get(&feature), declared) {
+            (Some(false), _) => SupportLevel::Unsupported,
+            (_, true) => SupportLevel::Declared,
+            (Some(true), false) => SupportLevel::Probed,
+            (None, false) => SupportLevel::Unsupported,
+        })
+    }
+
+    /// Whether the device's capabilities declare `feature`
+    fn declares(&mut self, feature: Feature) -> TMCResult<bool> {
+        let has_interrupt_in = self.interface().interrupt_in_address.is_some();
+        let usb488 = self.usb488_capabilities()?;
+
+        Ok(match feature {
+            Feature::Trigger => usb488.is_some_and(|caps| caps.trigger),
+            Feature::Srq => has_interrupt_in && usb488.is_some_and(|caps| caps.sr),
+            Feature::RemoteLocal => usb488.is_some_and(|caps| caps.remote_local),
+            Feature::Scpi => usb488.is_some_and(|caps| caps.scpi),
+            Feature::Usb488_2 => usb488.is_some_and(|caps| caps.usb488_2),
+            Feature::TermChar => self.usbtmc_capabilities()?.term_char,
+            Feature::Pulse => self.usbtmc_capabilities()?.pulse,
+            Feature::VendorBulk => false,
+        })
+    }
+
+    /// Find out whether a feature the device doesn't declare works anyway, and
+    /// record the outcome.  SCPI is probed by querying `*IDN?`, and pulse by
+    /// requesting one; other features can't be probed safely, and keep the
+    /// support they have.  Probing sends requests to the device, so it shouldn't
+    /// be done while the device is in use.
+    pub fn probe_support(&mut self, feature: Feature) -> TMCResult<SupportLevel> {
+        let result = self.probe(feature);
+        self.observe(result)
+    }
+
+    fn probe(&mut self, feature: Feature) -> TMCResult<SupportLevel> {
+        if !self.probed_support.contains_key(&feature) && !self.declares(feature)? {
+            let works = match feature {
+                Feature::Scpi => Some(self.probe_scpi()?),
+                Feature::Pulse => Some(self.probe_pulse()?),
+                _ => None,
+            };
+            if let Some(works) = works {
+                self.probed_support.insert(feature, works);
+            }
+        }
+
+        self.supports(feature)
+    }
+
+    fn probe_scpi(&mut self) -> TMCResult<bool> {
+        self.send_message(b"*IDN?")?;
+
+        // a device which doesn't understand the query may not answer at all,
+        // which mustn't poison the handle
+        let timeout = std::mem::replace(&mut self.bulk_timeout, PROBE_TIMEOUT.into());
+        let poison_policy = std::mem::replace(&mut self.poison_policy, PoisonPolicy::Never);
+        let result = self.read_message(None);
+        self.bulk_timeout = timeout;
+        self.poison_policy = poison_policy;
+
+        match result {
+            Ok(response) => Ok(response.contains(&b',')),
+            Err(TMCError::Rusb {
+                source: rusb::Error::Timeout,
+            }) => {
+                // the device still holds our request for data
+                self.abort_bulk_in_transfer()?;
+                Ok(false)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    fn probe_pulse(&mut self) -> TMCResult<bool> {
+        match self.indicator_pulse() {
+            Ok(()) => Ok(true),
+            Err(TMCError::Class { .. })
+            | Err(TMCError::Rusb {
+                source: rusb::Error::Pipe,
+            }) => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Record whether `feature` works, from an application's own probe or a
+    /// known quirk of the device, overriding what it declares; `None` forgets
+    /// what was recorded
+    pub fn set_probed_support(&mut self, feature: Feature, works: Option<bool>) {
+        match works {
+            Some(works) => self.probed_support.insert(feature, works),
+            None => self.probed_support.remove(&feature),
+        };
+    }
+
+    #[cfg(feature = "scpi")]
+    fn is_scpi(&mut self) -> TMCResult<bool> {
+        Ok(self.supports(Feature::Scpi)?.is_supported())
+    }
+
+    /// The device's response to `*IDN?`, if it supports SCPI, queried on first use.
+    /// As when it was queried on connecting, a device which doesn't answer has
+    /// no identity, but it is asked again next time.
+    #[cfg(feature = "scpi")]
+    pub fn scpi_id(&mut self) -> TMCResult<Option<&str>> {
+        if self.scpi_id.get().is_none() {
+            let scpi_id = if self.is_scpi()? {
+                match self.ask("*IDN?") {
+                    Ok(scpi_id) => Some(scpi_id.trim().to_owned()),
+                    Err(_) => return Ok(None),
+                }
+            } else {
+                None
+            };
+            let _ = self.scpi_id.set(scpi_id);
+        }
+
+        Ok(self.scpi_id.get().and_then(Option::as_deref))
+    }
+
+    pub fn pulse(&mut self) -> TMCResult<()> {
+        if !self.supports(Feature::Pulse)?.is_supported() {
+            return Err(ClassError::UnsupportedFeature.into());
+        }
+
+        self.indicator_pulse()
+    }
+
+    /// Trigger the device as chosen by its [TriggerStrategy]: by default with the
+    /// USB488 TRIGGER message, which triggers the device like the IEEE 488.1 GET
+    /// message, if it supports it and otherwise with `*TRG`
+    pub fn trigger(&mut self) -> TMCResult<()> {
+        let strategy = self.trigger_strategy.clone();
+        self.trigger_with(&strategy)
+    }
+
+    /// Trigger the device with `strategy` rather than the handle's.  With
+    /// [Usb488](TriggerStrategy::Usb488), fails with
+    /// [UnsupportedFeature](ClassError::UnsupportedFeature) unless the device
+    /// supports the TRIGGER message.
+    pub fn trigger_with(&mut self, strategy: &TriggerStrategy) -> TMCResult<()> {
+        match self.resolve_trigger_strategy(strategy)? {
+            TriggerStrategy::Command(command) => self.write(&command),
+            _ => {
+                let result = if self.supports(Feature::Trigger)?.is_supported() {
+                    self.guarded(|handle| handle.send_trigger())
+                } else {
+                    Err(ClassError::UnsupportedFeature.into())
+                };
+                self.observe(result)
+            }
+        }
+    }
+
+    /// Settle what [Auto](TriggerStrategy::Auto) means for this device
+    pub(crate) fn resolve_trigger_strategy(
+        &mut self,
+        strategy: &TriggerStrategy,
+    ) -> TMCResult<TriggerStrategy> {
+        Ok(match strategy {
+            TriggerStrategy::Auto => {
+                if self.supports(Feature::Trigger)?.is_supported() {
+                    TriggerStrategy::Usb488
+                } else {
+                    TriggerStrategy::Command("*TRG".to_owned())
+                }
+            }
+            strategy => strategy.clone(),
+        })
+    }
+
+    fn send_trigger(&mut self) -> TMCResult<()> {
+        if self.partial_message.is_some() {
+            return Err(TMCError::MessageInProgress);
+        }
+        self.ensure_claimed()?;
+        self.resolve_pending_request()?;
+
+        self.incr_b_tag();
+        self.last_bulk_tag = self.b_tag;
+        let mut buf = Vec::with_capacity(HEADER_SIZE);
+        TriggerHeader::encode_message(self.b_tag, &mut buf);
+
+        self.record_frame(Direction::Sent, &buf);
+        self.write_transfer(&buf)
+    }
+
+    fn indicator_pulse(&mut self) -> TMCResult<()> {
+        let mut out = Vec::with_capacity(1);
+        self.read_control(ControlRequest::IndicatorPulse, 1, &mut out)?;
+        ControlRequest::check_response_status(&out)?;
+        Ok(())
+    }
+
+    fn incr_b_tag(&mut self) {
+        let mut b_tag = self.tag_policy.next_tag(self.b_tag);
+        if b_tag == 0 {
+            // 0 is never a valid bTag, whatever the policy says
+            b_tag = DefaultTagPolicy.next_tag(self.b_tag);
+        }
+        if b_tag < self.b_tag {
+            self.diagnostics.b_tag_wraps += 1;
+        }
+        self.b_tag = b_tag;
+    }
+
+    /// Write a command message to the instrument
+    pub fn write_raw(&mut self, data: &[u8]) -> TMCResult<()> {
+        let mut result = self.send_message(data);
+
+        if result.is_ok() && self.verify_writes {
+            result = self.verify_write();
+        }
+
+        self.observe(result)
+    }
+
+    fn send_message(&mut self, data: &[u8]) -> TMCResult<()> {
+        if self.partial_message.is_some() {
+            return Err(TMCError::MessageInProgress);
+        }
+        self.check_command_policy(data)?;
+
+        let started = Timestamp::now();
+
+        self.send_transfers(data, true)?;
+
+        #[cfg(feature = "timing")]
+        self.timing.record(OperationClass::Write, started.elapsed());
+
+        self.response_pending |= is_query(data);
+
+        self.record_transcript(started, Direction::Sent, data);
+        Ok(())
+    }
+
+    /// Refuse a message if the command policy rejects any of its commands
+    fn check_command_policy(&self, data: &[u8]) -> TMCResult<()> {
+        match &self.command_policy {
+            Some(policy) => check_commands(policy.as_ref(), commands(data)),
+            None => Ok(()),
+        }
+    }
+
+    /// Run part of a message exchange, unless the handle has been poisoned by an
+    /// earlier error, and poison it if this part fails as set by the poison
+    /// policy
+    fn guarded<R, F>(&mut self, f: F) -> TMCResult<R>
+    where
+        F: FnOnce(&mut Self) -> TMCResult<R>,
+    {
+        if let Some(cause) = &self.poisoned {
+            return Err(TMCError::Poisoned(Box::new(cause.clone())));
+        }
+
+        let result = f(self);
+        if let Err(error) = &result {
+            if self.poison_policy.poisons(error) {
+                self.poisoned = Some(error.clone());
+            }
+        }
+
+        result
+    }
+
+    /// Send data in transfers of up to the maximum transfer size, marking the
+    /// last one as the end of the message if `eom` is set
+    fn send_transfers(&mut self, data: &[u8], eom: bool) -> TMCResult<()> {
+        self.guarded(|handle| handle.write_transfers(data, eom))
+    }
+
+    fn write_transfers(&mut self, data: &[u8], eom: bool) -> TMCResult<()> {
+        self.ensure_claimed()?;
+        self.resolve_pending_request()?;
+
+        let mut buf = Vec::with_capacity(HEADER_SIZE + data.len() + 3);
+        let mut end_offset: usize = 0;
+
+        for block in data.chunks(self.max_transfer_size as usize) {
+            end_offset += block.len();
+            let eom = eom && end_offset >= data.len();
+
+            self.incr_b_tag();
+            self.last_bulk_tag = self.b_tag;
+            DevDepMsgOutHeader::encode_message(self.b_tag, block, eom, &mut buf);
+
+            self.record_frame(Direction::Sent, &buf);
+            self.write_transfer(&buf)?;
+        }
+
+        Ok(())
+    }
+
+    /// Send a whole bulk-out transfer, header and padding included.  The
+    /// device sees the endpoint as a stream of packets, so after a short write
+    /// the rest of the transfer is sent to carry on from where it stopped.
+    fn write_transfer(&mut self, buf: &[u8]) -> TMCResult<()> {
+        let ep = self.bulk_endpoint(Operation::BulkOut)?;
+        let started = Timestamp::now();
+
+        let mut written = 0;
+        while written < buf.len() {
+            let n = self.watched(Operation::BulkOut, |transport, timeout| {
+                transport.write_bulk(ep, &buf[written..], timeout)
+            })?;
+            if n == 0 {
+                return Err(ClassError::TruncatedBulkOut {
+                    expected: buf.len(),
+                    received: written,
+                }
+                .into());
+            }
+            written += n;
+        }
+
+        self.record_transfer(started, Direction::Sent, buf.len());
+        Ok(())
+    }
+
+    /// Write part of a command message, for building one message over several
+    /// calls when the whole message isn't known up front.  Data is sent in
+    /// transfers of the maximum transfer size as it accumulates, but the end of
+    /// the message is only marked by [flush_message](Self::flush_message).  Other
+    /// writes fail with [MessageInProgress](TMCError::MessageInProgress) until then.
+    /// Each message unit is checked against the command policy once its text is
+    /// complete, and held back until it has been; a part completing a rejected
+    /// unit is not taken, and the rest of the message can still be written.
+    pub fn write_partial_raw(&mut self, data: &[u8]) -> TMCResult<()> {
+        let result = self.send_partial(data);
+        self.observe(result)
+    }
+
+    fn send_partial(&mut self, data: &[u8]) -> TMCResult<()> {
+        let mut commands = self
+            .partial_message
+            .as_ref()
+            .map(|partial| partial.commands.clone())
+            .unwrap_or_default();
+        if let Some(policy) = &self.command_policy {
+            check_commands(policy.as_ref(), commands.feed(data))?;
+        }
+
+        let record = self.transcript.is_some();
+        let partial = self.partial_message.get_or_insert_with(|| PartialMessage {
+            started: Timestamp::now(),
+            held: Vec::new(),
+            sent: Vec::new(),
+            query: QueryScan::default(),
+            commands: CommandScan::default(),
+        });
+        partial.held.extend_from_slice(data);
+        partial.query.feed(data);
+        partial.commands = commands;
+
+        // always hold some data back, as the transfer marking the end of the
+        // message can't be empty, along with any not checked yet
+        let max_transfer_size = self.max_transfer_size as usize;
+        let checked = partial.held.len() - partial.commands.unchecked().min(partial.held.len());
+        let ready = checked.min(partial.held.len().saturating_sub(1)) / max_transfer_size
+            * max_transfer_size;
+        if ready == 0 {
+            return Ok(());
+        }
+
+        let block: Vec<u8> = partial.held.drain(..ready).collect();
+        if record {
+            partial.sent.extend_from_slice(&block);
+        }
+
+        self.send_transfers(&block, false)
+    }
+
+    /// Send the rest of a message started with
+    /// [write_partial_raw](Self::write_partial_raw), marking its end.  Does
+    /// nothing if no message was started.  If the command policy rejects the
+    /// message's last unit, nothing is sent and the message stays in progress,
+    /// to be [aborted](Self::abort_bulk_out) or [cleared](Self::clear).
+    pub fn flush_message(&mut self) -> TMCResult<()> {
+        let mut result = self.send_held();
+
+        if result.is_ok() && self.verify_writes {
+            result = self.verify_write();
+        }
+
+        self.observe(result)
+    }
+
+    fn send_held(&mut self) -> TMCResult<()> {
+        let mut partial = match self.partial_message.take() {
+            Some(partial) if !partial.held.is_empty() => partial,
+            _ => return Ok(()),
+        };
+
+        if let Some(policy) = &self.command_policy {
+            let mut commands = partial.commands.clone();
+            if let Err(error) = check_commands(policy.as_ref(), commands.finish()) {
+                self.partial_message = Some(partial);
+                return Err(error);
+            }
+        }
+
+        self.send_transfers(&partial.held, true)?;
+
+        #[cfg(feature = "timing")]
+        self.timing
+            .record(OperationClass::Write, partial.started.elapsed());
+
+        self.response_pending |= partial.query.is_query();
+
+        if self.transcript.is_some() {
+            partial.sent.append(&mut partial.held);
+            self.record_transcript(partial.started, Direction::Sent, &partial.sent);
+        }
+        Ok(())
+    }
+
+    /// Check the instrument didn't report an error for the last write.  Error
+    /// queue entries which aren't in the standard `<code>,"<message>"` format are
+    /// not treated as errors.
+    fn verify_write(&mut self) -> TMCResult<()> {
+        #[cfg(feature = "scpi")]
+        if self.is_scpi()? {
+            self.send_message(b"SYST:ERR?")?;
+            let response = self.read_message(None)?;
+            let response = self.decode(response)?;
+            return match parse_error_entry(&response) {
+                Some((0, _)) | None => Ok(()),
+                Some((code, message)) => Err(TMCError::InstrumentError { code, message }),
+            };
+        }
+
+        // error/event queue (SCPI) or event status summary (IEEE 488.2) bits
+        let status_byte = self.read_status_byte()?;
+        if status_byte & 0x24 != 0 {
+            return Err(TMCError::StatusByteError { status_byte });
+        }
+
+        Ok(())
+    }
+
+    /// Read the status byte with READ_STATUS_BYTE.  Devices with an interrupt-in
+    /// endpoint send it there instead of in the control response.
+    fn read_status_byte(&mut self) -> TMCResult<u8> {
+        let mut out = Vec::with_capacity(3);
+        self.read_control(ControlRequest::Tmc488ReadStatusByte, 3, &mut out)?;
+        ControlRequest::check_response_status(&out)?;
+
+        match self.interface().interrupt_in_address {
+            Some(ep) if out.len() >= 2 => {
+                // skip any service request notifications queued before ours,
+                // passing on vendor notifications
+                let expected = 0x80 | status_b_tag(self.b_tag);
+                let mut buf = [0u8; MAX_INTERRUPT_PACKET];
+                loop {
+                    let n = self.transport.read_interrupt(
+                        ep,
+                        &mut buf,
+                        self.interrupt_timeout.to_transfer(),
+                    )?;
+                    if self.vendor_interrupts.dispatch(&buf[..n]) {
+                        continue;
+                    }
+                    if n < 2 {
+                        return Err(ClassError::TruncatedControlResponse {
+                            expected: 2,
+                            received: n,
+                        }
+                        .into());
+                    }
+
+                    if buf[0] == expected {
+                        return Ok(buf[1]);
+                    }
+                }
+            }
+            Some(_) => Err(ClassError::TruncatedControlResponse {
+                expected: 2,
+                received: out.len(),
+            }
+            .into()),
+            None if out.len() >= 3 => Ok(out[2]),
+            None => Err(ClassError::TruncatedControlResponse {
+                expected: 3,
+                received: out.len(),
+            }
+            .into()),
+        }
+    }
+
+    /// Check whether the instrument has a message available, from the message
+    /// available bit of its status byte.  `timeout` limits the wait for the
+    /// status byte to arrive on the interrupt-in endpoint, instead of the
+    /// interrupt timeout.  Fails with
+    /// [UnsupportedFeature](ClassError::UnsupportedFeature) unless the device is
+    /// a USB488 device.
+    pub fn read_stb(&mut self, timeout: Option<Duration>) -> TMCResult<bool> {
+        let result = self.read_message_available(timeout);
+        self.observe(result)
+    }
+
+    /// Call `callback` with each vendor-specific notification from the
+    /// interrupt-in endpoint whose first byte (`bNotify1`) is in `notify1`, such
+    /// as the acquisition progress some instruments report.  Vendor notifications
+    /// have `bNotify1` below `0x80`; see [interrupts](crate::interrupts).
+    ///
+    /// Notifications are only read while the handle reads the interrupt-in
+    /// endpoint for something else, such as the status byte or a service
+    /// request, or while [listening](Self::listen_interrupts).
+    pub fn subscribe_vendor_interrupts<F>(
+        &mut self,
+        notify1: RangeInclusive<u8>,
+        callback: F,
+    ) -> SubscriptionId
+    where
+        F: FnMut(&[u8]) + Send + 'static,
+    {
+        self.vendor_interrupts
+            .subscribe(notify1, Box::new(callback))
+    }
+
+    /// Stop delivering notifications to a subscription, returning whether it
+    /// existed
+    pub fn unsubscribe_vendor_interrupts(&mut self, id: SubscriptionId) -> bool {
+        self.vendor_interrupts.unsubscribe(id)
+    }
+
+    /// Read the interrupt-in endpoint for `timeout`, delivering vendor
+    /// notifications to their subscribers, and return how many arrived.  USB488
+    /// notifications read meanwhile, such as service requests, are discarded.
+    /// Fails with [UnsupportedFeature](ClassError::UnsupportedFeature) if the
+    /// interface has no interrupt-in endpoint.
+    pub fn listen_interrupts(&mut self, timeout: Duration) -> TMCResult<usize> {
+        let result = self.listen_interrupts_impl(timeout);
+        self.observe(result)
+    }
+
+    fn listen_interrupts_impl(&mut self, timeout: Duration) -> TMCResult<usize> {
+        let endpoint = self
+            .interface()
+            .interrupt_in_address
+            .ok_or(ClassError::UnsupportedFeature)?;
+
+        let deadline = Instant::now().checked_add(timeout);
+        let mut buf = [0u8; MAX_INTERRUPT_PACKET];
+        let mut delivered = 0;
+        loop {
+            let remaining = deadline.map_or(timeout, |deadline| {
+                deadline.saturating_duration_since(Instant::now())
+            });
+            if remaining.is_zero() {
+                return Ok(delivered);
+            }
+
+            match self.transport.read_interrupt(
+                endpoint,
+                &mut buf,
+                Timeout::from(remaining).to_transfer(),
+            ) {
+                Ok(n) => {
+                    if self.vendor_interrupts.dispatch(&buf[..n]) {
+                        delivered += 1;
+                    }
+                }
+                Err(rusb::Error::Timeout) => return Ok(delivered),
+                Err(error) => return Err(error.into()),
+            }
+        }
+    }
+
+    fn read_message_available(&mut self, timeout: Option<Duration>) -> TMCResult<bool> {
+        if self.usb488_capabilities()?.is_none() {
+            return Err(ClassError::UnsupportedFeature.into());
+        }
+
+        let interrupt_timeout = self.interrupt_timeout;
+        self.interrupt_timeout = timeout.map_or(interrupt_timeout, Timeout::from);
+        let result = self.read_status_byte();
+        self.interrupt_timeout = interrupt_timeout;
+
+        Ok(result? & STB_MAV != 0)
+    }
+
+    /// Read response data from the instrument
+    ///
+    /// The device may return less data than requested in each transfer; more data
+    /// is requested until the device marks the end of the message, or until one of
+    /// the limits set by [set_max_reads](Self::set_max_reads) or
+    /// [set_max_response_size](Self::set_max_response_size) is exceeded.
+    pub fn read_raw(
+        &mut self,
+        transfer_size: Option<u32>,
+        //timeout: Option<Duration>,
+    ) -> TMCResult<Vec<u8>> {
+        let result = self.read_message(transfer_size);
+        self.observe(result)
+    }
+
+    /// Read response data from the instrument like [read_raw](Self::read_raw),
+    /// spilling it to a temporary file if it outgrows the budget set with
+    /// [set_response_spill](Self::set_response_spill), and return it to be read
+    /// as a stream.  If the file can't be written, the rest of the message is
+    /// discarded and the read fails with [Spill](TMCError::Spill).
+    pub fn read_raw_spooled(&mut self, transfer_size: Option<u32>) -> TMCResult<SpooledResponse> {
+        let result = self.read_message_spooled(transfer_size);
+        self.observe(result)
+    }
+
+    fn read_message_spooled(&mut self, transfer_size: Option<u32>) -> TMCResult<SpooledResponse> {
+        let mut spooler = Spooler::new(self.response_spill.clone());
+        self.read_message_with(transfer_size, |data, _| spooler.push(data))?;
+        spooler.finish().map_err(|error| TMCError::Spill {
+            kind: error.kind(),
+            message: error.to_string(),
+        })
+    }
+
+    /// Read response data from the instrument like [read_raw](Self::read_raw),
+    /// requesting `chunk_size` bytes in each transfer whatever the
+    /// [maximum transfer size](Self::set_max_transfer_size), which then only
+    /// limits writes.  `expected` is how large the whole response is likely to
+    /// be, only used to allocate room for it, or 0 if unknown.  A chunk size of
+    /// zero is rejected.
+    pub fn read_raw_chunked(&mut self, chunk_size: u32, expected: usize) -> TMCResult<Vec<u8>> {
+        let result = self.read_message_chunked(chunk_size, expected);
+        self.observe(result)
+    }
+
+    fn read_message_chunked(&mut self, chunk_size: u32, expected: usize) -> TMCResult<Vec<u8>> {
+        if chunk_size == 0 {
+            return Err(ClassError::InvalidTransferSize(chunk_size).into());
+        }
+
+        let mut read_data = Vec::with_capacity(expected);
+        self.guarded(|handle| {
+            handle.read_transfers(chunk_size, |data, _| {
+                read_data.extend_from_slice(data);
+                ControlFlow::Continue(())
+            })
+        })?;
+        Ok(read_data)
+    }
+
+    /// Check whether the instrument has response data ready, without reading it.
+    /// USB488 devices are asked with the message available bit of the status
+    /// byte.  For other devices this only tells whether a query (a message
+    /// containing `?`) has been sent since the last response was read, not
+    /// whether the response is ready yet.
+    pub fn peek_available(&mut self) -> TMCResult<bool> {
+        let result = match self.usb488_capabilities() {
+            Ok(Some(_)) => self
+                .read_status_byte()
+                .map(|status_byte| status_byte & STB_MAV != 0),
+            Ok(None) => Ok(self.response_pending),
+            Err(error) => Err(error),
+        };
+        self.observe(result)
+    }
+
+    /// Read a response message, passing the data from each transfer to
+    /// `consumer` as it arrives instead of collecting it.  If the consumer
+    /// returns [ControlFlow::Break] before the end of the message, the device is
+    /// told to abort the bulk-in transfer and the rest of the message is
+    /// discarded rather than read.  Returns whether the whole message was read.
+    pub fn read_stream<F>(&mut self, transfer_size: Option<u32>, mut consumer: F) -> TMCResult<bool>
+    where
+        F: FnMut(&[u8]) -> ControlFlow<()>,
+    {
+        let result = self.read_message_with(transfer_size, |data, _| consumer(data));
+        self.observe(result)
+    }
+
+    /// Read a response message, sending the data from each transfer over a
+    /// [bounded](crate::bounded) channel as it arrives.  If the channel refuses
+    /// the data, because it is full with [Backpressure::Abort](crate::bounded::Backpressure::Abort)
+    /// or its receiver has been dropped, the bulk-in transfer is aborted as with
+    /// [read_stream](Self::read_stream).  Returns whether the whole message was
+    /// read and sent.
+    pub fn read_stream_into(
+        &mut self,
+        transfer_size: Option<u32>,
+        sender: &BoundedSender<Vec<u8>>,
+    ) -> TMCResult<bool> {
+        let mut refused = false;
+        let complete =
+            self.read_stream(transfer_size, |data| match sender.send(data.to_vec()) {
+                Ok(()) => ControlFlow::Continue(()),
+                Err(_) => {
+                    refused = true;
+                    ControlFlow::Break(())
+                }
+            })?;
+        Ok(complete && !refused)
+    }
+
+    /// Read `count` response messages, keeping them separate rather than
+    /// stopping at the end of the first, for queries which the device answers
+    /// with several messages
+    pub fn read_messages(&mut self, count: usize) -> TMCResult<Vec<Vec<u8>>> {
+        let result = (0..count).map(|_| self.read_message(None)).collect();
+        self.observe(result)
+    }
+
+    /// Read every response message the device has ready, keeping them separate.
+    /// USB488 devices are asked with the status byte whether a message is
+    /// available before each read.  Other devices are read until a read times
+    /// out after a short wait, and that read is then aborted.
+    pub fn read_all_pending(&mut self) -> TMCResult<Vec<Vec<u8>>> {
+        let result = self.read_pending_messages();
+        self.observe(result)
+    }
+
+    fn read_pending_messages(&mut self) -> TMCResult<Vec<Vec<u8>>> {
+        let mut messages = Vec::new();
+
+        if self.usb488_capabilities()?.is_some() {
+            while self.read_status_byte()? & STB_MAV != 0 {
+                messages.push(self.read_message(None)?);
+            }
+            return Ok(messages);
+        }
+
+        // the last read is expected to time out, which mustn't poison the handle
+        let timeout = std::mem::replace(&mut self.bulk_timeout, PENDING_READ_TIMEOUT.into());
+        let poison_policy = std::mem::replace(&mut self.poison_policy, PoisonPolicy::Never);
+        let error = loop {
+            match self.read_message(None) {
+                Ok(message) => messages.push(message),
+                Err(error) => break error,
+            }
+        };
+        self.bulk_timeout = timeout;
+        self.poison_policy = poison_policy;
+
+        match error {
+            TMCError::Rusb {
+                source: rusb::Error::Timeout,
+            } => {
+                // the device still holds our request for data
+                self.abort_bulk_in_transfer()?;
+                Ok(messages)
+            }
+            error => Err(error),
+        }
+    }
+
+    fn read_message(&mut self, transfer_size: Option<u32>) -> TMCResult<Vec<u8>> {
+        self.read_message_into(transfer_size, Vec::new())
+    }
+
+    /// Read a response message, appending it to `read_data`, which may have been
+    /// allocated with room for the expected response
+    fn read_message_into(
+        &mut self,
+        transfer_size: Option<u32>,
+        mut read_data: Vec<u8>,
+    ) -> TMCResult<Vec<u8>> {
+        self.read_message_with(transfer_size, |data, _| {
+            read_data.extend_from_slice(data);
+            ControlFlow::Continue(())
+        })?;
+        Ok(read_data)
+    }
+
+    /// Read a response message, passing the data from each transfer to `on_data`
+    /// along with whether it ends the message.  If `on_data` breaks before the
+    /// end, the rest of the message is discarded.  Returns whether the whole
+    /// message was read.
+    fn read_message_with<F>(&mut self, transfer_size: Option<u32>, on_data: F) -> TMCResult<bool>
+    where
+        F: FnMut(&[u8], bool) -> ControlFlow<()>,
+    {
+        let transfer_size = match transfer_size {
+            Some(size) if size < self.max_transfer_size => size,
+            _ => self.max_transfer_size,
+        };
+        self.guarded(|handle| handle.read_transfers(transfer_size, on_data))
+    }
+
+    /// Read a response message, requesting `transfer_size` bytes in each transfer
+    fn read_transfers<F>(&mut self, transfer_size: u32, mut on_data: F) -> TMCResult<bool>
+    where
+        F: FnMut(&[u8], bool) -> ControlFlow<()>,
+    {
+        let started = Timestamp::now();
+
+        // only kept for the transcript, as callers may not keep the data
+        let mut recorded = Vec::new();
+        let mut n_read: usize = 0;
+        let mut buf = Vec::new();
+        let mut n_reads: u32 = 0;
+
+        // the previous transfer of the message, to recognise a device repeating it
+        let mut previous = Vec::new();
+        let mut duplicates: u32 = 0;
+
+        // a response over the size or transfer limits stops the read, and the
+        // rest of it is discarded so the next read doesn't get its tail
+        let mut too_large = false;
+
+        self.ensure_claimed()?;
+
+        /* let time = std::time::Instant::now();
+        let end_time = time + timeout.unwrap_or(Duration::from_millis(1000));
+
+        let mut message_available = false;
+        //TODO For compatibility reasons, before doing this while loop we
+        //      need to check whether the instrument claims to implement IEEE 488
+        //      (All Keithley Instruments implement IEEE 488, so we don't need to change it right now.)
+        while std::time::Instant::now() < end_time && !message_available {
+            let mut status_buf: Vec<u8> = Vec::with_capacity(3);
+            self.read_control(ControlRequest::Tmc488ReadStatusByte, 3, &mut status_buf)?;
+
+            if ControlRequest::check_response_status(&status_buf).is_ok() {
+                let buf = &mut [0u8, 2];
+                let _interrupt = self.transport.read_interrupt(
+                    self.interface().interrupt_in_address.unwrap_or(0),
+                    buf,
+                    Duration::from_millis(10),
+                )?;
+
+                if *buf.last().unwrap_or(&0) & 16 != 0 {
+                    message_available = true;
+                }
+            }
+            sleep(Duration::from_millis(100));
+        }
+
+        if !message_available {
+            return Ok(Vec::new());
+        } */
+
+        let complete = loop {
+            // ask for no more than one byte over the response size limit, so that
+            // going over it is noticed without buffering a whole transfer
+            let transfer_size = match self.max_response_size {
+                Some(max_response_size) => {
+                    let budget = max_response_size.saturating_sub(n_read).saturating_add(1);
+                    (transfer_size as usize).min(budget) as u32
+                }
+                None => transfer_size,
+            };
+            self.request_transfer(transfer_size, &mut buf)?;
+
+            // Read the requested data from the device
+            let result = self.read_bulk_in_transfer(transfer_size, &mut buf);
+            if let Err(TMCError::Rusb {
+                source: rusb::Error::Timeout,
+            }) = result
+            {
+                let received = n_read + buf.len().saturating_sub(HEADER_SIZE);
+                if received > 0 {
+                    return Err(TMCError::ResponseInterrupted { received });
+                }
+            }
+            result?;
+
+            self.record_frame(Direction::Received, &buf);
+
+            let (header, data) = DevDepMsgInHeader::decode_transfer(&buf)?;
+
+            // a transfer answering the request just made carries its tag, so a
+            // copy of the previous transfer with the old tag is a repeat
+            if header.bulk_in_header.b_tag != self.b_tag && buf == previous {
+                self.diagnostics.duplicate_transfers += 1;
+                duplicates += 1;
+                if duplicates > MAX_DUPLICATE_TRANSFERS {
+                    let mut header = [0u8; HEADER_SIZE];
+                    header.copy_from_slice(&buf[..HEADER_SIZE]);
+                    // the rest of the message may still be queued behind the
+                    // repeats, and would otherwise start the next read
+                    let _ = self.discard_message(transfer_size);
+                    return Err(ClassError::RepeatedTransfer { header }.into());
+                }
+                continue;
+            }
+            duplicates = 0;
+            previous.clone_from(&buf);
+
+            self.check_padding(&buf, HEADER_SIZE + data.len())?;
+            n_read += data.len();
+            n_reads += 1;
+
+            if let Some(max_response_size) = self.max_response_size {
+                if n_read > max_response_size {
+                    too_large = true;
+                    break header.is_eom();
+                }
+            }
+
+            if self.transcript.is_some() {
+                recorded.extend_from_slice(data);
+            }
+
+            let eom = header.is_eom();
+            if on_data(data, eom).is_break() && !eom {
+                break false;
+            }
+
+            if eom {
+                break true;
+            }
+
+            if let Some(max_reads) = self.max_reads {
+                if n_reads >= max_reads {
+                    too_large = true;
+                    break false;
+                }
+            }
+        };
+
+        if !complete {
+            self.discard_message(transfer_size)?;
+        }
+        if too_large {
+            self.response_pending = false;
+            return Err(ClassError::ResponseTooLarge.into());
+        }
+
+        #[cfg(feature = "timing")]
+        self.timing
+            .record(OperationClass::for_read(n_read), started.elapsed());
+
+        self.response_pending = false;
+
+        self.record_transcript(started, Direction::Received, &recorded);
+        Ok(complete)
+    }
+
+    /// Request and read one bulk-in transfer, returning it whole
+    pub(crate) fn read_raw_transfer(&mut self, transfer_size: u32) -> TMCResult<Vec<u8>> {
+        let result = self.guarded(|handle| {
+            handle.ensure_claimed()?;
+
+            let mut buf = Vec::new();
+            handle.request_transfer(transfer_size, &mut buf)?;
+            handle.read_bulk_in_transfer(transfer_size, &mut buf)?;
+            handle.record_frame(Direction::Received, &buf);
+            Ok(buf)
+        });
+        self.observe(result)
+    }
+
+    /// Discard the rest of a response message which has been partly read,
+    /// returning whether the device had the transfer started to do so in
+    /// progress when it was aborted
+    pub(crate) fn discard_message(&mut self, transfer_size: u32) -> TMCResult<bool> {
+        // the device only discards the rest of a message when a transfer is
+        // aborted, so start one to abort
+        let mut buf = Vec::new();
+        self.request_transfer(transfer_size, &mut buf)?;
+        self.try_abort_bulk_in()
+    }
+
+    /// Send REQUEST_DEV_DEP_MSG_IN, asking the device for up to `transfer_size`
+    /// bytes of the current response message
+    fn request_transfer(&mut self, transfer_size: u32, buf: &mut Vec<u8>) -> TMCResult<()> {
+        // a device without a bulk-out endpoint can't be asked for data, so it
+        // sends its transfers unrequested
+        if !self.interface().can_write() {
+            return Ok(());
+        }
+        self.resolve_pending_request()?;
+
+        self.incr_b_tag();
+        self.last_bulk_tag = self.b_tag;
+        RequestDevDepMsgInHeader::encode_message(
+            self.b_tag,
+            transfer_size,
+            self.get_term_char(),
+            buf,
+        );
+
+        self.record_frame(Direction::Sent, buf);
+        self.write_transfer(buf)?;
+        self.bulk_in_outstanding = true;
+        Ok(())
+    }
+
+    /// Abort the bulk-in transfer last requested if reading it was cut short and
+    /// the device may still be sending it, since it would otherwise answer the
+    /// next request with that transfer, its bTag no longer matching.  Called
+    /// before anything else is sent on the bulk-out endpoint, while the
+    /// transfer's bTag is still the most recent one, and before the device can
+    /// queue a response to a new message which the abort would discard too.
+    fn resolve_pending_request(&mut self) -> TMCResult<()> {
+        if self.bulk_in_outstanding && self.resolve_pending_requests && self.try_abort_bulk_in()? {
+            self.diagnostics.pending_requests_aborted += 1;
+        }
+        Ok(())
+    }
+
+    fn decode(&self, data: Vec<u8>) -> TMCResult<String> {
+        self.encoding.decode(data, self.text_decoding)
+    }
+
+    /// Pass a text message through the middleware and encode it in the
+    /// handle's [Encoding]
+    pub(crate) fn encode_command(&mut self, message: &str) -> TMCResult<Vec<u8>> {
+        let message = self.rewrite_command(message);
+        Ok(self.encoding.encode(&message)?.into_owned())
+    }
+
+    /// Decode a response in the handle's [Encoding] and pass it back through
+    /// the middleware
+    fn decode_response(&self, data: Vec<u8>) -> TMCResult<String> {
+        let response = self.decode(data)?;
+        Ok(self.normalize_response(response))
+    }
+
+    /// Read response data from the instrument as text in the handle's
+    /// [Encoding]
+    pub fn read(&mut self, transfer_size: Option<u32>) -> TMCResult<String> {
+        //let read_data = self.read_raw(transfer_size, None)?;
+        let read_data = self.read_raw(transfer_size)?;
+        self.decode_response(read_data)
+    }
+
+    /// Write a command message to the instrument in the handle's [Encoding]
+    pub fn write(&mut self, message: &str) -> TMCResult<()> {
+        let data = self.encode_command(message)?;
+        self.write_raw(&data)
+    }
+
+    /// Pass a text message through the middleware, remembering it for the
+    /// response
+    fn rewrite_command<'a>(&mut self, message: &'a str) -> Cow<'a, str> {
+        if self.middleware.is_empty() {
+            return Cow::Borrowed(message);
+        }
+
+        message.clone_into(&mut self.last_message);
+        let mut rewritten = Cow::Borrowed(message);
+        for middleware in self.middleware.iter() {
+            if let Some(message) = middleware.command(&rewritten) {
+                rewritten = Cow::Owned(message);
+            }
+        }
+        rewritten
+    }
+
+    /// Pass a text response back through the middleware
+    fn normalize_response(&self, response: String) -> String {
+        self.middleware
+            .iter()
+            .rev()
+            .fold(response, |response, middleware| {
+                middleware
+                    .response(&self.last_message, &response)
+                    .unwrap_or(response)
+            })
+    }
+
+    /// Write a command message to the instrument and read a response, both in
+    /// the handle's [Encoding]
+    pub fn ask(&mut self, data: &str) -> TMCResult<String> {
+        #[cfg(feature = "scpi")]
+        if let Some(response) = self
+            .response_cache
+            .as_mut()
+            .and_then(|cache| cache.lookup(data))
+        {
+            return Ok(response);
+        }
+
+        let encoded = self.encode_command(data)?;
+        let response_data = self.ask_raw(&encoded)?;
+        let response = self.decode_response(response_data)?;
+
+        #[cfg(feature = "scpi")]
+        if let Some(cache) = &mut self.response_cache {
+            cache.store(data, &response);
+        }
+        Ok(response)
+    }
+
+    /// Write a query and pass the response to `parser` as it arrives, one
+    /// transfer at a time along with whether it ends the message.  Once the
+    /// parser returns a result the rest of the response is discarded, so that a
+    /// large response needn't be read in full.  Returns `None` if the parser
+    /// hasn't returned a result by the end of the response.
+    pub fn ask_parse_with<R, F>(&mut self, command: &str, mut parser: F) -> TMCResult<Option<R>>
+    where
+        F: FnMut(&[u8], bool) -> Option<R>,
+    {
+        let data = self.encode_command(command)?;
+        let mut parsed = None;
+
+        let result = self.send_message(&data).and_then(|()| {
+            self.read_message_with(None, |data, eom| {
+                parsed = parser(data, eom);
+                match parsed {
+                    Some(_) => ControlFlow::Break(()),
+                    None => ControlFlow::Continue(()),
+                }
+            })
+        });
+        self.observe(result)?;
+
+        Ok(parsed)
+    }
+
+    /// Write a command message to the instrument and read a response
+    pub fn ask_raw(&mut self, data: &[u8]) -> TMCResult<Vec<u8>> {
+        let result = self
+            .send_message(data)
+            .and_then(|()| self.read_message(None));
+        self.observe(result)
+    }
+
+    /// Write a command message to the instrument and read a response which may
+    /// be spilled to disk, as with [read_raw_spooled](Self::read_raw_spooled)
+    pub fn ask_raw_spooled(&mut self, data: &[u8]) -> TMCResult<SpooledResponse> {
+        let result = self
+            .send_message(data)
+            .and_then(|()| self.read_message_spooled(None));
+        self.observe(result)
+    }
+
+    /// Write a command message to the instrument and read a response of
+    /// about `expected` bytes, such as a fixed-length waveform record.  The
+    /// response buffer is allocated for `expected` bytes up front, and no more
+    /// than that is requested in each transfer.  `expected` should include any
+    /// block header and terminator, as a longer response takes an extra
+    /// transfer to read.  A hint of 0 is ignored.
+    pub fn ask_raw_with_hint(&mut self, data: &[u8], expected: usize) -> TMCResult<Vec<u8>> {
+        // larger than any transfer is the same as the maximum transfer size
+        let transfer_size = match expected {
+            0 => None,
+            expected => Some(expected.min(u32::MAX as usize) as u32),
+        };
+
+        let result = self
+            .send_message(data)
+            .and_then(|()| self.read_message_into(transfer_size, Vec::with_capacity(expected)));
+        self.observe(result)
+    }
+
+    /// Write a command message and read a response of about `bytes` bytes like
+    /// [ask_raw_with_hint](Self::ask_raw_with_hint), both in the handle's
+    /// [Encoding].  The response cache is not consulted.
+    pub fn ask_with_hint(&mut self, command: &str, bytes: usize) -> TMCResult<String> {
+        let data = self.encode_command(command)?;
+        let response_data = self.ask_raw_with_hint(&data, bytes)?;
+        self.decode_response(response_data)
+    }
+
+    /// Write a query and wait for the device to request service once the
+    /// response is available, then read it, instead of polling the status byte.
+    /// Message available is enabled as a cause of service requests with `*SRE`
+    /// for the duration of the query.  Needs a device which supports service
+    /// requests and has an interrupt-in endpoint.  If no service request arrives
+    /// within `timeout` the response is left for a later read.
+    #[cfg(feature = "scpi")]
+    pub fn query_with_srq(&mut self, command: &str, timeout: Duration) -> TMCResult<String> {
+        let data = self.encode_command(command)?;
+        let result = self.ask_with_srq(&data, timeout);
+        let response_data = self.observe(result)?;
+        self.decode_response(response_data)
+    }
+
+    /// Write a query and wait, however long the device takes, for it to request
+    /// service because the response is available, then read it.  No bulk-in
+    /// request is made until the response is ready and nothing polls the device
+    /// meanwhile, so neither times out on slow acquisitions; the handle's timeout
+    /// only applies to sending the query and reading the ready response.  Needs
+    /// a device which supports service requests, as for
+    /// [query_with_srq](Self::query_with_srq).
+    #[cfg(feature = "scpi")]
+    pub fn query_on_mav(&mut self, command: &str) -> TMCResult<String> {
+        self.query_with_srq(command, Duration::MAX)
+    }
+
+    /// Write a command message and read the response once the device requests
+    /// service for it, as [query_on_mav](Self::query_on_mav) does
+    #[cfg(feature = "scpi")]
+    pub fn ask_raw_on_mav(&mut self, data: &[u8]) -> TMCResult<Vec<u8>> {
+        let result = self.ask_with_srq(data, Duration::MAX);
+        self.observe(result)
+    }
+
+    #[cfg(feature = "scpi")]
+    fn ask_with_srq(&mut self, data: &[u8], timeout: Duration) -> TMCResult<Vec<u8>> {
+        let supports_srq = self.supports(Feature::Srq)?.is_supported();
+        let endpoint = match self.interface().interrupt_in_address {
+            Some(endpoint) if supports_srq => endpoint,
+            _ => return Err(ClassError::UnsupportedFeature.into()),
+        };
+
+        let enable = self.sre_query()?;
+        let armed = enable.0 & STB_MAV == 0;
+        if armed {
+            self.sre(StatusByte(enable.0 | STB_MAV))?;
+        }
+
+        let result = self
+            .discard_notifications(endpoint)
+            .and_then(|()| self.send_message(data))
+            .and_then(|()| self.wait_for_srq(endpoint, STB_MAV, timeout))
+            .and_then(|()| self.read_message(None));
+
+        if armed {
+            let restored = self.sre(enable);
+            return result.and_then(|response| restored.map(|()| response));
+        }
+        result
+    }
+
+    /// Discard notifications already queued on the interrupt-in endpoint, so
+    /// that a stale service request isn't taken for a new one.  Vendor
+    /// notifications are still delivered to their subscribers.
+    #[cfg(feature = "scpi")]
+    fn discard_notifications(&mut self, endpoint: u8) -> TMCResult<()> {
+        let mut buf = [0u8; MAX_INTERRUPT_PACKET];
+        loop {
+            match self
+                .transport
+                .read_interrupt(endpoint, &mut buf, Duration::from_millis(1))
+            {
+                Ok(n) => {
+                    self.vendor_interrupts.dispatch(&buf[..n]);
+                }
+                Err(rusb::Error::Timeout) => return Ok(()),
+                Err(error) => return Err(error.into()),
+            }
+        }
+    }
+
+    /// Wait for a service request notification whose status byte has any of the
+    /// bits in `mask` set
+    #[cfg(feature = "scpi")]
+    fn wait_for_srq(&mut self, endpoint: u8, mask: u8, timeout: Duration) -> TMCResult<()> {
+        let deadline = Instant::now().checked_add(timeout);
+        let mut buf = [0u8; MAX_INTERRUPT_PACKET];
+        loop {
+            let remaining = deadline.map_or(timeout, |deadline| {
+                deadline.saturating_duration_since(Instant::now())
+            });
+            if remaining.is_zero() {
+                return Err(rusb::Error::Timeout.into());
+            }
+
+            let n = self.transport.read_interrupt(
+                endpoint,
+                &mut buf,
+                Timeout::from(remaining).to_transfer(),
+            )?;
+            if self.vendor_interrupts.dispatch(&buf[..n]) {
+                continue;
+            }
+            if n >= 2 && buf[0] == SRQ_NOTIFICATION && buf[1] & mask != 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Send several queries in a single message, joined with `;`, and split the
+    /// single response on `;` into one string per query.  Each query's header is
+    /// taken from the root, as though it had been sent alone, rather than
+    /// relative to the one before; nothing is sent for no queries.
+    ///
+    /// The responses must not themselves contain `;` (as quoted strings or
+    /// arbitrary blocks could), otherwise the split goes wrong and the count check
+    /// fails.
+    pub fn ask_many(&mut self, queries: &[&str]) -> TMCResult<Vec<String>> {
+        if queries.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let message: Vec<String> = queries
+            .iter()
+            .map(|query| {
+                let query = query.trim();
+                if query.starts_with(['*', ':']) {
+                    query.to_owned()
+                } else {
+                    format!(":{}", query)
+                }
+            })
+            .collect();
+        let response = self.ask(&message.join(";"))?;
+
+        let responses: Vec<String> = response
+            .trim_end()
+            .split(';')
+            .map(|part| part.trim().to_owned())
+            .collect();
+
+        if responses.len() != queries.len() {
+            return Err(TMCError::ResponseCountMismatch {
+                expected: queries.len(),
+                received: responses.len(),
+            });
+        }
+
+        Ok(responses)
+    }
+
+    /// Write a command and make sure the instrument has finished executing it before
+    /// any later command takes effect, using IEEE 488.2 synchronization.
+    ///
+    /// If the instrument declares USB488.2 support, `*OPC?` is appended and this call
+    /// waits for the response, so the operation is complete when it returns (the
+    /// timeout must be long enough for that).  Otherwise `*WAI` is appended, which
+    /// needs no response and makes the instrument itself hold off later commands.
+    ///
+    /// A query's response would be read along with the `*OPC?` one and lost, so
+    /// queries are refused with [QueryNotSynchronized](TMCError::QueryNotSynchronized);
+    /// [ask](Self::ask) them instead.
+    pub fn write_synchronized(&mut self, command: &str) -> TMCResult<()> {
+        if is_query(command.as_bytes()) {
+            return Err(TMCError::QueryNotSynchronized {
+                command: command.to_owned(),
+            });
+        }
+
+        // keep any trailing terminator after the appended command
+        let body = command.trim_end();
+        let terminator = &command[body.len()..];
+
+        let usb488_2 = self
+            .usb488_capabilities()?
+            .is_some_and(|caps| caps.usb488_2);
+
+        if usb488_2 {
+            self.ask(&format!("{};*OPC?{}", body, terminator))?;
+        } else {
+            self.write(&format!("{};*WAI{}", body, terminator))?;
+        }
+
+        Ok(())
+    }
+
+    // TODO: support for vendor-specific bulk transfers
+    // TODO: support for interrupt in endpoint
+    // TODO: more complete support for USB488 features
+}
+
+/// The bTag to use for a READ_STATUS_BYTE request, which must be in 2..=127,
+/// given the current bTag from the handle's policy
+fn status_b_tag(b_tag: u8) -> u8 {
+    if (2..=127).contains(&b_tag) {
+        b_tag
+    } else {
+        2 + b_tag % 126
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_b_tag_in_range() {
+        for b_tag in 0..=u8::MAX {
+            assert!((2..=127).contains(&status_b_tag(b_tag)), "{}", b_tag);
+        }
+        assert_eq!(status_b_tag(2), 2);
+        assert_eq!(status_b_tag(127), 127);
+    }
+
+    #[cfg(feature = "sim")]
+    mod sim {
+        use super::*;
+        use crate::sim::{Script, SimTransport};
+
+        use crate::transport::FaultInjectingTransport;
+        use crate::UsbtmcConfig;
+
+        type Recorder = FaultInjectingTransport<SimTransport>;
+
+        /// A handle on a simulated instrument, recording the transfers to and
+        /// from it
+        fn open<P: TagPolicy + 'static>(tag_policy: P) -> TMCHandle<Recorder> {
+            let script =
+                Script::from_yaml("rules:\n  - pattern: 'DATA\\?'\n    response: 'data'\n")
+                    .unwrap();
+            let transport = FaultInjectingTransport::new(SimTransport::new(&script).unwrap());
+            transport.injector().record();
+            TMCHandle::with_transport(transport, OpenOptions::new().tag_policy(tag_policy)).unwrap()
+        }
+
+        /// The data of the last recorded operation of the given kind
+        fn last_recorded(handle: &TMCHandle<Recorder>, operation: Operation) -> Vec<u8> {
+            let recorded = handle.transport.injector().recorded(operation);
+            recorded.last().unwrap().clone()
+        }
+
+        /// The bTags of `n` commands written after `from`, checking that each
+        /// header carries the inverse and that the handle reports the same bTag
+        fn tags_after(handle: &mut TMCHandle<Recorder>, from: u8, n: usize) -> Vec<u8> {
+            handle.b_tag = from;
+            (0..n)
+                .map(|_| {
+                    handle.write_raw(b"*CLS").unwrap();
+                    let header = last_recorded(handle, Operation::BulkOut);
+                    assert_eq!(header[2], !header[1]);
+                    assert_eq!(handle.current_b_tag(), header[1]);
+                    header[1]
+                })
+                .collect()
+        }
+
+        #[test]
+        fn default_policy_wraps() {
+            let mut handle = open(DefaultTagPolicy);
+            assert_eq!(tags_after(&mut handle, 126, 3), [127, 128, 2]);
+            assert_eq!(handle.diagnostics.b_tag_wraps, 1);
+        }
+
+        #[test]
+        fn sequential_policy_wraps() {
+            let mut handle = open(SequentialTagPolicy);
+            assert_eq!(tags_after(&mut handle, 126, 3), [127, 128, 129]);
+            assert_eq!(tags_after(&mut handle, 254, 3), [255, 1, 2]);
+            assert_eq!(handle.diagnostics.b_tag_wraps, 1);
+        }
+
+        #[test]
+        fn fixed_policy() {
+            let mut handle = open(FixedTagPolicy(9));
+            assert_eq!(tags_after(&mut handle, 0, 2), [9, 9]);
+            assert_eq!(tags_after(&mut handle, 255, 1), [9]);
+        }
+
+        #[test]
+        fn fixed_zero_falls_back_to_default() {
+            let mut handle = open(FixedTagPolicy(0));
+            assert_eq!(tags_after(&mut handle, 0, 2), [2, 3]);
+            assert_eq!(tags_after(&mut handle, 127, 2), [128, 2]);
+        }
+
+        #[test]
+        fn queries_across_wrap() {
+            let mut handle = open(SequentialTagPolicy);
+            handle.b_tag = 253;
+            for _ in 0..3 {
+                assert_eq!(handle.ask("DATA?").unwrap(), "data\n");
+            }
+            assert!(handle.current_b_tag() < 253);
+        }
+
+        #[test]
+        fn status_byte_tag_across_wrap() {
+            let mut handle = open(SequentialTagPolicy);
+            for &from in &[126, 127, 128, 254, 255] {
+                handle.b_tag = from;
+                handle.read_stb(None).unwrap();
+                // the response to READ_STATUS_BYTE echoes the request's bTag
+                let status_tag = last_recorded(&handle, Operation::ControlIn)[1];
+                assert!((2..=127).contains(&status_tag), "{}", status_tag);
+            }
+        }
+
+        #[test]
+        fn whole_packet_transfer_size_rejected() {
+            let mut handle = open(DefaultTagPolicy);
+            let packet = handle.interface().bulk_out_max_packet() as u32;
+            assert!(handle.set_max_transfer_size(0).is_err());
+            assert!(handle
+                .set_max_transfer_size(packet - HEADER_SIZE as u32)
+                .is_err());
+            handle.set_max_transfer_size(packet).unwrap();
+            assert_eq!(handle.get_max_transfer_size(), packet);
+        }
+
+        #[test]
+        fn configured_transfer_size_checked() {
+            let script = Script::from_yaml("rules: []\n").unwrap();
+            let open = |max_transfer_size| {
+                let config = UsbtmcConfig::new().max_transfer_size(max_transfer_size);
+                let transport = SimTransport::new(&script).unwrap();
+                TMCHandle::with_transport(transport, OpenOptions::with_config(config))
+            };
+
+            let packet = open(1024).unwrap().interface().bulk_out_max_packet() as u32;
+            match open(packet - HEADER_SIZE as u32) {
+                Err(TMCError::Class {
+                    source: ClassError::InvalidTransferSize(_),
+                }) => {}
+                result => panic!("{:?}", result.map(|_| ())),
+            }
+            assert_eq!(open(packet).unwrap().get_max_transfer_size(), packet);
+        }
+
+        #[test]
+        fn capabilities_read_once_until_cleared() {
+            let mut handle = open(DefaultTagPolicy);
+            let injector = handle.transport.injector();
+            let control_requests = || injector.count(Operation::ControlIn);
+
+            let before = control_requests();
+            handle.usbtmc_capabilities().unwrap();
+            handle.usb488_capabilities().unwrap();
+            assert_eq!(control_requests(), before + 1);
+
+            handle.clear().unwrap();
+            let before = control_requests();
+            handle.usbtmc_capabilities().unwrap();
+            handle.usbtmc_capabilities().unwrap();
+            assert_eq!(control_requests(), before + 1);
+        }
+
+        #[test]
+        fn term_char_kept_while_disabled() {
+            let mut handle = open(DefaultTagPolicy);
+            handle.set_term_char_value(b',').unwrap();
+            assert_eq!(handle.get_term_char(), None);
+
+            handle.set_term_char_enabled(true).unwrap();
+            assert_eq!(handle.get_term_char(), Some(b','));
+            handle.write_raw(b"DATA?").unwrap();
+            handle.read_raw(None).unwrap();
+            let request = last_recorded(&handle, Operation::BulkOut);
+            assert_eq!((request[8], request[9]), (0x02, b','));
+
+            handle.set_term_char(None).unwrap();
+            assert_eq!(handle.term_char_value(), b',');
+            handle.write_raw(b"DATA?").unwrap();
+            handle.read_raw(None).unwrap();
+            let request = last_recorded(&handle, Operation::BulkOut);
+            assert_eq!(request[8], 0);
+
+            handle.set_term_char_enabled(true).unwrap();
+            assert_eq!(handle.get_term_char(), Some(b','));
+            assert!(handle.set_term_char_value(0).is_err());
+        }
+
+        #[test]
+        fn term_char_chosen_without_support() {
+            let script =
+                Script::from_yaml("capabilities:\n  term_char: false\nrules: []\n").unwrap();
+            let transport = SimTransport::new(&script).unwrap();
+            let mut handle = TMCHandle::with_transport(transport, OpenOptions::new()).unwrap();
+            handle.set_term_char_value(b',').unwrap();
+            assert!(handle.set_term_char_enabled(true).is_err());
+            assert_eq!(handle.get_term_char(), None);
+            assert_eq!(handle.term_char_value(), b',');
+        }
+
+        #[test]
+        fn reset_tag_on_clear() {
+            let mut handle = open(DefaultTagPolicy);
+            handle.write_raw(b"*CLS").unwrap();
+            handle.clear().unwrap();
+            assert_ne!(handle.current_b_tag(), 0);
+
+            handle.set_reset_tag_on_clear(true);
+            handle.clear().unwrap();
+            assert_eq!(handle.current_b_tag(), 0);
+            assert_eq!(tags_after(&mut handle, 0, 1), [2]);
+        }
+    }
 }
@@ -1,7 +1,10 @@
 use rusb::Version;
+use std::collections::BTreeSet;
+#[cfg(feature = "deep-scan")]
+use std::time::Duration;
 
 use crate::class::*;
-use crate::{InstrumentHandle, TMCResult};
+use crate::{DiscoveryError, InstrumentHandle, OpenOptions, TMCError, TMCResult};
 
 /// Information about an instrument detected on the USB bus.
 ///
@@ -27,13 +30,7 @@ impl<Ctx: rusb::UsbContext> Instrument<Ctx> {
         if self.manufacturer_string.is_none() {
             self.manufacturer_string = match self.device_desc.manufacturer_string_index() {
                 None => None,
-                Some(index) => Some(
-                    self.device
-                        .open()?
-                        .read_string_descriptor_ascii(index)?
-                        .trim_end_matches(char::from(0))
-                        .to_string(),
-                ),
+                Some(index) => Some(self.read_string(index)?),
             };
         }
         Ok(self.manufacturer_string.clone())
@@ -42,13 +39,7 @@ impl<Ctx: rusb::UsbContext> Instrument<Ctx> {
         if self.product_string.is_none() {
             self.product_string = match self.device_desc.product_string_index() {
                 None => None,
-                Some(index) => Some(
-                    self.device
-                        .open()?
-                        .read_string_descriptor_ascii(index)?
-                        .trim_end_matches(char::from(0))
-                        .to_string(),
-                ),
+                Some(index) => Some(self.read_string(index)?),
             };
         }
         Ok(self.product_string.clone())
@@ -65,13 +56,7 @@ impl<Ctx: rusb::UsbContext> Instrument<Ctx> {
         if !self.serial_number_loaded {
             self.serial_number = match self.device_desc.serial_number_string_index() {
                 None => None,
-                Some(index) => Some(
-                    self.device
-                        .open()?
-                        .read_string_descriptor_ascii(index)?
-                        .trim_end_matches(char::from(0))
-                        .to_string(),
-                ),
+                Some(index) => Some(self.read_string(index)?),
             };
 
             self.serial_number_loaded = true;
@@ -80,6 +65,16 @@ impl<Ctx: rusb::UsbContext> Instrument<Ctx> {
         Ok(self.serial_number.clone())
     }
 
+    fn read_string(&self, index: u8) -> TMCResult<String> {
+        let string = self
+            .device
+            .open()
+            .and_then(|usb| usb.read_string_descriptor_ascii(index))
+            .map_err(DiscoveryError::from_descriptor)?;
+
+        Ok(string.trim_end_matches(char::from(0)).to_string())
+    }
+
     /// Get the device's resource string; this may involve connecting to it in order to read its serial number.
     pub fn read_resource_string(&mut self) -> TMCResult<String> {
         let vendor_id = self.device_desc.vendor_id();
@@ -94,16 +89,28 @@ impl<Ctx: rusb::UsbContext> Instrument<Ctx> {
         }
     }
 
-    pub fn open(mut self) -> TMCResult<InstrumentHandle<Ctx>> {
+    /// Get the key identifying this instrument in a [ScanSnapshot]
+    pub fn key(&self) -> InstrumentKey {
+        InstrumentKey::new(&self.device, &self.device_desc)
+    }
+
+    pub fn open(self) -> TMCResult<InstrumentHandle<Ctx>> {
+        self.open_with(OpenOptions::default())
+    }
+
+    /// Like [open](Self::open), but with non-default connection options
+    pub fn open_with(mut self, options: OpenOptions) -> TMCResult<InstrumentHandle<Ctx>> {
         self.read_serial_number()?;
 
-        InstrumentHandle::connect(self)
+        InstrumentHandle::connect(self, options)
     }
 }
 
 impl<Ctx: rusb::UsbContext> Instrument<Ctx> {
     pub fn new(device: rusb::Device<Ctx>) -> TMCResult<Option<Instrument<Ctx>>> {
-        let device_desc = device.device_descriptor()?;
+        let device_desc = device
+            .device_descriptor()
+            .map_err(DiscoveryError::from_descriptor)?;
 
         for cfg_id in 0..device_desc.num_configurations() {
             let config_desc = match device.config_descriptor(cfg_id) {
@@ -147,9 +154,9 @@ impl<Ctx: rusb::UsbContext> Instrument<Ctx> {
                             }
                         }
 
-                        if let (Some(bulk_in_address), Some(bulk_out_address)) =
-                            (bulk_in_address, bulk_out_address)
-                        {
+                        // a gadget may only have one direction; it can still be
+                        // opened, and fails on transfers in the other
+                        if bulk_in_address.is_some() || bulk_out_address.is_some() {
                             found_interface = Some(TMCInterface {
                                 interface_number: interface_desc.interface_number(),
                                 interface_protocol: interface_desc.protocol_code(),
@@ -205,7 +212,9 @@ fn is_usbtmc_device<Ctx: rusb::UsbContext>(
 
 /// List detected USBTMC devices
 pub fn list_instruments<Ctx: rusb::UsbContext>(context: Ctx) -> TMCResult<Vec<Instrument<Ctx>>> {
-    let all_devices = context.devices()?;
+    let all_devices = context
+        .devices()
+        .map_err(|source| DiscoveryError::Enumeration { source })?;
     let mut usbtmc_devices = Vec::new();
 
     for device in all_devices.iter() {
@@ -233,3 +242,339 @@ pub fn find_instrument_with_vid_pid<Ctx: rusb::UsbContext>(
 
     Ok(None)
 }
+
+/// Identifies an attached device by its location on the bus and its IDs.  The
+/// bus address changes when a device is re-plugged, so a re-plugged instrument
+/// is reported as removed and added again.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct InstrumentKey {
+    pub bus_number: u8,
+    pub address: u8,
+    pub vendor_id: u16,
+    pub product_id: u16,
+}
+
+impl InstrumentKey {
+    fn new<Ctx: rusb::UsbContext>(
+        device: &rusb::Device<Ctx>,
+        device_desc: &rusb::DeviceDescriptor,
+    ) -> Self {
+        Self {
+            bus_number: device.bus_number(),
+            address: device.address(),
+            vendor_id: device_desc.vendor_id(),
+            product_id: device_desc.product_id(),
+        }
+    }
+}
+
+/// The set of instruments seen by a scan, used by [scan_changes] to work out
+/// what has been attached or detached since.  The other devices seen are
+/// remembered too, so that they aren't inspected again.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScanSnapshot {
+    keys: BTreeSet<InstrumentKey>,
+    // attached devices which aren't USBTMC devices
+    rejected: BTreeSet<InstrumentKey>,
+}
+
+impl ScanSnapshot {
+    /// A snapshot containing no instruments, so that the first call to
+    /// [scan_changes] reports every attached instrument as added.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn contains(&self, key: &InstrumentKey) -> bool {
+        self.keys.contains(key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &InstrumentKey> {
+        self.keys.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+/// Result of [scan_changes]
+#[derive(Debug)]
+pub struct ScanChanges<Ctx: rusb::UsbContext> {
+    /// Instruments which were not in the previous snapshot
+    pub added: Vec<Instrument<Ctx>>,
+
+    /// Instruments from the previous snapshot which are no longer attached
+    pub removed: Vec<InstrumentKey>,
+
+    /// Snapshot of the current scan, to pass to the next call
+    pub snapshot: ScanSnapshot,
+
+    /// Errors inspecting devices, which were skipped rather than failing the
+    /// scan.  A device unplugged during the scan, for example, fails with
+    /// `NoDevice`.
+    pub errors: Vec<TMCError>,
+}
+
+/// Scan for USBTMC devices and report which have been attached or detached since
+/// the `previous` snapshot was taken.  This is meant for poll-based discovery on
+/// platforms where hotplug notifications are not available.  Devices already
+/// seen by the previous scan, whether instruments or not, are not inspected
+/// again, so polling is cheap when nothing has changed.
+///
+/// A device which can't be inspected is skipped and its error reported in
+/// [errors](ScanChanges::errors).  An instrument from the previous snapshot at
+/// the same bus address is kept in the new snapshot, and any other device is
+/// inspected again by the next scan.
+pub fn scan_changes<Ctx: rusb::UsbContext>(
+    context: Ctx,
+    previous: &ScanSnapshot,
+) -> TMCResult<ScanChanges<Ctx>> {
+    let all_devices = context
+        .devices()
+        .map_err(|source| DiscoveryError::Enumeration { source })?;
+    let mut added = Vec::new();
+    let mut snapshot = ScanSnapshot::new();
+    let mut errors = Vec::new();
+
+    for device in all_devices.iter() {
+        let device_desc = match device.device_descriptor() {
+            Ok(device_desc) => device_desc,
+            Err(error) => {
+                // without the descriptor there's no key, so keep whatever was
+                // previously seen at the device's address
+                snapshot.keys.extend(previous.keys.iter().filter(|key| {
+                    key.bus_number == device.bus_number() && key.address == device.address()
+                }));
+                errors.push(DiscoveryError::from_descriptor(error).into());
+                continue;
+            }
+        };
+        let key = InstrumentKey::new(&device, &device_desc);
+
+        if previous.contains(&key) {
+            snapshot.keys.insert(key);
+        } else if previous.rejected.contains(&key) {
+            snapshot.rejected.insert(key);
+        } else {
+            match is_usbtmc_device(device) {
+                Ok(Some(instrument)) => {
+                    snapshot.keys.insert(key);
+                    added.push(instrument);
+                }
+                Ok(None) => {
+                    snapshot.rejected.insert(key);
+                }
+                Err(error) => errors.push(error),
+            }
+        }
+    }
+
+    let removed = previous
+        .iter()
+        .filter(|key| !snapshot.contains(key))
+        .copied()
+        .collect();
+
+    Ok(ScanChanges {
+        added,
+        removed,
+        snapshot,
+        errors,
+    })
+}
+
+/// Criteria for [find_instruments].  Every criterion which is set must match.
+///
+/// With the `deep-scan` feature, instruments can also be matched on the identity
+/// they report to `*IDN?`, which finds a model regardless of which vendor and
+/// product IDs it uses.  This is a deep scan: each instrument passing the other
+/// criteria is opened and queried, so it should not be done while other programs
+/// are using the instruments.
+#[derive(Debug, Clone, Default)]
+pub struct InstrumentFilter {
+    vendor_id: Option<u16>,
+    product_id: Option<u16>,
+    serial_number: Option<String>,
+
+    #[cfg(feature = "deep-scan")]
+    identity: Option<regex::Regex>,
+    #[cfg(feature = "deep-scan")]
+    deep_scan_timeout: Option<Duration>,
+}
+
+impl InstrumentFilter {
+    /// A filter matching every instrument
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn vendor_id(mut self, vendor_id: u16) -> Self {
+        self.vendor_id = Some(vendor_id);
+        self
+    }
+
+    pub fn product_id(mut self, product_id: u16) -> Self {
+        self.product_id = Some(product_id);
+        self
+    }
+
+    pub fn serial_number(mut self, serial_number: &str) -> Self {
+        self.serial_number = Some(serial_number.to_owned());
+        self
+    }
+
+    /// Only match instruments whose `*IDN?` response matches `pattern`.
+    /// Instruments which don't support SCPI, or can't be opened or queried, never
+    /// match.
+    #[cfg(feature = "deep-scan")]
+    pub fn identity(mut self, pattern: regex::Regex) -> Self {
+        self.identity = Some(pattern);
+        self
+    }
+
+    /// Timeout for the transfers made while querying identities, so that an
+    /// unresponsive instrument doesn't hold up the scan for the default timeout
+    #[cfg(feature = "deep-scan")]
+    pub fn deep_scan_timeout(mut self, timeout: Duration) -> Self {
+        self.deep_scan_timeout = Some(timeout);
+        self
+    }
+
+    /// Whether [find_instruments] will open instruments to match this filter
+    pub fn is_deep(&self) -> bool {
+        #[cfg(feature = "deep-scan")]
+        {
+            self.identity.is_some()
+        }
+        #[cfg(not(feature = "deep-scan"))]
+        {
+            false
+        }
+    }
+
+    fn matches_descriptor<Ctx: rusb::UsbContext>(&self, instrument: &mut Instrument<Ctx>) -> bool {
+        if self
+            .vendor_id
+            .is_some_and(|vendor_id| instrument.device_desc.vendor_id() != vendor_id)
+            || self
+                .product_id
+                .is_some_and(|product_id| instrument.device_desc.product_id() != product_id)
+        {
+            return false;
+        }
+
+        match &self.serial_number {
+            Some(serial_number) => {
+                matches!(instrument.read_serial_number(), Ok(Some(found)) if found == *serial_number)
+            }
+            None => true,
+        }
+    }
+
+    /// Open the instrument and check its identity.  The handle is dropped before
+    /// returning, which releases the interface and restores the device's
+    /// configuration and kernel drivers.
+    #[cfg(feature = "deep-scan")]
+    fn matches_identity<Ctx: rusb::UsbContext>(
+        &self,
+        instrument: Instrument<Ctx>,
+        identity: &regex::Regex,
+    ) -> bool {
+        let mut handle = match instrument.open() {
+            Ok(handle) => handle,
+            Err(_) => return false,
+        };
+
+        if let Some(timeout) = self.deep_scan_timeout {
+            handle.set_timeout(timeout);
+        }
+
+        match handle.scpi_id() {
+            Ok(scpi_id) => scpi_id.is_some_and(|scpi_id| identity.is_match(scpi_id)),
+            Err(_) => {
+                // don't leave a half-read response for the next user
+                let _ = handle.clear();
+                false
+            }
+        }
+    }
+}
+
+/// List the detected USBTMC devices which match `filter`.  If the filter is
+/// [deep](InstrumentFilter::is_deep), each candidate is opened and queried in
+/// turn and closed again before the next is tried.
+pub fn find_instruments<Ctx: rusb::UsbContext>(
+    context: Ctx,
+    filter: &InstrumentFilter,
+) -> TMCResult<Vec<Instrument<Ctx>>> {
+    let mut found = Vec::new();
+
+    for mut instrument in list_instruments(context)? {
+        if !filter.matches_descriptor(&mut instrument) {
+            continue;
+        }
+
+        #[cfg(feature = "deep-scan")]
+        if let Some(identity) = &filter.identity {
+            // opening the instrument consumes it, so it is described again afterwards
+            let device = instrument.device.clone();
+            if filter.matches_identity(instrument, identity) {
+                if let Some(instrument) = Instrument::new(device)? {
+                    found.push(instrument);
+                }
+            }
+            continue;
+        }
+
+        found.push(instrument);
+    }
+
+    Ok(found)
+}
+
+/// An instrument found by [find_instruments_in], with the name of the context
+/// it was found through
+#[derive(Debug)]
+pub struct FoundInstrument<Ctx: rusb::UsbContext> {
+    /// The name the context was given, such as `host` or `usbip`
+    pub origin: String,
+    pub instrument: Instrument<Ctx>,
+}
+
+/// Find the instruments matching `filter` through each of several named
+/// contexts, such as the host's own and one set up for instruments attached with
+/// usbip, and merge the results in the order the contexts are given.  Contexts
+/// on the same host can see the same devices, so a device already found through
+/// an earlier context (with the same [InstrumentKey]) is left out.  A context
+/// which can't list its devices fails the whole scan.
+pub fn find_instruments_in<Ctx, I, S>(
+    contexts: I,
+    filter: &InstrumentFilter,
+) -> TMCResult<Vec<FoundInstrument<Ctx>>>
+where
+    Ctx: rusb::UsbContext,
+    I: IntoIterator<Item = (S, Ctx)>,
+    S: Into<String>,
+{
+    let mut seen = BTreeSet::new();
+    let mut found = Vec::new();
+
+    for (origin, context) in contexts {
+        let origin = origin.into();
+        for instrument in find_instruments(context, filter)? {
+            if seen.insert(instrument.key()) {
+                found.push(FoundInstrument {
+                    origin: origin.clone(),
+                    instrument,
+                });
+            }
+        }
+    }
+
+    Ok(found)
+}
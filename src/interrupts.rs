@@ -0,0 +1,81 @@
+//! Vendor-specific notifications on the interrupt-in endpoint, such as the
+//! acquisition progress some instruments report.
+//!
+//! USBTMC reserves notifications whose first byte (`bNotify1`) has bit 7 set
+//! for the subclass, as USB488 does for service requests and status bytes, and
+//! leaves the rest, `0x00` to `0x7f`, to vendors.  Callbacks registered with
+//! [subscribe_vendor_interrupts](crate::TMCHandle::subscribe_vendor_interrupts)
+//! are given the whole packet of each vendor notification whose `bNotify1` is in
+//! their range.  Notifications are delivered whenever the handle reads the
+//! interrupt-in endpoint: while reading the status byte or waiting for a
+//! service request, and while
+//! [listening](crate::TMCHandle::listen_interrupts) for them alone.
+
+use std::fmt;
+use std::ops::RangeInclusive;
+
+/// The longest packet an interrupt endpoint can send at high or super speed
+pub(crate) const MAX_INTERRUPT_PACKET: usize = 1024;
+
+/// Identifies a subscription, to cancel it with
+/// [unsubscribe_vendor_interrupts](crate::TMCHandle::unsubscribe_vendor_interrupts)
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(u64);
+
+type VendorCallback = Box<dyn FnMut(&[u8]) + Send>;
+
+/// The vendor notification subscriptions of a handle
+#[derive(Default)]
+pub(crate) struct VendorSubscriptions {
+    next_id: u64,
+    subscriptions: Vec<(SubscriptionId, RangeInclusive<u8>, VendorCallback)>,
+}
+
+impl VendorSubscriptions {
+    pub(crate) fn subscribe(
+        &mut self,
+        notify1: RangeInclusive<u8>,
+        callback: VendorCallback,
+    ) -> SubscriptionId {
+        let id = SubscriptionId(self.next_id);
+        self.next_id += 1;
+        self.subscriptions.push((id, notify1, callback));
+        id
+    }
+
+    pub(crate) fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        let before = self.subscriptions.len();
+        self.subscriptions
+            .retain(|(subscription, _, _)| *subscription != id);
+        self.subscriptions.len() != before
+    }
+
+    /// Deliver `packet` to the subscribers interested in it if it is a vendor
+    /// notification, returning whether it was one
+    pub(crate) fn dispatch(&mut self, packet: &[u8]) -> bool {
+        let notify1 = match packet.first() {
+            Some(&notify1) if is_vendor(notify1) => notify1,
+            _ => return false,
+        };
+
+        for (_, range, callback) in self.subscriptions.iter_mut() {
+            if range.contains(&notify1) {
+                callback(packet);
+            }
+        }
+        true
+    }
+}
+
+impl fmt::Debug for VendorSubscriptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list()
+            .entries(self.subscriptions.iter().map(|(id, range, _)| (id, range)))
+            .finish()
+    }
+}
+
+/// Whether a notification's `bNotify1` marks it as vendor-specific
+pub(crate) fn is_vendor(notify1: u8) -> bool {
+    notify1 & 0x80 == 0
+}
@@ -1,9 +1,88 @@
+pub mod acquisition;
+pub mod array;
+#[cfg(feature = "tokio")]
+pub mod async_io;
+pub mod attributes;
+pub mod block;
+pub mod bounded;
+#[cfg(feature = "scpi")]
+pub mod cache;
+pub mod calibration;
+#[cfg(feature = "capture")]
+pub mod capture;
+#[cfg(feature = "scpi")]
+pub mod channel;
 pub mod class;
+pub mod clock;
+pub mod command_policy;
+#[cfg(feature = "scpi")]
+pub mod command_tree;
+#[cfg(feature = "scpi")]
+pub mod common;
+pub mod compliance;
+mod config;
+#[cfg(feature = "corpus")]
+pub mod corpus;
+pub mod debug;
+pub mod diagnostics;
+#[cfg(feature = "scpi")]
+pub mod event_log;
+pub mod event_loop;
+pub mod export;
+pub mod frames;
+#[cfg(all(feature = "gadget", target_os = "linux"))]
+pub mod gadget;
 
 mod error;
+mod global;
 mod handle;
 mod instrument;
+pub mod interrupts;
+#[cfg(feature = "scpi")]
+pub mod mass_memory;
+pub mod middleware;
+pub mod observer;
+mod options;
+pub mod poller;
+pub mod prelude;
+#[cfg(feature = "profiles")]
+pub mod profile;
+#[cfg(feature = "replay")]
+pub mod replay;
+#[cfg(feature = "scpi")]
+pub mod screen;
+pub mod session;
+#[cfg(feature = "sim")]
+pub mod sim;
+pub mod spill;
+pub mod storage;
+pub mod support;
+pub mod tasks;
+#[cfg(feature = "metrics")]
+mod telemetry;
+mod text;
+mod timeout;
+#[cfg(feature = "timing")]
+pub mod timing;
+mod transaction;
+pub mod transcript;
+pub mod transport;
+#[cfg(all(feature = "usbip", target_os = "linux"))]
+pub mod usbip;
+mod version;
+mod watchdog;
 
+/// The version of rusb used in this crate's API, for constructing contexts and
+/// devices of matching types
+pub use rusb;
+
+pub use config::*;
 pub use error::*;
+pub use global::*;
 pub use handle::*;
 pub use instrument::*;
+pub use options::*;
+pub use text::*;
+pub use timeout::*;
+pub use transaction::*;
+pub use version::*;
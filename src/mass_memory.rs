@@ -0,0 +1,227 @@
+//! Access to the files in an instrument's mass memory, with the SCPI `MMEMory`
+//! commands: listing directories, transferring files as arbitrary blocks, and
+//! deleting them.
+
+use crate::block::{block_header, block_range, encode_block_header};
+use crate::transport::Transport;
+use crate::{TMCError, TMCHandle, TMCResult};
+use std::ops::ControlFlow;
+
+/// An entry in a directory listing from `MMEM:CAT?`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CatalogEntry {
+    pub name: String,
+
+    /// The instrument's name for the kind of entry, such as `BIN`, `ASC` or
+    /// `DIR`, or empty if it doesn't say
+    pub kind: String,
+    pub size: u64,
+}
+
+impl CatalogEntry {
+    /// Whether the entry is a directory, as instruments usually mark them
+    pub fn is_directory(&self) -> bool {
+        matches!(
+            self.kind.to_ascii_uppercase().as_str(),
+            "DIR" | "FOLD" | "FOLDER"
+        )
+    }
+}
+
+/// A directory listing from `MMEM:CAT?`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Catalog {
+    /// Bytes used on the medium
+    pub used: u64,
+
+    /// Bytes free on the medium
+    pub free: u64,
+    pub entries: Vec<CatalogEntry>,
+}
+
+impl Catalog {
+    /// Parse a `MMEM:CAT?` response: the bytes used and free, then an entry
+    /// `"name,kind,size"` for each file
+    pub fn parse(response: &str) -> Option<Self> {
+        let mut fields = split_outside_quotes(response.trim()).into_iter();
+        let used = fields.next()?.trim().parse().ok()?;
+        let free = fields.next()?.trim().parse().ok()?;
+
+        let entries = fields
+            .map(|field| {
+                let field = field.trim().strip_prefix('"')?.strip_suffix('"')?;
+                // the name may itself contain commas, so split from the end
+                let mut parts = field.rsplitn(3, ',');
+                let size = parts.next()?.trim();
+                let kind = parts.next()?.trim().to_owned();
+                let name = parts.next()?.to_owned();
+
+                Some(CatalogEntry {
+                    name,
+                    kind,
+                    size: if size.is_empty() {
+                        0
+                    } else {
+                        size.parse().ok()?
+                    },
+                })
+            })
+            .collect::<Option<_>>()?;
+
+        Some(Self {
+            used,
+            free,
+            entries,
+        })
+    }
+}
+
+/// How much of a file transfer is done, as passed to progress callbacks
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct TransferProgress {
+    /// Bytes of the file transferred so far
+    pub done: usize,
+
+    /// Size of the file, if known yet
+    pub total: Option<usize>,
+}
+
+/// Split on commas which aren't inside double-quoted strings
+pub(crate) fn split_outside_quotes(s: &str) -> Vec<&str> {
+    let mut fields = Vec::new();
+    let mut quoted = false;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                fields.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    fields.push(&s[start..]);
+    fields
+}
+
+/// A SCPI string parameter, with embedded quotes doubled
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "\"\""))
+}
+
+impl<T: Transport> TMCHandle<T> {
+    /// List the current directory, or `directory` if given
+    pub fn mmem_catalog(&mut self, directory: Option<&str>) -> TMCResult<Catalog> {
+        let query = match directory {
+            Some(directory) => format!("MMEM:CAT? {}", quote(directory)),
+            None => "MMEM:CAT?".to_owned(),
+        };
+
+        let response = self.ask(&query)?;
+        Catalog::parse(&response).ok_or(TMCError::InvalidResponse(response))
+    }
+
+    /// Read a file from the instrument
+    pub fn mmem_download(&mut self, path: &str) -> TMCResult<Vec<u8>> {
+        self.mmem_download_with_progress(path, |_| {})
+    }
+
+    /// Read a file from the instrument, calling `progress` as each transfer of
+    /// it arrives
+    pub fn mmem_download_with_progress<F>(
+        &mut self,
+        path: &str,
+        mut progress: F,
+    ) -> TMCResult<Vec<u8>>
+    where
+        F: FnMut(TransferProgress),
+    {
+        self.write(&format!("MMEM:DATA? {}", quote(path)))?;
+
+        let mut message = Vec::new();
+        let mut header = None;
+        let mut malformed = None;
+        self.read_stream(None, |data| {
+            message.extend_from_slice(data);
+
+            if header.is_none() {
+                match block_header(&message) {
+                    Ok(parsed) => header = parsed,
+                    Err(error) => {
+                        malformed = Some(error);
+                        return ControlFlow::Break(());
+                    }
+                }
+            }
+
+            if let Some((start, total)) = header {
+                // not counting the line ending after the block
+                let done = message.len().saturating_sub(start);
+                progress(TransferProgress {
+                    done: total.map_or(done, |total| done.min(total)),
+                    total,
+                });
+            }
+            ControlFlow::Continue(())
+        })?;
+
+        if let Some(error) = malformed {
+            return Err(error);
+        }
+
+        let range = block_range(&message)?;
+        message.truncate(range.end);
+        message.drain(..range.start);
+        Ok(message)
+    }
+
+    /// Write a file to the instrument, replacing any file already at `path`
+    pub fn mmem_upload(&mut self, path: &str, contents: &[u8]) -> TMCResult<()> {
+        self.mmem_upload_with_progress(path, contents, |_| {})
+    }
+
+    /// Write a file to the instrument in transfers of the maximum transfer size,
+    /// calling `progress` after each.  If a transfer fails the rest of the
+    /// message is aborted, so that the instrument doesn't wait for it.
+    pub fn mmem_upload_with_progress<F>(
+        &mut self,
+        path: &str,
+        contents: &[u8],
+        progress: F,
+    ) -> TMCResult<()>
+    where
+        F: FnMut(TransferProgress),
+    {
+        let result = self.send_file(path, contents, progress);
+        if result.is_err() {
+            let _ = self.abort_bulk_out();
+        }
+        result
+    }
+
+    fn send_file<F>(&mut self, path: &str, contents: &[u8], mut progress: F) -> TMCResult<()>
+    where
+        F: FnMut(TransferProgress),
+    {
+        let mut header = self.encode_command(&format!("MMEM:DATA {},", quote(path)))?;
+        header.extend(encode_block_header(contents.len()));
+        self.write_partial_raw(&header)?;
+
+        let total = Some(contents.len());
+        let chunk_size = (self.get_max_transfer_size() as usize).max(1);
+        let mut done = 0;
+        for chunk in contents.chunks(chunk_size) {
+            self.write_partial_raw(chunk)?;
+            done += chunk.len();
+            progress(TransferProgress { done, total });
+        }
+
+        self.flush_message()
+    }
+
+    /// Delete a file from the instrument
+    pub fn mmem_delete(&mut self, path: &str) -> TMCResult<()> {
+        self.write(&format!("MMEM:DEL {}", quote(path)))
+    }
+}
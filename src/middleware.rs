@@ -0,0 +1,78 @@
+//! Transformations of the text a handle sends and receives, so that one
+//! application can drive instruments whose firmware speaks slightly different
+//! dialects through a common command set, or convert units at the edge.
+//!
+//! Middleware applies to text messages: commands given as text, such as those
+//! sent with [write](crate::TMCHandle::write), [ask](crate::TMCHandle::ask) and
+//! [ask_with_hint](crate::TMCHandle::ask_with_hint), and responses returned as
+//! text, such as those read with [read](crate::TMCHandle::read) and
+//! [ask](crate::TMCHandle::ask).  Raw messages are passed through untouched.  Commands pass through each
+//! middleware in the order they were added, and responses in the reverse order.
+
+use std::fmt;
+
+/// Rewrites commands before they are sent and responses before they are
+/// returned.  Both methods leave the text alone by default.
+pub trait Middleware: fmt::Debug + Send {
+    /// The message to send instead of `message`, or `None` to send it unchanged
+    fn command(&self, _message: &str) -> Option<String> {
+        None
+    }
+
+    /// The response to return instead of `response`, or `None` to return it
+    /// unchanged.  `message` is the last message the application sent (before
+    /// any rewriting), which the response is usually to.
+    fn response(&self, _message: &str, _response: &str) -> Option<String> {
+        None
+    }
+}
+
+/// Middleware made of plain text substitutions, for firmware which names a
+/// command differently, such as `:CHAN1:SCAL?` for `:CHANnel1:SCALe?`, or which
+/// answers with different words
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Dialect {
+    commands: Vec<(String, String)>,
+    responses: Vec<(String, String, String)>,
+}
+
+impl Dialect {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace `common` with `dialect` wherever it appears in a message sent
+    pub fn command(mut self, common: &str, dialect: &str) -> Self {
+        self.commands.push((common.to_owned(), dialect.to_owned()));
+        self
+    }
+
+    /// Return `common` in place of a response which is exactly `dialect`
+    /// (ignoring surrounding whitespace) to a message containing `query`
+    pub fn response(mut self, query: &str, dialect: &str, common: &str) -> Self {
+        self.responses
+            .push((query.to_owned(), dialect.to_owned(), common.to_owned()));
+        self
+    }
+}
+
+impl Middleware for Dialect {
+    fn command(&self, message: &str) -> Option<String> {
+        let mut rewritten = None;
+        for (common, dialect) in self.commands.iter() {
+            let current = rewritten.as_deref().unwrap_or(message);
+            if current.contains(common.as_str()) {
+                rewritten = Some(current.replace(common.as_str(), dialect));
+            }
+        }
+        rewritten
+    }
+
+    fn response(&self, message: &str, response: &str) -> Option<String> {
+        let trimmed = response.trim();
+        self.responses
+            .iter()
+            .find(|(query, dialect, _)| message.contains(query.as_str()) && trimmed == dialect)
+            .map(|(_, _, common)| response.replacen(trimmed, common, 1))
+    }
+}
@@ -0,0 +1,31 @@
+//! Notification of session events, for applications which report metrics or
+//! update a UI when an instrument connects, fails or goes away.
+
+use crate::TMCError;
+use std::fmt;
+
+/// Callbacks for events on a session.  Every method does nothing by default, so
+/// implementations only need the ones they are interested in.  Callbacks are made
+/// on the thread using the handle, and should return quickly.
+pub trait SessionObserver: fmt::Debug + Send + Sync {
+    /// The session has connected to the instrument
+    fn connected(&self) {}
+
+    /// The device has been cleared
+    fn cleared(&self) {}
+
+    /// An operation on the session failed
+    fn error(&self, _error: &TMCError) {}
+
+    /// An operation failed because the instrument has gone away; this follows the
+    /// call to [error](Self::error) for that failure
+    fn disconnected(&self) {}
+
+    /// A [Poller](crate::poller::Poller) reopened the instrument after it went
+    /// away.  This is called on the new handle after it has connected.
+    fn reconnected(&self) {}
+
+    /// The host woke from sleep, and the session was
+    /// [revalidated](crate::TMCHandle::revalidate) before it was used again
+    fn resumed(&self) {}
+}
@@ -0,0 +1,136 @@
+//! Options applied while connecting to an instrument, for settings which must be
+//! in place before the first transfer.
+
+use crate::class::{DefaultTagPolicy, TagPolicy};
+use crate::command_policy::CommandPolicy;
+use crate::middleware::Middleware;
+use crate::observer::SessionObserver;
+#[cfg(feature = "profiles")]
+use crate::profile::ProfileRegistry;
+use crate::UsbtmcConfig;
+use core::time::Duration;
+use std::sync::Arc;
+
+/// Whether the device is cleared while connecting.  Clearing discards output left
+/// over from an earlier session, but resets some instruments' output queues and
+/// can take hundreds of milliseconds.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ConnectClear {
+    #[default]
+    Always,
+    Never,
+
+    /// Only clear if the device seems to have data left over: bulk-in data is
+    /// waiting, or a USB488 device's status byte reports a message available
+    IfPendingData,
+}
+
+/// Options for [Instrument::open_with](crate::Instrument::open_with), built up
+/// with chained calls starting from [OpenOptions::new].  Settings not given here
+/// come from the installed [UsbtmcConfig].
+#[derive(Debug)]
+pub struct OpenOptions {
+    pub(crate) tag_policy: Box<dyn TagPolicy>,
+    pub(crate) claim_retry: Duration,
+    pub(crate) connect_clear: ConnectClear,
+    pub(crate) observers: Vec<Arc<dyn SessionObserver>>,
+    pub(crate) shared: bool,
+    pub(crate) command_policy: Option<Box<dyn CommandPolicy>>,
+    pub(crate) middleware: Vec<Box<dyn Middleware>>,
+    #[cfg(feature = "profiles")]
+    pub(crate) profiles: Option<ProfileRegistry>,
+    pub(crate) config: UsbtmcConfig,
+}
+
+impl OpenOptions {
+    /// Options starting from the installed [UsbtmcConfig]
+    pub fn new() -> Self {
+        Self::with_config(UsbtmcConfig::current())
+    }
+
+    /// Options starting from `config` rather than the installed configuration
+    pub fn with_config(config: UsbtmcConfig) -> Self {
+        Self {
+            tag_policy: Box::new(DefaultTagPolicy),
+            claim_retry: config.claim_retry,
+            connect_clear: ConnectClear::default(),
+            observers: Vec::new(),
+            shared: false,
+            command_policy: None,
+            middleware: Vec::new(),
+            #[cfg(feature = "profiles")]
+            profiles: config.profiles.clone(),
+            config,
+        }
+    }
+
+    /// Use a non-default strategy for choosing bTags, for devices which don't
+    /// accept the default sequence.
+    pub fn tag_policy<P: TagPolicy + 'static>(mut self, tag_policy: P) -> Self {
+        self.tag_policy = Box::new(tag_policy);
+        self
+    }
+
+    /// Check every message against `command_policy` before sending it, from the
+    /// first message on
+    pub fn command_policy<P: CommandPolicy + 'static>(mut self, command_policy: P) -> Self {
+        self.command_policy = Some(Box::new(command_policy));
+        self
+    }
+
+    /// Pass text messages and their responses through `middleware`, after any
+    /// added before it (see [middleware](crate::middleware))
+    pub fn middleware<M: Middleware + 'static>(mut self, middleware: M) -> Self {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Keep trying to claim the TMC interface for up to `window` while it is in use
+    /// by another driver or process, rather than failing immediately.
+    pub fn claim_retry(mut self, window: Duration) -> Self {
+        self.claim_retry = window;
+        self
+    }
+
+    /// Choose whether the device is cleared while connecting
+    pub fn connect_clear(mut self, connect_clear: ConnectClear) -> Self {
+        self.connect_clear = connect_clear;
+        self
+    }
+
+    /// Open the instrument alongside other software using it, such as a vendor's
+    /// control application, for monitoring its status.  Kernel drivers are left
+    /// attached and the configuration is left alone, the device isn't cleared,
+    /// and the TMC interface is claimed only if it is free.  Anything which
+    /// would disturb the other software (sending or reading messages, clearing,
+    /// aborting, triggering and other requests which change the device's state)
+    /// fails with [SharedMode](crate::TMCError::SharedMode), and
+    /// [supports](crate::TMCHandle::supports) reports the features needing them
+    /// as unsupported.  Reading the capabilities and the status byte, and
+    /// waiting for service requests, work where the platform allows.
+    pub fn shared(mut self, shared: bool) -> Self {
+        self.shared = shared;
+        self
+    }
+
+    /// Register an observer on the session, which will be told when it has
+    /// connected
+    pub fn observer(mut self, observer: Arc<dyn SessionObserver>) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    /// Apply the registry's profile for the instrument's model, if it has one,
+    /// once connected.  This reads the instrument's identity while opening it.
+    #[cfg(feature = "profiles")]
+    pub fn profiles(mut self, profiles: ProfileRegistry) -> Self {
+        self.profiles = Some(profiles);
+        self
+    }
+}
+
+impl Default for OpenOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,243 @@
+//! Periodic polling of instrument queries on a background thread, for data
+//! logging applications.
+
+use crate::bounded::{self, Backpressure, BoundedReceiver, BoundedSender};
+use crate::transport::Transport;
+use crate::{TMCHandle, TMCResult};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Result of one query made by a [Poller]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sample {
+    /// The query, as registered with [Poller::query]
+    pub query: String,
+
+    /// When the response was received (or the query failed)
+    pub timestamp: SystemTime,
+
+    pub result: TMCResult<String>,
+}
+
+/// Where a running poller delivers its samples
+enum SampleSender {
+    Unbounded(Sender<Sample>),
+    Bounded(BoundedSender<Sample>),
+}
+
+impl SampleSender {
+    /// Deliver a sample.  Returns false if the poller should stop, because
+    /// nobody is listening any more or a bounded channel refused the sample.
+    fn send(&self, sample: Sample) -> bool {
+        match self {
+            SampleSender::Unbounded(sender) => sender.send(sample).is_ok(),
+            SampleSender::Bounded(sender) => sender.send(sample).is_ok(),
+        }
+    }
+}
+
+type Reconnect<T> = Box<dyn FnMut() -> TMCResult<TMCHandle<T>> + Send>;
+
+/// Runs a set of queries at a fixed interval on a background thread, delivering
+/// each response as a timestamped [Sample] over a channel.
+///
+/// A failing query doesn't stop the poller; the error is delivered as that query's
+/// sample.  If the error means the instrument has been disconnected and a
+/// reconnect function was given, the poller keeps trying to reopen the instrument
+/// once per interval, delivering the failures as samples until it succeeds.
+pub struct Poller<T: Transport> {
+    interval: Duration,
+    queries: Vec<String>,
+    reconnect: Option<Reconnect<T>>,
+}
+
+impl<T: Transport + 'static> Poller<T> {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            queries: Vec::new(),
+            reconnect: None,
+        }
+    }
+
+    /// Add a query to be made every interval.  Queries are made in the order they
+    /// were added.
+    pub fn query(mut self, query: &str) -> Self {
+        self.queries.push(query.to_owned());
+        self
+    }
+
+    /// Reopen the instrument with `reconnect` when it is disconnected, for example
+    /// by finding it again with [find_instrument_with_vid_pid](crate::find_instrument_with_vid_pid).
+    pub fn reconnect_with<F>(mut self, reconnect: F) -> Self
+    where
+        F: FnMut() -> TMCResult<TMCHandle<T>> + Send + 'static,
+    {
+        self.reconnect = Some(Box::new(reconnect));
+        self
+    }
+
+    /// Start polling on a background thread, which takes ownership of the handle
+    /// until the poller is stopped.
+    pub fn start(self, handle: TMCHandle<T>) -> (RunningPoller<T>, Receiver<Sample>) {
+        let (sample_tx, sample_rx) = mpsc::channel();
+        let running = self.spawn(handle, SampleSender::Unbounded(sample_tx));
+        (running, sample_rx)
+    }
+
+    /// Start polling on a background thread like [start](Self::start), but
+    /// deliver samples over a channel holding at most `capacity` samples.  When
+    /// it is full, [Backpressure::Block] delays the next query until the
+    /// consumer catches up, and [Backpressure::Abort] stops the poller.
+    pub fn start_bounded(
+        self,
+        handle: TMCHandle<T>,
+        capacity: usize,
+        backpressure: Backpressure,
+    ) -> (RunningPoller<T>, BoundedReceiver<Sample>) {
+        let (sample_tx, sample_rx) = bounded::bounded(capacity, backpressure);
+        let close = sample_tx.closer();
+        let mut running = self.spawn(handle, SampleSender::Bounded(sample_tx));
+        running.close = Some(Box::new(close));
+        (running, sample_rx)
+    }
+
+    fn spawn(self, handle: TMCHandle<T>, samples: SampleSender) -> RunningPoller<T> {
+        let (stop_tx, stop_rx) = mpsc::channel();
+
+        let thread = thread::spawn(move || self.run(handle, samples, stop_rx));
+
+        RunningPoller {
+            stop: Some(stop_tx),
+            close: None,
+            thread: Some(thread),
+        }
+    }
+
+    fn run(
+        mut self,
+        handle: TMCHandle<T>,
+        samples: SampleSender,
+        stop: Receiver<()>,
+    ) -> Option<TMCHandle<T>> {
+        let mut handle = Some(handle);
+        let mut next_poll = Instant::now();
+
+        loop {
+            if handle.is_none() {
+                if let Some(reconnect) = self.reconnect.as_mut() {
+                    match reconnect() {
+                        Ok(new_handle) => {
+                            new_handle.notify_reconnected();
+                            handle = Some(new_handle);
+                        }
+                        Err(err) => {
+                            if !self.send_all(&samples, Err(err)) {
+                                return None;
+                            }
+                        }
+                    }
+                }
+            }
+
+            if let Some(current) = handle.as_mut() {
+                for query in self.queries.iter() {
+                    let result = current.ask(query);
+                    let disconnected = matches!(&result, Err(err) if err.is_disconnect());
+
+                    let sample = Sample {
+                        query: query.clone(),
+                        timestamp: SystemTime::now(),
+                        result,
+                    };
+
+                    if !samples.send(sample) {
+                        // nobody is listening any more, or they fell behind
+                        return handle;
+                    }
+
+                    if disconnected {
+                        handle = None;
+                        break;
+                    }
+                }
+            }
+
+            // without a way to reconnect, there's nothing more to do
+            if handle.is_none() && self.reconnect.is_none() {
+                return None;
+            }
+
+            // wait for the next tick, skipping any that have already been missed
+            let now = Instant::now();
+            while next_poll <= now {
+                match next_poll.checked_add(self.interval.max(Duration::from_millis(1))) {
+                    Some(next) => next_poll = next,
+                    None => {
+                        // the next tick is too far away to represent, so it never comes
+                        let _ = stop.recv();
+                        return handle;
+                    }
+                }
+            }
+
+            match stop.recv_timeout(next_poll - now) {
+                Err(RecvTimeoutError::Timeout) => {}
+                Ok(()) | Err(RecvTimeoutError::Disconnected) => return handle,
+            }
+        }
+    }
+
+    /// Deliver the same failure as the sample for every query.  Returns false if
+    /// the poller should stop.
+    fn send_all(&self, samples: &SampleSender, result: TMCResult<String>) -> bool {
+        let timestamp = SystemTime::now();
+
+        self.queries.iter().all(|query| {
+            let sample = Sample {
+                query: query.clone(),
+                timestamp,
+                result: result.clone(),
+            };
+            samples.send(sample)
+        })
+    }
+}
+
+/// A [Poller] running on a background thread.  Dropping this stops the poller.
+pub struct RunningPoller<T: Transport> {
+    stop: Option<Sender<()>>,
+
+    /// Wakes the thread if it is blocked on a full bounded channel
+    close: Option<Box<dyn Fn() + Send>>,
+    thread: Option<JoinHandle<Option<TMCHandle<T>>>>,
+}
+
+impl<T: Transport> RunningPoller<T> {
+    /// Stop polling and wait for the background thread to finish, getting back the
+    /// instrument handle if it is still connected.
+    pub fn stop(mut self) -> Option<TMCHandle<T>> {
+        self.stop_thread()
+    }
+
+    fn stop_thread(&mut self) -> Option<TMCHandle<T>> {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+        if let Some(close) = self.close.take() {
+            close();
+        }
+
+        self.thread
+            .take()
+            .and_then(|thread| thread.join().ok())
+            .flatten()
+    }
+}
+
+impl<T: Transport> Drop for RunningPoller<T> {
+    fn drop(&mut self) {
+        self.stop_thread();
+    }
+}
@@ -0,0 +1,23 @@
+//! The types and traits most applications need, for glob import with
+//! `use tmc::prelude::*`.  Items are only added here, never removed or renamed
+//! outside of a major version change.
+
+pub use crate::class::{TagPolicy, USB488Capabilities, USBTMCCapabilities};
+pub use crate::command_policy::CommandPolicy;
+#[cfg(feature = "scpi")]
+pub use crate::common::{Identity, StandardEvents, StatusByte};
+pub use crate::observer::SessionObserver;
+pub use crate::session::Session;
+pub use crate::support::{Feature, SupportLevel};
+pub use crate::transaction::TransactionCleanup;
+pub use crate::transport::Transport;
+pub use crate::{
+    find_instrument_with_vid_pid, find_instruments, find_instruments_in, list_instruments,
+};
+pub use crate::{ClassError, ConnectError, DiscoveryError, TMCError, TMCResult};
+pub use crate::{
+    ConnectClear, DropCleanup, Instrument, InstrumentFilter, InstrumentHandle, OpenOptions,
+    TMCHandle, UsbtmcConfig,
+};
+pub use crate::{Encoding, StallRecovery, TextDecoding};
+pub use rusb::{Context, GlobalContext, UsbContext};
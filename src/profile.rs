@@ -0,0 +1,363 @@
+//! Saved handle settings, so that instruments which need non-default settings get
+//! them whenever they are opened.
+//!
+//! A [ProfileRegistry] maps instrument models, identified by the manufacturer and
+//! model fields of their `*IDN?` response, to [SettingsProfile]s.  It can be
+//! saved and loaded as YAML:
+//!
+//! ```yaml
+//! "KEYSIGHT TECHNOLOGIES,34465A":
+//!   timeout_ms: 5000
+//!   term_char: 10
+//!   max_transfer_size: 65536
+//! ```
+//!
+//! Settings missing from a profile are left as they are.  Registries passed to
+//! [OpenOptions::profiles](crate::OpenOptions::profiles) are applied when an
+//! instrument is opened.  A registry shared by the applications on a bench can
+//! be kept in the [configuration directory](crate::storage::config_dir), with
+//! [load_default](ProfileRegistry::load_default) and
+//! [save_default](ProfileRegistry::save_default).
+
+use crate::class::{CapabilitiesPolicy, PaddingPolicy};
+use crate::storage;
+use crate::transport::Transport;
+use crate::{
+    DropCleanup, Encoding, PoisonPolicy, ResumeRecovery, StallRecovery, TMCHandle, TMCResult,
+    TextDecoding, TransactionCleanup, TriggerStrategy,
+};
+use core::time::Duration;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ProfileError {
+    /// The registry file could not be read or written
+    #[error("Error accessing profiles: {source}")]
+    Io {
+        #[from]
+        source: std::io::Error,
+    },
+
+    /// The registry is not valid YAML, or doesn't have the expected structure
+    #[error("Error parsing profiles: {source}")]
+    Yaml {
+        #[from]
+        source: serde_yaml::Error,
+    },
+}
+
+/// Handle settings to apply together.  Settings which are `None` are left
+/// unchanged.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SettingsProfile {
+    /// Timeout for all transfers, applied before the more specific timeouts
+    #[serde(rename = "timeout_ms", with = "millis")]
+    pub timeout: Option<Duration>,
+    #[serde(rename = "bulk_timeout_ms", with = "millis")]
+    pub bulk_timeout: Option<Duration>,
+    #[serde(rename = "control_timeout_ms", with = "millis")]
+    pub control_timeout: Option<Duration>,
+    #[serde(rename = "interrupt_timeout_ms", with = "millis")]
+    pub interrupt_timeout: Option<Duration>,
+
+    /// Termination character; `Some(None)` (`null` in YAML) turns it off
+    #[serde(deserialize_with = "present", skip_serializing_if = "Option::is_none")]
+    pub term_char: Option<Option<u8>>,
+    pub max_transfer_size: Option<u32>,
+    #[serde(deserialize_with = "present", skip_serializing_if = "Option::is_none")]
+    pub max_reads: Option<Option<u32>>,
+    #[serde(deserialize_with = "present", skip_serializing_if = "Option::is_none")]
+    pub max_response_size: Option<Option<usize>>,
+
+    #[serde(rename = "claim_retry_ms", with = "millis")]
+    pub claim_retry: Option<Duration>,
+    pub transaction_cleanup: Option<TransactionCleanup>,
+    pub verify_writes: Option<bool>,
+    pub drop_cleanup: Option<DropCleanup>,
+    pub poison_policy: Option<PoisonPolicy>,
+    pub stall_recovery: Option<StallRecovery>,
+    pub resume_recovery: Option<ResumeRecovery>,
+    pub reset_tag_on_clear: Option<bool>,
+    pub trigger_strategy: Option<TriggerStrategy>,
+
+    pub padding_policy: Option<PaddingPolicy>,
+    pub capabilities_policy: Option<CapabilitiesPolicy>,
+    pub encoding: Option<Encoding>,
+    pub text_decoding: Option<TextDecoding>,
+}
+
+impl SettingsProfile {
+    /// A profile holding all of the handle's current settings
+    pub fn capture<T: Transport>(handle: &TMCHandle<T>) -> Self {
+        Self {
+            timeout: None,
+            bulk_timeout: Some(handle.get_bulk_timeout()),
+            control_timeout: Some(handle.get_control_timeout()),
+            interrupt_timeout: Some(handle.get_interrupt_timeout()),
+            term_char: Some(handle.get_term_char()),
+            max_transfer_size: Some(handle.get_max_transfer_size()),
+            max_reads: Some(handle.get_max_reads()),
+            max_response_size: Some(handle.get_max_response_size()),
+            claim_retry: Some(handle.get_claim_retry()),
+            transaction_cleanup: Some(handle.get_transaction_cleanup()),
+            verify_writes: Some(handle.get_verify_writes()),
+            drop_cleanup: Some(handle.get_drop_cleanup()),
+            poison_policy: Some(handle.get_poison_policy()),
+            stall_recovery: Some(handle.get_stall_recovery()),
+            resume_recovery: Some(handle.get_resume_recovery()),
+            reset_tag_on_clear: Some(handle.get_reset_tag_on_clear()),
+            trigger_strategy: Some(handle.get_trigger_strategy().clone()),
+            padding_policy: Some(handle.get_padding_policy()),
+            capabilities_policy: Some(handle.get_capabilities_policy()),
+            encoding: Some(handle.get_encoding()),
+            text_decoding: Some(handle.get_text_decoding()),
+        }
+    }
+
+    /// Apply the settings in this profile to the handle.  Fails if the
+    /// instrument doesn't support one of them, in which case the settings before
+    /// it have been applied.
+    pub fn apply<T: Transport>(&self, handle: &mut TMCHandle<T>) -> TMCResult<()> {
+        if let Some(timeout) = self.timeout {
+            handle.set_timeout(timeout);
+        }
+        if let Some(timeout) = self.bulk_timeout {
+            handle.set_bulk_timeout(timeout);
+        }
+        if let Some(timeout) = self.control_timeout {
+            handle.set_control_timeout(timeout);
+        }
+        if let Some(timeout) = self.interrupt_timeout {
+            handle.set_interrupt_timeout(timeout);
+        }
+        if let Some(term_char) = self.term_char {
+            handle.set_term_char(term_char)?;
+        }
+        if let Some(max_transfer_size) = self.max_transfer_size {
+            handle.set_max_transfer_size(max_transfer_size)?;
+        }
+        if let Some(max_reads) = self.max_reads {
+            handle.set_max_reads(max_reads);
+        }
+        if let Some(max_response_size) = self.max_response_size {
+            handle.set_max_response_size(max_response_size);
+        }
+        if let Some(claim_retry) = self.claim_retry {
+            handle.set_claim_retry(claim_retry);
+        }
+        if let Some(transaction_cleanup) = self.transaction_cleanup {
+            handle.set_transaction_cleanup(transaction_cleanup);
+        }
+        if let Some(verify_writes) = self.verify_writes {
+            handle.set_verify_writes(verify_writes)?;
+        }
+        if let Some(drop_cleanup) = self.drop_cleanup {
+            handle.set_drop_cleanup(drop_cleanup);
+        }
+        if let Some(poison_policy) = self.poison_policy {
+            handle.set_poison_policy(poison_policy);
+        }
+        if let Some(stall_recovery) = self.stall_recovery {
+            handle.set_stall_recovery(stall_recovery);
+        }
+        if let Some(resume_recovery) = self.resume_recovery {
+            handle.set_resume_recovery(resume_recovery);
+        }
+        if let Some(reset_tag_on_clear) = self.reset_tag_on_clear {
+            handle.set_reset_tag_on_clear(reset_tag_on_clear);
+        }
+        if let Some(trigger_strategy) = &self.trigger_strategy {
+            handle.set_trigger_strategy(trigger_strategy.clone());
+        }
+        if let Some(padding_policy) = self.padding_policy {
+            handle.set_padding_policy(padding_policy);
+        }
+        if let Some(capabilities_policy) = self.capabilities_policy {
+            handle.set_capabilities_policy(capabilities_policy);
+        }
+        if let Some(encoding) = self.encoding {
+            handle.set_encoding(encoding);
+        }
+        if let Some(text_decoding) = self.text_decoding {
+            handle.set_text_decoding(text_decoding);
+        }
+
+        Ok(())
+    }
+}
+
+/// Settings profiles for instrument models
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(
+    from = "BTreeMap<String, SettingsProfile>",
+    into = "BTreeMap<String, SettingsProfile>"
+)]
+pub struct ProfileRegistry {
+    profiles: BTreeMap<String, SettingsProfile>,
+}
+
+impl From<BTreeMap<String, SettingsProfile>> for ProfileRegistry {
+    fn from(profiles: BTreeMap<String, SettingsProfile>) -> Self {
+        Self {
+            profiles: profiles
+                .into_iter()
+                .map(|(identity, profile)| (model_key(&identity), profile))
+                .collect(),
+        }
+    }
+}
+
+impl From<ProfileRegistry> for BTreeMap<String, SettingsProfile> {
+    fn from(registry: ProfileRegistry) -> Self {
+        registry.profiles
+    }
+}
+
+impl ProfileRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_yaml(yaml: &str) -> Result<Self, ProfileError> {
+        Ok(serde_yaml::from_str(yaml)?)
+    }
+
+    pub fn to_yaml(&self) -> Result<String, ProfileError> {
+        Ok(serde_yaml::to_string(self)?)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ProfileError> {
+        Self::from_yaml(&fs::read_to_string(path)?)
+    }
+
+    /// Save the registry, replacing the file at `path` atomically
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ProfileError> {
+        Ok(storage::write_atomic(path, self.to_yaml()?)?)
+    }
+
+    /// Where [load_default](Self::load_default) and
+    /// [save_default](Self::save_default) keep the registry: `profiles.yaml` in
+    /// the [configuration directory](storage::config_dir)
+    pub fn default_path() -> Option<PathBuf> {
+        storage::config_dir().map(|dir| dir.join("profiles.yaml"))
+    }
+
+    /// Load the registry from its [default path](Self::default_path), or an
+    /// empty registry if it hasn't been saved there
+    pub fn load_default() -> Result<Self, ProfileError> {
+        match Self::load(Self::default_path().ok_or_else(no_config_dir)?) {
+            Err(ProfileError::Io { source }) if source.kind() == io::ErrorKind::NotFound => {
+                Ok(Self::default())
+            }
+            result => result,
+        }
+    }
+
+    /// Save the registry to its [default path](Self::default_path)
+    pub fn save_default(&self) -> Result<(), ProfileError> {
+        self.save(Self::default_path().ok_or_else(no_config_dir)?)
+    }
+
+    /// Associate a profile with the model an `*IDN?` response belongs to.
+    /// `identity` can be a whole response or just its manufacturer and model
+    /// fields.
+    pub fn insert(&mut self, identity: &str, profile: SettingsProfile) {
+        self.profiles.insert(model_key(identity), profile);
+    }
+
+    pub fn remove(&mut self, identity: &str) -> Option<SettingsProfile> {
+        self.profiles.remove(&model_key(identity))
+    }
+
+    /// The profile for the model an `*IDN?` response belongs to
+    pub fn get(&self, identity: &str) -> Option<&SettingsProfile> {
+        self.profiles.get(&model_key(identity))
+    }
+
+    /// Save the handle's current settings as the profile for its instrument's
+    /// model.  Returns false if the instrument doesn't identify itself.
+    pub fn remember<T: Transport>(&mut self, handle: &mut TMCHandle<T>) -> TMCResult<bool> {
+        let profile = SettingsProfile::capture(handle);
+        match handle.scpi_id()? {
+            Some(identity) => {
+                self.insert(identity, profile);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Apply the profile for the handle's instrument, if there is one.  Returns
+    /// whether a profile was applied.
+    pub fn apply<T: Transport>(&self, handle: &mut TMCHandle<T>) -> TMCResult<bool> {
+        let profile = match handle.scpi_id()?.and_then(|identity| self.get(identity)) {
+            Some(profile) => profile,
+            None => return Ok(false),
+        };
+
+        profile.apply(handle)?;
+        Ok(true)
+    }
+}
+
+/// The manufacturer and model fields of an `*IDN?` response, ignoring case and
+/// surrounding whitespace
+fn model_key(identity: &str) -> String {
+    identity
+        .split(',')
+        .take(2)
+        .map(|field| field.trim().to_ascii_uppercase())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn no_config_dir() -> ProfileError {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        "no configuration directory; is HOME set?",
+    )
+    .into()
+}
+
+/// Deserialize a setting which can be turned off, so that a `null` value turns
+/// it off rather than leaving it unchanged as a missing value does
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// Serialize optional durations as whole milliseconds
+mod millis {
+    use core::time::Duration;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        duration: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => {
+                // Duration::MAX, meaning no timeout, saturates to a timeout
+                // which is just as long in practice
+                let millis = duration.as_millis().min(u64::MAX as u128) as u64;
+                serializer.serialize_some(&millis)
+            }
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        Ok(Option::<u64>::deserialize(deserializer)?.map(Duration::from_millis))
+    }
+}
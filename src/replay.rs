@@ -0,0 +1,166 @@
+//! Re-running a recorded [transcript](crate::transcript) against a real or
+//! simulated instrument, checking its responses against the recorded ones, for
+//! regression testing of instrument firmware or of the simulation itself.
+
+use crate::transcript::{Direction, TranscriptEntry};
+use crate::transport::Transport;
+use crate::{TMCHandle, TMCResult};
+use core::time::Duration;
+use regex::Regex;
+use std::thread::sleep;
+use std::time::Instant;
+
+/// When to send each command
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum Pacing {
+    /// Send each command as soon as the previous message has been handled
+    #[default]
+    Immediate,
+
+    /// Send each command at the same time, relative to the first command, as when
+    /// the transcript was recorded
+    Recorded,
+
+    /// Wait a fixed time before sending each command
+    Fixed(Duration),
+}
+
+/// How responses are compared with the recorded responses
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum ResponseCheck {
+    /// The response must be identical
+    #[default]
+    Exact,
+
+    /// The recorded response is a regular expression which must match the whole
+    /// response, so that a transcript can be edited to allow for varying output
+    Regex,
+
+    /// Responses are split into fields on `,` and `;`, and numeric fields must be
+    /// within `tolerance` of the recorded value.  Other fields must be identical,
+    /// apart from surrounding whitespace.
+    Numeric { tolerance: f64 },
+
+    /// Responses are read but not checked
+    Ignore,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct ReplayOptions {
+    pub pacing: Pacing,
+    pub check: ResponseCheck,
+
+    /// Stop at the first response which doesn't match
+    pub stop_on_mismatch: bool,
+}
+
+/// A response which didn't match the recorded response
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Mismatch {
+    /// Index of the response in the transcript
+    pub index: usize,
+    pub expected: String,
+    pub actual: String,
+}
+
+/// Outcome of a [replay]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ReplayReport {
+    pub commands_sent: usize,
+    pub responses_checked: usize,
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl ReplayReport {
+    pub fn passed(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+/// Send the recorded commands to `target` in order, reading a response wherever
+/// one was recorded and checking it as set in `options`.  Transfer errors end
+/// the replay; mismatched responses are collected in the report.
+pub fn replay<T: Transport>(
+    transcript: &[TranscriptEntry],
+    target: &mut TMCHandle<T>,
+    options: &ReplayOptions,
+) -> TMCResult<ReplayReport> {
+    let mut report = ReplayReport::default();
+
+    let first_sent = transcript
+        .iter()
+        .find(|entry| entry.direction == Direction::Sent)
+        .map(|entry| entry.timestamp);
+    let start = Instant::now();
+
+    for (index, entry) in transcript.iter().enumerate() {
+        match entry.direction {
+            Direction::Sent => {
+                match options.pacing {
+                    Pacing::Immediate => {}
+                    Pacing::Recorded => {
+                        let offset = first_sent
+                            .and_then(|first| entry.timestamp.duration_since(first).ok())
+                            .unwrap_or_default();
+                        sleep(offset.saturating_sub(start.elapsed()));
+                    }
+                    Pacing::Fixed(delay) => sleep(delay),
+                }
+
+                target.write_raw(entry.message.as_bytes())?;
+                report.commands_sent += 1;
+            }
+            Direction::Received => {
+                let actual = String::from_utf8_lossy(&target.read_raw(None)?).into_owned();
+                report.responses_checked += 1;
+
+                if !matches(&entry.message, &actual, options.check) {
+                    report.mismatches.push(Mismatch {
+                        index,
+                        expected: entry.message.clone(),
+                        actual,
+                    });
+
+                    if options.stop_on_mismatch {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+fn matches(expected: &str, actual: &str, check: ResponseCheck) -> bool {
+    match check {
+        ResponseCheck::Exact => expected == actual,
+        ResponseCheck::Regex => {
+            // an invalid pattern can't match anything
+            Regex::new(&format!("^(?:{})$", expected)).is_ok_and(|pattern| pattern.is_match(actual))
+        }
+        ResponseCheck::Numeric { tolerance } => {
+            let split = |response: &str| -> Vec<String> {
+                response
+                    .trim()
+                    .split([',', ';'])
+                    .map(|field| field.trim().to_owned())
+                    .collect()
+            };
+            let expected = split(expected);
+            let actual = split(actual);
+
+            expected.len() == actual.len()
+                && expected
+                    .iter()
+                    .zip(actual.iter())
+                    .all(|(expected, actual)| {
+                        match (expected.parse::<f64>(), actual.parse::<f64>()) {
+                            (Ok(expected), Ok(actual)) => (expected - actual).abs() <= tolerance,
+                            _ => expected == actual,
+                        }
+                    })
+        }
+        ResponseCheck::Ignore => true,
+    }
+}
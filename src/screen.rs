@@ -0,0 +1,118 @@
+//! Capturing an image of the instrument's display.
+//!
+//! Instruments differ in the commands they take for this, so the commands are
+//! described by a [ScreenCapture], which drivers for particular instruments can
+//! build to suit.  The image is expected as an arbitrary block in the response
+//! to the capture query.
+
+use crate::transport::Transport;
+use crate::{TMCHandle, TMCResult};
+
+/// Format of a captured image, recognised by its signature
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ImageFormat {
+    Bmp,
+    Png,
+    Jpeg,
+    Gif,
+    Tiff,
+
+    /// None of the formats above
+    Unknown,
+}
+
+impl ImageFormat {
+    /// Recognise the format of an image from its first bytes
+    pub fn detect(image: &[u8]) -> Self {
+        match image {
+            [0x89, b'P', b'N', b'G', ..] => ImageFormat::Png,
+            [b'B', b'M', ..] => ImageFormat::Bmp,
+            [0xff, 0xd8, 0xff, ..] => ImageFormat::Jpeg,
+            [b'G', b'I', b'F', b'8', ..] => ImageFormat::Gif,
+            [b'I', b'I', 0x2a, 0x00, ..] | [b'M', b'M', 0x00, 0x2a, ..] => ImageFormat::Tiff,
+            _ => ImageFormat::Unknown,
+        }
+    }
+
+    /// The usual file name extension for the format
+    pub fn extension(self) -> Option<&'static str> {
+        match self {
+            ImageFormat::Bmp => Some("bmp"),
+            ImageFormat::Png => Some("png"),
+            ImageFormat::Jpeg => Some("jpg"),
+            ImageFormat::Gif => Some("gif"),
+            ImageFormat::Tiff => Some("tiff"),
+            ImageFormat::Unknown => None,
+        }
+    }
+}
+
+/// The commands which make an instrument send an image of its display
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ScreenCapture {
+    /// Commands sent before the query, such as to choose the image format
+    pub setup: Vec<String>,
+
+    /// The query answered with the image as an arbitrary block
+    pub query: String,
+}
+
+impl ScreenCapture {
+    pub fn new(query: &str) -> Self {
+        Self {
+            setup: Vec::new(),
+            query: query.to_owned(),
+        }
+    }
+
+    /// Add a command to send before the query
+    pub fn setup(mut self, command: &str) -> Self {
+        self.setup.push(command.to_owned());
+        self
+    }
+
+    /// `DISP:DATA?`, taken by many oscilloscopes and signal generators
+    pub fn display_data() -> Self {
+        Self::new("DISP:DATA?")
+    }
+
+    /// `HCOP:SDUM:DATA?`, the SCPI hardcopy screen dump
+    pub fn hardcopy_dump() -> Self {
+        Self::new("HCOP:SDUM:DATA?")
+    }
+}
+
+impl Default for ScreenCapture {
+    fn default() -> Self {
+        Self::display_data()
+    }
+}
+
+/// An image of the instrument's display
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Screenshot {
+    pub format: ImageFormat,
+    pub data: Vec<u8>,
+}
+
+impl<T: Transport> TMCHandle<T> {
+    /// Capture the display with the common `DISP:DATA?` query
+    pub fn capture_screen(&mut self) -> TMCResult<Screenshot> {
+        self.capture_screen_with(&ScreenCapture::default())
+    }
+
+    /// Capture the display with the commands in `capture`.  Any limit set with
+    /// [set_max_response_size](Self::set_max_response_size) must allow for the
+    /// whole image.
+    pub fn capture_screen_with(&mut self, capture: &ScreenCapture) -> TMCResult<Screenshot> {
+        for command in capture.setup.iter() {
+            self.write(command)?;
+        }
+
+        let data = self.ask_block(&capture.query)?;
+        Ok(Screenshot {
+            format: ImageFormat::detect(&data),
+            data,
+        })
+    }
+}
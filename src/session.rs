@@ -0,0 +1,84 @@
+//! Measurement sessions which put an instrument into a known state before some
+//! work and always take it back out afterwards, so that a failed or crashed
+//! script can't leave an output switched on.
+
+use crate::transport::Transport;
+use crate::{TMCHandle, TMCResult};
+use std::panic::{self, AssertUnwindSafe};
+
+/// Commands written to an instrument before and after a piece of work, built
+/// with [Session::with_setup] and used with [run](Session::run)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Session {
+    setup: Vec<String>,
+    teardown: Vec<String>,
+}
+
+impl Session {
+    /// A session which writes `setup` before the work and `teardown` (such as
+    /// `OUTP OFF` and a return to local mode) after it, each command as its own
+    /// message in the order given
+    pub fn with_setup<I, J>(setup: I, teardown: J) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+        J: IntoIterator,
+        J::Item: Into<String>,
+    {
+        Self {
+            setup: setup.into_iter().map(Into::into).collect(),
+            teardown: teardown.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    pub fn setup(&self) -> &[String] {
+        &self.setup
+    }
+
+    pub fn teardown(&self) -> &[String] {
+        &self.teardown
+    }
+
+    /// Write the setup commands, run `f`, and then write the teardown commands,
+    /// whether the setup or `f` succeeded, failed or panicked.
+    ///
+    /// If the setup or `f` failed or panicked, the device is first
+    /// [recovered](TMCHandle::recover) so that the teardown commands aren't
+    /// refused because of the state it was left in.  Every teardown command is
+    /// written even if an earlier one fails.  A panic carries on once the
+    /// teardown is done, and an error from the setup or `f` is returned in
+    /// preference to one from the teardown.
+    pub fn run<T, F, R>(&self, handle: &mut TMCHandle<T>, f: F) -> TMCResult<R>
+    where
+        T: Transport,
+        F: FnOnce(&mut TMCHandle<T>) -> TMCResult<R>,
+    {
+        let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+            for command in self.setup.iter() {
+                handle.write(command)?;
+            }
+            f(handle)
+        }));
+
+        if !matches!(outcome, Ok(Ok(_))) {
+            let _ = handle.recover();
+        }
+
+        let mut teardown = Ok(());
+        for command in self.teardown.iter() {
+            let result = handle.write(command);
+            if teardown.is_ok() {
+                teardown = result;
+            }
+        }
+
+        match outcome {
+            Ok(result) => {
+                let value = result?;
+                teardown?;
+                Ok(value)
+            }
+            Err(panic) => panic::resume_unwind(panic),
+        }
+    }
+}
@@ -0,0 +1,25 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum SimError {
+    /// The script file could not be read
+    #[error("Error reading script: {source}")]
+    Io {
+        #[from]
+        source: std::io::Error,
+    },
+
+    /// The script is not valid YAML, or doesn't have the expected structure
+    #[error("Error parsing script: {source}")]
+    Yaml {
+        #[from]
+        source: serde_yaml::Error,
+    },
+
+    /// A rule's pattern is not a valid regular expression
+    #[error("invalid pattern {pattern:?}: {source}")]
+    Pattern {
+        pattern: String,
+        source: regex::Error,
+    },
+}
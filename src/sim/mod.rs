@@ -0,0 +1,35 @@
+//! A simulated USBTMC instrument, for testing applications without hardware.
+//!
+//! The instrument's behaviour is described by a [Script], usually written in YAML:
+//!
+//! ```yaml
+//! idn: "ACME,SIM-1,0,1.0"
+//! rules:
+//!   # responses may refer to groups captured by the pattern
+//!   - pattern: 'MEAS:VOLT\? CH(\d)'
+//!     response: "1.25E-3,$1"
+//!     delay_ms: 20
+//!   # commands without a response can still take time and request service
+//!   - pattern: 'INIT'
+//!     delay_ms: 500
+//!     srq: true
+//! ```
+//!
+//! Each command in a message (commands are separated by `;`) is matched against
+//! the rules in order, and the first rule whose pattern matches the whole command
+//! applies.  Commands matching no rule are handled by the built-in `*IDN?`, `*OPC?`,
+//! `*STB?`, `*SRE`, `*CLS`, `*RST`, `*WAI` and `SYST:ERR?` implementations, or otherwise
+//! ignored.  The responses to the queries in a message are joined into one
+//! response message.
+//!
+//! A [SimTransport] running the script can be used with
+//! [TMCHandle::with_transport](crate::TMCHandle::with_transport) in place of a
+//! real instrument.
+
+mod error;
+mod script;
+mod transport;
+
+pub use error::*;
+pub use script::*;
+pub use transport::*;
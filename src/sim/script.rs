@@ -0,0 +1,96 @@
+use crate::sim::SimError;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+/// Description of a simulated instrument's behaviour
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Script {
+    /// Response to `*IDN?`, unless a rule overrides it
+    #[serde(default = "default_idn")]
+    pub idn: String,
+
+    #[serde(default)]
+    pub capabilities: SimCapabilities,
+
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+}
+
+fn default_idn() -> String {
+    "tmc,Simulated Instrument,0,0".to_owned()
+}
+
+impl Script {
+    pub fn from_yaml(yaml: &str) -> Result<Self, SimError> {
+        Ok(serde_yaml::from_str(yaml)?)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SimError> {
+        Self::from_yaml(&fs::read_to_string(path)?)
+    }
+}
+
+impl Default for Script {
+    fn default() -> Self {
+        Self {
+            idn: default_idn(),
+            capabilities: SimCapabilities::default(),
+            rules: Vec::new(),
+        }
+    }
+}
+
+/// Capabilities the simulated instrument reports.  A USB488 instrument reports
+/// USB488.2 support, and supports service requests and `READ_STATUS_BYTE`.  Its
+/// TRIGGER message is executed as a `*TRG` command, which rules can match.
+/// Turning off `bulk_in` or `bulk_out` leaves out that endpoint, as gadgets which
+/// only send or only receive do.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct SimCapabilities {
+    pub usb488: bool,
+    pub scpi: bool,
+    pub pulse: bool,
+    pub term_char: bool,
+    pub trigger: bool,
+    pub bulk_in: bool,
+    pub bulk_out: bool,
+}
+
+impl Default for SimCapabilities {
+    fn default() -> Self {
+        Self {
+            usb488: true,
+            scpi: true,
+            pulse: true,
+            term_char: true,
+            trigger: true,
+            bulk_in: true,
+            bulk_out: true,
+        }
+    }
+}
+
+/// How the simulated instrument reacts to commands matching a pattern
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Rule {
+    /// Regular expression which must match the whole command, with surrounding
+    /// whitespace removed
+    pub pattern: String,
+
+    /// Response to queue, in which `$1`, `${name}` etc. are replaced by the groups
+    /// captured by the pattern.  Without one the command is not a query.
+    #[serde(default)]
+    pub response: Option<String>,
+
+    /// How long the instrument takes to execute the command, before its response is
+    /// available or its service request is raised
+    #[serde(default)]
+    pub delay_ms: u64,
+
+    /// Request service (setting RQS in the status byte and sending an SRQ
+    /// notification) once the command has executed
+    #[serde(default)]
+    pub srq: bool,
+}
@@ -0,0 +1,556 @@
+use crate::class::*;
+use crate::sim::{Script, SimError};
+use crate::transport::{DeviceLabel, Transport};
+use byteorder::{ByteOrder, LittleEndian};
+use core::time::Duration;
+use regex::Regex;
+use std::collections::VecDeque;
+use std::thread::sleep;
+use std::time::Instant;
+
+const BULK_OUT_ADDRESS: u8 = 0x01;
+const BULK_IN_ADDRESS: u8 = 0x82;
+const INTERRUPT_IN_ADDRESS: u8 = 0x83;
+const MAX_PACKET_SIZE: u16 = 512;
+
+// status byte bits
+const STB_MAV: u8 = 0x10;
+const STB_RQS: u8 = 0x40;
+
+/// The longest a transfer may wait, where zero is forever as for libusb
+fn wait_limit(timeout: Duration) -> Duration {
+    if timeout.is_zero() {
+        Duration::MAX
+    } else {
+        timeout
+    }
+}
+
+/// The length of the bulk-out transfer at the start of `buf`, padding included,
+/// if all of it has arrived
+fn transfer_len(buf: &[u8]) -> Option<usize> {
+    let header = BulkOutHeader::unpack(buf.get(..HEADER_SIZE)?).ok();
+    let len = match header.map(|header| header.msg_id) {
+        Some(MsgIdOut::DevDepMsgOut) => {
+            let transfer_size = LittleEndian::read_u32(&buf[4..8]) as usize;
+            HEADER_SIZE.saturating_add(transfer_size).saturating_add(3) & !3
+        }
+        _ => HEADER_SIZE,
+    };
+
+    if buf.len() >= len {
+        Some(len)
+    } else {
+        None
+    }
+}
+
+#[derive(Debug)]
+struct CompiledRule {
+    pattern: Regex,
+    response: Option<String>,
+    delay: Duration,
+    srq: bool,
+}
+
+/// Transport to a simulated instrument, which behaves as described by a [Script]
+#[derive(Debug)]
+pub struct SimTransport {
+    script: Script,
+    rules: Vec<CompiledRule>,
+    interface: TMCInterface,
+    claimed: bool,
+
+    // bulk-out data which doesn't yet make up a whole transfer, as a transfer
+    // may arrive over several writes
+    received: Vec<u8>,
+
+    // command data received so far in the current message
+    command: Vec<u8>,
+
+    // response messages not yet sent, the length of each still unsent, and when
+    // the latest is ready to be sent
+    response: VecDeque<u8>,
+    response_lengths: VecDeque<usize>,
+    response_ready: Instant,
+
+    // the host's latest REQUEST_DEV_DEP_MSG_IN, and the part of the transfer
+    // answering it which hasn't been read yet
+    request: Option<RequestDevDepMsgInHeader>,
+    transfer: VecDeque<u8>,
+
+    status_byte: u8,
+    service_request_enable: u8,
+    srq_at: Option<Instant>,
+    interrupts: VecDeque<[u8; 2]>,
+}
+
+impl SimTransport {
+    pub fn new(script: &Script) -> Result<Self, SimError> {
+        let rules = script
+            .rules
+            .iter()
+            .map(|rule| {
+                let pattern = Regex::new(&format!("^(?:{})$", rule.pattern)).map_err(|source| {
+                    SimError::Pattern {
+                        pattern: rule.pattern.clone(),
+                        source,
+                    }
+                })?;
+
+                Ok(CompiledRule {
+                    pattern,
+                    response: rule.response.clone(),
+                    delay: Duration::from_millis(rule.delay_ms),
+                    srq: rule.srq,
+                })
+            })
+            .collect::<Result<_, SimError>>()?;
+
+        let interface = TMCInterface {
+            interface_number: 0,
+            interface_protocol: if script.capabilities.usb488 { 1 } else { 0 },
+            bulk_out_address: Some(BULK_OUT_ADDRESS).filter(|_| script.capabilities.bulk_out),
+            bulk_in_address: Some(BULK_IN_ADDRESS).filter(|_| script.capabilities.bulk_in),
+            interrupt_in_address: if script.capabilities.usb488 {
+                Some(INTERRUPT_IN_ADDRESS)
+            } else {
+                None
+            },
+            control_in_max_packet_size: 64,
+            bulk_out_max_packet_size: MAX_PACKET_SIZE,
+            bulk_in_max_packet_size: MAX_PACKET_SIZE,
+        };
+
+        Ok(Self {
+            script: script.clone(),
+            rules,
+            interface,
+            claimed: false,
+
+            received: Vec::new(),
+            command: Vec::new(),
+            response: VecDeque::new(),
+            response_lengths: VecDeque::new(),
+            response_ready: Instant::now(),
+            request: None,
+            transfer: VecDeque::new(),
+
+            status_byte: 0,
+            service_request_enable: 0,
+            srq_at: None,
+            interrupts: VecDeque::new(),
+        })
+    }
+
+    pub fn script(&self) -> &Script {
+        &self.script
+    }
+
+    /// The status byte as it would be read now
+    fn status_byte(&self) -> u8 {
+        if self.response.is_empty() && self.transfer.is_empty() {
+            self.status_byte
+        } else {
+            self.status_byte | STB_MAV
+        }
+    }
+
+    /// Raise a service request if one is due
+    fn update(&mut self) {
+        if let Some(at) = self.srq_at {
+            if Instant::now() >= at {
+                self.srq_at = None;
+                self.status_byte |= STB_RQS;
+                self.interrupts.push_back([0x81, self.status_byte()]);
+            }
+        }
+    }
+
+    fn execute(&mut self, message: &[u8]) {
+        let message = String::from_utf8_lossy(message).into_owned();
+        let mut responses = Vec::new();
+        let mut delay = Duration::ZERO;
+
+        for command in message.split(';').map(str::trim) {
+            if command.is_empty() {
+                continue;
+            }
+
+            if let Some(rule) = self
+                .rules
+                .iter()
+                .find(|rule| rule.pattern.is_match(command))
+            {
+                delay += rule.delay;
+
+                if let Some(template) = &rule.response {
+                    let mut response = String::new();
+                    if let Some(captures) = rule.pattern.captures(command) {
+                        captures.expand(template, &mut response);
+                    }
+                    responses.push(response);
+                }
+
+                if rule.srq {
+                    self.srq_at = Some(Instant::now() + delay);
+                }
+                continue;
+            }
+
+            let command = command.to_ascii_uppercase();
+            if let Some(mask) = command.strip_prefix("*SRE ") {
+                self.service_request_enable = mask.trim().parse().unwrap_or(0);
+                continue;
+            }
+
+            match command.as_str() {
+                "*IDN?" => responses.push(self.script.idn.clone()),
+                "*SRE?" => responses.push(self.service_request_enable.to_string()),
+                "*OPC?" => responses.push("1".to_owned()),
+                "*STB?" => responses.push(self.status_byte().to_string()),
+                "SYST:ERR?" | "SYSTEM:ERROR?" => responses.push("0,\"No error\"".to_owned()),
+                "*CLS" => {
+                    self.status_byte = 0;
+                    self.srq_at = None;
+                }
+                _ => {}
+            }
+        }
+
+        if !responses.is_empty() {
+            let response = responses.join(";") + "\n";
+            self.response.extend(response.bytes());
+            self.response_lengths.push_back(response.len());
+            self.response_ready = Instant::now() + delay;
+
+            // request service once the message is available, if enabled
+            if self.service_request_enable & STB_MAV != 0 {
+                let at = self
+                    .srq_at
+                    .map_or(self.response_ready, |at| at.min(self.response_ready));
+                self.srq_at = Some(at);
+            }
+        }
+    }
+
+    /// Start the transfer answering the host's request, waiting up to `timeout`
+    /// for the response to be ready.  Returns false if there is nothing to send.
+    fn start_transfer(&mut self, timeout: Duration) -> bool {
+        let request = match &self.request {
+            Some(request) => request.clone(),
+            None => return false,
+        };
+
+        // a response which will never come can't be waited for, even forever
+        if self.response.is_empty() {
+            if !timeout.is_zero() {
+                sleep(timeout);
+            }
+            return false;
+        }
+
+        let wait = self
+            .response_ready
+            .saturating_duration_since(Instant::now());
+        if wait > wait_limit(timeout) {
+            sleep(timeout);
+            return false;
+        }
+        sleep(wait);
+
+        let term_char = request
+            .term_char()
+            .filter(|_| self.script.capabilities.term_char);
+        // each response is its own message, ended when its last byte is sent
+        let length = self.response_lengths.front().copied().unwrap_or(0);
+        let mut size = length.min(request.transfer_size as usize);
+        let mut ended_on_term_char = false;
+        if let Some(term_char) = term_char {
+            if let Some(pos) = self
+                .response
+                .iter()
+                .take(size)
+                .position(|&b| b == term_char)
+            {
+                size = pos + 1;
+                ended_on_term_char = true;
+            }
+        }
+
+        let data: Vec<u8> = self.response.drain(..size).collect();
+        let eom = size == length;
+        if eom {
+            self.response_lengths.pop_front();
+        } else if let Some(length) = self.response_lengths.front_mut() {
+            *length -= size;
+        }
+        let mut buf = Vec::new();
+        DevDepMsgInHeader::encode_message(
+            request.bulk_out_header.b_tag,
+            &data,
+            eom,
+            ended_on_term_char,
+            &mut buf,
+        );
+
+        self.request = None;
+        self.transfer.extend(buf);
+        true
+    }
+
+    fn control_response(&mut self, request: u8, value: u16) -> rusb::Result<Vec<u8>> {
+        let success = 0x01;
+        let transfer_not_in_progress = 0x81;
+        let caps = &self.script.capabilities;
+
+        let response = match request {
+            r if r == ControlRequest::InitiateClear as u8 => {
+                self.received.clear();
+                self.command.clear();
+                self.response.clear();
+                self.response_lengths.clear();
+                self.request = None;
+                self.transfer.clear();
+                vec![success]
+            }
+            r if r == ControlRequest::CheckClearStatus as u8 => vec![success, 0],
+            r if r == ControlRequest::GetCapabilities as u8 => {
+                let mut buf = vec![0u8; 0x18];
+                buf[0] = success;
+                LittleEndian::write_u16(&mut buf[2..4], 0x0100);
+                buf[4] = if caps.pulse { 0x04 } else { 0 };
+                buf[5] = if caps.term_char { 0x01 } else { 0 };
+                if caps.usb488 {
+                    LittleEndian::write_u16(&mut buf[12..14], 0x0100);
+                    buf[14] = if caps.trigger { 0x05 } else { 0x04 };
+                    buf[15] = if caps.scpi { 0x0c } else { 0x04 };
+                }
+                buf
+            }
+            r if r == ControlRequest::IndicatorPulse as u8 && caps.pulse => vec![success],
+            r if r == ControlRequest::Tmc488ReadStatusByte as u8 && caps.usb488 => {
+                let b_tag = value as u8;
+                self.interrupts
+                    .push_back([0x80 | (b_tag & 0x7f), self.status_byte()]);
+                self.status_byte &= !STB_RQS;
+                vec![success, b_tag, 0]
+            }
+            r if r == ControlRequest::InitiateAbortBulkOut as u8 => {
+                let status = if self.command.is_empty() && self.received.is_empty() {
+                    transfer_not_in_progress
+                } else {
+                    self.received.clear();
+                    self.command.clear();
+                    success
+                };
+                vec![status, value as u8]
+            }
+            r if r == ControlRequest::CheckAbortBulkOutStatus as u8 => {
+                vec![success, 0, 0, 0, 0, 0, 0, 0]
+            }
+            r if r == ControlRequest::InitiateAbortBulkIn as u8 => {
+                let status = if self.request.is_none() && self.transfer.is_empty() {
+                    transfer_not_in_progress
+                } else {
+                    self.response.clear();
+                    self.response_lengths.clear();
+                    self.request = None;
+                    self.transfer.clear();
+                    success
+                };
+                vec![status, value as u8]
+            }
+            r if r == ControlRequest::CheckAbortBulkInStatus as u8 => {
+                vec![success, 0, 0, 0, 0, 0, 0, 0]
+            }
+            _ => return Err(rusb::Error::Pipe),
+        };
+
+        Ok(response)
+    }
+
+    /// Act on a whole bulk-out transfer
+    fn receive_transfer(&mut self, buf: &[u8]) -> rusb::Result<()> {
+        let header = BulkOutHeader::unpack(buf).map_err(|_| rusb::Error::Pipe)?;
+        let caps = &self.script.capabilities;
+        let accepts_trigger = caps.usb488 && caps.trigger;
+        match header.msg_id {
+            MsgIdOut::DevDepMsgOut => {
+                let (header, data) =
+                    DevDepMsgOutHeader::decode_transfer(buf).map_err(|_| rusb::Error::Pipe)?;
+                self.command.extend_from_slice(data);
+
+                if header.is_eom() {
+                    let message = std::mem::take(&mut self.command);
+                    self.execute(&message);
+                }
+            }
+            MsgIdOut::RequestDevDepMsgIn => {
+                let request =
+                    RequestDevDepMsgInHeader::unpack(buf).map_err(|_| rusb::Error::Pipe)?;
+                self.request = Some(request);
+            }
+            MsgIdOut::Trigger if accepts_trigger => self.execute(b"*TRG"),
+            _ => return Err(rusb::Error::Pipe),
+        }
+
+        Ok(())
+    }
+}
+
+impl Transport for SimTransport {
+    fn interface(&self) -> &TMCInterface {
+        &self.interface
+    }
+
+    fn backend(&self) -> &'static str {
+        "sim"
+    }
+
+    /// The model and serial number from the script's identification string
+    fn device_label(&self) -> DeviceLabel {
+        let mut fields = self.script.idn.split(',').map(str::trim);
+        DeviceLabel {
+            model: fields.nth(1).map(str::to_owned),
+            serial_number: fields.next().map(str::to_owned),
+            ..DeviceLabel::default()
+        }
+    }
+
+    fn claim_interface(&mut self) -> rusb::Result<()> {
+        self.claimed = true;
+        Ok(())
+    }
+
+    fn release_interface(&mut self) -> rusb::Result<()> {
+        if !self.claimed {
+            return Err(rusb::Error::NotFound);
+        }
+
+        self.claimed = false;
+        Ok(())
+    }
+
+    fn read_control(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        _index: u16,
+        buf: &mut [u8],
+        _timeout: Duration,
+    ) -> rusb::Result<usize> {
+        let class_in = rusb::request_type(
+            rusb::Direction::In,
+            rusb::RequestType::Class,
+            rusb::Recipient::Interface,
+        );
+        let class_endpoint_in = rusb::request_type(
+            rusb::Direction::In,
+            rusb::RequestType::Class,
+            rusb::Recipient::Endpoint,
+        );
+        if request_type != class_in && request_type != class_endpoint_in {
+            return Err(rusb::Error::Pipe);
+        }
+
+        self.update();
+        let response = self.control_response(request, value)?;
+        let n = response.len().min(buf.len());
+        buf[..n].copy_from_slice(&response[..n]);
+        Ok(n)
+    }
+
+    fn write_control(
+        &mut self,
+        _request_type: u8,
+        _request: u8,
+        _value: u16,
+        _index: u16,
+        _buf: &[u8],
+        _timeout: Duration,
+    ) -> rusb::Result<usize> {
+        // no USBTMC or USB488 requests send data to the device
+        Err(rusb::Error::Pipe)
+    }
+
+    fn read_bulk(
+        &mut self,
+        endpoint: u8,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize> {
+        if Some(endpoint) != self.interface.bulk_in_address {
+            return Err(rusb::Error::InvalidParam);
+        }
+
+        if self.transfer.is_empty() && !self.start_transfer(timeout) {
+            return Err(rusb::Error::Timeout);
+        }
+
+        let n = self.transfer.len().min(buf.len());
+        for (dst, src) in buf.iter_mut().zip(self.transfer.drain(..n)) {
+            *dst = src;
+        }
+        Ok(n)
+    }
+
+    fn write_bulk(&mut self, endpoint: u8, buf: &[u8], _timeout: Duration) -> rusb::Result<usize> {
+        if Some(endpoint) != self.interface.bulk_out_address {
+            return Err(rusb::Error::InvalidParam);
+        }
+
+        self.received.extend_from_slice(buf);
+        while let Some(len) = transfer_len(&self.received) {
+            let transfer: Vec<u8> = self.received.drain(..len).collect();
+            if let Err(error) = self.receive_transfer(&transfer) {
+                self.received.clear();
+                return Err(error);
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    fn read_interrupt(
+        &mut self,
+        endpoint: u8,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize> {
+        if Some(endpoint) != self.interface.interrupt_in_address {
+            return Err(rusb::Error::InvalidParam);
+        }
+
+        self.update();
+        if self.interrupts.is_empty() {
+            if let Some(at) = self.srq_at {
+                sleep(
+                    at.saturating_duration_since(Instant::now())
+                        .min(wait_limit(timeout)),
+                );
+                self.update();
+            }
+        }
+
+        match self.interrupts.pop_front() {
+            Some(notification) => {
+                let n = notification.len().min(buf.len());
+                buf[..n].copy_from_slice(&notification[..n]);
+                Ok(n)
+            }
+            None => Err(rusb::Error::Timeout),
+        }
+    }
+
+    fn clear_halt(&mut self, endpoint: u8) -> rusb::Result<()> {
+        if Some(endpoint) == self.interface.bulk_out_address
+            || Some(endpoint) == self.interface.bulk_in_address
+        {
+            Ok(())
+        } else {
+            Err(rusb::Error::InvalidParam)
+        }
+    }
+}
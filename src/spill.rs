@@ -0,0 +1,241 @@
+//! Responses too large to hold in memory, such as the full record of a
+//! deep-memory oscilloscope read on a small single-board computer.
+//!
+//! With a [ResponseSpill] set on a handle, a response read with
+//! [read_raw_spooled](crate::TMCHandle::read_raw_spooled) is collected in memory
+//! until it outgrows the budget, and then moved to a temporary file which the
+//! rest of it is written to.  Either way it is returned as a [SpooledResponse],
+//! to be read as a stream.  The temporary file is removed when the response is
+//! dropped, unless it is [persisted](SpooledResponse::persist).
+
+use crate::storage;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Cursor, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// When and where responses are spilled to disk
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ResponseSpill {
+    budget: usize,
+    directory: Option<PathBuf>,
+}
+
+impl ResponseSpill {
+    /// Spill responses longer than `budget` bytes to a temporary file
+    pub fn new(budget: usize) -> Self {
+        Self {
+            budget,
+            directory: None,
+        }
+    }
+
+    /// Create temporary files in `directory` (which is created if needed)
+    /// rather than the system's temporary directory, such as to put them on a
+    /// larger disk
+    pub fn directory<P: AsRef<Path>>(mut self, directory: P) -> Self {
+        self.directory = Some(directory.as_ref().to_owned());
+        self
+    }
+
+    /// The most bytes of a response held in memory
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    /// Where temporary files are created, if not the system's temporary
+    /// directory
+    pub fn get_directory(&self) -> Option<&Path> {
+        self.directory.as_deref()
+    }
+}
+
+#[derive(Debug)]
+enum Storage {
+    Memory(Cursor<Vec<u8>>),
+    File { file: File, path: PathBuf },
+}
+
+/// A response read from the start, held in memory or in a temporary file
+#[derive(Debug)]
+pub struct SpooledResponse {
+    storage: Storage,
+    len: u64,
+}
+
+impl SpooledResponse {
+    /// The length of the response in bytes
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Whether the response outgrew the budget and was spilled to disk
+    pub fn is_spilled(&self) -> bool {
+        matches!(self.storage, Storage::File { .. })
+    }
+
+    /// The temporary file holding the response, if it was spilled
+    pub fn path(&self) -> Option<&Path> {
+        match &self.storage {
+            Storage::Memory(_) => None,
+            Storage::File { path, .. } => Some(path),
+        }
+    }
+
+    /// Keep the whole response as the file at `path`, creating its parent
+    /// directories if needed.  A spilled response's temporary file is moved
+    /// there, or copied if it is on another filesystem.
+    pub fn persist<P: AsRef<Path>>(mut self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        match self.take_storage() {
+            Storage::Memory(cursor) => storage::write_atomic(path, cursor.into_inner()),
+            Storage::File {
+                file,
+                path: temporary,
+            } => {
+                drop(file);
+                let result = storage::create(path)
+                    .and_then(|_| fs::rename(&temporary, path))
+                    .or_else(|_| fs::copy(&temporary, path).map(|_| ()));
+                let _ = fs::remove_file(&temporary);
+                result
+            }
+        }
+    }
+
+    fn take_storage(&mut self) -> Storage {
+        mem::replace(&mut self.storage, Storage::Memory(Cursor::new(Vec::new())))
+    }
+}
+
+impl Read for SpooledResponse {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.storage {
+            Storage::Memory(cursor) => cursor.read(buf),
+            Storage::File { file, .. } => file.read(buf),
+        }
+    }
+}
+
+impl Seek for SpooledResponse {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match &mut self.storage {
+            Storage::Memory(cursor) => cursor.seek(pos),
+            Storage::File { file, .. } => file.seek(pos),
+        }
+    }
+}
+
+impl Drop for SpooledResponse {
+    fn drop(&mut self) {
+        // the file is closed first, as Windows won't remove an open file
+        if let Storage::File { file, path } = self.take_storage() {
+            drop(file);
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Collects a response as it is read, spilling it to a temporary file once it
+/// outgrows the budget
+#[derive(Debug)]
+pub(crate) struct Spooler {
+    spill: Option<ResponseSpill>,
+    memory: Vec<u8>,
+    file: Option<(BufWriter<File>, PathBuf)>,
+    len: u64,
+    error: Option<io::Error>,
+}
+
+impl Spooler {
+    pub(crate) fn new(spill: Option<ResponseSpill>) -> Self {
+        Self {
+            spill,
+            memory: Vec::new(),
+            file: None,
+            len: 0,
+            error: None,
+        }
+    }
+
+    /// Add the data from a transfer, breaking if it can't be written
+    pub(crate) fn push(&mut self, data: &[u8]) -> ControlFlow<()> {
+        match self.write(data) {
+            Ok(()) => {
+                self.len += data.len() as u64;
+                ControlFlow::Continue(())
+            }
+            Err(error) => {
+                self.error = Some(error);
+                ControlFlow::Break(())
+            }
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        if let Some((writer, _)) = &mut self.file {
+            return writer.write_all(data);
+        }
+
+        let spill = match &self.spill {
+            Some(spill) if self.memory.len() + data.len() > spill.budget => spill,
+            _ => {
+                self.memory.extend_from_slice(data);
+                return Ok(());
+            }
+        };
+
+        let directory = spill.directory.clone().unwrap_or_else(std::env::temp_dir);
+        let stem = format!("tmc-response-{}", process::id());
+        let (file, path) = storage::create_unique(directory, &stem, "tmp")?;
+        let (writer, _) = self.file.insert((BufWriter::new(file), path));
+        writer.write_all(&mem::take(&mut self.memory))?;
+        writer.write_all(data)
+    }
+
+    /// The whole response, ready to be read from the start, or the error which
+    /// stopped it being collected
+    pub(crate) fn finish(mut self) -> io::Result<SpooledResponse> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+
+        let storage = match self.file.take() {
+            None => Storage::Memory(Cursor::new(mem::take(&mut self.memory))),
+            Some((writer, path)) => {
+                // flushed and reopened for reading, removing the file if either fails
+                let file = writer
+                    .into_inner()
+                    .map_err(|error| error.into_error())
+                    .and_then(|_| File::open(&path));
+                match file {
+                    Ok(file) => Storage::File { file, path },
+                    Err(error) => {
+                        let _ = fs::remove_file(&path);
+                        return Err(error);
+                    }
+                }
+            }
+        };
+
+        Ok(SpooledResponse {
+            storage,
+            len: self.len,
+        })
+    }
+}
+
+impl Drop for Spooler {
+    fn drop(&mut self) {
+        if let Some((writer, path)) = self.file.take() {
+            drop(writer);
+            let _ = fs::remove_file(path);
+        }
+    }
+}
@@ -0,0 +1,121 @@
+//! File handling shared by the features which save data to disk: transcripts,
+//! frame captures and settings profiles.  Files are created along with any
+//! missing parent directories, and files which are replaced as a whole are
+//! written atomically, so that a crash or a full disk leaves either the old
+//! contents or the new ones and never a mixture.
+
+use std::env;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// The name of this crate's directory within the platform's configuration
+/// directory
+const APP_DIR: &str = "tmc";
+
+/// The directory this crate keeps configuration in by default:
+/// `$XDG_CONFIG_HOME/tmc` (or `~/.config/tmc`) on Linux and other Unix systems,
+/// `~/Library/Application Support/tmc` on macOS and `%APPDATA%\tmc` on Windows.
+/// `None` if the environment doesn't say where that is.  The directory may not
+/// exist yet.
+pub fn config_dir() -> Option<PathBuf> {
+    platform_config_dir().map(|dir| dir.join(APP_DIR))
+}
+
+#[cfg(target_os = "macos")]
+fn platform_config_dir() -> Option<PathBuf> {
+    absolute_var("HOME").map(|home| home.join("Library/Application Support"))
+}
+
+#[cfg(windows)]
+fn platform_config_dir() -> Option<PathBuf> {
+    absolute_var("APPDATA")
+}
+
+#[cfg(not(any(target_os = "macos", windows)))]
+fn platform_config_dir() -> Option<PathBuf> {
+    absolute_var("XDG_CONFIG_HOME")
+        .or_else(|| absolute_var("HOME").map(|home| home.join(".config")))
+}
+
+/// The directory in an environment variable, ignoring relative paths as the
+/// XDG specification requires
+fn absolute_var(name: &str) -> Option<PathBuf> {
+    env::var_os(name)
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+}
+
+/// Replace the file at `path` with `contents`, creating it and its parent
+/// directories if needed.  The contents are written to a temporary file in the
+/// same directory, flushed to disk and then renamed over `path`.
+pub fn write_atomic<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> io::Result<()> {
+    let path = path.as_ref();
+    let name = path.file_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not a file path", path.display()),
+        )
+    })?;
+    create_parent(path)?;
+
+    let mut temporary_name = OsString::from(".");
+    temporary_name.push(name);
+    temporary_name.push(format!(".{}.tmp", process::id()));
+    let temporary = path.with_file_name(temporary_name);
+
+    let result = File::create(&temporary)
+        .and_then(|mut file| {
+            file.write_all(contents.as_ref())?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&temporary, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+    result
+}
+
+/// Create (or truncate) the file at `path` for writing, creating its parent
+/// directories if needed
+pub fn create<P: AsRef<Path>>(path: P) -> io::Result<File> {
+    let path = path.as_ref();
+    create_parent(path)?;
+    File::create(path)
+}
+
+/// Create a new file in `directory` named `stem.extension`, or `stem-1.extension`
+/// and so on if that exists, creating the directory if needed.  Returns the file
+/// and its path.
+pub fn create_unique<P: AsRef<Path>>(
+    directory: P,
+    stem: &str,
+    extension: &str,
+) -> io::Result<(File, PathBuf)> {
+    let directory = directory.as_ref();
+    fs::create_dir_all(directory)?;
+
+    let mut suffix = 0;
+    loop {
+        let name = match suffix {
+            0 => format!("{}.{}", stem, extension),
+            n => format!("{}-{}.{}", stem, n, extension),
+        };
+        let path = directory.join(name);
+
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((file, path)),
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => suffix += 1,
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+fn create_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => fs::create_dir_all(parent),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,72 @@
+//! One place to ask whether an instrument supports an optional feature, whether
+//! that is known from the capabilities it declares, from probing it, or from
+//! what the application has learned about it.
+//!
+//! Use [supports](crate::TMCHandle::supports) to ask, and
+//! [probe_support](crate::TMCHandle::probe_support) to try a feature the device
+//! doesn't declare.  [set_probed_support](crate::TMCHandle::set_probed_support)
+//! records the outcome of an application's own probe, or a known quirk such as
+//! a feature which a device declares but which doesn't work.  The handle checks
+//! the features it depends on (term char, pulse, service requests and SCPI)
+//! this way, so recorded support also enables or disables those operations.
+
+/// Optional features of a USBTMC or USB488 device
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Feature {
+    /// The USB488 TRIGGER message
+    Trigger,
+
+    /// Ending bulk-in transfers on a term char
+    TermChar,
+
+    /// The INDICATOR_PULSE request
+    Pulse,
+
+    /// Service requests on the interrupt-in endpoint
+    Srq,
+
+    /// The USB488 REN_CONTROL, GO_TO_LOCAL and LOCAL_LOCKOUT requests
+    RemoteLocal,
+
+    /// SCPI commands
+    Scpi,
+
+    /// IEEE 488.2 common commands and status reporting
+    Usb488_2,
+
+    /// Vendor-specific bulk messages, which devices have no way to declare
+    VendorBulk,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 8] = [
+        Feature::Trigger,
+        Feature::TermChar,
+        Feature::Pulse,
+        Feature::Srq,
+        Feature::RemoteLocal,
+        Feature::Scpi,
+        Feature::Usb488_2,
+        Feature::VendorBulk,
+    ];
+}
+
+/// How a feature is known to be supported, if it is
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum SupportLevel {
+    /// The device's capabilities declare it
+    Declared,
+
+    /// The device doesn't declare it, but it was found to work
+    Probed,
+
+    /// The device doesn't declare it and it wasn't found to work, or it was
+    /// found not to work despite being declared
+    Unsupported,
+}
+
+impl SupportLevel {
+    pub fn is_supported(self) -> bool {
+        self != SupportLevel::Unsupported
+    }
+}
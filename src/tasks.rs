@@ -0,0 +1,198 @@
+//! Background tasks tied to a scope, so that pollers, service request listeners
+//! and keep-alives can't outlive the code that started them and keep instruments
+//! open.  [scope] runs a closure which starts tasks with the [TaskScope] it is
+//! given; when the closure returns (or panics), every task is told to stop and
+//! waited for before `scope` returns.
+//!
+//! Tasks run on scoped threads, so they can borrow handles and other data from
+//! outside the scope.
+
+use crate::poller::{Poller, Sample};
+use crate::transport::Transport;
+use crate::TMCHandle;
+use core::time::Duration;
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::thread::{self, Scope, ScopedJoinHandle};
+use std::time::Instant;
+
+/// The longest [release_when_idle](TaskScope::release_when_idle) waits between
+/// checks, so that changes to the idle time take effect promptly
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Tells a task started in a [TaskScope] when the scope has ended and it should
+/// return.  Clones refer to the same signal.
+#[derive(Debug, Clone, Default)]
+pub struct StopToken(Arc<(Mutex<bool>, Condvar)>);
+
+impl StopToken {
+    pub fn is_stopped(&self) -> bool {
+        *self.0 .0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Wait up to `timeout` for the scope to end, returning whether it has.
+    /// Tasks which sleep between iterations should sleep with this, so that they
+    /// stop promptly.
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let (stopped, condvar) = &*self.0;
+        let deadline = Instant::now().checked_add(timeout);
+
+        let mut stopped = stopped.lock().unwrap_or_else(PoisonError::into_inner);
+        while !*stopped {
+            let remaining = match deadline {
+                Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+                None => Duration::MAX,
+            };
+            if remaining.is_zero() {
+                break;
+            }
+            stopped = condvar
+                .wait_timeout(stopped, remaining)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+        *stopped
+    }
+
+    fn stop(&self) {
+        let (stopped, condvar) = &*self.0;
+        *stopped.lock().unwrap_or_else(PoisonError::into_inner) = true;
+        condvar.notify_all();
+    }
+}
+
+type ExitHook<'env> = Box<dyn FnOnce() + Send + 'env>;
+
+#[derive(Default)]
+struct Shared<'env> {
+    stop: StopToken,
+    on_exit: Mutex<Vec<ExitHook<'env>>>,
+}
+
+/// Stops the tasks when the scope's closure returns or unwinds, before the
+/// threads are joined
+struct StopOnExit<'env>(Arc<Shared<'env>>);
+
+impl Drop for StopOnExit<'_> {
+    fn drop(&mut self) {
+        self.0.stop.stop();
+
+        let hooks = std::mem::take(
+            &mut *self
+                .0
+                .on_exit
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner),
+        );
+        for hook in hooks {
+            hook();
+        }
+    }
+}
+
+/// Starts tasks which are stopped when the [scope] ends
+pub struct TaskScope<'scope, 'env: 'scope> {
+    threads: &'scope Scope<'scope, 'env>,
+    shared: Arc<Shared<'env>>,
+}
+
+impl<'scope, 'env> TaskScope<'scope, 'env> {
+    /// Run `task` on a thread of its own.  It is given the scope's [StopToken],
+    /// and must return once it is stopped.
+    pub fn spawn<F, R>(&self, task: F) -> ScopedJoinHandle<'scope, R>
+    where
+        F: FnOnce(StopToken) -> R + Send + 'scope,
+        R: Send + 'scope,
+    {
+        let stop = self.shared.stop.clone();
+        self.threads.spawn(move || task(stop))
+    }
+
+    /// Run `task` every `interval` on a thread of its own, such as to keep an
+    /// instrument's session alive, until the scope ends
+    pub fn every<F>(&self, interval: Duration, mut task: F) -> ScopedJoinHandle<'scope, ()>
+    where
+        F: FnMut() + Send + 'scope,
+    {
+        self.spawn(move |stop| {
+            while !stop.wait(interval) {
+                task();
+            }
+        })
+    }
+
+    /// Start `poller` on `handle`, stopping it when the scope ends.  The handle
+    /// is closed then, even if the poller has reconnected it.
+    pub fn poll<T>(&self, poller: Poller<T>, handle: TMCHandle<T>) -> Receiver<Sample>
+    where
+        T: Transport + 'static,
+    {
+        let (running, samples) = poller.start(handle);
+        self.on_exit(move || drop(running));
+        samples
+    }
+
+    /// Release `handle`'s TMC interface once it has been idle for the time set
+    /// with [set_idle_release](TMCHandle::set_idle_release), checking on a thread
+    /// of its own until the scope ends, so that the application needn't call
+    /// [release_if_idle](TMCHandle::release_if_idle) itself.  The handle is only
+    /// locked while it is checked, at least once a second.
+    pub fn release_when_idle<T>(
+        &self,
+        handle: &'env Mutex<TMCHandle<T>>,
+    ) -> ScopedJoinHandle<'scope, ()>
+    where
+        T: Transport,
+    {
+        self.spawn(move |stop| loop {
+            let interval = {
+                let mut handle = handle.lock().unwrap_or_else(PoisonError::into_inner);
+                // a failed release leaves the interface claimed, which is harmless
+                let _ = handle.release_if_idle();
+                handle
+                    .get_idle_release()
+                    .map_or(IDLE_CHECK_INTERVAL, |idle| idle.min(IDLE_CHECK_INTERVAL))
+            };
+            if stop.wait(interval) {
+                return;
+            }
+        })
+    }
+
+    /// Call `hook` when the scope ends, before waiting for the tasks, such as to
+    /// stop an [EventLoop](crate::event_loop::EventLoop) started for them
+    pub fn on_exit<F>(&self, hook: F)
+    where
+        F: FnOnce() + Send + 'env,
+    {
+        self.shared
+            .on_exit
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(Box::new(hook));
+    }
+
+    /// The token the scope's tasks are stopped with
+    pub fn stop_token(&self) -> StopToken {
+        self.shared.stop.clone()
+    }
+}
+
+/// Run `f`, letting it start background tasks with the [TaskScope] it is given,
+/// then stop every task and wait for them all before returning what `f`
+/// returned.  The tasks are stopped if `f` panics too, and the panic carries on
+/// once they have finished; a panic in a task which hasn't been joined panics
+/// here.
+pub fn scope<'env, F, R>(f: F) -> R
+where
+    F: for<'scope> FnOnce(&TaskScope<'scope, 'env>) -> R,
+{
+    thread::scope(|threads| {
+        let tasks = TaskScope {
+            threads,
+            shared: Arc::new(Shared::default()),
+        };
+        let _stop = StopOnExit(tasks.shared.clone());
+        f(&tasks)
+    })
+}
@@ -0,0 +1,90 @@
+//! Counters and histograms reported through the `metrics` facade, so that an
+//! application exporting metrics (to Prometheus, say) gets each handle's
+//! traffic, errors and latency without code of its own.  Every metric is
+//! labelled with the instrument's model and serial number, where known.
+//!
+//! The metrics are registered when a handle is opened, so the application's
+//! recorder must be installed before then.
+
+use crate::transcript::Direction;
+use crate::transport::DeviceLabel;
+use crate::TMCError;
+use core::time::Duration;
+use metrics::{Counter, Histogram, Label};
+
+/// The metrics for one handle
+#[derive(Debug)]
+pub(crate) struct HandleMetrics {
+    bytes_in: Counter,
+    bytes_out: Counter,
+    errors: Counter,
+    timeouts: Counter,
+    write_latency: Histogram,
+    read_latency: Histogram,
+}
+
+impl HandleMetrics {
+    pub(crate) fn new(label: &DeviceLabel) -> Self {
+        metrics::describe_counter!(
+            "tmc_bytes_in_total",
+            metrics::Unit::Bytes,
+            "Bulk-in transfer bytes received, including headers"
+        );
+        metrics::describe_counter!(
+            "tmc_bytes_out_total",
+            metrics::Unit::Bytes,
+            "Bulk-out transfer bytes sent, including headers"
+        );
+        metrics::describe_counter!("tmc_errors_total", "Operations which failed");
+        metrics::describe_counter!("tmc_timeouts_total", "Operations which timed out");
+        metrics::describe_histogram!(
+            "tmc_latency_seconds",
+            metrics::Unit::Seconds,
+            "Time taken to send or read a message"
+        );
+
+        let mut labels = Vec::new();
+        if let Some(model) = &label.model {
+            labels.push(Label::new("model", model.clone()));
+        }
+        if let Some(serial_number) = &label.serial_number {
+            labels.push(Label::new("serial", serial_number.clone()));
+        }
+        let with_operation = |operation: &'static str| {
+            let mut labels = labels.clone();
+            labels.push(Label::new("operation", operation));
+            labels
+        };
+
+        Self {
+            bytes_in: metrics::counter!("tmc_bytes_in_total", labels.clone()),
+            bytes_out: metrics::counter!("tmc_bytes_out_total", labels.clone()),
+            errors: metrics::counter!("tmc_errors_total", labels.clone()),
+            timeouts: metrics::counter!("tmc_timeouts_total", labels.clone()),
+            write_latency: metrics::histogram!("tmc_latency_seconds", with_operation("write")),
+            read_latency: metrics::histogram!("tmc_latency_seconds", with_operation("read")),
+        }
+    }
+
+    pub(crate) fn frame(&self, direction: Direction, frame: &[u8]) {
+        match direction {
+            Direction::Sent => self.bytes_out.increment(frame.len() as u64),
+            Direction::Received => self.bytes_in.increment(frame.len() as u64),
+        }
+    }
+
+    pub(crate) fn error(&self, error: &TMCError) {
+        self.errors.increment(1);
+        if error.is_timeout() {
+            self.timeouts.increment(1);
+        }
+    }
+
+    /// Record how long sending (or reading) a message took
+    pub(crate) fn latency(&self, direction: Direction, elapsed: Duration) {
+        match direction {
+            Direction::Sent => self.write_latency.record(elapsed),
+            Direction::Received => self.read_latency.record(elapsed),
+        }
+    }
+}
@@ -0,0 +1,97 @@
+//! Conversion between strings and message data, for instruments which don't
+//! use UTF-8.
+
+use crate::{TMCError, TMCResult};
+use std::borrow::Cow;
+
+/// The character set an instrument uses for commands and responses
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "profiles", derive(serde::Serialize, serde::Deserialize))]
+pub enum Encoding {
+    #[default]
+    Utf8,
+
+    /// ISO 8859-1, which many instruments use for degree and micro signs.  Every
+    /// byte is a character, so responses always decode.
+    Latin1,
+
+    /// 7-bit ASCII only
+    Ascii,
+}
+
+impl Encoding {
+    /// Convert a command to message data, failing with
+    /// [Unencodable](TMCError::Unencodable) if it contains a character the
+    /// instrument doesn't accept
+    pub fn encode(self, text: &str) -> TMCResult<Cow<'_, [u8]>> {
+        let limit: u32 = match self {
+            Encoding::Utf8 => return Ok(Cow::Borrowed(text.as_bytes())),
+            Encoding::Latin1 => 0xFF,
+            Encoding::Ascii => 0x7F,
+        };
+
+        if text.is_ascii() {
+            return Ok(Cow::Borrowed(text.as_bytes()));
+        }
+
+        text.char_indices()
+            .map(|(position, c)| {
+                if c as u32 <= limit {
+                    Ok(c as u8)
+                } else {
+                    Err(TMCError::Unencodable {
+                        encoding: self,
+                        character: c,
+                        position,
+                    })
+                }
+            })
+            .collect::<TMCResult<Vec<u8>>>()
+            .map(Cow::Owned)
+    }
+
+    /// Convert response data to a string, handling data which isn't valid in this
+    /// encoding as set by `decoding`
+    pub fn decode(self, data: Vec<u8>, decoding: TextDecoding) -> TMCResult<String> {
+        match (self, decoding) {
+            (Encoding::Utf8, TextDecoding::Strict) => Ok(String::from_utf8(data)?),
+            (Encoding::Utf8, TextDecoding::Lossy) => Ok(match String::from_utf8(data) {
+                Ok(text) => text,
+                Err(err) => String::from_utf8_lossy(err.as_bytes()).into_owned(),
+            }),
+            (Encoding::Latin1, _) => Ok(data.into_iter().map(char::from).collect()),
+            (Encoding::Ascii, TextDecoding::Strict) => {
+                match data.iter().position(|b| !b.is_ascii()) {
+                    Some(position) => Err(TMCError::Undecodable {
+                        encoding: self,
+                        position,
+                    }),
+                    None => Ok(data.into_iter().map(char::from).collect()),
+                }
+            }
+            (Encoding::Ascii, TextDecoding::Lossy) => Ok(data
+                .into_iter()
+                .map(|b| {
+                    if b.is_ascii() {
+                        char::from(b)
+                    } else {
+                        char::REPLACEMENT_CHARACTER
+                    }
+                })
+                .collect()),
+        }
+    }
+}
+
+/// What [read](crate::TMCHandle::read) and [ask](crate::TMCHandle::ask) do with
+/// response data which isn't valid in the handle's [Encoding]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "profiles", derive(serde::Serialize, serde::Deserialize))]
+pub enum TextDecoding {
+    /// Fail the read
+    #[default]
+    Strict,
+
+    /// Replace invalid data with U+FFFD
+    Lossy,
+}
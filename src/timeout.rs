@@ -0,0 +1,65 @@
+//! How long transfers may wait, with waiting forever and not waiting at all
+//! spelled out rather than left to libusb's convention that a zero timeout
+//! means forever.
+
+use core::time::Duration;
+
+/// The longest timeout libusb takes, in milliseconds; anything longer is as
+/// good as forever
+const MAX_MILLIS: u128 = u32::MAX as u128;
+
+/// How long a transfer may wait for the device.  Handle timeouts can be set
+/// from a [Duration], where [Duration::ZERO] means [Immediate](Timeout::Immediate)
+/// and [Duration::MAX] means [Forever](Timeout::Forever).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Timeout {
+    /// Wait as long as the device takes
+    Forever,
+
+    /// Only take what the device has ready, waiting for the shortest time
+    /// libusb can (a millisecond)
+    Immediate,
+
+    /// Wait up to the given time, rounded up to whole milliseconds
+    After(Duration),
+}
+
+impl Timeout {
+    /// The timeout in libusb's convention, as passed to
+    /// [Transport](crate::transport::Transport) methods: zero waits forever, and
+    /// a timeout which would otherwise round down to zero is a millisecond
+    pub fn to_transfer(self) -> Duration {
+        match self {
+            Timeout::Forever => Duration::ZERO,
+            Timeout::Immediate => Duration::from_millis(1),
+            Timeout::After(timeout) if timeout.as_millis() > MAX_MILLIS => Duration::ZERO,
+            Timeout::After(timeout) => timeout.max(Duration::from_millis(1)),
+        }
+    }
+
+    /// The timeout as a duration, [Duration::MAX] for [Forever](Timeout::Forever)
+    /// and [Duration::ZERO] for [Immediate](Timeout::Immediate)
+    pub fn as_duration(self) -> Duration {
+        match self {
+            Timeout::Forever => Duration::MAX,
+            Timeout::Immediate => Duration::ZERO,
+            Timeout::After(timeout) => timeout,
+        }
+    }
+
+    pub fn is_forever(self) -> bool {
+        self.to_transfer().is_zero()
+    }
+}
+
+impl From<Duration> for Timeout {
+    fn from(timeout: Duration) -> Self {
+        if timeout.is_zero() {
+            Timeout::Immediate
+        } else if timeout == Duration::MAX {
+            Timeout::Forever
+        } else {
+            Timeout::After(timeout)
+        }
+    }
+}
@@ -0,0 +1,120 @@
+//! Latency histograms for profiling instrument transactions.
+//!
+//! Enabled with the `timing` cargo feature.  Every bulk transaction performed
+//! through an [InstrumentHandle](crate::InstrumentHandle) is timed and
+//! recorded into a histogram for its [OperationClass], which can be retrieved
+//! with [InstrumentHandle::timing_report](crate::InstrumentHandle::timing_report).
+
+use core::time::Duration;
+use hdrhistogram::Histogram;
+
+/// Responses at least this large are classified as [OperationClass::LongRead]
+pub const LONG_READ_THRESHOLD: usize = 4096;
+
+/// Highest latency that can be recorded (one hour, in microseconds).  Longer
+/// transactions are clamped to this value.
+const MAX_TRACKABLE_MICROS: u64 = 3_600_000_000;
+
+/// Kinds of transactions which are tracked separately, since their expected
+/// latencies differ by orders of magnitude.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum OperationClass {
+    /// A read returning fewer than [LONG_READ_THRESHOLD] bytes, typically
+    /// the response to a query
+    ShortQuery,
+
+    /// A read returning [LONG_READ_THRESHOLD] bytes or more
+    LongRead,
+
+    /// A command message sent to the instrument
+    Write,
+}
+
+/// Latency histograms for each [OperationClass]
+#[derive(Debug, Clone)]
+pub struct TimingReport {
+    /// Latencies of short queries, in microseconds
+    pub short_query: Histogram<u64>,
+
+    /// Latencies of long reads, in microseconds
+    pub long_read: Histogram<u64>,
+
+    /// Latencies of writes, in microseconds
+    pub write: Histogram<u64>,
+}
+
+impl TimingReport {
+    fn new() -> Self {
+        let new_histogram = || {
+            Histogram::new_with_bounds(1, MAX_TRACKABLE_MICROS, 3)
+                .expect("static histogram bounds are valid")
+        };
+
+        Self {
+            short_query: new_histogram(),
+            long_read: new_histogram(),
+            write: new_histogram(),
+        }
+    }
+
+    /// Histogram of latencies recorded for the given class, in microseconds
+    pub fn histogram(&self, class: OperationClass) -> &Histogram<u64> {
+        match class {
+            OperationClass::ShortQuery => &self.short_query,
+            OperationClass::LongRead => &self.long_read,
+            OperationClass::Write => &self.write,
+        }
+    }
+
+    fn histogram_mut(&mut self, class: OperationClass) -> &mut Histogram<u64> {
+        match class {
+            OperationClass::ShortQuery => &mut self.short_query,
+            OperationClass::LongRead => &mut self.long_read,
+            OperationClass::Write => &mut self.write,
+        }
+    }
+
+    /// Number of transactions recorded for the given class
+    pub fn count(&self, class: OperationClass) -> u64 {
+        self.histogram(class).len()
+    }
+
+    /// Latency at the given quantile (0.0 ..= 1.0) for the given class
+    pub fn quantile(&self, class: OperationClass, quantile: f64) -> Duration {
+        Duration::from_micros(self.histogram(class).value_at_quantile(quantile))
+    }
+
+    /// Largest latency recorded for the given class
+    pub fn max(&self, class: OperationClass) -> Duration {
+        Duration::from_micros(self.histogram(class).max())
+    }
+
+    pub(crate) fn record(&mut self, class: OperationClass, elapsed: Duration) {
+        let micros = (elapsed.as_micros() as u64).clamp(1, MAX_TRACKABLE_MICROS);
+        // values are clamped to the histogram's range, so this cannot fail
+        let _ = self.histogram_mut(class).record(micros);
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.short_query.reset();
+        self.long_read.reset();
+        self.write.reset();
+    }
+}
+
+impl Default for TimingReport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OperationClass {
+    /// Classify a completed read by the amount of data it returned
+    pub fn for_read(n_bytes: usize) -> Self {
+        if n_bytes >= LONG_READ_THRESHOLD {
+            OperationClass::LongRead
+        } else {
+            OperationClass::ShortQuery
+        }
+    }
+}
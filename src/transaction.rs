@@ -0,0 +1,121 @@
+//! Scoped sequences of operations which leave the session usable if any of them
+//! fails.
+
+#[cfg(feature = "scpi")]
+use crate::handle::parse_error_entry;
+#[cfg(feature = "scpi")]
+use crate::support::Feature;
+use crate::transport::Transport;
+use crate::{TMCError, TMCHandle, TMCResult};
+
+/// Upper bound on error queue entries read while cleaning up, in case a device
+/// never reports an empty queue
+#[cfg(feature = "scpi")]
+const MAX_DRAINED_ERRORS: usize = 32;
+
+/// Recovery steps taken when a [transaction](TMCHandle::transaction) fails, in
+/// the order listed
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "profiles", derive(serde::Serialize, serde::Deserialize))]
+pub struct TransactionCleanup {
+    /// Abort the most recent bulk-out and bulk-in transfers, discarding any
+    /// response the device has queued
+    pub abort: bool,
+
+    /// Clear the device with the USBTMC clear request
+    pub clear: bool,
+
+    /// Empty the instrument's error queue with `SYST:ERR?`, if it supports SCPI
+    /// and the `scpi` feature is enabled
+    pub drain_errors: bool,
+}
+
+impl Default for TransactionCleanup {
+    fn default() -> Self {
+        Self {
+            abort: true,
+            clear: false,
+            drain_errors: true,
+        }
+    }
+}
+
+impl<T: Transport> TMCHandle<T> {
+    /// Run a sequence of operations on this handle.  If it fails, the cleanup
+    /// steps set by [set_transaction_cleanup](Self::set_transaction_cleanup) are
+    /// taken before the error is returned, so the session can carry on.  Errors
+    /// during cleanup are ignored in favour of the original error.
+    pub fn transaction<F, R>(&mut self, f: F) -> TMCResult<R>
+    where
+        F: FnOnce(&mut Self) -> TMCResult<R>,
+    {
+        let result = f(self);
+
+        if result.is_err() {
+            self.clean_up(self.get_transaction_cleanup());
+        }
+
+        result
+    }
+
+    /// [Ask](Self::ask) a query, and if it times out, put the device back in a
+    /// known state and try again, up to
+    /// [set_robust_attempts](Self::set_robust_attempts) times in all.  Between
+    /// attempts the device is [recovered](Self::recover) (aborting any
+    /// transfers, clearing it and lifting any poisoning) and its error queue
+    /// emptied as for a failed [transaction](Self::transaction).
+    ///
+    /// An error other than a timeout on the first attempt is returned as it
+    /// is, since trying again wouldn't help.  Once the query has timed out,
+    /// failing every attempt gives [AttemptsFailed](TMCError::AttemptsFailed)
+    /// with each attempt's error.
+    pub fn robust_ask(&mut self, command: &str) -> TMCResult<String> {
+        let mut failures = Vec::new();
+
+        for _ in 0..self.get_robust_attempts() {
+            match self.ask(command) {
+                Ok(response) => return Ok(response),
+                Err(error) if failures.is_empty() && !error.is_timeout() => return Err(error),
+                Err(error) => failures.push(error),
+            }
+
+            let _ = self.recover();
+            self.clean_up(TransactionCleanup {
+                abort: false,
+                clear: false,
+                drain_errors: true,
+            });
+        }
+
+        Err(TMCError::AttemptsFailed {
+            command: command.to_owned(),
+            failures,
+        })
+    }
+
+    fn clean_up(&mut self, cleanup: TransactionCleanup) {
+        if cleanup.abort {
+            let _ = self.abort_bulk_out();
+            let _ = self.abort_bulk_in();
+        }
+
+        if cleanup.clear {
+            let _ = self.clear();
+        }
+
+        #[cfg(feature = "scpi")]
+        if cleanup.drain_errors
+            && matches!(self.supports(Feature::Scpi), Ok(level) if level.is_supported())
+        {
+            for _ in 0..MAX_DRAINED_ERRORS {
+                match self
+                    .ask("SYST:ERR?")
+                    .map(|response| parse_error_entry(&response))
+                {
+                    Ok(Some((code, _))) if code != 0 => {}
+                    _ => break,
+                }
+            }
+        }
+    }
+}
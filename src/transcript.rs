@@ -0,0 +1,300 @@
+//! Timestamped records of the messages exchanged with an instrument, for test
+//! reports and for replaying sessions.
+//!
+//! Transcripts can be kept in memory, streamed to a writer (such as a file) or
+//! both.  Streamed transcripts are written one message per line, as produced by
+//! [TranscriptEntry::to_line], and can be read back with [read_transcript].
+//!
+//! A transcript can also [time every bulk transfer](Transcript::with_transfers)
+//! making up the messages, on both the monotonic and wall clocks, for
+//! correlating the instrument's activity with host-side events.
+
+use crate::clock::Timestamp;
+use crate::storage;
+use std::fmt;
+use std::io::{self, BufRead, BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Which way a message went
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// A command message sent to the instrument
+    Sent,
+
+    /// A response message received from the instrument
+    Received,
+}
+
+/// One message in a transcript
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TranscriptEntry {
+    /// When sending or receiving the message started
+    pub timestamp: SystemTime,
+
+    /// When sending or receiving the message started, on the host's monotonic
+    /// clock.  Entries read back from a file don't have this.
+    pub monotonic: Option<Instant>,
+
+    /// How long sending or receiving the message took
+    pub duration: Duration,
+
+    pub direction: Direction,
+
+    /// The message, with any invalid UTF-8 replaced
+    pub message: String,
+}
+
+impl TranscriptEntry {
+    /// Format the entry as a single line (without a line ending) of tab-separated
+    /// fields: timestamp in seconds since the Unix epoch, `>` for sent or `<` for
+    /// received, duration in microseconds, and the message with backslash, tab,
+    /// CR and LF escaped.
+    pub fn to_line(&self) -> String {
+        let since_epoch = self
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let direction = match self.direction {
+            Direction::Sent => '>',
+            Direction::Received => '<',
+        };
+
+        format!(
+            "{}.{:06}\t{}\t{}\t{}",
+            since_epoch.as_secs(),
+            since_epoch.subsec_micros(),
+            direction,
+            self.duration.as_micros(),
+            escape(&self.message)
+        )
+    }
+
+    /// Parse a line produced by [to_line](Self::to_line)
+    pub fn from_line(line: &str) -> Option<Self> {
+        let mut fields = line.splitn(4, '\t');
+        let timestamp = fields.next()?;
+        let direction = fields.next()?;
+        let duration = fields.next()?;
+        let message = fields.next()?;
+
+        let (secs, micros) = timestamp.split_once('.')?;
+        let since_epoch =
+            Duration::from_secs(secs.parse().ok()?) + Duration::from_micros(micros.parse().ok()?);
+
+        let direction = match direction {
+            ">" => Direction::Sent,
+            "<" => Direction::Received,
+            _ => return None,
+        };
+
+        Some(Self {
+            timestamp: UNIX_EPOCH + since_epoch,
+            monotonic: None,
+            duration: Duration::from_micros(duration.parse().ok()?),
+            direction,
+            message: unescape(message)?,
+        })
+    }
+}
+
+/// One bulk transfer, timed by a transcript recording
+/// [transfers](Transcript::with_transfers)
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct TransferEntry {
+    /// When the transfer started
+    pub started: Timestamp,
+
+    /// How long the transfer took
+    pub duration: Duration,
+
+    /// [Sent](Direction::Sent) for bulk-out transfers and
+    /// [Received](Direction::Received) for bulk-in
+    pub direction: Direction,
+
+    /// The length of the transfer, header and padding included
+    pub length: usize,
+}
+
+fn escape(message: &str) -> String {
+    let mut escaped = String::with_capacity(message.len());
+    for c in message.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape(escaped: &str) -> Option<String> {
+    let mut message = String::with_capacity(escaped.len());
+    let mut chars = escaped.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            message.push(match chars.next()? {
+                '\\' => '\\',
+                't' => '\t',
+                'r' => '\r',
+                'n' => '\n',
+                _ => return None,
+            });
+        } else {
+            message.push(c);
+        }
+    }
+    Some(message)
+}
+
+/// Read a transcript streamed by a [Transcript], skipping blank lines
+pub fn read_transcript<R: BufRead>(reader: R) -> io::Result<Vec<TranscriptEntry>> {
+    let mut entries = Vec::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match TranscriptEntry::from_line(&line) {
+            Some(entry) => entries.push(entry),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid transcript line: {:?}", line),
+                ))
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+/// A transcript being recorded by a handle, set up with
+/// [start_transcript](crate::TMCHandle::start_transcript)
+pub struct Transcript {
+    entries: Option<Vec<TranscriptEntry>>,
+    transfers: Option<Vec<TransferEntry>>,
+    writer: Option<Box<dyn Write + Send>>,
+    stream_error: Option<io::Error>,
+}
+
+impl Transcript {
+    /// Keep the transcript in memory
+    pub fn in_memory() -> Self {
+        Self {
+            entries: Some(Vec::new()),
+            transfers: None,
+            writer: None,
+            stream_error: None,
+        }
+    }
+
+    /// Stream the transcript to a writer, without keeping it in memory
+    pub fn streamed<W: Write + Send + 'static>(writer: W) -> Self {
+        Self {
+            entries: None,
+            transfers: None,
+            writer: Some(Box::new(writer)),
+            stream_error: None,
+        }
+    }
+
+    /// Stream the transcript to a newly created file, creating its directory if
+    /// needed
+    pub fn file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::streamed(BufWriter::new(storage::create(path)?)))
+    }
+
+    /// Also time every bulk transfer, keeping the timings in memory.  They
+    /// aren't streamed.
+    pub fn with_transfers(mut self) -> Self {
+        self.transfers.get_or_insert_with(Vec::new);
+        self
+    }
+
+    /// Also keep a streamed transcript in memory
+    pub fn keep_in_memory(mut self) -> Self {
+        self.entries.get_or_insert_with(Vec::new);
+        self
+    }
+
+    /// The entries kept in memory, if the transcript is kept in memory
+    pub fn entries(&self) -> Option<&[TranscriptEntry]> {
+        self.entries.as_deref()
+    }
+
+    /// Take the entries kept in memory so far, leaving the transcript empty
+    pub fn take_entries(&mut self) -> Vec<TranscriptEntry> {
+        self.entries
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// The transfers timed so far, if the transcript times transfers
+    pub fn transfers(&self) -> Option<&[TransferEntry]> {
+        self.transfers.as_deref()
+    }
+
+    /// Take the transfers timed so far, leaving none
+    pub fn take_transfers(&mut self) -> Vec<TransferEntry> {
+        self.transfers
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// The error which stopped streaming, if writing the transcript failed.
+    /// Nothing more is written after an error, but recording in memory continues.
+    pub fn stream_error(&self) -> Option<&io::Error> {
+        self.stream_error.as_ref()
+    }
+
+    /// Write out anything the stream has buffered
+    pub fn flush(&mut self) -> io::Result<()> {
+        match &mut self.writer {
+            Some(writer) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn record(&mut self, entry: TranscriptEntry) {
+        if let Some(writer) = &mut self.writer {
+            if let Err(err) = writeln!(writer, "{}", entry.to_line()) {
+                self.stream_error = Some(err);
+                self.writer = None;
+            }
+        }
+
+        if let Some(entries) = &mut self.entries {
+            entries.push(entry);
+        }
+    }
+
+    pub(crate) fn record_transfer(&mut self, entry: TransferEntry) {
+        if let Some(transfers) = &mut self.transfers {
+            transfers.push(entry);
+        }
+    }
+}
+
+impl fmt::Debug for Transcript {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Transcript")
+            .field("entries", &self.entries)
+            .field("transfers", &self.transfers)
+            .field("streamed", &self.writer.is_some())
+            .field("stream_error", &self.stream_error)
+            .finish()
+    }
+}
+
+impl Drop for Transcript {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
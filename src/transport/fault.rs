@@ -0,0 +1,358 @@
+use crate::class::TMCInterface;
+use crate::transport::{DeviceLabel, Transport};
+use core::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::thread::sleep;
+
+/// Kinds of transport operation which faults can be injected into
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Operation {
+    ControlIn,
+    ControlOut,
+    BulkIn,
+    BulkOut,
+    InterruptIn,
+}
+
+impl Operation {
+    const COUNT: usize = 5;
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// A fault to inject into one transport operation
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Fault {
+    /// Lose the transfer.  Data sent to the device is reported as sent but never
+    /// arrives; data read from the device is discarded and the read times out.
+    Drop,
+
+    /// Flip the bits set in `mask` in the byte at `offset` of the transferred data
+    /// (including any header), if the transfer is that long
+    Corrupt { offset: usize, mask: u8 },
+
+    /// Wait before performing the operation
+    Delay(Duration),
+
+    /// Send only the first `n` bytes of data sent to the device, reporting that
+    /// many as sent.  Has no effect on reads.
+    ShortWrite(usize),
+
+    /// Return only the first `n` bytes of data read from the device, as though
+    /// it had sent no more.  Has no effect on writes.
+    ShortRead(usize),
+
+    /// Read at most the first `n` bytes, leaving the rest with the device for the
+    /// next read, as when a transfer arrives over several reads.  Has no effect on
+    /// writes.
+    Split(usize),
+
+    /// Return the data of the last read of the same kind again without reading
+    /// from the device, as a device resending a transfer would.  Has no effect
+    /// on writes.
+    Repeat,
+
+    /// Fail with the given error (such as `Pipe` or `Timeout`) without performing
+    /// the operation
+    Error(rusb::Error),
+}
+
+#[derive(Debug)]
+struct ScheduledFault {
+    operation: Operation,
+    at: u64,
+    fault: Fault,
+}
+
+#[derive(Debug, Default)]
+struct FaultPlan {
+    counts: [u64; Operation::COUNT],
+    faults: Vec<ScheduledFault>,
+    // the data of each operation since recording started, if it has
+    recorded: Option<Vec<(Operation, Vec<u8>)>>,
+}
+
+impl FaultPlan {
+    /// Count an operation, and take the faults scheduled for it
+    fn next(&mut self, operation: Operation) -> Vec<Fault> {
+        let at = self.counts[operation.index()];
+        self.counts[operation.index()] += 1;
+
+        let mut faults = Vec::new();
+        self.faults.retain(|scheduled| {
+            if scheduled.operation == operation && scheduled.at == at {
+                faults.push(scheduled.fault);
+                false
+            } else {
+                true
+            }
+        });
+        faults
+    }
+}
+
+/// Schedules faults for a [FaultInjectingTransport], which may already be in use
+/// by a handle.  Clones control the same transport.
+#[derive(Debug, Clone, Default)]
+pub struct FaultInjector {
+    plan: Arc<Mutex<FaultPlan>>,
+}
+
+impl FaultInjector {
+    /// Inject a fault into the `nth` next operation of the given kind, counting
+    /// from 0.  Several faults may be injected into the same operation; delays are
+    /// applied first.
+    pub fn inject(&self, operation: Operation, nth: u64, fault: Fault) {
+        let mut plan = self.plan.lock().unwrap();
+        let at = plan.counts[operation.index()] + nth;
+        plan.faults.push(ScheduledFault {
+            operation,
+            at,
+            fault,
+        });
+    }
+
+    /// Remove all faults which haven't been injected yet
+    pub fn clear(&self) {
+        self.plan.lock().unwrap().faults.clear();
+    }
+
+    /// Number of faults which haven't been injected yet
+    pub fn pending(&self) -> usize {
+        self.plan.lock().unwrap().faults.len()
+    }
+
+    /// Number of operations of the given kind performed (or attempted) so far
+    pub fn count(&self, operation: Operation) -> u64 {
+        self.plan.lock().unwrap().counts[operation.index()]
+    }
+
+    /// Keep the data of every operation which succeeds from now on, as it was
+    /// sent to or received from the device with any faults applied
+    pub fn record(&self) {
+        let mut plan = self.plan.lock().unwrap();
+        plan.recorded.get_or_insert_with(Vec::new);
+    }
+
+    /// The data of each recorded operation of the given kind, oldest first
+    pub fn recorded(&self, operation: Operation) -> Vec<Vec<u8>> {
+        let plan = self.plan.lock().unwrap();
+        plan.recorded
+            .iter()
+            .flatten()
+            .filter(|(recorded, _)| *recorded == operation)
+            .map(|(_, data)| data.clone())
+            .collect()
+    }
+}
+
+/// Wrapper around another transport which injects faults into its operations at
+/// deterministic points, for testing recovery from transfer errors.
+#[derive(Debug)]
+pub struct FaultInjectingTransport<T: Transport> {
+    inner: T,
+    injector: FaultInjector,
+    // the data of the last read of each kind, for repeating it
+    last_reads: Vec<Vec<u8>>,
+}
+
+impl<T: Transport> FaultInjectingTransport<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            injector: FaultInjector::default(),
+            last_reads: vec![Vec::new(); Operation::COUNT],
+        }
+    }
+
+    pub fn injector(&self) -> FaultInjector {
+        self.injector.clone()
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    fn faults(&self, operation: Operation) -> Vec<Fault> {
+        let faults = self.injector.plan.lock().unwrap().next(operation);
+
+        for fault in faults.iter() {
+            if let Fault::Delay(delay) = fault {
+                sleep(*delay);
+            }
+        }
+        faults
+    }
+
+    fn record(&self, operation: Operation, data: &[u8]) {
+        if let Some(recorded) = &mut self.injector.plan.lock().unwrap().recorded {
+            recorded.push((operation, data.to_vec()));
+        }
+    }
+
+    fn write<F>(&mut self, operation: Operation, buf: &[u8], f: F) -> rusb::Result<usize>
+    where
+        F: FnOnce(&mut T, &[u8]) -> rusb::Result<usize>,
+    {
+        let mut data = None;
+        let mut len = buf.len();
+        for fault in self.faults(operation) {
+            match fault {
+                Fault::Drop => return Ok(buf.len()),
+                Fault::ShortWrite(n) => len = len.min(n),
+                Fault::Error(error) => return Err(error),
+                Fault::Corrupt { offset, mask } if offset < buf.len() => {
+                    let data = data.get_or_insert_with(|| buf.to_vec());
+                    data[offset] ^= mask;
+                }
+                Fault::Corrupt { .. }
+                | Fault::Delay(_)
+                | Fault::ShortRead(_)
+                | Fault::Split(_)
+                | Fault::Repeat => {}
+            }
+        }
+
+        let data = data.as_deref().unwrap_or(buf);
+        let n = f(&mut self.inner, &data[..len])?;
+        self.record(operation, &data[..n]);
+        Ok(n)
+    }
+
+    fn read<F>(&mut self, operation: Operation, buf: &mut [u8], f: F) -> rusb::Result<usize>
+    where
+        F: FnOnce(&mut T, &mut [u8]) -> rusb::Result<usize>,
+    {
+        let faults = self.faults(operation);
+        if let Some(error) = faults.iter().find_map(|fault| match fault {
+            Fault::Error(error) => Some(*error),
+            _ => None,
+        }) {
+            return Err(error);
+        }
+
+        if faults.contains(&Fault::Repeat) {
+            let last = &self.last_reads[operation.index()];
+            let n = last.len().min(buf.len());
+            buf[..n].copy_from_slice(&last[..n]);
+            self.record(operation, &buf[..n]);
+            return Ok(n);
+        }
+
+        let len = faults
+            .iter()
+            .filter_map(|fault| match fault {
+                Fault::Split(n) => Some(*n),
+                _ => None,
+            })
+            .fold(buf.len(), usize::min);
+        let mut n = f(&mut self.inner, &mut buf[..len])?;
+        for fault in faults.iter() {
+            if let Fault::ShortRead(len) = fault {
+                n = n.min(*len);
+            }
+        }
+        for fault in faults {
+            match fault {
+                Fault::Drop => return Err(rusb::Error::Timeout),
+                Fault::Corrupt { offset, mask } if offset < n => buf[offset] ^= mask,
+                _ => {}
+            }
+        }
+        self.record(operation, &buf[..n]);
+        self.last_reads[operation.index()] = buf[..n].to_vec();
+        Ok(n)
+    }
+}
+
+impl<T: Transport> Transport for FaultInjectingTransport<T> {
+    fn interface(&self) -> &TMCInterface {
+        self.inner.interface()
+    }
+
+    fn claim_interface(&mut self) -> rusb::Result<()> {
+        self.inner.claim_interface()
+    }
+
+    fn release_interface(&mut self) -> rusb::Result<()> {
+        self.inner.release_interface()
+    }
+
+    fn read_control(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize> {
+        self.read(Operation::ControlIn, buf, |inner, buf| {
+            inner.read_control(request_type, request, value, index, buf, timeout)
+        })
+    }
+
+    fn write_control(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &[u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize> {
+        self.write(Operation::ControlOut, buf, |inner, buf| {
+            inner.write_control(request_type, request, value, index, buf, timeout)
+        })
+    }
+
+    fn read_bulk(
+        &mut self,
+        endpoint: u8,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize> {
+        self.read(Operation::BulkIn, buf, |inner, buf| {
+            inner.read_bulk(endpoint, buf, timeout)
+        })
+    }
+
+    fn write_bulk(&mut self, endpoint: u8, buf: &[u8], timeout: Duration) -> rusb::Result<usize> {
+        self.write(Operation::BulkOut, buf, |inner, buf| {
+            inner.write_bulk(endpoint, buf, timeout)
+        })
+    }
+
+    fn read_interrupt(
+        &mut self,
+        endpoint: u8,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize> {
+        self.read(Operation::InterruptIn, buf, |inner, buf| {
+            inner.read_interrupt(endpoint, buf, timeout)
+        })
+    }
+
+    fn clear_halt(&mut self, endpoint: u8) -> rusb::Result<()> {
+        self.inner.clear_halt(endpoint)
+    }
+
+    fn interface_holder(&self) -> Option<String> {
+        self.inner.interface_holder()
+    }
+
+    fn device_label(&self) -> DeviceLabel {
+        self.inner.device_label()
+    }
+
+    fn backend(&self) -> &'static str {
+        self.inner.backend()
+    }
+}
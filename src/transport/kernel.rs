@@ -0,0 +1,390 @@
+//! Transport through the Linux kernel's usbtmc driver, for devices the driver
+//! already owns where detaching it isn't wanted, such as on shared systems.
+//!
+//! The driver's raw transfer ioctls (API version 2, Linux 4.20 and later) pass
+//! bulk and control transfers through unchanged, so the protocol is handled by
+//! this crate as usual.  The driver keeps the interrupt-in endpoint to itself:
+//! the status byte is read through the driver instead, and the interface is
+//! reported without an interrupt-in endpoint, so service request notifications
+//! aren't available.
+
+use super::{DeviceLabel, Transport};
+use crate::class::{ControlRequest, TMCInterface};
+use crate::{ConnectError, TMCResult};
+use core::time::Duration;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+const IOC_NR: u64 = 91;
+const IOC_NONE: u64 = 0;
+const IOC_WRITE: u64 = 1;
+const IOC_READ: u64 = 2;
+
+const fn ioc(direction: u64, nr: u64, size: usize) -> u64 {
+    (direction << 30) | ((size as u64) << 16) | (IOC_NR << 8) | nr
+}
+
+const IOCTL_CLEAR_OUT_HALT: u64 = ioc(IOC_NONE, 6, 0);
+const IOCTL_CLEAR_IN_HALT: u64 = ioc(IOC_NONE, 7, 0);
+const IOCTL_CTRL_REQUEST: u64 = ioc(IOC_READ | IOC_WRITE, 8, std::mem::size_of::<CtrlRequest>());
+const IOCTL_SET_TIMEOUT: u64 = ioc(IOC_WRITE, 10, 4);
+const IOCTL_WRITE: u64 = ioc(IOC_READ | IOC_WRITE, 13, std::mem::size_of::<Message>());
+const IOCTL_READ: u64 = ioc(IOC_READ | IOC_WRITE, 14, std::mem::size_of::<Message>());
+const IOCTL_API_VERSION: u64 = ioc(IOC_READ, 16, 4);
+const IOCTL_READ_STB: u64 = ioc(IOC_READ, 18, 1);
+
+/// The first driver API version with raw transfer ioctls
+const MIN_API_VERSION: u32 = 2;
+
+/// Shortest timeout the driver accepts
+const MIN_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// `struct usbtmc_ctrlrequest`
+#[repr(C, packed)]
+struct CtrlRequest {
+    request_type: u8,
+    request: u8,
+    value: u16,
+    index: u16,
+    length: u16,
+    data: *mut u8,
+}
+
+/// `struct usbtmc_message`
+#[repr(C, packed)]
+struct Message {
+    transfer_size: u32,
+    transferred: u32,
+    flags: u32,
+    message: *mut u8,
+}
+
+/// Paths of the usbtmc driver's character devices on this system
+pub fn kernel_devices() -> io::Result<Vec<PathBuf>> {
+    let mut devices = Vec::new();
+    for entry in fs::read_dir("/dev")? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with("usbtmc") {
+            devices.push(entry.path());
+        }
+    }
+    devices.sort();
+    Ok(devices)
+}
+
+/// Transport to an instrument through a usbtmc character device, such as
+/// `/dev/usbtmc0`
+#[derive(Debug)]
+pub struct KernelTransport {
+    file: File,
+    interface: TMCInterface,
+    label: DeviceLabel,
+    timeout: Option<Duration>,
+}
+
+impl KernelTransport {
+    /// Open the character device, and find out about the interface behind it
+    /// from sysfs.  Fails with `NotSupported` if the driver is too old to pass
+    /// transfers through.
+    pub fn open<P: AsRef<Path>>(path: P) -> TMCResult<Self> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .map_err(|error| ConnectError::from_open(rusb_error(&error)))?;
+
+        let mut api_version: u32 = 0;
+        ioctl(
+            &file,
+            IOCTL_API_VERSION,
+            &mut api_version as *mut u32 as usize,
+        )
+        .map_err(ConnectError::from_open)?;
+        if api_version < MIN_API_VERSION {
+            return Err(ConnectError::from_open(rusb::Error::NotSupported).into());
+        }
+
+        Ok(Self {
+            file,
+            interface: sysfs_interface(path).unwrap_or_else(default_interface),
+            label: sysfs_label(path),
+            timeout: None,
+        })
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> rusb::Result<()> {
+        // the driver has no way to wait forever, so 0 (forever, to libusb)
+        // becomes the longest timeout it takes
+        let timeout = if timeout.is_zero() {
+            Duration::from_millis(u32::MAX as u64)
+        } else {
+            timeout.max(MIN_TIMEOUT)
+        };
+
+        if self.timeout != Some(timeout) {
+            let millis = timeout.as_millis().min(u32::MAX as u128) as u32;
+            ioctl(
+                &self.file,
+                IOCTL_SET_TIMEOUT,
+                &millis as *const u32 as usize,
+            )?;
+            self.timeout = Some(timeout);
+        }
+        Ok(())
+    }
+
+    fn transfer(
+        &mut self,
+        request: u64,
+        data: *mut u8,
+        len: usize,
+        timeout: Duration,
+    ) -> rusb::Result<usize> {
+        self.set_timeout(timeout)?;
+
+        let mut message = Message {
+            transfer_size: len.min(u32::MAX as usize) as u32,
+            transferred: 0,
+            flags: 0,
+            message: data,
+        };
+        ioctl(&self.file, request, &mut message as *mut Message as usize)?;
+        Ok(message.transferred as usize)
+    }
+}
+
+impl Transport for KernelTransport {
+    fn interface(&self) -> &TMCInterface {
+        &self.interface
+    }
+
+    fn device_label(&self) -> DeviceLabel {
+        self.label.clone()
+    }
+
+    fn backend(&self) -> &'static str {
+        "kernel"
+    }
+
+    // the driver has the interface claimed for as long as the device is open
+    fn claim_interface(&mut self) -> rusb::Result<()> {
+        Ok(())
+    }
+
+    fn release_interface(&mut self) -> rusb::Result<()> {
+        Ok(())
+    }
+
+    fn read_control(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize> {
+        // the status byte arrives on the interrupt-in endpoint, which only the
+        // driver can read, so have it make the request
+        if request == ControlRequest::Tmc488ReadStatusByte as u8 {
+            let mut status_byte: u8 = 0;
+            ioctl(
+                &self.file,
+                IOCTL_READ_STB,
+                &mut status_byte as *mut u8 as usize,
+            )?;
+
+            let response = [0x01, value as u8, status_byte];
+            let n = response.len().min(buf.len());
+            buf[..n].copy_from_slice(&response[..n]);
+            return Ok(n);
+        }
+
+        self.set_timeout(timeout)?;
+        let mut control = CtrlRequest {
+            request_type,
+            request,
+            value,
+            index,
+            length: buf.len().min(u16::MAX as usize) as u16,
+            data: buf.as_mut_ptr(),
+        };
+        ioctl(
+            &self.file,
+            IOCTL_CTRL_REQUEST,
+            &mut control as *mut CtrlRequest as usize,
+        )
+    }
+
+    fn write_control(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &[u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize> {
+        self.set_timeout(timeout)?;
+
+        // the driver copies from, but never writes to, the data of an OUT request
+        let mut control = CtrlRequest {
+            request_type,
+            request,
+            value,
+            index,
+            length: buf.len().min(u16::MAX as usize) as u16,
+            data: buf.as_ptr() as *mut u8,
+        };
+        ioctl(
+            &self.file,
+            IOCTL_CTRL_REQUEST,
+            &mut control as *mut CtrlRequest as usize,
+        )
+    }
+
+    fn read_bulk(
+        &mut self,
+        _endpoint: u8,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize> {
+        self.transfer(IOCTL_READ, buf.as_mut_ptr(), buf.len(), timeout)
+    }
+
+    fn write_bulk(&mut self, _endpoint: u8, buf: &[u8], timeout: Duration) -> rusb::Result<usize> {
+        // as for control requests, the driver only reads the data
+        self.transfer(IOCTL_WRITE, buf.as_ptr() as *mut u8, buf.len(), timeout)
+    }
+
+    fn read_interrupt(
+        &mut self,
+        _endpoint: u8,
+        _buf: &mut [u8],
+        _timeout: Duration,
+    ) -> rusb::Result<usize> {
+        Err(rusb::Error::NotSupported)
+    }
+
+    fn clear_halt(&mut self, endpoint: u8) -> rusb::Result<()> {
+        let request = if endpoint & 0x80 != 0 {
+            IOCTL_CLEAR_IN_HALT
+        } else {
+            IOCTL_CLEAR_OUT_HALT
+        };
+        ioctl(&self.file, request, 0).map(|_| ())
+    }
+
+    fn interface_holder(&self) -> Option<String> {
+        Some("kernel driver usbtmc".to_owned())
+    }
+}
+
+fn ioctl(file: &File, request: u64, arg: usize) -> rusb::Result<usize> {
+    // SAFETY: each request is given a pointer to the argument type the driver
+    // expects for it, valid for the duration of the call
+    let result = unsafe { libc::ioctl(file.as_raw_fd(), request as _, arg) };
+    if result < 0 {
+        Err(rusb_error(&io::Error::last_os_error()))
+    } else {
+        Ok(result as usize)
+    }
+}
+
+/// The libusb error corresponding to an error from the driver
+fn rusb_error(error: &io::Error) -> rusb::Error {
+    match error.raw_os_error() {
+        Some(libc::ETIMEDOUT) => rusb::Error::Timeout,
+        Some(libc::EPIPE) => rusb::Error::Pipe,
+        Some(libc::ENODEV) | Some(libc::ESHUTDOWN) | Some(libc::ENOENT) => rusb::Error::NoDevice,
+        Some(libc::EBUSY) => rusb::Error::Busy,
+        Some(libc::EINVAL) => rusb::Error::InvalidParam,
+        Some(libc::EOVERFLOW) => rusb::Error::Overflow,
+        Some(libc::ENOMEM) => rusb::Error::NoMem,
+        Some(libc::EACCES) | Some(libc::EPERM) => rusb::Error::Access,
+        Some(libc::EINTR) => rusb::Error::Interrupted,
+        Some(libc::ENOTTY) | Some(libc::EOPNOTSUPP) => rusb::Error::NotSupported,
+        Some(libc::EIO) => rusb::Error::Io,
+        _ => rusb::Error::Other,
+    }
+}
+
+/// Describe the interface behind a character device from its sysfs entry
+fn sysfs_interface(path: &Path) -> Option<TMCInterface> {
+    let name = path.file_name()?;
+    let interface_dir = Path::new("/sys/class/usbmisc").join(name).join("device");
+    let read_hex = |path: PathBuf| -> Option<u16> {
+        u16::from_str_radix(fs::read_to_string(path).ok()?.trim(), 16).ok()
+    };
+
+    let mut interface = default_interface();
+    interface.interface_number = read_hex(interface_dir.join("bInterfaceNumber"))? as u8;
+    interface.interface_protocol = read_hex(interface_dir.join("bInterfaceProtocol"))? as u8;
+
+    for entry in fs::read_dir(&interface_dir).ok()?.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let address = match name.strip_prefix("ep_") {
+            Some(address) => u8::from_str_radix(address, 16).ok()?,
+            None => continue,
+        };
+        let kind = fs::read_to_string(entry.path().join("type")).ok()?;
+        let max_packet_size = read_hex(entry.path().join("wMaxPacketSize")).unwrap_or(0);
+
+        if kind.trim() == "Bulk" {
+            if address & 0x80 != 0 {
+                interface.bulk_in_address = Some(address);
+                interface.bulk_in_max_packet_size = max_packet_size;
+            } else {
+                interface.bulk_out_address = Some(address);
+                interface.bulk_out_max_packet_size = max_packet_size;
+            }
+        }
+    }
+
+    Some(interface)
+}
+
+/// Find the product name, serial number and bus location of the device from
+/// sysfs, in the device directory above the interface's
+fn sysfs_label(path: &Path) -> DeviceLabel {
+    let device_dir = match path.file_name() {
+        Some(name) => Path::new("/sys/class/usbmisc")
+            .join(name)
+            .join("device")
+            .join(".."),
+        None => return DeviceLabel::default(),
+    };
+    let read = |name: &str| -> Option<String> {
+        Some(
+            fs::read_to_string(device_dir.join(name))
+                .ok()?
+                .trim()
+                .to_owned(),
+        )
+    };
+
+    DeviceLabel {
+        model: read("product")
+            .or_else(|| Some(format!("{}:{}", read("idVendor")?, read("idProduct")?))),
+        serial_number: read("serial"),
+        bus_number: read("busnum").and_then(|number| number.parse().ok()),
+        address: read("devnum").and_then(|number| number.parse().ok()),
+    }
+}
+
+/// The interface assumed when sysfs can't describe it
+fn default_interface() -> TMCInterface {
+    TMCInterface {
+        interface_number: 0,
+        interface_protocol: 0,
+        bulk_out_address: Some(0x01),
+        bulk_in_address: Some(0x82),
+        interrupt_in_address: None,
+        control_in_max_packet_size: 64,
+        bulk_out_max_packet_size: 0,
+        bulk_in_max_packet_size: 0,
+    }
+}
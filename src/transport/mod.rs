@@ -0,0 +1,322 @@
+//! The USB operations the protocol layer needs, abstracted so that instruments
+//! can be reached through something other than a libusb device handle (such as a
+//! simulated device for testing).
+
+use crate::class::TMCInterface;
+use crate::{ConnectError, Instrument, TMCResult};
+use core::time::Duration;
+use rusb::{DeviceHandle, UsbContext};
+
+mod fault;
+#[cfg(all(feature = "kernel", target_os = "linux"))]
+mod kernel;
+
+pub use fault::*;
+#[cfg(all(feature = "kernel", target_os = "linux"))]
+pub use kernel::*;
+
+/// Access to the endpoints of one USBTMC interface.  Endpoint addresses and
+/// control request fields have the same meaning as in the USB spec, and errors are
+/// reported the way libusb would report them.  Timeouts follow libusb's
+/// convention too, with zero meaning no timeout (see
+/// [Timeout::to_transfer](crate::Timeout::to_transfer)).
+pub trait Transport: Send {
+    /// Information about the TMC interface and its endpoints
+    fn interface(&self) -> &TMCInterface;
+
+    fn claim_interface(&mut self) -> rusb::Result<()>;
+
+    fn release_interface(&mut self) -> rusb::Result<()>;
+
+    fn read_control(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize>;
+
+    fn write_control(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &[u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize>;
+
+    fn read_bulk(&mut self, endpoint: u8, buf: &mut [u8], timeout: Duration)
+        -> rusb::Result<usize>;
+
+    fn write_bulk(&mut self, endpoint: u8, buf: &[u8], timeout: Duration) -> rusb::Result<usize>;
+
+    fn read_interrupt(
+        &mut self,
+        endpoint: u8,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize>;
+
+    fn clear_halt(&mut self, endpoint: u8) -> rusb::Result<()>;
+
+    /// Describe what is holding the TMC interface after claiming it failed with
+    /// `Busy`, if the platform can tell.
+    fn interface_holder(&self) -> Option<String> {
+        None
+    }
+
+    /// What and where the device is, as far as the transport can tell, for
+    /// labelling the handle's logs
+    fn device_label(&self) -> DeviceLabel {
+        DeviceLabel::default()
+    }
+
+    /// A short name for how the transport reaches the device, such as `rusb` or
+    /// `kernel`, for logs and bug reports
+    fn backend(&self) -> &'static str {
+        "custom"
+    }
+}
+
+/// Identity and bus location of a device, for telling instruments apart in logs.
+/// Anything a transport can't tell is left out.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct DeviceLabel {
+    /// The product name, or the vendor and product IDs if it has none
+    pub model: Option<String>,
+    pub serial_number: Option<String>,
+    pub bus_number: Option<u8>,
+    pub address: Option<u8>,
+}
+
+/// Transport for an instrument attached to this host, via libusb
+#[derive(Debug)]
+pub struct UsbTransport<Ctx: UsbContext> {
+    usb: DeviceHandle<Ctx>,
+    instrument: Instrument<Ctx>,
+
+    // When connecting, we may need to reconfigure some stuff.  Remember the
+    // previous state here and restore it on drop().
+    restore_config: Option<u8>,
+    reattach_kernel_driver: Vec<u8>,
+}
+
+impl<Ctx: UsbContext> Drop for UsbTransport<Ctx> {
+    fn drop(&mut self) {
+        // TODO: is there something more useful we can do if these fail?
+        if let Some(old_config) = self.restore_config {
+            let _ = self.usb.set_active_configuration(old_config);
+        }
+
+        for &interface in self.reattach_kernel_driver.iter() {
+            let _ = self.usb.attach_kernel_driver(interface);
+        }
+    }
+}
+
+impl<Ctx: UsbContext> UsbTransport<Ctx> {
+    /// Open the instrument's device and make its TMC interface available to us:
+    /// detach any kernel drivers and select the right configuration.  The
+    /// interface itself is not claimed yet.
+    pub fn open(instrument: Instrument<Ctx>) -> TMCResult<Self> {
+        Ok(Self::open_device(instrument, true).map_err(ConnectError::from_open)?)
+    }
+
+    /// Open the instrument's device without disturbing other software using it:
+    /// kernel drivers are left attached and the configuration is left alone
+    pub fn open_shared(instrument: Instrument<Ctx>) -> TMCResult<Self> {
+        Ok(Self::open_device(instrument, false).map_err(ConnectError::from_open)?)
+    }
+
+    fn open_device(instrument: Instrument<Ctx>, exclusive: bool) -> rusb::Result<Self> {
+        let usb = instrument.device.open()?;
+
+        let mut transport = Self {
+            usb,
+            instrument,
+            restore_config: None,
+            reattach_kernel_driver: Vec::new(),
+        };
+        if !exclusive {
+            return Ok(transport);
+        }
+        let usb = &mut transport.usb;
+
+        let old_config = usb.active_configuration()?;
+
+        if rusb::supports_detach_kernel_driver() {
+            for config in 0..transport
+                .instrument
+                .device
+                .device_descriptor()?
+                .num_configurations()
+            {
+                for interface in 0..transport
+                    .instrument
+                    .device
+                    .config_descriptor(config)?
+                    .num_interfaces()
+                {
+                    if usb.kernel_driver_active(interface)? {
+                        transport.reattach_kernel_driver.push(interface);
+                        usb.detach_kernel_driver(interface)?;
+                    }
+                }
+            }
+        }
+
+        if old_config != 0 {
+            match transport.instrument.device.config_descriptor(old_config) {
+                Err(rusb::Error::NotFound) => {}
+                Err(rusb_error) => return Err(rusb_error),
+                Ok(_old_config_desc) => {}
+            };
+        }
+
+        let new_config = transport.instrument.config_desc.number();
+        if old_config != new_config {
+            transport.restore_config = Some(old_config);
+            usb.set_active_configuration(new_config)?;
+        }
+
+        Ok(transport)
+    }
+
+    pub fn instrument(&self) -> &Instrument<Ctx> {
+        &self.instrument
+    }
+
+    pub fn instrument_mut(&mut self) -> &mut Instrument<Ctx> {
+        &mut self.instrument
+    }
+
+    pub fn device_handle(&self) -> &DeviceHandle<Ctx> {
+        &self.usb
+    }
+}
+
+impl<Ctx: UsbContext> Transport for UsbTransport<Ctx> {
+    fn interface(&self) -> &TMCInterface {
+        &self.instrument.endpoints
+    }
+
+    fn claim_interface(&mut self) -> rusb::Result<()> {
+        self.usb
+            .claim_interface(self.instrument.endpoints.interface_number)
+    }
+
+    fn release_interface(&mut self) -> rusb::Result<()> {
+        self.usb
+            .release_interface(self.instrument.endpoints.interface_number)
+    }
+
+    fn read_control(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize> {
+        self.usb
+            .read_control(request_type, request, value, index, buf, timeout)
+    }
+
+    fn write_control(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &[u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize> {
+        self.usb
+            .write_control(request_type, request, value, index, buf, timeout)
+    }
+
+    fn read_bulk(
+        &mut self,
+        endpoint: u8,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize> {
+        self.usb.read_bulk(endpoint, buf, timeout)
+    }
+
+    fn write_bulk(&mut self, endpoint: u8, buf: &[u8], timeout: Duration) -> rusb::Result<usize> {
+        self.usb.write_bulk(endpoint, buf, timeout)
+    }
+
+    fn read_interrupt(
+        &mut self,
+        endpoint: u8,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize> {
+        self.usb.read_interrupt(endpoint, buf, timeout)
+    }
+
+    fn clear_halt(&mut self, endpoint: u8) -> rusb::Result<()> {
+        self.usb.clear_halt(endpoint)
+    }
+
+    fn backend(&self) -> &'static str {
+        "rusb"
+    }
+
+    fn device_label(&self) -> DeviceLabel {
+        let device = &self.instrument.device;
+        let desc = &self.instrument.device_desc;
+        let trimmed = |string: String| string.trim_end_matches(char::from(0)).to_string();
+
+        let model = self
+            .usb
+            .read_product_string_ascii(desc)
+            .map(trimmed)
+            .unwrap_or_else(|_| format!("{:04x}:{:04x}", desc.vendor_id(), desc.product_id()));
+
+        DeviceLabel {
+            model: Some(model),
+            serial_number: self
+                .usb
+                .read_serial_number_string_ascii(desc)
+                .ok()
+                .map(trimmed),
+            bus_number: Some(device.bus_number()),
+            address: Some(device.address()),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn interface_holder(&self) -> Option<String> {
+        // sysfs names interfaces by bus, port path, configuration and interface,
+        // and links each one to the driver bound to it
+        let device = &self.instrument.device;
+        let ports: Vec<String> = device
+            .port_numbers()
+            .ok()?
+            .iter()
+            .map(u8::to_string)
+            .collect();
+        let path = format!(
+            "/sys/bus/usb/devices/{}-{}:{}.{}/driver",
+            device.bus_number(),
+            ports.join("."),
+            self.instrument.config_desc.number(),
+            self.instrument.endpoints.interface_number
+        );
+
+        let driver = std::fs::read_link(path).ok()?;
+        let driver = driver.file_name()?.to_string_lossy();
+        if driver == "usbfs" {
+            Some("another process".to_owned())
+        } else {
+            Some(format!("kernel driver {}", driver))
+        }
+    }
+}
@@ -0,0 +1,355 @@
+//! Instruments on other machines, attached to this one with USB/IP.  A USB/IP
+//! server (`usbipd`) exports devices over TCP; importing one hands the connection
+//! to the kernel's `vhci_hcd` driver, after which the device appears on a
+//! virtual host controller and is found and opened like any other instrument.
+//!
+//! This speaks the USB/IP protocol itself rather than running the `usbip` tool,
+//! but still needs the `vhci-hcd` module loaded and permission to write to its
+//! sysfs files (usually root).  Only the first virtual host controller,
+//! `vhci_hcd.0`, is used.
+
+use crate::{list_instruments, DiscoveryError, Instrument, TMCResult};
+use byteorder::{BigEndian, ByteOrder};
+use core::time::Duration;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::thread::sleep;
+use std::time::Instant;
+use thiserror::Error;
+
+/// The TCP port USB/IP servers listen on
+pub const USBIP_PORT: u16 = 3240;
+
+const VHCI_PATH: &str = "/sys/devices/platform/vhci_hcd.0";
+
+const PROTOCOL_VERSION: u16 = 0x0111;
+const OP_REQ_DEVLIST: u16 = 0x8005;
+const OP_REP_DEVLIST: u16 = 0x0005;
+const OP_REQ_IMPORT: u16 = 0x8003;
+const OP_REP_IMPORT: u16 = 0x0003;
+
+const HEADER_SIZE: usize = 8;
+const BUSID_SIZE: usize = 32;
+const DEVICE_SIZE: usize = 312;
+const INTERFACE_SIZE: usize = 4;
+
+/// Speed of a SuperSpeed device, which must be attached to a SuperSpeed port
+const SPEED_SUPER: u32 = 5;
+
+/// A vhci port with nothing attached
+const PORT_FREE: u32 = 4;
+
+/// How long to wait on the server before giving up
+const SERVER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Pause between scans while waiting for an attached device to appear
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Why listing, attaching or detaching a USB/IP device failed
+#[derive(Error, Debug)]
+pub enum UsbipError {
+    /// Talking to the server, or to `vhci_hcd` through sysfs, failed
+    #[error("usbip I/O failed: {source}")]
+    Io {
+        #[from]
+        source: io::Error,
+    },
+
+    /// The server's reply wasn't in the USB/IP protocol, or a version of it
+    /// this crate knows
+    #[error("unexpected reply from usbip server: {0}")]
+    Protocol(String),
+
+    /// A bus ID too long for the protocol
+    #[error("invalid bus ID {0:?}")]
+    InvalidBusId(String),
+
+    /// The server wouldn't export the device, because it isn't exported or
+    /// someone else has imported it
+    #[error("usbip server refused to export {busid} (status {status})")]
+    Refused { busid: String, status: u32 },
+
+    /// The `vhci-hcd` module isn't loaded
+    #[error("vhci_hcd is not available; is the vhci-hcd module loaded?")]
+    VhciUnavailable,
+
+    /// Every virtual port for a device of this speed is in use
+    #[error("no free vhci port for a device of speed {speed}")]
+    NoFreePort { speed: u32 },
+}
+
+/// A device exported by a USB/IP server, as it describes it
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ExportedDevice {
+    /// The ID the server knows the device by, such as `1-1.2`, for [attach]
+    pub busid: String,
+
+    /// The device's sysfs path on the server
+    pub path: String,
+
+    pub bus_number: u32,
+    pub device_number: u32,
+
+    /// The device's speed, in the kernel's numbering (3 for high speed)
+    pub speed: u32,
+
+    pub vendor_id: u16,
+    pub product_id: u16,
+
+    /// Class, subclass and protocol of each interface of the active
+    /// configuration; only listed devices have these
+    pub interfaces: Vec<(u8, u8, u8)>,
+}
+
+impl ExportedDevice {
+    /// Whether the device has a USBTMC interface
+    pub fn is_usbtmc(&self) -> bool {
+        self.interfaces
+            .iter()
+            .any(|&(class, subclass, _)| class == 0xfe && subclass == 0x03)
+    }
+
+    fn parse(data: &[u8]) -> Self {
+        let string = |bytes: &[u8]| {
+            let end = bytes
+                .iter()
+                .position(|&byte| byte == 0)
+                .unwrap_or(bytes.len());
+            String::from_utf8_lossy(&bytes[..end]).into_owned()
+        };
+
+        Self {
+            path: string(&data[..256]),
+            busid: string(&data[256..288]),
+            bus_number: BigEndian::read_u32(&data[288..]),
+            device_number: BigEndian::read_u32(&data[292..]),
+            speed: BigEndian::read_u32(&data[296..]),
+            vendor_id: BigEndian::read_u16(&data[300..]),
+            product_id: BigEndian::read_u16(&data[302..]),
+            interfaces: Vec::new(),
+        }
+    }
+}
+
+/// List the devices a USB/IP server exports, such as
+/// `list_exported(("lab-pc", USBIP_PORT))`
+pub fn list_exported<A: ToSocketAddrs>(server: A) -> Result<Vec<ExportedDevice>, UsbipError> {
+    let mut stream = connect(server)?;
+    stream.write_all(&request_header(OP_REQ_DEVLIST))?;
+
+    let status = read_reply_header(&mut stream, OP_REP_DEVLIST)?;
+    if status != 0 {
+        return Err(UsbipError::Protocol(format!(
+            "device list failed with status {}",
+            status
+        )));
+    }
+
+    let mut count = [0u8; 4];
+    stream.read_exact(&mut count)?;
+
+    let mut devices = Vec::new();
+    for _ in 0..BigEndian::read_u32(&count) {
+        let mut data = [0u8; DEVICE_SIZE];
+        stream.read_exact(&mut data)?;
+        let mut device = ExportedDevice::parse(&data);
+
+        // the interface count is the last byte of the device
+        for _ in 0..data[DEVICE_SIZE - 1] {
+            let mut interface = [0u8; INTERFACE_SIZE];
+            stream.read_exact(&mut interface)?;
+            device
+                .interfaces
+                .push((interface[0], interface[1], interface[2]));
+        }
+        devices.push(device);
+    }
+
+    Ok(devices)
+}
+
+/// Import the device with bus ID `busid` from a USB/IP server and attach it to
+/// a free port of the virtual host controller.  The device stays attached, even
+/// after this process exits, until it is [detached](Attachment::detach) (or
+/// unplugged from the server).
+pub fn attach<A: ToSocketAddrs>(server: A, busid: &str) -> Result<Attachment, UsbipError> {
+    if busid.len() >= BUSID_SIZE {
+        return Err(UsbipError::InvalidBusId(busid.to_owned()));
+    }
+    if !Path::new(VHCI_PATH).exists() {
+        return Err(UsbipError::VhciUnavailable);
+    }
+
+    let mut stream = connect(server)?;
+    let mut request = request_header(OP_REQ_IMPORT).to_vec();
+    let mut busid_field = [0u8; BUSID_SIZE];
+    busid_field[..busid.len()].copy_from_slice(busid.as_bytes());
+    request.extend_from_slice(&busid_field);
+    stream.write_all(&request)?;
+
+    let status = read_reply_header(&mut stream, OP_REP_IMPORT)?;
+    if status != 0 {
+        return Err(UsbipError::Refused {
+            busid: busid.to_owned(),
+            status,
+        });
+    }
+
+    let mut data = [0u8; DEVICE_SIZE];
+    stream.read_exact(&mut data)?;
+    let device = ExportedDevice::parse(&data);
+    if device.busid != busid {
+        return Err(UsbipError::Protocol(format!(
+            "imported {} instead of {}",
+            device.busid, busid
+        )));
+    }
+
+    // the kernel takes its own reference to the socket, so ours can be closed
+    // once it has been handed over
+    let port = free_port(device.speed)?;
+    let device_id = (device.bus_number << 16) | device.device_number;
+    fs::write(
+        Path::new(VHCI_PATH).join("attach"),
+        format!(
+            "{} {} {} {}",
+            port,
+            stream.as_raw_fd(),
+            device_id,
+            device.speed
+        ),
+    )?;
+
+    Ok(Attachment { port, device })
+}
+
+/// A device imported with [attach]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Attachment {
+    port: u32,
+    device: ExportedDevice,
+}
+
+impl Attachment {
+    /// The vhci port the device is attached to
+    pub fn port(&self) -> u32 {
+        self.port
+    }
+
+    /// The device as the server described it
+    pub fn device(&self) -> &ExportedDevice {
+        &self.device
+    }
+
+    /// Wait up to `timeout` for the attached instrument to appear, and return
+    /// it ready to open.  It is found by its vendor and product IDs among the
+    /// devices on the virtual host controller's buses.
+    pub fn find_instrument<Ctx: rusb::UsbContext>(
+        &self,
+        context: Ctx,
+        timeout: Duration,
+    ) -> TMCResult<Instrument<Ctx>> {
+        let buses = vhci_buses();
+        let start = Instant::now();
+
+        loop {
+            let found = list_instruments(context.clone())?
+                .into_iter()
+                .find(|instrument| {
+                    let key = instrument.key();
+                    buses.contains(&key.bus_number)
+                        && key.vendor_id == self.device.vendor_id
+                        && key.product_id == self.device.product_id
+                });
+            if let Some(instrument) = found {
+                return Ok(instrument);
+            }
+
+            if start.elapsed() >= timeout {
+                return Err(DiscoveryError::NotFound(format!(
+                    "{:04x}:{:04x} on vhci port {}",
+                    self.device.vendor_id, self.device.product_id, self.port
+                ))
+                .into());
+            }
+            sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Detach the device, releasing it on the server for others to import
+    pub fn detach(self) -> Result<(), UsbipError> {
+        fs::write(Path::new(VHCI_PATH).join("detach"), self.port.to_string())?;
+        Ok(())
+    }
+}
+
+fn connect<A: ToSocketAddrs>(server: A) -> Result<TcpStream, UsbipError> {
+    let stream = TcpStream::connect(server)?;
+    stream.set_read_timeout(Some(SERVER_TIMEOUT))?;
+    stream.set_write_timeout(Some(SERVER_TIMEOUT))?;
+    Ok(stream)
+}
+
+fn request_header(code: u16) -> [u8; HEADER_SIZE] {
+    let mut header = [0u8; HEADER_SIZE];
+    BigEndian::write_u16(&mut header[0..], PROTOCOL_VERSION);
+    BigEndian::write_u16(&mut header[2..], code);
+    header
+}
+
+/// Read the header of a reply, returning its status
+fn read_reply_header(stream: &mut TcpStream, code: u16) -> Result<u32, UsbipError> {
+    let mut header = [0u8; HEADER_SIZE];
+    stream.read_exact(&mut header)?;
+
+    let version = BigEndian::read_u16(&header[0..]);
+    let reply = BigEndian::read_u16(&header[2..]);
+    if version != PROTOCOL_VERSION || reply != code {
+        return Err(UsbipError::Protocol(format!(
+            "version {:#06x}, reply {:#06x}",
+            version, reply
+        )));
+    }
+
+    Ok(BigEndian::read_u32(&header[4..]))
+}
+
+/// Find a free vhci port for a device of `speed` in the controller's status:
+/// a line per port such as `hs  0000 004 000 00000000 000000 0-0`, giving the
+/// hub (high or super speed), port number and state
+fn free_port(speed: u32) -> Result<u32, UsbipError> {
+    let status = fs::read_to_string(Path::new(VHCI_PATH).join("status"))?;
+    let hub = if speed == SPEED_SUPER { "ss" } else { "hs" };
+
+    status
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let line_hub = fields.next()?;
+            let port = fields.next()?.parse::<u32>().ok()?;
+            let state = fields.next()?.parse::<u32>().ok()?;
+            (line_hub == hub && state == PORT_FREE).then_some(port)
+        })
+        .next()
+        .ok_or(UsbipError::NoFreePort { speed })
+}
+
+/// The bus numbers of the virtual host controller's root hubs
+fn vhci_buses() -> Vec<u8> {
+    let entries = match fs::read_dir(VHCI_PATH) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name();
+            name.to_str()?.strip_prefix("usb")?.parse().ok()
+        })
+        .collect()
+}
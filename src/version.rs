@@ -0,0 +1,92 @@
+//! The versions of this crate and the USB stack under it, for applications to
+//! log and include in bug reports.
+
+use std::fmt;
+
+/// Versions of the software between an application and its instruments, from
+/// [version_info]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct VersionInfo {
+    /// This crate's version
+    pub crate_version: &'static str,
+
+    /// The libusb library's version, such as `1.0.26.11724`, with any release
+    /// candidate suffix
+    pub libusb_version: String,
+
+    /// The transports this build can reach real instruments through, as named
+    /// by [Transport::backend](crate::transport::Transport::backend)
+    pub backends: Vec<&'static str>,
+
+    /// The crate's optional features enabled in this build
+    pub features: Vec<&'static str>,
+}
+
+/// The versions of this crate and the USB stack in use
+pub fn version_info() -> VersionInfo {
+    let libusb = rusb::version();
+    let libusb_version = format!(
+        "{}.{}.{}.{}{}",
+        libusb.major(),
+        libusb.minor(),
+        libusb.micro(),
+        libusb.nano(),
+        libusb.rc().unwrap_or_default()
+    );
+
+    let mut backends = vec!["rusb"];
+    if cfg!(all(feature = "kernel", target_os = "linux")) {
+        backends.push("kernel");
+    }
+
+    let features = [
+        ("arrow", cfg!(feature = "arrow")),
+        ("capture", cfg!(feature = "capture")),
+        ("command-policy", cfg!(feature = "command-policy")),
+        ("corpus", cfg!(feature = "corpus")),
+        ("csv", cfg!(feature = "csv")),
+        ("deep-scan", cfg!(feature = "deep-scan")),
+        ("fast-float", cfg!(feature = "fast-float")),
+        ("gadget", cfg!(feature = "gadget")),
+        ("kernel", cfg!(feature = "kernel")),
+        ("metrics", cfg!(feature = "metrics")),
+        ("profiles", cfg!(feature = "profiles")),
+        ("replay", cfg!(feature = "replay")),
+        ("scpi", cfg!(feature = "scpi")),
+        ("sim", cfg!(feature = "sim")),
+        ("timing", cfg!(feature = "timing")),
+        ("tokio", cfg!(feature = "tokio")),
+        ("tracing", cfg!(feature = "tracing")),
+        ("usbip", cfg!(feature = "usbip")),
+    ]
+    .iter()
+    .filter(|(_, enabled)| *enabled)
+    .map(|(feature, _)| *feature)
+    .collect();
+
+    VersionInfo {
+        crate_version: env!("CARGO_PKG_VERSION"),
+        libusb_version,
+        backends,
+        features,
+    }
+}
+
+/// One line, such as `tmc 0.1.1 (libusb 1.0.26.11724; backends rusb, kernel;
+/// features scpi, sim)`
+impl fmt::Display for VersionInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "tmc {} (libusb {}; backends {}; features {})",
+            self.crate_version,
+            self.libusb_version,
+            self.backends.join(", "),
+            if self.features.is_empty() {
+                "none".to_owned()
+            } else {
+                self.features.join(", ")
+            }
+        )
+    }
+}
@@ -0,0 +1,146 @@
+//! A background thread which notices transfers that stay blocked well beyond
+//! their timeout, as libusb's blocking calls can on some platforms.
+//!
+//! Blocking transfers can't be cancelled, so a stalled transfer still has to
+//! return by itself.  The watchdog tells the handle's observers as soon as the
+//! limit passes, and the transfer then fails with
+//! [WatchdogTriggered](crate::TMCError::WatchdogTriggered) whatever its outcome.
+
+use crate::observer::SessionObserver;
+use crate::transport::Operation;
+use crate::TMCError;
+use core::time::Duration;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+/// Shortest interval at which the watchdog thread checks the transfer in flight
+const MIN_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Debug)]
+struct InFlight {
+    operation: Operation,
+    started: Instant,
+    limit: Duration,
+    triggered: Option<Duration>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    in_flight: Option<InFlight>,
+    stop: bool,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    state: Mutex<State>,
+    wake: Condvar,
+}
+
+impl Shared {
+    /// Lock the state, even if an observer panicked while it was locked, as it
+    /// is always left consistent
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Watches the transfers of one handle, set up with
+/// [set_watchdog](crate::TMCHandle::set_watchdog)
+#[derive(Debug)]
+pub(crate) struct Watchdog {
+    grace: Duration,
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Watchdog {
+    /// Start a watchdog thread which triggers when a transfer runs `grace` past
+    /// its timeout, telling `observers`
+    pub(crate) fn spawn(grace: Duration, observers: Vec<Arc<dyn SessionObserver>>) -> Self {
+        let shared = Arc::new(Shared::default());
+        let interval = (grace / 4).max(MIN_CHECK_INTERVAL);
+
+        let thread = {
+            let shared = shared.clone();
+            thread::spawn(move || run(&shared, interval, &observers))
+        };
+
+        Self {
+            grace,
+            shared,
+            thread: Some(thread),
+        }
+    }
+
+    pub(crate) fn grace(&self) -> Duration {
+        self.grace
+    }
+
+    /// Note the start of a transfer with the given timeout
+    pub(crate) fn watch(&self, operation: Operation, timeout: Duration) {
+        let mut state = self.shared.lock();
+        state.in_flight = Some(InFlight {
+            operation,
+            started: Instant::now(),
+            limit: timeout.saturating_add(self.grace),
+            triggered: None,
+        });
+    }
+
+    /// Note the end of the transfer, returning how long it had been running when
+    /// the watchdog triggered, if it did
+    pub(crate) fn finish(&self) -> Option<Duration> {
+        let mut state = self.shared.lock();
+        state
+            .in_flight
+            .take()
+            .and_then(|in_flight| in_flight.triggered)
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.shared.lock().stop = true;
+        self.shared.wake.notify_one();
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run(shared: &Shared, interval: Duration, observers: &[Arc<dyn SessionObserver>]) {
+    let mut state = shared.lock();
+
+    while !state.stop {
+        let mut triggered = None;
+        if let Some(in_flight) = &mut state.in_flight {
+            let elapsed = in_flight.started.elapsed();
+            if in_flight.triggered.is_none() && elapsed > in_flight.limit {
+                in_flight.triggered = Some(elapsed);
+                triggered = Some(TMCError::WatchdogTriggered {
+                    operation: in_flight.operation,
+                    elapsed,
+                });
+            }
+        }
+
+        // a slow observer mustn't hold up the I/O thread starting or finishing
+        // transfers, so the state is unlocked while they are told
+        if let Some(error) = triggered {
+            drop(state);
+            for observer in observers.iter() {
+                observer.error(&error);
+            }
+            state = shared.lock();
+            continue;
+        }
+
+        state = shared
+            .wake
+            .wait_timeout(state, interval)
+            .unwrap_or_else(PoisonError::into_inner)
+            .0;
+    }
+}
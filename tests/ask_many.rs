@@ -0,0 +1,34 @@
+//! Several queries in one message
+
+#![cfg(feature = "sim")]
+
+mod common;
+
+use common::Handle;
+
+fn open() -> Handle {
+    // only rooted headers match, as the instrument would resolve the others
+    // against the previous header's path
+    common::open(
+        "rules:\n  - pattern: ':MEAS:VOLT\\?'\n    response: '1.5'\n  - pattern: ':MEAS:CURR\\?'\n    response: '0.25'\n  - pattern: '\\*OPC\\?'\n    response: '1'\n",
+    )
+    .0
+}
+
+#[test]
+fn queries_taken_from_root() {
+    let mut handle = open();
+    assert_eq!(
+        handle
+            .ask_many(&["MEAS:VOLT?", ":MEAS:CURR?", "*OPC?"])
+            .unwrap(),
+        ["1.5", "0.25", "1"]
+    );
+}
+
+#[test]
+fn no_queries() {
+    let mut handle = open();
+    assert!(handle.ask_many(&[]).unwrap().is_empty());
+    assert_eq!(handle.ask_many(&["MEAS:CURR?"]).unwrap(), ["0.25"]);
+}
@@ -0,0 +1,77 @@
+//! Reading bulk-in transfers which arrive over several `read_bulk` calls
+
+#![cfg(feature = "sim")]
+
+mod common;
+
+use common::Handle;
+use tmc::class::HEADER_SIZE;
+use tmc::transcript::{Direction, Transcript};
+use tmc::transport::{Fault, FaultInjector, Operation};
+use tmc::MAX_BULK_IN_READ;
+
+/// A handle on a device answering `DATA?` with `data` and `PING?` with `pong`
+fn open(data: &str) -> (Handle, FaultInjector) {
+    common::open(&format!(
+        "rules:\n  - pattern: 'DATA\\?'\n    response: '{}'\n  - pattern: 'PING\\?'\n    response: 'pong'\n",
+        data
+    ))
+}
+
+/// Ask for `DATA?` with the next bulk-in reads cut to `splits`, then check the
+/// whole response arrived and the following message is read cleanly
+fn ask_split(data: &str, splits: &[usize]) {
+    let (mut handle, injector) = open(data);
+    for (nth, &split) in splits.iter().enumerate() {
+        injector.inject(Operation::BulkIn, nth as u64, Fault::Split(split));
+    }
+
+    assert_eq!(handle.ask("DATA?").unwrap(), format!("{}\n", data));
+    assert_eq!(injector.pending(), 0);
+
+    assert_eq!(handle.ask("PING?").unwrap(), "pong\n");
+}
+
+#[test]
+fn split_inside_header() {
+    ask_split("tiny", &[5]);
+    ask_split("tiny", &[1, 1, HEADER_SIZE - 2]);
+}
+
+#[test]
+fn split_at_data_padding_boundary() {
+    // "tiny\n" ends the data 1 byte short of a multiple of 4
+    ask_split("tiny", &[HEADER_SIZE + 5]);
+    ask_split("tiny", &[HEADER_SIZE, 5]);
+}
+
+#[test]
+fn split_at_max_packet() {
+    let packet = open("").0.interface().bulk_in_max_packet();
+    let data = "x".repeat(3 * packet);
+    ask_split(&data, &[packet]);
+    ask_split(&data, &[packet, packet, packet]);
+}
+
+#[test]
+fn transfer_larger_than_one_read() {
+    // one transfer, several times the most a single read_bulk call asks for
+    let data = "y".repeat(3 * MAX_BULK_IN_READ);
+    let (mut handle, _) = open(&data);
+    handle
+        .set_max_transfer_size(4 * MAX_BULK_IN_READ as u32 - 1)
+        .unwrap();
+    handle.start_transcript(Transcript::in_memory().with_transfers());
+
+    assert_eq!(handle.ask("DATA?").unwrap(), format!("{}\n", data));
+
+    let transfers = handle.transcript().unwrap().transfers().unwrap();
+    let received: Vec<usize> = transfers
+        .iter()
+        .filter(|transfer| transfer.direction == Direction::Received)
+        .map(|transfer| transfer.length)
+        .collect();
+    // the data, its newline and padding to a multiple of 4
+    let padded = (HEADER_SIZE + data.len() + 1 + 3) & !3;
+    assert_eq!(received, vec![padded]);
+}
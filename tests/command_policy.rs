@@ -0,0 +1,68 @@
+//! Commands a policy denies never reach the device, however they are written
+
+#![cfg(all(feature = "sim", feature = "command-policy"))]
+
+use regex::Regex;
+use tmc::command_policy::PatternPolicy;
+mod common;
+
+use common::Handle;
+use tmc::{OpenOptions, TMCError};
+
+fn open() -> Handle {
+    let policy = PatternPolicy::new()
+        .deny(Regex::new(r"(?i)^:?SYST(em)?:SEC(urity)?:IMM").unwrap())
+        .deny(Regex::new(r"(?i)^\*RST").unwrap());
+    let options = OpenOptions::new().command_policy(policy);
+    common::open_with(
+        "rules:\n  - pattern: 'DATA\\?'\n    response: 'data'\n",
+        options,
+    )
+    .0
+}
+
+fn rejected<T: std::fmt::Debug>(result: Result<T, TMCError>) -> String {
+    match result {
+        Err(TMCError::CommandRejected { command, .. }) => command,
+        result => panic!("{:?}", result),
+    }
+}
+
+#[test]
+fn relative_header_resolved() {
+    let mut handle = open();
+    assert_eq!(
+        rejected(handle.write_raw(b"SYST:BEEP;SEC:IMM")),
+        "SYST:SEC:IMM"
+    );
+    handle.write_raw(b"SYST:BEEP;:SEC:IMM").unwrap();
+}
+
+#[test]
+fn partial_writes_checked_per_unit() {
+    let mut handle = open();
+    handle.write_partial_raw(b"DATA?;").unwrap();
+    assert_eq!(rejected(handle.write_partial_raw(b"*RST;")), "*RST");
+
+    // the rejected part wasn't taken, so the message can go on without it
+    handle.write_partial_raw(b"SYST:BEEP").unwrap();
+    handle.flush_message().unwrap();
+}
+
+#[test]
+fn last_partial_unit_checked_on_flush() {
+    let mut handle = open();
+    handle.write_partial_raw(b"*IDN?;").unwrap();
+    handle.write_partial_raw(b"*RST").unwrap();
+    assert_eq!(rejected(handle.flush_message()), "*RST");
+    handle.clear().unwrap();
+
+    handle.write_partial_raw(b"SYST:BEEP;SEC").unwrap();
+    handle.write_partial_raw(b":IMM").unwrap();
+    assert_eq!(rejected(handle.flush_message()), "SYST:SEC:IMM");
+
+    // the message stays in progress until it is cleared
+    assert!(handle.write_raw(b"DATA?").is_err());
+    handle.clear().unwrap();
+    assert_eq!(handle.ask("DATA?").unwrap(), "data\n");
+}
@@ -0,0 +1,46 @@
+//! Fixtures shared by the integration tests, each of which uses only some
+
+#![allow(dead_code)]
+
+use byteorder::{ByteOrder, LittleEndian};
+use tmc::class::HEADER_SIZE;
+use tmc::sim::{Script, SimTransport};
+use tmc::transport::{FaultInjectingTransport, FaultInjector, Operation};
+use tmc::{OpenOptions, TMCHandle};
+
+/// A handle on a simulated instrument, through a transport which faults can be
+/// injected into
+pub type Handle = TMCHandle<FaultInjectingTransport<SimTransport>>;
+
+/// Script for a device answering `ECHO` with its digits
+pub const ECHO: &str = "rules:\n  - pattern: 'ECHO (\\d+)'\n    response: '$1'\n";
+
+/// Open a handle on a simulated instrument running the YAML script
+pub fn open(yaml: &str) -> (Handle, FaultInjector) {
+    open_with(yaml, OpenOptions::new())
+}
+
+/// Open a handle on a simulated instrument running the YAML script, with the
+/// given options
+pub fn open_with(yaml: &str, options: OpenOptions) -> (Handle, FaultInjector) {
+    let script = Script::from_yaml(yaml).unwrap();
+    let transport = FaultInjectingTransport::new(SimTransport::new(&script).unwrap());
+    let injector = transport.injector();
+    let handle = TMCHandle::with_transport(transport, options).unwrap();
+    (handle, injector)
+}
+
+/// The message data and EOM bit of each DEV_DEP_MSG_OUT transfer recorded so
+/// far, leaving out requests for responses
+pub fn sent(injector: &FaultInjector) -> Vec<(Vec<u8>, bool)> {
+    injector
+        .recorded(Operation::BulkOut)
+        .iter()
+        .filter(|transfer| transfer[0] == 1)
+        .map(|transfer| {
+            let size = LittleEndian::read_u32(&transfer[4..8]) as usize;
+            let data = transfer[HEADER_SIZE..HEADER_SIZE + size].to_vec();
+            (data, transfer[8] & 0x01 != 0)
+        })
+        .collect()
+}
@@ -0,0 +1,19 @@
+//! The compliance checks against the simulated device
+
+#![cfg(feature = "sim")]
+
+mod common;
+
+use tmc::compliance::{check_compliance, Outcome};
+
+#[test]
+fn simulated_device_passes() {
+    let (mut handle, _) = common::open("rules: []\n");
+
+    let report = check_compliance(&mut handle);
+    assert!(report.passed(), "{}", report);
+    for name in ["abort pending query", "term char", "end of message"] {
+        let result = report.results.iter().find(|result| result.name == name);
+        assert_eq!(result.unwrap().outcome, Outcome::Pass, "{}", name);
+    }
+}
@@ -0,0 +1,12 @@
+//! Replaying the recorded exchanges in `tests/corpus`
+
+#![cfg(feature = "corpus")]
+
+use tmc::corpus::run_corpus;
+
+#[test]
+fn corpus_passes() {
+    let report = run_corpus("tests/corpus").unwrap();
+    assert!(!report.cases.is_empty());
+    assert!(report.passed(), "{:#?}", report);
+}
@@ -0,0 +1,45 @@
+//! I/O errors are only taken as a disconnect once the device stops answering
+
+#![cfg(feature = "sim")]
+
+mod common;
+
+use tmc::transport::{Fault, Operation};
+use tmc::TMCError;
+
+#[test]
+fn transient_io_error() {
+    let (mut handle, injector) = common::open(common::ECHO);
+    injector.inject(Operation::BulkOut, 0, Fault::Error(rusb::Error::Io));
+
+    let error = handle.write("ECHO 1").unwrap_err();
+    assert!(matches!(
+        error,
+        TMCError::Rusb {
+            source: rusb::Error::Io
+        }
+    ));
+    assert!(!error.is_disconnect());
+    assert_eq!(
+        std::io::Error::from(error).kind(),
+        std::io::ErrorKind::Other
+    );
+
+    // the device still answers
+    assert_eq!(handle.ask("ECHO 2").unwrap(), "2\n");
+}
+
+#[test]
+fn io_error_from_departed_device() {
+    let (mut handle, injector) = common::open(common::ECHO);
+    injector.inject(Operation::BulkOut, 0, Fault::Error(rusb::Error::Io));
+    // the descriptor request checking whether the device is there fails too
+    injector.inject(Operation::ControlIn, 0, Fault::Error(rusb::Error::Io));
+
+    let error = handle.write("ECHO 1").unwrap_err();
+    assert!(error.is_disconnect(), "{:?}", error);
+    assert_eq!(
+        std::io::Error::from(error).kind(),
+        std::io::ErrorKind::NotConnected
+    );
+}
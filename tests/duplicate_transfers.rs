@@ -0,0 +1,46 @@
+//! Bulk-in transfers which the device sends again are dropped
+
+#![cfg(feature = "sim")]
+
+mod common;
+
+use common::Handle;
+use tmc::class::ClassError;
+use tmc::transport::{Fault, FaultInjector, Operation};
+use tmc::TMCError;
+
+/// Read four bytes a transfer, so that a response takes several
+fn open() -> (Handle, FaultInjector) {
+    let (mut handle, injector) = common::open(common::ECHO);
+    handle.set_max_transfer_size(4).unwrap();
+    (handle, injector)
+}
+
+#[test]
+fn repeated_transfer_dropped() {
+    let (mut handle, injector) = open();
+    injector.inject(Operation::BulkIn, 1, Fault::Repeat);
+
+    assert_eq!(handle.ask("ECHO 0123456789").unwrap(), "0123456789\n");
+    assert_eq!(injector.pending(), 0);
+    assert_eq!(handle.diagnostics().duplicate_transfers, 1);
+}
+
+#[test]
+fn transfer_repeated_too_often() {
+    let (mut handle, injector) = open();
+    for nth in 1..=5 {
+        injector.inject(Operation::BulkIn, nth, Fault::Repeat);
+    }
+
+    match handle.ask("ECHO 0123456789") {
+        Err(TMCError::Class {
+            source: ClassError::RepeatedTransfer { header },
+        }) => assert_eq!(header[1], !header[2]),
+        result => panic!("{:?}", result),
+    }
+    assert_eq!(handle.diagnostics().duplicate_transfers, 5);
+
+    // the rest of the response was discarded
+    assert_eq!(handle.ask("ECHO 42").unwrap(), "42\n");
+}
@@ -0,0 +1,69 @@
+//! Commands and responses in the handle's encoding
+
+#![cfg(feature = "sim")]
+
+mod common;
+
+use common::{sent, Handle};
+use tmc::transport::FaultInjector;
+use tmc::{Encoding, TMCError};
+
+fn open(encoding: Encoding) -> (Handle, FaultInjector) {
+    let (mut handle, injector) =
+        common::open("rules:\n  - pattern: 'TEMP\\?'\n    response: '25.0°C'\n");
+    handle.set_encoding(encoding);
+    injector.record();
+    (handle, injector)
+}
+
+#[test]
+fn utf8_commands_sent_as_is() {
+    let (mut handle, injector) = open(Encoding::Utf8);
+    handle.write("UNIT °C").unwrap();
+    assert_eq!(sent(&injector), [("UNIT °C".as_bytes().to_vec(), true)]);
+}
+
+#[test]
+fn latin1_commands_one_byte_a_character() {
+    let (mut handle, injector) = open(Encoding::Latin1);
+    handle.write("UNIT °C").unwrap();
+    assert_eq!(sent(&injector), [(b"UNIT \xB0C".to_vec(), true)]);
+}
+
+#[test]
+fn unencodable_command_not_sent() {
+    let (mut handle, injector) = open(Encoding::Ascii);
+    match handle.write("UNIT °C") {
+        Err(TMCError::Unencodable {
+            encoding: Encoding::Ascii,
+            character: '°',
+            position: 5,
+        }) => {}
+        result => panic!("{:?}", result),
+    }
+    assert!(sent(&injector).is_empty());
+
+    let (mut handle, _) = open(Encoding::Latin1);
+    assert!(matches!(
+        handle.write("UNIT µV → mV"),
+        Err(TMCError::Unencodable {
+            character: '→', ..
+        })
+    ));
+}
+
+#[test]
+fn ascii_responses_checked() {
+    // the simulated device answers in UTF-8, whose degree sign is 2 bytes
+    let (mut handle, _) = open(Encoding::Ascii);
+    match handle.ask("TEMP?") {
+        Err(TMCError::Undecodable {
+            encoding: Encoding::Ascii,
+            position: 4,
+        }) => {}
+        result => panic!("{:?}", result),
+    }
+
+    let (mut handle, _) = open(Encoding::Latin1);
+    assert_eq!(handle.ask("TEMP?").unwrap(), "25.0\u{C2}\u{B0}C\n");
+}
@@ -0,0 +1,61 @@
+//! Responses which stop partway through
+
+#![cfg(feature = "sim")]
+
+mod common;
+
+use common::Handle;
+use core::time::Duration;
+use tmc::class::HEADER_SIZE;
+use tmc::transport::{Fault, FaultInjector, Operation};
+use tmc::TMCError;
+
+/// Read four bytes a transfer, so that a response takes several, and don't
+/// wait long for a transfer which never comes
+fn open() -> (Handle, FaultInjector) {
+    let (mut handle, injector) = common::open(common::ECHO);
+    handle.set_max_transfer_size(4).unwrap();
+    handle.set_timeout(Duration::from_millis(200));
+    (handle, injector)
+}
+
+#[test]
+fn interrupted_between_transfers() {
+    let (mut handle, injector) = open();
+    injector.inject(Operation::BulkIn, 1, Fault::Error(rusb::Error::Timeout));
+
+    match handle.ask("ECHO 0123456789") {
+        Err(TMCError::ResponseInterrupted { received }) => assert_eq!(received, 4),
+        result => panic!("{:?}", result),
+    }
+
+    // the rest of the interrupted response is discarded
+    assert_eq!(handle.ask("ECHO 42").unwrap(), "42\n");
+}
+
+#[test]
+fn interrupted_within_transfer() {
+    let (mut handle, injector) = open();
+    injector.inject(Operation::BulkIn, 0, Fault::Split(HEADER_SIZE + 2));
+    injector.inject(Operation::BulkIn, 1, Fault::Error(rusb::Error::Timeout));
+
+    match handle.ask("ECHO 0123456789") {
+        Err(TMCError::ResponseInterrupted { received }) => assert_eq!(received, 2),
+        result => panic!("{:?}", result),
+    }
+    assert_eq!(handle.ask("ECHO 42").unwrap(), "42\n");
+}
+
+#[test]
+fn nothing_received_is_a_timeout() {
+    let (mut handle, injector) = open();
+    injector.inject(Operation::BulkIn, 0, Fault::Error(rusb::Error::Timeout));
+
+    match handle.ask("ECHO 0123456789") {
+        Err(TMCError::Rusb {
+            source: rusb::Error::Timeout,
+        }) => {}
+        result => panic!("{:?}", result),
+    }
+    assert_eq!(handle.ask("ECHO 42").unwrap(), "42\n");
+}
@@ -0,0 +1,142 @@
+//! Malformed data from the device is reported as an error, never a panic
+
+#![cfg(feature = "sim")]
+
+mod common;
+
+use common::Handle;
+use core::time::Duration;
+use tmc::class::ClassError;
+use tmc::transport::{Fault, FaultInjector, Operation};
+use tmc::{ConnectClear, OpenOptions, TMCError};
+
+const RESPONSE: &[u8] = b"data\n";
+
+fn open() -> (Handle, FaultInjector) {
+    let (mut handle, injector) =
+        common::open("rules:\n  - pattern: 'DATA\\?'\n    response: 'data'\n");
+    handle.set_timeout(Duration::from_millis(200));
+    // read now, so that the control requests which follow are the ones tested
+    handle.usbtmc_capabilities().unwrap();
+    (handle, injector)
+}
+
+/// Faults which mangle the header of the next bulk-in transfer
+fn malformed_headers() -> Vec<(&'static str, Vec<Fault>)> {
+    let mut transfer_size = vec![0xff; 4];
+    transfer_size[0] ^= RESPONSE.len() as u8;
+    vec![
+        ("truncated header", vec![Fault::ShortRead(6)]),
+        (
+            "transfer_size of u32::MAX",
+            transfer_size
+                .into_iter()
+                .enumerate()
+                .map(|(i, mask)| Fault::Corrupt {
+                    offset: 4 + i,
+                    mask,
+                })
+                .collect(),
+        ),
+        (
+            "bad MsgID",
+            vec![Fault::Corrupt {
+                offset: 0,
+                mask: 0x80,
+            }],
+        ),
+        (
+            "bad bTagInverse",
+            vec![Fault::Corrupt {
+                offset: 2,
+                mask: 0x01,
+            }],
+        ),
+    ]
+}
+
+fn inject(injector: &FaultInjector, operation: Operation, faults: Vec<Fault>) {
+    for fault in faults {
+        injector.inject(operation, 0, fault);
+    }
+}
+
+#[test]
+fn read_raw_malformed_header() {
+    for (name, faults) in malformed_headers() {
+        let (mut handle, injector) = open();
+        handle.write_raw(b"DATA?").unwrap();
+        inject(&injector, Operation::BulkIn, faults);
+        assert!(handle.read_raw(None).is_err(), "{}", name);
+    }
+}
+
+#[test]
+fn read_raw_frames_malformed_header() {
+    for (name, faults) in malformed_headers() {
+        let (mut handle, injector) = open();
+        handle.write_raw(b"DATA?").unwrap();
+        inject(&injector, Operation::BulkIn, faults);
+        let mut frames = handle.read_raw_frames(None);
+        assert!(frames.next().unwrap().is_err(), "{}", name);
+        assert!(frames.next().is_none(), "{}", name);
+    }
+}
+
+#[test]
+fn short_capabilities_response() {
+    // without a clear on connecting, the capabilities are the first control request
+    let options = OpenOptions::new().connect_clear(ConnectClear::Never);
+    let (mut handle, injector) = common::open_with("rules: []\n", options);
+    injector.inject(Operation::ControlIn, 0, Fault::ShortRead(4));
+    assert!(handle.usbtmc_capabilities().is_err());
+}
+
+#[test]
+fn short_status_byte_response() {
+    // the status byte itself comes on the interrupt-in endpoint
+    for len in 0..2 {
+        let (mut handle, injector) = open();
+        injector.inject(Operation::ControlIn, 0, Fault::ShortRead(len));
+        assert!(handle.read_stb(None).is_err(), "{} bytes", len);
+    }
+}
+
+#[test]
+fn bad_status_byte_response() {
+    let (mut handle, injector) = open();
+    injector.inject(
+        Operation::ControlIn,
+        0,
+        Fault::Corrupt {
+            offset: 0,
+            mask: 0x01,
+        },
+    );
+    assert!(handle.read_stb(None).is_err());
+}
+
+#[test]
+fn transfer_larger_than_requested() {
+    let (mut handle, injector) = open();
+    handle.write_raw(b"DATA?").unwrap();
+    // announce 64 KiB more than the response, far beyond what was requested
+    injector.inject(
+        Operation::BulkIn,
+        0,
+        Fault::Corrupt {
+            offset: 6,
+            mask: 0x01,
+        },
+    );
+    match handle.read_raw(Some(RESPONSE.len() as u32)) {
+        Err(TMCError::Class {
+            source: ClassError::TransferTooLarge { requested, .. },
+        }) => assert_eq!(requested, RESPONSE.len() as u32),
+        result => panic!("{:?}", result),
+    }
+
+    // the rest of the transfer isn't taken as the start of the next response
+    handle.write_raw(b"DATA?").unwrap();
+    assert_eq!(handle.read_raw(None).unwrap(), RESPONSE);
+}
@@ -0,0 +1,42 @@
+//! Middleware applies to every method taking a command as text
+
+#![cfg(feature = "sim")]
+
+mod common;
+
+use common::Handle;
+use tmc::middleware::Dialect;
+
+/// A handle on a device which only understands `MEAS:VOLT?`, with a dialect
+/// translating `VOLT?` to it and its answer back
+fn open() -> Handle {
+    let (mut handle, _) =
+        common::open("rules:\n  - pattern: 'MEAS:VOLT\\?'\n    response: '1.5'\n");
+    handle.add_middleware(Dialect::new().command("VOLT?", "MEAS:VOLT?").response(
+        "VOLT?",
+        "1.5",
+        "+1.50000E+00",
+    ));
+    handle
+}
+
+#[test]
+fn ask_rewritten() {
+    let mut handle = open();
+    assert_eq!(handle.ask("VOLT?").unwrap(), "+1.50000E+00\n");
+}
+
+#[test]
+fn ask_with_hint_rewritten() {
+    let mut handle = open();
+    assert_eq!(handle.ask_with_hint("VOLT?", 16).unwrap(), "+1.50000E+00\n");
+}
+
+#[test]
+fn ask_parse_with_rewritten() {
+    let mut handle = open();
+    let parsed = handle
+        .ask_parse_with("VOLT?", |data, _| Some(data.to_vec()))
+        .unwrap();
+    assert_eq!(parsed.unwrap(), b"1.5\n");
+}
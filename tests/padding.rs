@@ -0,0 +1,60 @@
+//! Bulk-in transfers whose alignment padding is missing or not zero
+
+#![cfg(feature = "sim")]
+
+mod common;
+
+use common::Handle;
+use tmc::class::{ClassError, PaddingPolicy, HEADER_SIZE};
+use tmc::transport::{Fault, FaultInjector, Operation};
+use tmc::TMCError;
+
+/// The response's data and newline end 3 bytes short of a multiple of 4
+const DATA_END: usize = HEADER_SIZE + 5;
+
+fn open(padding_policy: PaddingPolicy) -> (Handle, FaultInjector) {
+    let (mut handle, injector) =
+        common::open("rules:\n  - pattern: 'DATA\\?'\n    response: 'tiny'\n");
+    handle.set_padding_policy(padding_policy);
+    (handle, injector)
+}
+
+fn nonzero_padding() -> Fault {
+    Fault::Corrupt {
+        offset: DATA_END + 1,
+        mask: 0x20,
+    }
+}
+
+#[test]
+fn lenient_counts_violations() {
+    let (mut handle, injector) = open(PaddingPolicy::Lenient);
+
+    injector.inject(Operation::BulkIn, 0, nonzero_padding());
+    assert_eq!(handle.ask("DATA?").unwrap(), "tiny\n");
+    assert_eq!(handle.diagnostics().nonzero_padding, 1);
+
+    injector.inject(Operation::BulkIn, 0, Fault::ShortRead(DATA_END));
+    assert_eq!(handle.ask("DATA?").unwrap(), "tiny\n");
+    assert_eq!(handle.diagnostics().missing_padding, 1);
+
+    assert_eq!(handle.ask("DATA?").unwrap(), "tiny\n");
+    assert_eq!(handle.diagnostics().nonzero_padding, 1);
+    assert_eq!(handle.diagnostics().missing_padding, 1);
+}
+
+#[test]
+fn strict_fails_the_read() {
+    for fault in [nonzero_padding(), Fault::ShortRead(DATA_END)] {
+        let (mut handle, injector) = open(PaddingPolicy::Strict);
+        injector.inject(Operation::BulkIn, 0, fault);
+        match handle.ask("DATA?") {
+            Err(TMCError::Class {
+                source: ClassError::InvalidPadding,
+            }) => {}
+            result => panic!("{:?}: {:?}", fault, result),
+        }
+        assert_eq!(handle.diagnostics().nonzero_padding, 0);
+        assert_eq!(handle.diagnostics().missing_padding, 0);
+    }
+}
@@ -0,0 +1,75 @@
+//! Building one command message over several `write_partial_raw` calls
+
+#![cfg(feature = "sim")]
+
+mod common;
+
+use common::{sent, Handle};
+use tmc::transport::FaultInjector;
+
+const COMMAND: &[u8] = b"ECHO 0123456789";
+
+const MAX_TRANSFER_SIZE: usize = 4;
+
+fn open() -> (Handle, FaultInjector) {
+    let (mut handle, injector) = common::open(common::ECHO);
+    handle
+        .set_max_transfer_size(MAX_TRANSFER_SIZE as u32)
+        .unwrap();
+    injector.record();
+    (handle, injector)
+}
+
+/// Write COMMAND in the given parts and flush it, then check it went in full
+/// transfers with only the last marked as the end of the message
+fn write_in_parts(parts: &[&[u8]]) {
+    let (mut handle, injector) = open();
+    for part in parts {
+        handle.write_partial_raw(part).unwrap();
+        assert!(sent(&injector).iter().all(|(_, eom)| !eom));
+    }
+    handle.flush_message().unwrap();
+
+    let transfers = sent(&injector);
+    let (last, others) = transfers.split_last().unwrap();
+    assert!(last.1);
+    assert!(!last.0.is_empty());
+    assert!(others
+        .iter()
+        .all(|(data, eom)| data.len() == MAX_TRANSFER_SIZE && !eom));
+    let data: Vec<u8> = transfers
+        .iter()
+        .flat_map(|(data, _)| data.clone())
+        .collect();
+    assert_eq!(data, COMMAND, "{:?}", parts);
+
+    // the response echoes the digits, so only comes if the whole command arrived
+    assert_eq!(handle.read(None).unwrap(), "0123456789\n");
+}
+
+#[test]
+fn parts_across_transfer_boundaries() {
+    write_in_parts(&[b"ECH", b"O 01", b"23456789"]);
+    write_in_parts(&[b"ECHO", b" 012", b"3456", b"789"]);
+    write_in_parts(&[COMMAND]);
+}
+
+#[test]
+fn empty_and_single_byte_parts() {
+    write_in_parts(&[b"", b"E", b"", b"CHO 0123456789", b""]);
+    let bytes: Vec<&[u8]> = COMMAND.chunks(1).collect();
+    write_in_parts(&bytes);
+}
+
+#[test]
+fn flush_after_only_empty_parts() {
+    let (mut handle, injector) = open();
+    handle.write_partial_raw(b"").unwrap();
+    handle.write_partial_raw(b"").unwrap();
+    handle.flush_message().unwrap();
+    assert!(sent(&injector).is_empty());
+
+    // no message is left in progress
+    handle.write_raw(COMMAND).unwrap();
+    assert_eq!(handle.read(None).unwrap(), "0123456789\n");
+}
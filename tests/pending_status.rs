@@ -0,0 +1,86 @@
+//! Requests a device keeps reporting as pending fail once the control timeout
+//! has passed, rather than being polled forever
+
+#![cfg(feature = "sim")]
+
+mod common;
+
+use common::Handle;
+use core::time::Duration;
+use std::time::Instant;
+use tmc::transport::{Fault, FaultInjector, Operation};
+use tmc::TMCError;
+
+const CONTROL_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// Turns STATUS_SUCCESS into STATUS_PENDING
+const PENDING: Fault = Fault::Corrupt {
+    offset: 0,
+    mask: 0x03,
+};
+
+/// Turns STATUS_TRANSFER_NOT_IN_PROGRESS into STATUS_SUCCESS
+const IN_PROGRESS: Fault = Fault::Corrupt {
+    offset: 0,
+    mask: 0x80,
+};
+
+fn open() -> (Handle, FaultInjector) {
+    let (mut handle, injector) = common::open(common::ECHO);
+    handle.set_control_timeout(CONTROL_TIMEOUT);
+    handle.set_bulk_timeout(Duration::from_millis(10));
+    (handle, injector)
+}
+
+/// Have the device answer the status check following the first control request
+/// with STATUS_PENDING for as long as it is asked, which is far longer than the
+/// control timeout at one poll every 100 ms
+fn stay_pending(injector: &FaultInjector) {
+    for nth in 1..100 {
+        injector.inject(Operation::ControlIn, nth, PENDING);
+    }
+}
+
+fn assert_times_out<R: std::fmt::Debug>(f: impl FnOnce() -> Result<R, TMCError>) {
+    let started = Instant::now();
+    match f() {
+        Err(TMCError::Rusb {
+            source: rusb::Error::Timeout,
+        }) => {}
+        result => panic!("{:?}", result),
+    }
+    assert!(started.elapsed() < CONTROL_TIMEOUT * 3);
+}
+
+/// Once the device stops reporting pending, the handle works again
+fn assert_recovers(mut handle: Handle, injector: &FaultInjector) {
+    injector.clear();
+    handle.clear().unwrap();
+    assert_eq!(handle.ask("ECHO 1").unwrap(), "1\n");
+}
+
+#[test]
+fn clear_stays_pending() {
+    let (mut handle, injector) = open();
+    stay_pending(&injector);
+    assert_times_out(|| handle.clear());
+    assert_recovers(handle, &injector);
+}
+
+#[test]
+fn abort_bulk_in_stays_pending() {
+    let (mut handle, injector) = open();
+    injector.inject(Operation::ControlIn, 0, IN_PROGRESS);
+    stay_pending(&injector);
+    assert_times_out(|| handle.abort_bulk_in());
+    assert_recovers(handle, &injector);
+}
+
+#[test]
+fn abort_bulk_out_stays_pending() {
+    let (mut handle, injector) = open();
+    injector.inject(Operation::ControlIn, 0, IN_PROGRESS);
+    stay_pending(&injector);
+    assert_times_out(|| handle.abort_bulk_out());
+    assert_recovers(handle, &injector);
+}
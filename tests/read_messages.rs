@@ -0,0 +1,63 @@
+//! Reading several queued response messages without joining them
+
+#![cfg(feature = "sim")]
+
+mod common;
+
+use common::Handle;
+
+fn open(usb488: bool) -> Handle {
+    common::open(&format!(
+        "capabilities:\n  usb488: {}\n{}",
+        usb488,
+        common::ECHO
+    ))
+    .0
+}
+
+/// Queue one response message per query
+fn queue(handle: &mut Handle, queries: &[&str]) {
+    for query in queries {
+        handle.write(query).unwrap();
+    }
+}
+
+#[test]
+fn read_messages_keeps_boundaries() {
+    let mut handle = open(true);
+    queue(&mut handle, &["ECHO 1", "ECHO 22", "ECHO 333"]);
+    assert_eq!(
+        handle.read_messages(2).unwrap(),
+        vec![b"1\n".to_vec(), b"22\n".to_vec()]
+    );
+
+    // the message not asked for is still queued
+    assert_eq!(handle.read(None).unwrap(), "333\n");
+    assert!(handle.read_messages(0).unwrap().is_empty());
+}
+
+#[test]
+fn read_all_pending_usb488() {
+    let mut handle = open(true);
+    queue(&mut handle, &["ECHO 1", "ECHO 22"]);
+    assert_eq!(
+        handle.read_all_pending().unwrap(),
+        vec![b"1\n".to_vec(), b"22\n".to_vec()]
+    );
+    assert!(handle.read_all_pending().unwrap().is_empty());
+}
+
+#[test]
+fn read_all_pending_until_timeout() {
+    let mut handle = open(false);
+    queue(&mut handle, &["ECHO 1", "ECHO 22"]);
+    assert_eq!(
+        handle.read_all_pending().unwrap(),
+        vec![b"1\n".to_vec(), b"22\n".to_vec()]
+    );
+    assert!(handle.read_all_pending().unwrap().is_empty());
+
+    // the aborted read leaves the handle usable
+    handle.write("ECHO 4").unwrap();
+    assert_eq!(handle.read(None).unwrap(), "4\n");
+}
@@ -0,0 +1,87 @@
+//! Replaying a recorded transcript against a simulated device
+
+#![cfg(all(feature = "sim", feature = "replay"))]
+
+mod common;
+
+use common::Handle;
+use tmc::replay::{replay, ReplayOptions, ResponseCheck};
+use tmc::transcript::{Transcript, TranscriptEntry};
+
+/// A handle on a device measuring `volts`
+fn open(volts: &str) -> Handle {
+    common::open(&format!(
+        "rules:\n  - pattern: 'MEAS:VOLT\\?'\n    response: '{}'\n  - pattern: 'ECHO (\\d+)'\n    response: '$1'\n",
+        volts
+    ))
+    .0
+}
+
+/// A session recorded against a device measuring 1.5 V
+fn record() -> Vec<TranscriptEntry> {
+    let mut handle = open("1.5");
+    handle.start_transcript(Transcript::in_memory());
+    handle.write("*CLS").unwrap();
+    handle.ask("MEAS:VOLT?").unwrap();
+    handle.ask("ECHO 42").unwrap();
+    handle.stop_transcript().unwrap().take_entries()
+}
+
+fn options(check: ResponseCheck) -> ReplayOptions {
+    ReplayOptions {
+        check,
+        ..ReplayOptions::default()
+    }
+}
+
+#[test]
+fn same_device_passes() {
+    let transcript = record();
+    let report = replay(&transcript, &mut open("1.5"), &ReplayOptions::default()).unwrap();
+    assert!(report.passed(), "{:?}", report.mismatches);
+    assert_eq!(report.commands_sent, 3);
+    assert_eq!(report.responses_checked, 2);
+}
+
+#[test]
+fn changed_response_mismatches() {
+    let transcript = record();
+    let report = replay(&transcript, &mut open("1.52"), &ReplayOptions::default()).unwrap();
+    assert_eq!(report.mismatches.len(), 1);
+    let mismatch = &report.mismatches[0];
+    assert_eq!(mismatch.expected, "1.5\n");
+    assert_eq!(mismatch.actual, "1.52\n");
+    assert_eq!(transcript[mismatch.index].message, "1.5\n");
+}
+
+#[test]
+fn numeric_tolerance() {
+    let transcript = record();
+    let within = options(ResponseCheck::Numeric { tolerance: 0.05 });
+    assert!(replay(&transcript, &mut open("1.52"), &within)
+        .unwrap()
+        .passed());
+
+    let outside = options(ResponseCheck::Numeric { tolerance: 0.01 });
+    assert!(!replay(&transcript, &mut open("1.52"), &outside)
+        .unwrap()
+        .passed());
+}
+
+#[test]
+fn regex_response() {
+    let mut transcript = record();
+    for entry in transcript.iter_mut() {
+        if entry.message == "1.5\n" {
+            entry.message = "1\\.\\d+\n".to_owned();
+        }
+    }
+
+    let regex = options(ResponseCheck::Regex);
+    assert!(replay(&transcript, &mut open("1.52"), &regex)
+        .unwrap()
+        .passed());
+    assert!(!replay(&transcript, &mut open("2.5"), &regex)
+        .unwrap()
+        .passed());
+}
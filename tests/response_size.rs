@@ -0,0 +1,57 @@
+//! The maximum response size bounds what is requested, not only what is kept
+
+#![cfg(feature = "sim")]
+
+mod common;
+
+use byteorder::{ByteOrder, LittleEndian};
+use common::Handle;
+use tmc::class::ClassError;
+use tmc::transport::{FaultInjector, Operation};
+use tmc::TMCError;
+
+const MAX_RESPONSE_SIZE: usize = 6;
+
+fn open() -> (Handle, FaultInjector) {
+    let (mut handle, injector) = common::open(common::ECHO);
+    handle.set_max_response_size(Some(MAX_RESPONSE_SIZE));
+    injector.record();
+    (handle, injector)
+}
+
+/// The transfer size of each REQUEST_DEV_DEP_MSG_IN sent so far
+fn requested(injector: &FaultInjector) -> Vec<u32> {
+    injector
+        .recorded(Operation::BulkOut)
+        .iter()
+        .filter(|transfer| transfer[0] == 2)
+        .map(|transfer| LittleEndian::read_u32(&transfer[4..8]))
+        .collect()
+}
+
+#[test]
+fn response_at_limit() {
+    let (mut handle, injector) = open();
+    assert_eq!(handle.ask("ECHO 12345").unwrap(), "12345\n");
+    assert!(requested(&injector)
+        .iter()
+        .all(|&size| size as usize <= MAX_RESPONSE_SIZE + 1));
+}
+
+#[test]
+fn response_over_limit() {
+    let (mut handle, injector) = open();
+    match handle.ask("ECHO 0123456789") {
+        Err(TMCError::Class {
+            source: ClassError::ResponseTooLarge,
+        }) => {}
+        result => panic!("{:?}", result),
+    }
+
+    // only one byte more than the limit was read, the request which follows
+    // being aborted to discard the rest
+    assert_eq!(requested(&injector)[0] as usize, MAX_RESPONSE_SIZE + 1);
+
+    // and the rest of the response was discarded
+    assert_eq!(handle.ask("ECHO 1").unwrap(), "1\n");
+}
@@ -0,0 +1,56 @@
+//! Writing bulk-out transfers which the transport only partly accepts
+
+#![cfg(feature = "sim")]
+
+mod common;
+
+use common::{open, ECHO};
+use tmc::class::{ClassError, HEADER_SIZE};
+use tmc::transport::{Fault, Operation};
+use tmc::TMCError;
+
+const COMMAND: &str = "ECHO 0123456789";
+
+#[test]
+fn short_writes_are_completed() {
+    for &n in &[
+        1,
+        HEADER_SIZE - 1,
+        HEADER_SIZE,
+        HEADER_SIZE + 1,
+        HEADER_SIZE + 8,
+    ] {
+        let (mut handle, injector) = open(ECHO);
+        injector.inject(Operation::BulkOut, 0, Fault::ShortWrite(n));
+
+        // the response echoes the digits, so only comes if the whole command arrived
+        handle.write(COMMAND).unwrap();
+        assert_eq!(injector.pending(), 0);
+        assert_eq!(handle.read(None).unwrap(), "0123456789\n", "{} bytes", n);
+    }
+}
+
+#[test]
+fn zero_byte_write_fails() {
+    let (mut handle, injector) = open(ECHO);
+    injector.inject(Operation::BulkOut, 0, Fault::ShortWrite(0));
+    match handle.write(COMMAND) {
+        Err(TMCError::Class {
+            source: ClassError::TruncatedBulkOut { received: 0, .. },
+        }) => {}
+        result => panic!("{:?}", result),
+    }
+}
+
+#[test]
+fn zero_byte_write_after_short_write_fails() {
+    let (mut handle, injector) = open(ECHO);
+    injector.inject(Operation::BulkOut, 0, Fault::ShortWrite(HEADER_SIZE - 2));
+    injector.inject(Operation::BulkOut, 1, Fault::ShortWrite(0));
+    match handle.write(COMMAND) {
+        Err(TMCError::Class {
+            source: ClassError::TruncatedBulkOut { received, .. },
+        }) => assert_eq!(received, HEADER_SIZE - 2),
+        result => panic!("{:?}", result),
+    }
+}
@@ -0,0 +1,91 @@
+//! Responses spilled to disk once they outgrow the memory budget
+
+#![cfg(feature = "sim")]
+
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+mod common;
+
+use common::Handle;
+use tmc::spill::ResponseSpill;
+
+const BUDGET: usize = 16;
+
+/// An empty directory for one test's temporary files
+fn directory(test: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("tmc-spill-{}-{}", std::process::id(), test));
+    let _ = fs::remove_dir_all(&directory);
+    directory
+}
+
+/// A handle on a device answering `ECHO` with its digits, read four bytes a
+/// transfer, spilling responses over the budget to `directory`
+fn open(directory: &Path) -> Handle {
+    let (mut handle, _) = common::open(common::ECHO);
+    handle.set_max_transfer_size(4).unwrap();
+    handle.set_response_spill(Some(ResponseSpill::new(BUDGET).directory(directory)));
+    handle
+}
+
+fn files_in(directory: &Path) -> usize {
+    fs::read_dir(directory).map_or(0, |entries| entries.count())
+}
+
+#[test]
+fn within_budget_held_in_memory() {
+    let directory = directory("memory");
+    let mut handle = open(&directory);
+
+    let mut response = handle.ask_raw_spooled(b"ECHO 0123").unwrap();
+    assert!(!response.is_spilled());
+    assert_eq!(response.path(), None);
+    assert_eq!(response.len(), 5);
+
+    let mut data = Vec::new();
+    response.read_to_end(&mut data).unwrap();
+    assert_eq!(data, b"0123\n");
+    assert_eq!(files_in(&directory), 0);
+}
+
+#[test]
+fn over_budget_spilled_and_removed_on_drop() {
+    let directory = directory("drop");
+    let mut handle = open(&directory);
+    let digits = "0123456789".repeat(4);
+
+    let mut response = handle
+        .ask_raw_spooled(format!("ECHO {}", digits).as_bytes())
+        .unwrap();
+    assert!(response.is_spilled());
+    assert_eq!(response.len(), digits.len() as u64 + 1);
+    let path = response.path().unwrap().to_owned();
+    assert_eq!(path.parent(), Some(directory.as_path()));
+
+    let mut data = Vec::new();
+    response.read_to_end(&mut data).unwrap();
+    assert_eq!(data, format!("{}\n", digits).as_bytes());
+
+    drop(response);
+    assert!(!path.exists());
+    assert_eq!(files_in(&directory), 0);
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn spilled_response_persisted() {
+    let directory = directory("persist");
+    let mut handle = open(&directory);
+    let digits = "9876543210".repeat(4);
+    let kept = directory.join("kept").join("response.txt");
+
+    let response = handle
+        .ask_raw_spooled(format!("ECHO {}", digits).as_bytes())
+        .unwrap();
+    let temporary = response.path().unwrap().to_owned();
+    response.persist(&kept).unwrap();
+
+    assert!(!temporary.exists());
+    assert_eq!(fs::read(&kept).unwrap(), format!("{}\n", digits).as_bytes());
+    fs::remove_dir_all(&directory).unwrap();
+}
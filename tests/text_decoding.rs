@@ -0,0 +1,60 @@
+//! Responses which aren't valid UTF-8
+
+#![cfg(feature = "sim")]
+
+mod common;
+
+use common::Handle;
+use tmc::class::HEADER_SIZE;
+use tmc::transport::{Fault, FaultInjector, Operation};
+use tmc::{Encoding, TMCError, TextDecoding};
+
+fn open() -> (Handle, FaultInjector) {
+    common::open("rules:\n  - pattern: 'TEMP\\?'\n    response: '25.0 C'\n")
+}
+
+/// Ask for the temperature, its space turned into 0xB0, the ISO 8859-1 degree
+/// sign, which is never valid on its own in UTF-8
+fn ask_degrees(handle: &mut Handle, injector: &FaultInjector) -> Result<String, TMCError> {
+    injector.inject(
+        Operation::BulkIn,
+        0,
+        Fault::Corrupt {
+            offset: HEADER_SIZE + 4,
+            mask: b' ' ^ 0xB0,
+        },
+    );
+    handle.ask("TEMP?")
+}
+
+#[test]
+fn strict_by_default() {
+    let (mut handle, injector) = open();
+    assert_eq!(handle.get_text_decoding(), TextDecoding::Strict);
+    match ask_degrees(&mut handle, &injector) {
+        Err(TMCError::FromUtf8Error { source }) => {
+            assert_eq!(source.utf8_error().valid_up_to(), 4)
+        }
+        result => panic!("{:?}", result),
+    }
+
+    // the failed decode doesn't affect the next read
+    assert_eq!(handle.ask("TEMP?").unwrap(), "25.0 C\n");
+}
+
+#[test]
+fn lossy_replaces_invalid_data() {
+    let (mut handle, injector) = open();
+    handle.set_text_decoding(TextDecoding::Lossy);
+    assert_eq!(
+        ask_degrees(&mut handle, &injector).unwrap(),
+        "25.0\u{FFFD}C\n"
+    );
+}
+
+#[test]
+fn latin1_decodes_every_byte() {
+    let (mut handle, injector) = open();
+    handle.set_encoding(Encoding::Latin1);
+    assert_eq!(ask_degrees(&mut handle, &injector).unwrap(), "25.0°C\n");
+}
@@ -0,0 +1,39 @@
+//! Commands synchronized with `*OPC?`, which can't carry a query's response
+
+#![cfg(feature = "sim")]
+
+mod common;
+
+use common::sent;
+use tmc::TMCError;
+
+#[test]
+fn command_synchronized() {
+    let (mut handle, injector) = common::open(common::ECHO);
+    injector.record();
+
+    handle.write_synchronized("VOLT 1\n").unwrap();
+    assert_eq!(sent(&injector)[0], (b"VOLT 1;*OPC?\n".to_vec(), true));
+
+    // the *OPC? response was read, leaving nothing behind for the next query
+    assert_eq!(handle.ask("ECHO 2").unwrap(), "2\n");
+}
+
+#[test]
+fn query_refused() {
+    let (mut handle, injector) = common::open(common::ECHO);
+    injector.record();
+
+    let error = handle.write_synchronized("MEAS:VOLT?").unwrap_err();
+    assert_eq!(
+        error,
+        TMCError::QueryNotSynchronized {
+            command: "MEAS:VOLT?".to_owned()
+        }
+    );
+    assert!(sent(&injector).is_empty());
+
+    let error = handle.write_synchronized("VOLT 1;MEAS:VOLT?").unwrap_err();
+    assert!(matches!(error, TMCError::QueryNotSynchronized { .. }));
+    assert_eq!(handle.ask("ECHO 2").unwrap(), "2\n");
+}